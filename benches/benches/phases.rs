@@ -0,0 +1,58 @@
+//! Times lexing, parsing, and compiling to C on small, medium, and large programs
+//! made up by haumea::synthetic, so a pass that gets slower shows up
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use haumea::codegen;
+use haumea::options::CompilerOptions;
+use haumea::parser::{self, SourceFile};
+use haumea::scanner::Scanner;
+use haumea::synthetic;
+
+/// The sizes of the programs, with how many functions they have
+const SIZES: &[(&str, usize)] = &[("small", 10), ("medium", 500), ("large", 5000)];
+
+fn lexing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lex");
+    for &(size, functions) in SIZES {
+        let source = synthetic::program(functions);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &source, |b, source| {
+            b.iter(|| Scanner::new(source).count())
+        });
+    }
+    group.finish();
+}
+
+fn parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for &(size, functions) in SIZES {
+        let source = synthetic::program(functions);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &source, |b, source| {
+            b.iter(|| parser::parse(Scanner::new(source)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn codegen(c: &mut Criterion) {
+    let mut group = c.benchmark_group("codegen");
+    let options = CompilerOptions::new("main.hm");
+    for &(size, functions) in SIZES {
+        let files = vec![SourceFile {
+            name: options.source_name(),
+            program: parser::parse(Scanner::new(&synthetic::program(functions))).unwrap(),
+        }];
+        group.throughput(Throughput::Elements(functions as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &files, |b, files| {
+            b.iter(|| {
+                let mut c = String::new();
+                codegen::compile_files(&mut c, files, true, &options);
+                c
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(phases, lexing, parsing, codegen);
+criterion_main!(phases);
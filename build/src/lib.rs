@@ -0,0 +1,133 @@
+//! Compiles the haumea sources bundled with a Rust crate to C from its build.rs, and links them
+//! into the crate with the [cc](https://docs.rs/cc) crate, so that the Rust can call their functions
+//!
+//! The sources are compiled like `haumea build --lib` compiles them: every function but `main`
+//! goes into the C, along with a header that declares them, and the full runtime if they need it.
+//!
+//! # Examples
+//! ```no_run
+//! // In the main function of build.rs
+//! let out_dir = std::env::var("OUT_DIR").unwrap();
+//! haumea_build::compile_dir("src/haumea", &out_dir)
+//!     .unwrap_or_else(|errors| panic!("{}", errors))
+//!     .link();
+//! ```
+//!
+//! The Rust then declares the functions that it calls, with a `c_long` for each parameter:
+//!
+//! ```ignore
+//! use std::os::raw::c_long;
+//!
+//! extern "C" {
+//!     fn square(x: c_long) -> c_long;
+//! }
+//! ```
+use std::fs;
+use std::path::{Path, PathBuf};
+use haumea::codegen;
+use haumea::diagnostic::{Diagnostic, Severity};
+use haumea::options::{self, CompilerOptions, OutputKind};
+use haumea::parser::{self, SourceFile};
+use haumea::project;
+use haumea::runtime;
+use haumea::scanner::Scanner;
+
+/// The C that compile_dir generated, ready to be compiled and linked into the crate
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compiled {
+    /// The name of the library, which is the name of the directory of the sources
+    pub name: String,
+    /// The C files to compile, which are the program and the runtime if it needs it
+    pub sources: Vec<PathBuf>,
+    /// The header that declares the functions of the program, for C code that calls them
+    pub header: PathBuf,
+    /// The directory that the C was written to, which the C includes its headers from
+    pub include_dir: PathBuf,
+}
+
+impl Compiled {
+    /// Returns a cc::Build that compiles the C, which can be given more flags before it is compiled
+    pub fn cc(&self) -> cc::Build {
+        let mut build = cc::Build::new();
+        build.files(&self.sources).include(&self.include_dir);
+        build
+    }
+
+    /// Compiles the C into a static library named name and links it into the crate
+    ///
+    /// Like cc::Build::compile, this tells Cargo to link the library, and panics if the C can't be compiled.
+    pub fn link(&self) {
+        self.cc().compile(&self.name);
+    }
+}
+
+/// Compiles the haumea sources in dir, and the directories in it, to C in out_dir
+///
+/// out_dir is normally the `OUT_DIR` that Cargo gives build.rs. The C is `<name>.c` and the
+/// header is `<name>.h`, where name is the name of dir, so the sources in `src/haumea` are
+/// compiled to `haumea.c`. Cargo is told to run build.rs again when anything in dir changes,
+/// and warnings are passed on to it, so they are shown by `cargo build`.
+///
+/// Returns the errors in the program, one on each line, if it has any.
+pub fn compile_dir<P: AsRef<Path>, Q: AsRef<Path>>(dir: P, out_dir: Q) -> Result<Compiled, String> {
+    let dir = dir.as_ref();
+    let out_dir = out_dir.as_ref();
+    println!("cargo:rerun-if-changed={}", dir.display());
+    let name = dir.file_name().map_or("haumea".into(), |name| name.to_string_lossy().into_owned());
+    let files = parse(&project::source_files(dir)?)?;
+    let mut options = CompilerOptions::new(dir);
+    options.output_kind = OutputKind::StaticLibrary;
+    warn(&files, &options);
+    let c = haumea::compile(&files, &options).map_err(|errors| lines(&errors))?;
+    let header_name = format!("{}.h", name);
+    let mut interface = String::new();
+    codegen::compile_interface(&mut interface, &files, &codegen::include_guard(&header_name));
+    let header = out_dir.join(header_name);
+    write(&header, &interface)?;
+    let c_file = out_dir.join(format!("{}.c", name));
+    write(&c_file, &c)?;
+    let mut sources = vec![c_file];
+    if runtime::is_used_by(&c) {
+        runtime::write_runtime(out_dir)?;
+        sources.push(out_dir.join(runtime::SOURCE_NAME));
+    }
+    Ok(Compiled { name, sources, header, include_dir: out_dir.to_path_buf() })
+}
+
+/// Reads and parses each of paths, returning the first error in each of them that has one
+fn parse(paths: &[PathBuf]) -> Result<Vec<SourceFile>, String> {
+    let mut files = vec![];
+    let mut errors = vec![];
+    for path in paths {
+        let name = options::source_name(path);
+        let source = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", name, e))?;
+        match parser::parse(Scanner::new(&source)) {
+            Ok(program) => files.push(SourceFile { name, program }),
+            Err(error) => errors.push(error.in_file(&name)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(files)
+    } else {
+        Err(lines(&errors))
+    }
+}
+
+/// Passes the warnings in files on to Cargo, which shows them, leaving the errors to haumea::compile
+fn warn(files: &[SourceFile], options: &CompilerOptions) {
+    for diagnostic in haumea::check(files, options) {
+        if diagnostic.severity == Severity::Warning {
+            println!("cargo:warning={}", diagnostic);
+        }
+    }
+}
+
+/// Returns diagnostics with one on each line
+fn lines(diagnostics: &[Diagnostic]) -> String {
+    diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect::<Vec<_>>().join("\n")
+}
+
+/// Writes contents to the file at path
+fn write(path: &Path, contents: &str) -> Result<(), String> {
+    fs::write(path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
@@ -0,0 +1,61 @@
+//! Compiles directories of haumea sources the way a build.rs would, and checks the C that comes out
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+/// Returns a new empty directory named name, which is different for each run of the tests
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("haumea-build-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn sources_are_compiled_into_a_library() {
+    let root = temp_dir("library");
+    let sources = root.join("haumea");
+    fs::create_dir_all(sources.join("shapes")).unwrap();
+    fs::write(sources.join("square.hm"), "to square with (x) is x * x\nto main do display(square(2)) end\n").unwrap();
+    fs::write(sources.join("shapes").join("cube.hm"), "to cube with (x) is x * square(x)\n").unwrap();
+    let out_dir = root.join("out");
+    fs::create_dir_all(&out_dir).unwrap();
+    let compiled = haumea_build::compile_dir(&sources, &out_dir).unwrap();
+    assert_eq!(compiled.name, "haumea");
+    assert_eq!(compiled.sources, vec![out_dir.join("haumea.c")]);
+    assert_eq!(compiled.include_dir, out_dir);
+    let header = fs::read_to_string(&compiled.header).unwrap();
+    assert!(header.starts_with("#ifndef HAUMEA_H\n"));
+    assert!(header.contains("long square(long x);\n"));
+    assert!(header.contains("long cube(long x);\n"));
+    let c = fs::read_to_string(&compiled.sources[0]).unwrap();
+    assert!(c.contains("long cube(long x)\n{"));
+    assert!(!c.contains("main("));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn the_full_runtime_is_written_when_it_is_needed() {
+    let root = temp_dir("runtime");
+    let sources = root.join("texts");
+    fs::create_dir_all(&sources).unwrap();
+    fs::write(sources.join("greet.hm"), "to greet do display_text(\"hello\") end\n").unwrap();
+    let compiled = haumea_build::compile_dir(&sources, &root).unwrap();
+    assert_eq!(compiled.sources, vec![root.join("texts.c"), root.join("haumea_rt.c")]);
+    assert!(root.join("haumea_rt.h").exists());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn errors_in_the_sources_are_returned() {
+    let root = temp_dir("errors");
+    let sources = root.join("broken");
+    fs::create_dir_all(&sources).unwrap();
+    let broken = sources.join("broken.hm");
+    fs::write(&broken, "to main do\n    set total to 1\nend\n").unwrap();
+    let error = haumea_build::compile_dir(&sources, &root).unwrap_err();
+    assert!(error.starts_with(&format!("{}:2:", broken.display())), "{}", error);
+    assert!(error.contains("total"), "{}", error);
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,10 @@
+//! Feeds arbitrary source to haumea, which should report errors rather than panicking
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Source files are read as UTF-8, so other bytes never get to the scanner
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = haumea::check_no_panic(source);
+    }
+});
@@ -0,0 +1,262 @@
+//! The `haumea!` macro, which compiles a haumea program written in Rust source to C as the
+//! Rust is compiled, so that the errors in the program are errors in the Rust
+//!
+//! The program is read from the Rust tokens, so its comments have to be Rust comments, like
+//! `// the answer`, which Rust takes out before the macro sees them. Everything else is written
+//! as it is in a haumea file. With the `interpret` feature, `haumea_output!` runs the program
+//! with the interpreter instead, and gives what it displays.
+extern crate proc_macro;
+
+use haumea::diagnostic::Diagnostic;
+use haumea::options::CompilerOptions;
+use haumea::parser::{self, SourceFile};
+use haumea::scanner::{self, Scanner};
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// The name of the program in its errors and in the #line directives of its C
+const SOURCE_NAME: &str = "haumea!";
+
+/// An error in a program, with where it is, if it is anywhere in particular
+type Error = (String, Option<scanner::Span>);
+
+/// Compiles the haumea program in the tokens to C, giving the C as a `&'static str`
+///
+/// The C is what `haumea build` would generate for the program. Like it, a program that uses
+/// builtins that aren't in the minimal runtime, like `display_text`, needs the full runtime
+/// to be compiled along with it, which `haumea::runtime::write_runtime` writes out. Inline C
+/// isn't allowed.
+///
+/// # Examples
+/// ```
+/// use haumea_macros::haumea;
+///
+/// const SQUARES: &str = haumea! {
+///     to square with (x) is x * x
+///
+///     to main do
+///         // Comments are Rust comments
+///         display(square(7))
+///     end
+/// };
+/// assert!(SQUARES.contains("long square(long x)"));
+/// assert!(SQUARES.contains("display(square(7l));"));
+/// ```
+///
+/// An error in the program is a compile error at the tokens that it is about:
+///
+/// ```compile_fail
+/// use haumea_macros::haumea;
+///
+/// const BROKEN: &str = haumea! {
+///     to main do
+///         set total to 1
+///     end
+/// };
+/// ```
+#[proc_macro]
+pub fn haumea(input: TokenStream) -> TokenStream {
+    let source = match Source::from_tokens(input) {
+        Ok(source) => source,
+        Err(error) => return rust_compile_errors(&[error]),
+    };
+    match compile(&source.text) {
+        Ok(c) => TokenTree::from(Literal::string(&c)).into(),
+        Err(errors) => source.compile_errors(&errors),
+    }
+}
+
+/// Runs the haumea program in the tokens with the interpreter, giving what it displays as a `&'static str`
+///
+/// A program that fails, or returns an exit code other than 0 from main, is a compile error.
+///
+/// # Examples
+/// ```
+/// use haumea_macros::haumea_output;
+///
+/// const TABLE: &str = haumea_output! {
+///     to main do
+///         variable row is 0
+///         repeat 3 times do
+///             increase row
+///             display(row * 7)
+///         end
+///     end
+/// };
+/// assert_eq!(TABLE, "7\n14\n21\n");
+/// ```
+#[cfg(feature = "interpret")]
+#[proc_macro]
+pub fn haumea_output(input: TokenStream) -> TokenStream {
+    let source = match Source::from_tokens(input) {
+        Ok(source) => source,
+        Err(error) => return rust_compile_errors(&[error]),
+    };
+    match interpret(&source.text) {
+        Ok(output) => TokenTree::from(Literal::string(&output)).into(),
+        Err(errors) => source.compile_errors(&errors),
+    }
+}
+
+/// Returns the C of the program in source, or its errors
+fn compile(source: &str) -> Result<String, Vec<Error>> {
+    let files = parse(source)?;
+    haumea::compile(&files, &CompilerOptions::new(SOURCE_NAME)).map_err(|errors| errors.iter().map(error_of).collect())
+}
+
+/// Returns what the program in source displays when it is interpreted, or its errors
+#[cfg(feature = "interpret")]
+fn interpret(source: &str) -> Result<String, Vec<Error>> {
+    use haumea::interpreter::Interpreter;
+
+    let files = check(source)?;
+    let mut interpreter = Interpreter::new(vec![]);
+    interpreter.define_all(files[0].program.clone());
+    let code = interpreter.run_main().map_err(|error| vec![error_of(&error)])?;
+    if code != 0 {
+        return Err(vec![(format!("The program exited with {}", code), None)]);
+    }
+    Ok(String::from_utf8_lossy(&interpreter.into_output()).into_owned())
+}
+
+/// Parses the program in source, returning it as the only file of a program
+fn parse(source: &str) -> Result<Vec<SourceFile>, Vec<Error>> {
+    let program = parser::parse(Scanner::new(source)).map_err(|error| vec![error_of(&error)])?;
+    Ok(vec![SourceFile { name: SOURCE_NAME.to_string(), program }])
+}
+
+/// Parses and checks the program in source, as haumea::compile checks it
+#[cfg(feature = "interpret")]
+fn check(source: &str) -> Result<Vec<SourceFile>, Vec<Error>> {
+    use haumea::diagnostic::Severity;
+
+    let files = parse(source)?;
+    let errors = haumea::check(&files, &CompilerOptions::new(SOURCE_NAME)).into_iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .map(|diagnostic| error_of(&diagnostic))
+        .collect::<Vec<_>>();
+    if errors.is_empty() {
+        Ok(files)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Returns the message of diagnostic, without where it is, which the span of the compile error says
+fn error_of(diagnostic: &Diagnostic) -> Error {
+    let message = Diagnostic { span: None, file: None, ..diagnostic.clone() }.to_string();
+    (message, diagnostic.span)
+}
+
+/// The source of a program, made from the Rust tokens it was written as
+///
+/// The tokens all go on one line, with a space between each of them apart from punctuation
+/// that is joined to the next, like the two halves of `!=`, as haumea doesn't care about lines.
+struct Source {
+    text: String,
+    /// The column that each token starts at, with its span in the Rust, in order
+    spans: Vec<(usize, Span)>,
+    /// The column that the next char in text will be at
+    column: usize,
+    /// Whether the last token was punctuation joined to the next token
+    joint: bool,
+}
+
+impl Source {
+    /// Makes the source of the program in tokens, or returns an error at the token in the
+    /// Rust that can't be in it
+    fn from_tokens(tokens: TokenStream) -> Result<Source, (String, Option<Span>)> {
+        let mut source = Source {
+            text: String::new(),
+            spans: vec![],
+            column: 1,
+            joint: false,
+        };
+        source.push_tokens(tokens)?;
+        Ok(source)
+    }
+
+    fn push_tokens(&mut self, tokens: TokenStream) -> Result<(), (String, Option<Span>)> {
+        for token in tokens {
+            match token {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => ("", ""),
+                    };
+                    self.push(open, group.span_open());
+                    self.push_tokens(group.stream())?;
+                    self.push(close, group.span_close());
+                },
+                TokenTree::Punct(ref punct) if punct.as_char() == '#' => {
+                    let message = "haumea! can't have # comments, as Rust would read them as code, so use // comments";
+                    return Err((message.to_string(), Some(punct.span())));
+                },
+                TokenTree::Punct(punct) => {
+                    self.push(&punct.as_char().to_string(), punct.span());
+                    self.joint = punct.spacing() == Spacing::Joint;
+                },
+                TokenTree::Ident(ident) => self.push(&ident.to_string(), ident.span()),
+                TokenTree::Literal(literal) => self.push(&literal.to_string(), literal.span()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds text, which is a token at span in the Rust
+    fn push(&mut self, text: &str, span: Span) {
+        if text.is_empty() {
+            return;
+        }
+        if !self.text.is_empty() && !self.joint {
+            self.text.push(' ');
+            self.column += 1;
+        }
+        self.joint = false;
+        self.spans.push((self.column, span));
+        self.text.push_str(text);
+        self.column += text.chars().count();
+    }
+
+    /// Returns the span in the Rust of the token at column, or the last one before it
+    fn span_at(&self, column: usize) -> Span {
+        self.spans.iter()
+            .take_while(|&&(start, _)| start <= column)
+            .last()
+            .map_or_else(Span::call_site, |&(_, span)| span)
+    }
+
+    /// Returns a compile error for each of errors, at the token in the Rust that it is about
+    fn compile_errors(&self, errors: &[Error]) -> TokenStream {
+        let errors = errors.iter()
+            .map(|(message, span)| (message.clone(), span.map(|span| self.span_at(span.column))))
+            .collect::<Vec<_>>();
+        rust_compile_errors(&errors)
+    }
+}
+
+/// Returns a block with a compile_error! for each of errors, at its span or at the macro if it
+/// has none, which is an expression of type &str
+fn rust_compile_errors(errors: &[(String, Option<Span>)]) -> TokenStream {
+    let mut tokens = vec![];
+    for (message, span) in errors {
+        let span = span.unwrap_or_else(Span::call_site);
+        let mut literal = Literal::string(message);
+        literal.set_span(span);
+        let mut arguments = Group::new(Delimiter::Parenthesis, TokenTree::from(literal).into());
+        arguments.set_span(span);
+        tokens.push(TokenTree::from(Ident::new("compile_error", span)));
+        tokens.push(with_span(Punct::new('!', Spacing::Alone), span));
+        tokens.push(TokenTree::from(arguments));
+        tokens.push(with_span(Punct::new(';', Spacing::Alone), span));
+    }
+    tokens.push(TokenTree::from(Literal::string("")));
+    TokenTree::from(Group::new(Delimiter::Brace, tokens.into_iter().collect())).into()
+}
+
+/// Returns punct at span
+fn with_span(mut punct: Punct, span: Span) -> TokenTree {
+    punct.set_span(span);
+    TokenTree::from(punct)
+}
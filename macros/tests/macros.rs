@@ -0,0 +1,27 @@
+//! Checks that the C that haumea! generates is the C that haumea itself generates for the program
+use haumea::codegen;
+use haumea::options::CompilerOptions;
+use haumea::parser::{self, SourceFile};
+use haumea::scanner::Scanner;
+use haumea_macros::haumea;
+
+const FACTORIAL: &str = haumea! {
+    to factorial with (n) do
+        if n <= 1 then return 1
+        return n * factorial(n - 1)
+    end
+
+    to main do
+        display(factorial(5) = 120)
+    end
+};
+
+#[test]
+fn macro_generates_the_same_c_as_haumea() {
+    let source = "to factorial with (n) do if n <= 1 then return 1 return n * factorial(n - 1) end \
+                  to main do display(factorial(5) = 120) end";
+    let files = [SourceFile { name: "haumea!".to_string(), program: parser::parse(Scanner::new(source)).unwrap() }];
+    let mut c = String::new();
+    codegen::compile_files(&mut c, &files, false, &CompilerOptions::new("haumea!"));
+    assert_eq!(FACTORIAL, c);
+}
@@ -0,0 +1,144 @@
+/// src/cache.rs
+/// Keeps the C generated for each function in a directory, so rebuilds only compile the functions that changed
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use parser::{Function, Statement, StatementKind};
+
+/// A directory of the C of functions, each in a file named after the key of the function
+///
+/// # Examples
+/// ```
+/// # use std::env;
+/// # use haumea::cache::FunctionCache;
+/// let dir = env::temp_dir().join(format!("haumea-cache-doctest-{}", std::process::id()));
+/// let mut cache = FunctionCache::open(&dir).unwrap();
+/// assert_eq!(cache.get(12), None);
+/// cache.insert(12, "long f(void)\n{\n}\n");
+/// assert_eq!(cache.get(12), Some("long f(void)\n{\n}\n".to_string()));
+/// assert_eq!((cache.hits, cache.misses), (1, 1));
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct FunctionCache {
+    dir: PathBuf,
+    /// How many functions were found in the cache
+    pub hits: usize,
+    /// How many functions weren't, and so had to be compiled
+    pub misses: usize,
+}
+
+impl FunctionCache {
+    /// Opens the cache in dir, creating the directory if it doesn't exist
+    pub fn open<P: Into<PathBuf>>(dir: P) -> Result<FunctionCache, String> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| format!("Could not create the cache {}: {}", dir.display(), e))?;
+        Ok(FunctionCache { dir, hits: 0, misses: 0 })
+    }
+
+    /// Returns the C kept for the function with key, if there is any
+    pub fn get(&mut self, key: u64) -> Option<String> {
+        match fs::read_to_string(self.path(key)) {
+            Ok(c) => {
+                self.hits += 1;
+                Some(c)
+            },
+            Err(_) => {
+                self.misses += 1;
+                None
+            },
+        }
+    }
+
+    /// Keeps c as the C of the function with key
+    ///
+    /// The C is written to a temporary file that is then renamed, so an interrupted build
+    /// never leaves half of a function behind. A cache that can't be written to only means
+    /// that the function is compiled again next time, so that isn't an error.
+    pub fn insert(&mut self, key: u64, c: &str) {
+        let path = self.path(key);
+        let temporary = path.with_extension("c.tmp");
+        if fs::write(&temporary, c).and_then(|_| fs::rename(&temporary, &path)).is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.c", key))
+    }
+}
+
+/// Returns the key that the C of func is kept under, when it is in source_name and compiled
+/// with full_runtime and safe, which are everything its C depends on
+///
+/// The key covers where func and its statements are in the source, as the C has #line
+/// directives for them, and the version of haumea, as its C can change from one to the next.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser;
+/// # use haumea::cache::function_key;
+/// let program = parser::parse(Scanner::new("to f do return 1 end")).unwrap();
+/// let moved = parser::parse(Scanner::new("\nto f do return 1 end")).unwrap();
+/// let key = function_key(&program[0], "main.hm", false, false);
+/// assert_eq!(key, function_key(&program[0], "main.hm", false, false));
+/// assert_ne!(key, function_key(&moved[0], "main.hm", false, false));
+/// assert_ne!(key, function_key(&program[0], "other.hm", false, false));
+/// assert_ne!(key, function_key(&program[0], "main.hm", false, true));
+/// ```
+pub fn function_key(func: &Function, source_name: &str, full_runtime: bool, safe: bool) -> u64 {
+    let mut hasher = Fnv::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    source_name.hash(&mut hasher);
+    full_runtime.hash(&mut hasher);
+    safe.hash(&mut hasher);
+    func.hash(&mut hasher);
+    func.span.hash(&mut hasher);
+    if let Some(ref code) = func.code {
+        hash_spans(code, &mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Hashes the spans of statement and the statements in it, which hashing a Statement leaves out
+fn hash_spans<H: Hasher>(statement: &Statement, state: &mut H) {
+    statement.span.hash(state);
+    match statement.kind {
+        StatementKind::Do(ref block) => {
+            for statement in block {
+                hash_spans(statement, state);
+            }
+        },
+        StatementKind::If { ref if_clause, ref else_clause, .. } => {
+            hash_spans(if_clause, state);
+            if let Some(ref else_clause) = *else_clause {
+                hash_spans(else_clause, state);
+            }
+        },
+        StatementKind::Repeat { ref body, .. } => hash_spans(body, state),
+        _ => {},
+    }
+}
+
+/// The 64 bit FNV-1a hash, which unlike the hasher of a HashMap gives the same hashes
+/// from one version of Rust to the next, so the keys of a cache stay the same
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,265 @@
+/// src/callgraph.rs
+/// Works out which functions in a haumea program call which others
+use std::collections::HashSet;
+use parser::{Expression, SourceFile, Statement, StatementKind};
+use scanner::Span;
+use checker::BUILTINS;
+use json::Json;
+
+/// A function in a call graph
+#[derive(Debug, Clone, PartialEq)]
+pub struct Caller {
+    /// The name of the function
+    pub name: String,
+    /// The name of the file that defines the function
+    pub file: String,
+    /// Where the function is defined
+    pub span: Span,
+    /// The functions that it calls, each once, in the order their first calls appear
+    pub calls: Vec<String>,
+    /// Whether the function can be called, directly or indirectly, from main
+    pub reachable: bool,
+}
+
+/// Which functions call which others in a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallGraph {
+    /// Every function defined in the program, in the order they are defined
+    ///
+    /// If a function is defined more than once only its first definition is included
+    pub functions: Vec<Caller>,
+}
+
+impl CallGraph {
+    /// Builds the call graph of the program made of files
+    ///
+    /// The functions that can't be reached from main are marked as unreachable.
+    /// A program without a main, like a library, is called from outside,
+    /// so then every function counts as reachable.
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::scanner::Scanner;
+    /// # use haumea::parser::{self, SourceFile};
+    /// # use haumea::callgraph::CallGraph;
+    /// let source = "to main do display(double(2)) end
+    /// to double with (n) do return n * 2 end
+    /// to unused do return double(1) end";
+    /// let file = SourceFile {
+    ///     name: "main.hm".to_string(),
+    ///     program: parser::parse(Scanner::new(source)).unwrap(),
+    /// };
+    /// let graph = CallGraph::new(&[file]);
+    /// assert_eq!(graph.functions[0].calls, vec!["display", "double"]);
+    /// assert_eq!(graph.unreachable(), vec!["unused"]);
+    /// ```
+    pub fn new(files: &[SourceFile]) -> CallGraph {
+        let defined = files.iter().flat_map(|file| file.program.iter()).map(|func| &func.name[..]).collect();
+        let mut functions: Vec<Caller> = vec![];
+        for file in files {
+            for func in &file.program {
+                if functions.iter().any(|caller| caller.name == func.name) {
+                    continue;
+                }
+                let mut calls = vec![];
+                if let Some(ref code) = func.code {
+                    statement_calls(code, &defined, &mut calls);
+                }
+                functions.push(Caller {
+                    name: func.name.clone(),
+                    file: file.name.clone(),
+                    span: func.span,
+                    calls,
+                    reachable: false,
+                });
+            }
+        }
+        let mut pending = match functions.iter().position(|caller| caller.name == "main") {
+            Some(main) => vec![main],
+            None => (0..functions.len()).collect(),
+        };
+        while let Some(index) = pending.pop() {
+            if functions[index].reachable {
+                continue;
+            }
+            functions[index].reachable = true;
+            for callee in &functions[index].calls {
+                if let Some(callee) = functions.iter().position(|caller| caller.name == *callee) {
+                    pending.push(callee);
+                }
+            }
+        }
+        CallGraph {
+            functions,
+        }
+    }
+
+    /// Returns the names of the functions that can't be reached from main, in the order they are defined
+    pub fn unreachable(&self) -> Vec<&str> {
+        self.functions.iter().filter(|caller| !caller.reachable).map(|caller| &caller.name[..]).collect()
+    }
+
+    /// Renders the call graph as a Graphviz digraph
+    ///
+    /// Unreachable functions are drawn dashed and grey, builtins as ellipses,
+    /// and calls to functions that aren't defined go to a red node.
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::scanner::Scanner;
+    /// # use haumea::parser::{self, SourceFile};
+    /// # use haumea::callgraph::CallGraph;
+    /// let file = SourceFile {
+    ///     name: "main.hm".to_string(),
+    ///     program: parser::parse(Scanner::new("to main do display(1) end\nto unused do missing() end")).unwrap(),
+    /// };
+    /// let dot = CallGraph::new(&[file]).to_dot();
+    /// assert!(dot.contains("\"main\" -> \"display\";"));
+    /// assert!(dot.contains("\"unused\" [label=\"unused\\n(unreachable)\", style=dashed, color=grey, fontcolor=grey];"));
+    /// assert!(dot.contains("\"missing\" [label=\"missing\\n(not defined)\", color=red, fontcolor=red];"));
+    /// ```
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        out.push_str("digraph calls {\n");
+        out.push_str("    node [fontname=\"monospace\", shape=box];\n");
+        for caller in &self.functions {
+            if caller.reachable {
+                out.push_str(&format!("    {};\n", quote(&caller.name)));
+            } else {
+                out.push_str(&format!("    {} [label={}, style=dashed, color=grey, fontcolor=grey];\n",
+                                      quote(&caller.name), quote(&format!("{}\\n(unreachable)", caller.name))));
+            }
+        }
+        let mut drawn = HashSet::new();
+        for callee in self.functions.iter().flat_map(|caller| caller.calls.iter()) {
+            if self.is_defined(callee) || !drawn.insert(callee) {
+                continue;
+            }
+            if is_builtin(callee) {
+                out.push_str(&format!("    {} [shape=ellipse];\n", quote(callee)));
+            } else {
+                out.push_str(&format!("    {} [label={}, color=red, fontcolor=red];\n",
+                                      quote(callee), quote(&format!("{}\\n(not defined)", callee))));
+            }
+        }
+        for caller in &self.functions {
+            for callee in &caller.calls {
+                out.push_str(&format!("    {} -> {};\n", quote(&caller.name), quote(callee)));
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Returns the call graph as JSON
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::scanner::Scanner;
+    /// # use haumea::parser::{self, SourceFile};
+    /// # use haumea::callgraph::CallGraph;
+    /// let file = SourceFile {
+    ///     name: "main.hm".to_string(),
+    ///     program: parser::parse(Scanner::new("to main do display(1) end")).unwrap(),
+    /// };
+    /// assert_eq!(CallGraph::new(&[file]).to_json().to_string(),
+    ///            concat!(r#"{"functions":[{"name":"main","file":"main.hm","line":1,"#,
+    ///                    r#""reachable":true,"calls":["display"]}],"unreachable":[]}"#));
+    /// ```
+    pub fn to_json(&self) -> Json {
+        let functions = self.functions.iter().map(|caller| Json::object(vec![
+            ("name", Json::from(&caller.name[..])),
+            ("file", Json::from(&caller.file[..])),
+            ("line", Json::from(caller.span.line)),
+            ("reachable", Json::from(caller.reachable)),
+            ("calls", Json::from(caller.calls.iter().map(|callee| Json::from(&callee[..])).collect::<Vec<_>>())),
+        ])).collect::<Vec<_>>();
+        let unreachable = self.unreachable().into_iter().map(Json::from).collect::<Vec<_>>();
+        Json::object(vec![
+            ("functions", Json::from(functions)),
+            ("unreachable", Json::from(unreachable)),
+        ])
+    }
+
+    /// Returns true if the program defines a function named name
+    fn is_defined(&self, name: &str) -> bool {
+        self.functions.iter().any(|caller| caller.name == name)
+    }
+}
+
+/// Returns true if name is one of the builtin functions
+fn is_builtin(name: &str) -> bool {
+    BUILTINS.iter().any(|&(builtin, _)| builtin == name)
+}
+
+/// Adds the functions called in statement to calls, if they aren't already there
+///
+/// defined is the names of the functions in the program. A function that is given to another
+/// one, for C to call it, counts as being called.
+fn statement_calls(statement: &Statement, defined: &HashSet<&str>, calls: &mut Vec<String>) {
+    match statement.kind {
+        StatementKind::Return(ref expr) |
+        StatementKind::Set(_, ref expr) |
+        StatementKind::Define(_, ref expr) |
+        StatementKind::Change(_, ref expr) => expression_calls(expr, defined, calls),
+        StatementKind::Var(_) | StatementKind::Persistent(_) | StatementKind::Swap(..) | StatementKind::InlineC(_) | StatementKind::Break(_) => (),
+        StatementKind::If { ref cond, ref if_clause, ref else_clause, .. } => {
+            expression_calls(cond, defined, calls);
+            statement_calls(if_clause, defined, calls);
+            if let Some(ref else_clause) = *else_clause {
+                statement_calls(else_clause, defined, calls);
+            }
+        },
+        StatementKind::Repeat { ref count, ref body, .. } => {
+            expression_calls(count, defined, calls);
+            statement_calls(body, defined, calls);
+        },
+        StatementKind::Do(ref block) => {
+            for statement in block {
+                statement_calls(statement, defined, calls);
+            }
+        },
+        StatementKind::Call { ref function, ref arguments } => {
+            add_call(function, calls);
+            for argument in arguments {
+                expression_calls(argument, defined, calls);
+            }
+        },
+    }
+}
+
+/// Adds the functions called in expression to calls, if they aren't already there
+///
+/// defined is as for statement_calls
+fn expression_calls(expression: &Expression, defined: &HashSet<&str>, calls: &mut Vec<String>) {
+    match *expression {
+        Expression::BinaryOp { ref left, ref right, .. } => {
+            expression_calls(left, defined, calls);
+            expression_calls(right, defined, calls);
+        },
+        Expression::UnaryOp { ref expression, .. } => expression_calls(expression, defined, calls),
+        Expression::Integer(_) | Expression::Text(_) => (),
+        // Variables can have the same names as functions, so this can count calls that won't happen
+        Expression::Ident(ref name) if defined.contains(&name[..]) => add_call(name, calls),
+        Expression::Ident(_) => (),
+        Expression::Call { ref function, ref arguments } => {
+            add_call(function, calls);
+            for argument in arguments {
+                expression_calls(argument, defined, calls);
+            }
+        },
+    }
+}
+
+fn add_call(function: &str, calls: &mut Vec<String>) {
+    if !calls.iter().any(|call| call == function) {
+        calls.push(function.to_string());
+    }
+}
+
+/// Returns s as a quoted DOT string
+///
+/// Backslashes are left alone, so that labels can use escapes like \n
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\\\""))
+}
@@ -0,0 +1,164 @@
+/// src/cfg.rs
+/// The control-flow graphs of functions lowered to the IR, which say which blocks can run after which
+use ir::{self, BlockId, Terminator};
+
+/// Which blocks of a function can go to which others
+///
+/// The nodes are the blocks of the function, numbered as they are in it, so the entry is 0.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::{cfg::Cfg, ir};
+/// let source = "to main do\n    repeat 3 times display(1)\n    return 0\nend";
+/// let file = SourceFile { name: "main.hm".to_string(), program: parser::parse(Scanner::new(source)).unwrap() };
+/// let func = &ir::lower(&[file])[0];
+/// let cfg = Cfg::new(func);
+/// assert_eq!(cfg.successors, vec![vec![1], vec![2, 3], vec![1], vec![]]);
+/// assert_eq!(cfg.predecessors, vec![vec![], vec![0, 2], vec![1], vec![1]]);
+/// assert_eq!(cfg.reverse_postorder(), vec![0, 1, 3, 2]);
+/// assert_eq!(cfg.back_edges(), vec![(2, 1)]);
+/// assert_eq!(cfg.exits(), vec![3]);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    /// The blocks that each block can go to, in the order its terminator has them
+    pub successors: Vec<Vec<BlockId>>,
+    /// The blocks that can go to each block, in order
+    pub predecessors: Vec<Vec<BlockId>>,
+}
+
+impl Cfg {
+    /// Builds the graph of the blocks of func
+    pub fn new(func: &ir::Function) -> Cfg {
+        let successors = func.blocks.iter().map(|block| block.terminator.successors()).collect::<Vec<_>>();
+        let mut predecessors = vec![vec![]; successors.len()];
+        for (block, targets) in successors.iter().enumerate() {
+            for &target in targets {
+                if !predecessors[target].contains(&block) {
+                    predecessors[target].push(block);
+                }
+            }
+        }
+        Cfg { successors, predecessors }
+    }
+
+    /// Returns the blocks in reverse postorder, where each block comes before the ones it can
+    /// go to apart from along a back edge, which is the order forward dataflow analyses go in
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.successors.len()];
+        let mut postorder = vec![];
+        // Each entry is a block with how many of its successors have been visited, so deep graphs don't recurse
+        let mut stack = vec![(0, 0)];
+        if !self.successors.is_empty() {
+            visited[0] = true;
+        }
+        while let Some(&(block, next)) = stack.last() {
+            match self.successors[block].get(next) {
+                Some(&target) => {
+                    stack.last_mut().expect("the stack isn't empty").1 += 1;
+                    if !visited[target] {
+                        visited[target] = true;
+                        stack.push((target, 0));
+                    }
+                },
+                None => {
+                    postorder.push(block);
+                    stack.pop();
+                },
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    /// Returns the edges that go back to a block that doesn't come after their start in
+    /// reverse postorder, which are the ones that loops go round by
+    pub fn back_edges(&self) -> Vec<(BlockId, BlockId)> {
+        let mut position = vec![0; self.successors.len()];
+        for (index, block) in self.reverse_postorder().into_iter().enumerate() {
+            position[block] = index;
+        }
+        let mut edges = vec![];
+        for (block, targets) in self.successors.iter().enumerate() {
+            for &target in targets {
+                if position[target] <= position[block] {
+                    edges.push((block, target));
+                }
+            }
+        }
+        edges
+    }
+
+    /// Returns the blocks that return from the function, in order
+    pub fn exits(&self) -> Vec<BlockId> {
+        (0..self.successors.len()).filter(|&block| self.successors[block].is_empty()).collect()
+    }
+}
+
+/// Renders the control-flow graph of each of functions as a Graphviz digraph
+///
+/// Each function is drawn in a box of its own, with a node for each block that has its
+/// instructions in it. The entry is bold, the edges of a branch are labelled with whether
+/// its condition was true, and back edges are dashed.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::{cfg, ir};
+/// let source = "to main do\n    if 1 = 2 then display(1)\nend";
+/// let file = SourceFile { name: "main.hm".to_string(), program: parser::parse(Scanner::new(source)).unwrap() };
+/// let dot = cfg::to_dot(&ir::lower(&[file]));
+/// assert!(dot.starts_with("digraph cfg {"));
+/// assert!(dot.contains(r#"f0_b0 [label="b0:\l    t0 = 1 = 2\l    if t0 then b1 else b2\l", style=bold];"#));
+/// assert!(dot.contains("f0_b0 -> f0_b1 [label=\"true\"];"));
+/// assert!(dot.contains("f0_b1 -> f0_b2;"));
+/// ```
+pub fn to_dot(functions: &[ir::Function]) -> String {
+    let mut out = String::new();
+    out.push_str("digraph cfg {\n");
+    out.push_str("    node [fontname=\"monospace\", shape=box];\n");
+    for (index, func) in functions.iter().enumerate() {
+        let cfg = Cfg::new(func);
+        let back_edges = cfg.back_edges();
+        out.push_str(&format!("    subgraph cluster_{} {{\n", index));
+        out.push_str(&format!("        label={};\n", quote(&format!("to {}", func.name))));
+        for (id, block) in func.blocks.iter().enumerate() {
+            let mut lines = vec![format!("b{}:", id)];
+            lines.extend(block.instructions.iter().map(|instruction| format!("    {}", func.instruction_to_string(instruction))));
+            lines.push(format!("    {}", func.terminator_to_string(&block.terminator)));
+            // \l ends a line that is lined up on the left
+            let label = lines.iter().map(|line| escape(line) + "\\l").collect::<String>();
+            let style = if id == 0 { ", style=bold" } else { "" };
+            out.push_str(&format!("        f{}_b{} [label=\"{}\"{}];\n", index, id, label, style));
+        }
+        for (id, block) in func.blocks.iter().enumerate() {
+            for (position, &target) in cfg.successors[id].iter().enumerate() {
+                let mut attributes = vec![];
+                if let Terminator::Branch { .. } = block.terminator {
+                    attributes.push(format!("label=\"{}\"", if position == 0 { "true" } else { "false" }));
+                }
+                if back_edges.contains(&(id, target)) {
+                    attributes.push("style=dashed".to_string());
+                }
+                let attributes = if attributes.is_empty() { String::new() } else { format!(" [{}]", attributes.join(", ")) };
+                out.push_str(&format!("        f{}_b{} -> f{}_b{}{};\n", index, id, index, target, attributes));
+            }
+        }
+        out.push_str("    }\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Returns s quoted as a Graphviz string
+fn quote(s: &str) -> String {
+    format!("\"{}\"", escape(s))
+}
+
+/// Returns s with the characters that can't be in a Graphviz string as they are escaped
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
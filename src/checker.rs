@@ -0,0 +1,912 @@
+/// src/checker.rs
+/// Semantic checks on parsed haumea programs
+use std::collections::{HashMap, HashSet};
+use parser::{Attribute, Expression, Function, Operator, SourceFile, Statement, StatementKind};
+use scanner::Span;
+use diagnostic::{Diagnostic, ErrorKind};
+use lints::{Level, LintLevels};
+
+/// The functions that every program can call without defining them, with how many arguments they take
+pub const BUILTINS: &[(&str, usize)] = &[
+    ("display", 1),
+    ("assert_equal", 2),
+    ("display_text", 1),
+    ("substring", 3),
+    ("index_of", 2),
+    ("same_text", 2),
+    ("to_text", 1),
+    ("to_number", 1),
+    ("uppercase", 1),
+    ("lowercase", 1),
+    ("open", 2),
+    ("read_line", 1),
+    ("write_line", 2),
+    ("close", 1),
+    ("argument_count", 0),
+    ("argument_at", 1),
+    ("current_time", 0),
+    ("current_milliseconds", 0),
+    ("elapsed_milliseconds", 1),
+    ("environment_value", 1),
+];
+
+/// The attributes that functions can have, with whether each one can be given a text
+///
+/// inline and test are for functions written in haumea, as are export, which is the same as
+/// `exported to`. deprecated can be given a text that says what to use instead.
+pub const ATTRIBUTES: &[(&str, bool)] = &[
+    ("inline", false),
+    ("test", false),
+    ("export", false),
+    ("deprecated", true),
+];
+
+/// The attributes that ifs can have, which say whether the then clause is likely to run,
+/// for the C compiler to lay the code out by
+pub const IF_ATTRIBUTES: &[&str] = &["likely", "unlikely"];
+
+/// Runs every semantic check on the program made of files, returning all of the errors found
+///
+/// As well as duplicate functions, this finds calls to functions that don't exist,
+/// calls with the wrong number of arguments, variables that are used without being declared,
+/// breaks out of loops that they aren't in,
+/// numbers given to external functions for texts or texts for numbers, functions given
+/// to external functions that don't take the right number of arguments, and attributes that
+/// don't exist or can't go on their functions
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::checker::check;
+/// let file = SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new("to main do\n set x to 1\n display(f(1), 2)\nend")).unwrap(),
+/// };
+/// let errors = check(&[file]).into_iter().map(|error| error.to_string()).collect::<Vec<_>>();
+/// assert_eq!(errors, vec!["main.hm:2:2: error[E0201]: The variable x is not declared",
+///                         "main.hm:3:2: error[E0203]: display takes 1 argument, but was given 2",
+///                         "main.hm:3:2: error[E0202]: The function f is not defined"]);
+///
+/// let source = "external function puts with (text s)\nto main do\n puts(42)\n puts(\"hi\")\nend";
+/// let file = SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// let errors = check(&[file]).into_iter().map(|error| error.to_string()).collect::<Vec<_>>();
+/// assert_eq!(errors, vec!["main.hm:3:2: error[E0209]: The argument s of puts is a text, but was given the number 42"]);
+///
+/// let source = "@tets\nto check_sum do end\n@test\nto check_with with (n) do end\nto main do end";
+/// let file = SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// let errors = check(&[file]).into_iter().map(|error| error.to_string()).collect::<Vec<_>>();
+/// assert_eq!(errors, vec!["main.hm:1:1: error[E0213]: Unknown attribute @tets",
+///                         "main.hm:3:1: error[E0214]: The test check_with can't have parameters"]);
+/// ```
+pub fn check(files: &[SourceFile]) -> Vec<Diagnostic> {
+    let mut errors = duplicate_functions(files);
+    let mut arities = BUILTINS.iter().cloned().collect::<HashMap<_, _>>();
+    let mut externals = HashMap::new();
+    for file in files {
+        for func in &file.program {
+            arities.entry(&func.name).or_insert_with(|| parameters(func).len());
+            if func.code.is_none() {
+                externals.entry(&func.name[..]).or_insert(func);
+            }
+        }
+    }
+    for file in files {
+        for func in &file.program {
+            let mut checker = FunctionChecker {
+                arities: &arities,
+                externals: &externals,
+                scopes: vec![parameters(func).iter().map(|name| &name[..]).collect()],
+                labels: vec![],
+                errors: vec![],
+            };
+            match func.code {
+                Some(ref code) => checker.check_statement(code),
+                // main is what runs the program, so it has to be written in haumea
+                None if func.name == "main" => {
+                    checker.errors.push(Diagnostic::new(ErrorKind::Semantic,
+                                                        "main can't be an external function", func.span)
+                                        .with_code("E0205"));
+                },
+                None => (),
+            }
+            checker.errors.extend(attribute_errors(func));
+            errors.extend(checker.errors.into_iter().map(|error| error.in_file(&file.name)));
+        }
+    }
+    errors
+}
+
+/// Returns an error for each of the attributes of an if that doesn't exist, is given a text,
+/// or comes after another one, as the then clause is either likely to run or it isn't
+fn if_attribute_errors(attributes: &[Attribute]) -> Vec<Diagnostic> {
+    let mut errors = vec![];
+    for (index, attribute) in attributes.iter().enumerate() {
+        let name = &attribute.name[..];
+        if !IF_ATTRIBUTES.contains(&name) {
+            let error = Diagnostic::new(ErrorKind::Semantic, format!("Unknown attribute @{}", name), attribute.span)
+                .with_code("E0213");
+            errors.push(did_you_mean(error, name, IF_ATTRIBUTES.iter().cloned()));
+            continue;
+        }
+        let earlier = attributes[..index].iter().find(|earlier| IF_ATTRIBUTES.contains(&&earlier.name[..]));
+        let message = match earlier {
+            Some(earlier) if earlier.name == name => format!("The if is marked @{} more than once", name),
+            Some(earlier) => format!("An if can't be marked both @{} and @{}", earlier.name, name),
+            None if attribute.argument.is_some() => format!("@{} can't be given a text", name),
+            None => continue,
+        };
+        errors.push(Diagnostic::new(ErrorKind::Semantic, message, attribute.span).with_code("E0214"));
+    }
+    errors
+}
+
+/// Returns an error for each attribute of func that doesn't exist, is given more than once,
+/// is given a text that it doesn't take, or can't go on func
+fn attribute_errors(func: &Function) -> Vec<Diagnostic> {
+    let mut errors = vec![];
+    for (index, attribute) in func.attributes.iter().enumerate() {
+        let name = &attribute.name[..];
+        let takes_text = match ATTRIBUTES.iter().find(|&&(known, _)| known == name) {
+            Some(&(_, takes_text)) => takes_text,
+            None => {
+                let error = Diagnostic::new(ErrorKind::Semantic, format!("Unknown attribute @{}", name), attribute.span)
+                    .with_code("E0213");
+                errors.push(did_you_mean(error, name, ATTRIBUTES.iter().map(|&(known, _)| known)));
+                continue;
+            },
+        };
+        let message = if func.attributes[..index].iter().any(|earlier| earlier.name == name) {
+            format!("The function {} is marked @{} more than once", func.name, name)
+        } else if attribute.argument.is_some() && !takes_text {
+            format!("@{} can't be given a text", name)
+        } else if func.code.is_none() && name != "deprecated" {
+            format!("The external function {} can't be marked @{}", func.name, name)
+        } else if name == "test" && !parameters(func).is_empty() {
+            format!("The test {} can't have parameters", func.name)
+        } else {
+            continue;
+        };
+        errors.push(Diagnostic::new(ErrorKind::Semantic, message, attribute.span).with_code("E0214"));
+    }
+    errors
+}
+
+/// Checks that no function is defined more than once in files
+///
+/// Returns an error at the second definition of the first duplicate found,
+/// which says where the first definition is
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::checker::check_duplicate_functions;
+/// let a = SourceFile {
+///     name: "a.hm".to_string(),
+///     program: parser::parse(Scanner::new("to helper do return 1 end")).unwrap(),
+/// };
+/// let b = SourceFile {
+///     name: "b.hm".to_string(),
+///     program: parser::parse(Scanner::new("to main do\n helper()\nend\nto helper do return 2 end")).unwrap(),
+/// };
+/// assert_eq!(check_duplicate_functions(&[a, b]).unwrap_err().to_string(),
+///            "b.hm:4:1: error[E0204]: The function helper is already defined at a.hm:1:1");
+/// ```
+pub fn check_duplicate_functions(files: &[SourceFile]) -> Result<(), Diagnostic> {
+    match duplicate_functions(files).into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// Returns an error for every block of inline C in files
+///
+/// Inline C can do anything that C can, so programs can only use it when it is allowed
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::checker::inline_c_errors;
+/// let file = SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new("to main do\n c { putchar('x'); }\nend")).unwrap(),
+/// };
+/// let errors = inline_c_errors(&[file]).into_iter().map(|error| error.to_string()).collect::<Vec<_>>();
+/// assert_eq!(errors, vec!["main.hm:2:2: error[E0206]: Inline C is only allowed with --allow-inline-c"]);
+/// ```
+pub fn inline_c_errors(files: &[SourceFile]) -> Vec<Diagnostic> {
+    let mut errors = vec![];
+    for file in files {
+        for func in &file.program {
+            if let Some(ref code) = func.code {
+                add_inline_c_errors(code, &file.name, &mut errors);
+            }
+        }
+    }
+    errors
+}
+
+/// Adds an error to errors for every block of inline C in statement, which is in the file named file_name
+fn add_inline_c_errors(statement: &Statement, file_name: &str, errors: &mut Vec<Diagnostic>) {
+    match statement.kind {
+        StatementKind::InlineC(_) => {
+            errors.push(Diagnostic::new(ErrorKind::Semantic, "Inline C is only allowed with --allow-inline-c",
+                                        statement.span).with_code("E0206").in_file(file_name));
+        },
+        StatementKind::If { ref if_clause, ref else_clause, .. } => {
+            add_inline_c_errors(if_clause, file_name, errors);
+            if let Some(ref else_clause) = *else_clause {
+                add_inline_c_errors(else_clause, file_name, errors);
+            }
+        },
+        StatementKind::Repeat { ref body, .. } => add_inline_c_errors(body, file_name, errors),
+        StatementKind::Do(ref block) => {
+            for statement in block {
+                add_inline_c_errors(statement, file_name, errors);
+            }
+        },
+        _ => (),
+    }
+}
+
+/// Returns the diagnostics of the lints in files that levels doesn't allow
+///
+/// These are about code that is allowed but easy to get wrong: calls on the right side of an
+/// and or an or, which only happen when the left side doesn't already decide what it is,
+/// variables that are never used, variables that hide others with the same name, and calls
+/// of functions marked @deprecated, apart from the ones in functions that are deprecated too.
+/// A lint that is denied gives errors rather than warnings.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::checker::lints;
+/// # use haumea::lints::{Level, LintLevels};
+/// let source = "to f is 1\nto main do\n variable x\n set x to x > 0 and f() = 1\n display(f() or x)\nend";
+/// let file = SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// let mut levels = LintLevels::default();
+/// assert!(lints(&[file.clone()], &levels).is_empty());
+/// levels.set("short-circuit-call", Level::Warn).unwrap();
+/// let warnings = lints(&[file], &levels).into_iter().map(|warning| warning.to_string()).collect::<Vec<_>>();
+/// assert_eq!(warnings, vec!["main.hm:4:2: warning[W0001]: and only evaluates its right side if the left side \
+///                            is true, so the call to f in it might not happen"]);
+///
+/// let source = "to main with (n) do\n variable x\n do\n  variable n is 2\n  display(n)\n end\nend";
+/// let file = SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// levels.set("all", Level::Deny).unwrap();
+/// let errors = lints(&[file], &levels).into_iter().map(|error| error.to_string()).collect::<Vec<_>>();
+/// assert_eq!(errors, vec!["main.hm:2:2: error[W0002]: The variable x is never used",
+///                         "main.hm:4:3: error[W0003]: The variable n hides the parameter n"]);
+///
+/// let source = "@deprecated(\"use add\")\nto plus with (a, b) is a + b\nto main do\n display(plus(1, 2))\nend";
+/// let file = SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// let warnings = lints(&[file], &LintLevels::default()).into_iter().map(|warning| warning.to_string()).collect::<Vec<_>>();
+/// assert_eq!(warnings, vec!["main.hm:4:2: warning[W0004]: The function plus is deprecated: use add"]);
+/// ```
+pub fn lints(files: &[SourceFile], levels: &LintLevels) -> Vec<Diagnostic> {
+    let mut deprecated = HashMap::new();
+    for func in files.iter().flat_map(|file| file.program.iter()) {
+        if let Some(attribute) = func.attribute("deprecated") {
+            deprecated.entry(&func.name[..]).or_insert(attribute.argument.as_ref().map(|message| &message[..]));
+        }
+    }
+    let mut diagnostics = vec![];
+    for file in files {
+        let mut warnings = vec![];
+        for func in &file.program {
+            let code = match func.code {
+                Some(ref code) => code,
+                None => continue,
+            };
+            for_each_expression(code, &mut |expression, span| add_short_circuit_warnings(expression, span, &mut warnings));
+            if func.attribute("deprecated").is_none() {
+                for_each_call(code, &mut |function, span| {
+                    if let Some(&message) = deprecated.get(function) {
+                        warnings.push(deprecated_warning(function, message, span));
+                    }
+                });
+            }
+            let mut variables = VariableLints {
+                scopes: vec![parameters(func).iter().map(|name| Declaration {
+                    name,
+                    span: func.span,
+                    parameter: true,
+                    used: true,
+                }).collect()],
+                inline_c: vec![],
+                warnings: vec![],
+            };
+            add_inline_c(code, &mut variables.inline_c);
+            variables.check_statement(code);
+            warnings.extend(variables.warnings);
+        }
+        warnings.sort_by_key(|warning| warning.span);
+        for warning in warnings {
+            let level = warning.lint().map_or(Level::Warn, |lint| levels.level(lint));
+            match level {
+                Level::Allow => (),
+                Level::Warn => diagnostics.push(warning.in_file(&file.name)),
+                Level::Deny => diagnostics.push(warning.denied().in_file(&file.name)),
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Calls f with each expression in statement and the statements in it, along with the span of its statement
+///
+/// Only the outermost expressions are given, not the ones in them
+fn for_each_expression<'a, F: FnMut(&'a Expression, Span)>(statement: &'a Statement, f: &mut F) {
+    let span = statement.span;
+    match statement.kind {
+        StatementKind::Return(ref expr) |
+        StatementKind::Set(_, ref expr) |
+        StatementKind::Define(_, ref expr) |
+        StatementKind::Change(_, ref expr) => f(expr, span),
+        StatementKind::If { ref cond, ref if_clause, ref else_clause, .. } => {
+            f(cond, span);
+            for_each_expression(if_clause, f);
+            if let Some(ref else_clause) = *else_clause {
+                for_each_expression(else_clause, f);
+            }
+        },
+        StatementKind::Repeat { ref count, ref body, .. } => {
+            f(count, span);
+            for_each_expression(body, f);
+        },
+        StatementKind::Do(ref block) => {
+            for statement in block {
+                for_each_expression(statement, f);
+            }
+        },
+        StatementKind::Call { ref arguments, .. } => {
+            for argument in arguments {
+                f(argument, span);
+            }
+        },
+        StatementKind::Var(_) | StatementKind::Persistent(_) | StatementKind::Swap(..) | StatementKind::InlineC(_) | StatementKind::Break(_) => (),
+    }
+}
+
+/// Calls f with the name of each function called in statement and the statements in it, along
+/// with the span of the statement that the call is in
+fn for_each_call<'a, F: FnMut(&'a str, Span)>(statement: &'a Statement, f: &mut F) {
+    for_each_expression(statement, &mut |expression, span| for_each_call_in(expression, span, f));
+    for_each_statement(statement, &mut |statement| {
+        if let StatementKind::Call { ref function, .. } = statement.kind {
+            f(function, statement.span);
+        }
+    });
+}
+
+/// Calls f with the name of each function called in expression, in the statement at span
+fn for_each_call_in<'a, F: FnMut(&'a str, Span)>(expression: &'a Expression, span: Span, f: &mut F) {
+    match *expression {
+        Expression::BinaryOp { ref left, ref right, .. } => {
+            for_each_call_in(left, span, f);
+            for_each_call_in(right, span, f);
+        },
+        Expression::UnaryOp { ref expression, .. } => for_each_call_in(expression, span, f),
+        Expression::Call { ref function, ref arguments } => {
+            f(function, span);
+            for argument in arguments {
+                for_each_call_in(argument, span, f);
+            }
+        },
+        Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) => (),
+    }
+}
+
+/// Calls f with statement and each of the statements in it
+fn for_each_statement<'a, F: FnMut(&'a Statement)>(statement: &'a Statement, f: &mut F) {
+    f(statement);
+    match statement.kind {
+        StatementKind::If { ref if_clause, ref else_clause, .. } => {
+            for_each_statement(if_clause, f);
+            if let Some(ref else_clause) = *else_clause {
+                for_each_statement(else_clause, f);
+            }
+        },
+        StatementKind::Repeat { ref body, .. } => for_each_statement(body, f),
+        StatementKind::Do(ref block) => {
+            for statement in block {
+                for_each_statement(statement, f);
+            }
+        },
+        _ => (),
+    }
+}
+
+/// Returns the warning for a call of function, which is deprecated with message, in the statement at span
+fn deprecated_warning(function: &str, message: Option<&str>, span: Span) -> Diagnostic {
+    let text = match message {
+        Some(message) => format!("The function {} is deprecated: {}", function, message),
+        None => format!("The function {} is deprecated", function),
+    };
+    Diagnostic::warning(ErrorKind::Semantic, text, span).with_code("W0004")
+}
+
+/// Adds a warning to warnings for every and or or in expression whose right side calls a function
+fn add_short_circuit_warnings(expression: &Expression, span: Span, warnings: &mut Vec<Diagnostic>) {
+    match *expression {
+        Expression::BinaryOp { ref operator, ref left, ref right } => {
+            let short_circuit = match *operator {
+                Operator::LogicalAnd => Some(("and", "true")),
+                Operator::LogicalOr => Some(("or", "false")),
+                _ => None,
+            };
+            add_short_circuit_warnings(left, span, warnings);
+            match (short_circuit, first_call(right)) {
+                // The warning covers the calls in the right side, so they don't get warnings of their own
+                (Some((name, when)), Some(function)) => {
+                    let message = format!("{} only evaluates its right side if the left side is {}, \
+                                           so the call to {} in it might not happen", name, when, function);
+                    warnings.push(Diagnostic::warning(ErrorKind::Semantic, message, span).with_code("W0001"));
+                },
+                _ => add_short_circuit_warnings(right, span, warnings),
+            }
+        },
+        Expression::UnaryOp { ref expression, .. } => add_short_circuit_warnings(expression, span, warnings),
+        Expression::Call { ref arguments, .. } => {
+            for argument in arguments {
+                add_short_circuit_warnings(argument, span, warnings);
+            }
+        },
+        Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) => (),
+    }
+}
+
+/// Returns the name of the first function that expression calls, if it calls any
+fn first_call(expression: &Expression) -> Option<&str> {
+    match *expression {
+        Expression::BinaryOp { ref left, ref right, .. } => first_call(left).or_else(|| first_call(right)),
+        Expression::UnaryOp { ref expression, .. } => first_call(expression),
+        Expression::Call { ref function, .. } => Some(function),
+        Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) => None,
+    }
+}
+
+/// Returns error with a suggestion of the name in names that is closest to name, if any of
+/// them is close enough to be what was meant
+///
+/// A name is close enough if at most a third of its letters, or one letter for a short name,
+/// have to be added, removed or replaced to make name. The closest name comes first, and
+/// then the first in alphabetical order, so the suggestion is the same every time.
+fn did_you_mean<'b, I: Iterator<Item = &'b str>>(error: Diagnostic, name: &str, names: I) -> Diagnostic {
+    let most = (name.chars().count() / 3).max(1);
+    let closest = names.filter(|&candidate| candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= most)
+        .min();
+    match closest {
+        Some((_, candidate)) => error.with_help(format!("did you mean '{}'?", candidate)),
+        None => error,
+    }
+}
+
+/// Returns how many chars have to be added, removed or replaced to turn a into b
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &b_char) in b.iter().enumerate() {
+            let replaced = previous[j] + if a_char == b_char { 0 } else { 1 };
+            current.push(replaced.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Returns true if expression uses the variable named name
+fn mentions(expression: &Expression, name: &str) -> bool {
+    match *expression {
+        Expression::BinaryOp { ref left, ref right, .. } => mentions(left, name) || mentions(right, name),
+        Expression::UnaryOp { ref expression, .. } => mentions(expression, name),
+        Expression::Call { ref arguments, .. } => arguments.iter().any(|argument| mentions(argument, name)),
+        Expression::Ident(ref ident) => ident == name,
+        Expression::Integer(_) | Expression::Text(_) => false,
+    }
+}
+
+/// Returns an error for every definition of a function after the first
+fn duplicate_functions(files: &[SourceFile]) -> Vec<Diagnostic> {
+    let mut defined: HashMap<&str, (&str, Span)> = HashMap::new();
+    let mut errors = vec![];
+    for file in files {
+        for func in &file.program {
+            if let Some(&(first_file, first_span)) = defined.get(&func.name[..]) {
+                let message = format!("The function {} is already defined at {}:{}:{}",
+                                      func.name, first_file, first_span.line, first_span.column);
+                errors.push(Diagnostic::new(ErrorKind::Semantic, message, func.span).with_code("E0204").in_file(&file.name));
+                continue;
+            }
+            defined.insert(&func.name, (&file.name, func.span));
+        }
+    }
+    errors
+}
+
+/// Returns true if name is one of the builtin functions
+fn is_builtin(name: &str) -> bool {
+    BUILTINS.iter().any(|&(builtin, _)| builtin == name)
+}
+
+/// Returns the names of the parameters of func
+fn parameters(func: &Function) -> &[String] {
+    match func.signature {
+        Some(ref signature) => signature,
+        None => &[],
+    }
+}
+
+/// Checks the calls and variables in the body of a function
+struct FunctionChecker<'a> {
+    /// How many arguments each function takes
+    arities: &'a HashMap<&'a str, usize>,
+    /// The external functions, by name
+    externals: &'a HashMap<&'a str, &'a Function>,
+    /// The variables declared in each enclosing block, starting with the parameters
+    scopes: Vec<HashSet<&'a str>>,
+    /// The labels of the loops that the statement being checked is in, innermost last
+    labels: Vec<&'a str>,
+    /// The errors found so far
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a> FunctionChecker<'a> {
+    fn check_statement(&mut self, statement: &'a Statement) {
+        let span = statement.span;
+        match statement.kind {
+            StatementKind::Return(ref expr) => self.check_expression(expr, span),
+            StatementKind::Var(ref names) => {
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.extend(names.iter().map(|name| &name[..]));
+                }
+            },
+            // The variable is declared after its starting value is checked, and C would work
+            // the value out with the new variable, so one declared outside can't be used in it
+            StatementKind::Persistent(ref name) => {
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name);
+                }
+            },
+            StatementKind::Define(ref name, ref expr) => {
+                self.check_expression(expr, span);
+                if mentions(expr, name) && self.scopes.iter().any(|scope| scope.contains(&name[..])) {
+                    self.errors.push(Diagnostic::new(ErrorKind::Semantic,
+                                                     format!("The starting value of {} can't use {}", name, name),
+                                                     span).with_code("E0208"));
+                }
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name);
+                }
+            },
+            StatementKind::Swap(ref first, ref second) => {
+                self.check_variable(first, span);
+                // Swapping a variable with itself is one mistake if it isn't declared, not two
+                if second != first {
+                    self.check_variable(second, span);
+                }
+            },
+            StatementKind::Set(ref name, ref expr) |
+            StatementKind::Change(ref name, ref expr) => {
+                self.check_variable(name, span);
+                self.check_expression(expr, span);
+            },
+            StatementKind::If { ref cond, ref if_clause, ref else_clause, ref attributes } => {
+                self.errors.extend(if_attribute_errors(attributes));
+                self.check_expression(cond, span);
+                self.check_statement(if_clause);
+                if let Some(ref else_clause) = *else_clause {
+                    self.check_statement(else_clause);
+                }
+            },
+            StatementKind::Repeat { ref count, ref label, ref body } => {
+                self.check_expression(count, span);
+                if let Some(ref label) = *label {
+                    self.labels.push(label);
+                }
+                self.check_statement(body);
+                if label.is_some() {
+                    self.labels.pop();
+                }
+            },
+            StatementKind::Break(ref label) => {
+                if !self.labels.contains(&&label[..]) {
+                    self.errors.push(Diagnostic::new(ErrorKind::Semantic,
+                                                     format!("There is no loop called {} around this break", label),
+                                                     span).with_code("E0207"));
+                }
+            },
+            StatementKind::Do(ref block) => {
+                self.scopes.push(HashSet::new());
+                for statement in block {
+                    self.check_statement(statement);
+                }
+                self.scopes.pop();
+            },
+            StatementKind::Call { ref function, ref arguments } => {
+                self.check_call(function, arguments.len(), span);
+                self.check_external_arguments(function, arguments, span);
+                for (index, argument) in arguments.iter().enumerate() {
+                    if !self.is_callback(function, index) {
+                        self.check_expression(argument, span);
+                    }
+                }
+            },
+            // haumea can't see into the C, so it is left to the C compiler to check
+            StatementKind::InlineC(_) => (),
+        }
+    }
+
+    /// Checks expression, which is in the statement at span
+    fn check_expression(&mut self, expression: &Expression, span: Span) {
+        match *expression {
+            Expression::BinaryOp { ref left, ref right, .. } => {
+                self.check_expression(left, span);
+                self.check_expression(right, span);
+            },
+            Expression::UnaryOp { ref expression, .. } => self.check_expression(expression, span),
+            Expression::Integer(_) | Expression::Text(_) => (),
+            Expression::Ident(ref name) => self.check_variable(name, span),
+            Expression::Call { ref function, ref arguments } => {
+                self.check_call(function, arguments.len(), span);
+                self.check_external_arguments(function, arguments, span);
+                for (index, argument) in arguments.iter().enumerate() {
+                    if !self.is_callback(function, index) {
+                        self.check_expression(argument, span);
+                    }
+                }
+            },
+        }
+    }
+
+    /// Returns true if the argument at index of a call to the function named name is a function for C to call
+    fn is_callback(&self, name: &str, index: usize) -> bool {
+        match self.externals.get(name) {
+            Some(func) => match parameters(func).get(index) {
+                Some(parameter) => func.callback_parameters.iter().any(|(callback, _)| callback == parameter),
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Checks that a call to the function named name, if it is external, isn't given a number
+    /// literal for a text or a text literal for a number, and that it is given haumea functions
+    /// that take the right number of arguments for its function parameters
+    ///
+    /// 0 can be given for a text, as it is how C says that there is no text
+    fn check_external_arguments(&mut self, name: &str, arguments: &[Expression], span: Span) {
+        let func = match self.externals.get(name) {
+            Some(func) => func,
+            None => return,
+        };
+        for (parameter, argument) in parameters(func).iter().zip(arguments) {
+            let is_text = func.text_parameters.contains(parameter);
+            let callback = func.callback_parameters.iter().find(|(callback, _)| callback == parameter);
+            let (code, message) = match (argument, callback) {
+                (Expression::Ident(given), Some((_, callback_parameters))) => {
+                    let arity = callback_parameters.len();
+                    match self.arities.get(&given[..]) {
+                        Some(_) if self.externals.contains_key(&given[..]) || is_builtin(given) => {
+                            ("E0210", format!("The argument {} of {} has to be a function written in haumea, but {} isn't",
+                                              parameter, name, given))
+                        },
+                        Some(&given_arity) if given_arity != arity => {
+                            ("E0210", format!("The argument {} of {} is a function that takes {} argument{}, but {} takes {}",
+                                              parameter, name, arity, if arity == 1 { "" } else { "s" }, given, given_arity))
+                        },
+                        Some(_) => continue,
+                        None => ("E0202", format!("The function {} is not defined", given)),
+                    }
+                },
+                (_, Some(_)) => {
+                    ("E0210", format!("The argument {} of {} is a function, so it has to be given the name of one",
+                                      parameter, name))
+                },
+                (&Expression::Integer(n), None) if is_text && n != 0 => {
+                    ("E0209", format!("The argument {} of {} is a text, but was given the number {}", parameter, name, n))
+                },
+                (&Expression::Text(_), None) if !is_text => {
+                    ("E0209", format!("The argument {} of {} is a number, but was given a text", parameter, name))
+                },
+                _ => continue,
+            };
+            self.errors.push(Diagnostic::new(ErrorKind::Semantic, message, span).with_code(code));
+        }
+    }
+
+    /// Checks that the variable named name has been declared
+    fn check_variable(&mut self, name: &str, span: Span) {
+        if !self.scopes.iter().any(|scope| scope.contains(name)) {
+            let error = Diagnostic::new(ErrorKind::Semantic, format!("The variable {} is not declared", name), span)
+                .with_code("E0201");
+            let declared = self.scopes.iter().flat_map(|scope| scope.iter().cloned());
+            self.errors.push(did_you_mean(error, name, declared));
+        }
+    }
+
+    /// Checks that the function named name exists and takes argument_count arguments
+    fn check_call(&mut self, name: &str, argument_count: usize, span: Span) {
+        let message = match self.arities.get(name) {
+            None => {
+                let error = Diagnostic::new(ErrorKind::Semantic, format!("The function {} is not defined", name), span)
+                    .with_code("E0202");
+                self.errors.push(did_you_mean(error, name, self.arities.keys().cloned()));
+                return;
+            },
+            Some(&arity) if arity != argument_count => {
+                format!("{} takes {} argument{}, but was given {}",
+                        name, arity, if arity == 1 { "" } else { "s" }, argument_count)
+            },
+            Some(_) => return,
+        };
+        self.errors.push(Diagnostic::new(ErrorKind::Semantic, message, span).with_code("E0203"));
+    }
+}
+
+/// Adds the C of every block of inline C in statement to inline_c
+fn add_inline_c<'a>(statement: &'a Statement, inline_c: &mut Vec<&'a str>) {
+    match statement.kind {
+        StatementKind::InlineC(ref c) => inline_c.push(c),
+        StatementKind::If { ref if_clause, ref else_clause, .. } => {
+            add_inline_c(if_clause, inline_c);
+            if let Some(ref else_clause) = *else_clause {
+                add_inline_c(else_clause, inline_c);
+            }
+        },
+        StatementKind::Repeat { ref body, .. } => add_inline_c(body, inline_c),
+        StatementKind::Do(ref block) => {
+            for statement in block {
+                add_inline_c(statement, inline_c);
+            }
+        },
+        _ => (),
+    }
+}
+
+/// A variable or parameter, as far as the lints about variables are concerned
+struct Declaration<'a> {
+    name: &'a str,
+    /// Where it was declared, which for a parameter is its function
+    span: Span,
+    parameter: bool,
+    /// Whether it has been used, which parameters count as, as functions given to C might have to take them
+    used: bool,
+}
+
+/// Finds the variables that are never used and the ones that hide others, in the body of a function
+struct VariableLints<'a> {
+    /// The variables declared in each enclosing block, starting with the parameters
+    scopes: Vec<Vec<Declaration<'a>>>,
+    /// The inline C in the function, which might use any of its variables
+    inline_c: Vec<&'a str>,
+    /// The warnings found so far
+    warnings: Vec<Diagnostic>,
+}
+
+impl<'a> VariableLints<'a> {
+    fn check_statement(&mut self, statement: &'a Statement) {
+        let span = statement.span;
+        match statement.kind {
+            StatementKind::Var(ref names) => {
+                for name in names {
+                    self.declare(name, span);
+                }
+            },
+            StatementKind::Persistent(ref name) => self.declare(name, span),
+            StatementKind::Define(ref name, ref expr) => {
+                self.use_expression(expr);
+                self.declare(name, span);
+            },
+            // Setting a variable doesn't use it, but changing it works out its value
+            StatementKind::Set(_, ref expr) | StatementKind::Return(ref expr) => self.use_expression(expr),
+            StatementKind::Change(ref name, ref expr) => {
+                self.use_variable(name);
+                self.use_expression(expr);
+            },
+            StatementKind::Swap(ref first, ref second) => {
+                self.use_variable(first);
+                self.use_variable(second);
+            },
+            StatementKind::If { ref cond, ref if_clause, ref else_clause, .. } => {
+                self.use_expression(cond);
+                self.check_statement(if_clause);
+                if let Some(ref else_clause) = *else_clause {
+                    self.check_statement(else_clause);
+                }
+            },
+            StatementKind::Repeat { ref count, ref body, .. } => {
+                self.use_expression(count);
+                self.check_statement(body);
+            },
+            StatementKind::Do(ref block) => {
+                self.scopes.push(vec![]);
+                for statement in block {
+                    self.check_statement(statement);
+                }
+                if let Some(scope) = self.scopes.pop() {
+                    self.add_unused_warnings(scope);
+                }
+            },
+            StatementKind::Call { ref arguments, .. } => {
+                for argument in arguments {
+                    self.use_expression(argument);
+                }
+            },
+            StatementKind::InlineC(_) | StatementKind::Break(_) => (),
+        }
+    }
+
+    /// Declares the variable named name at span, warning if it hides another
+    fn declare(&mut self, name: &'a str, span: Span) {
+        let hidden = self.scopes.iter().rev().flat_map(|scope| scope.iter()).find(|declared| declared.name == name);
+        if let Some(hidden) = hidden {
+            let message = if hidden.parameter {
+                format!("The variable {} hides the parameter {}", name, name)
+            } else {
+                format!("The variable {} hides the one declared at {}:{}", name, hidden.span.line, hidden.span.column)
+            };
+            self.warnings.push(Diagnostic::warning(ErrorKind::Semantic, message, span).with_code("W0003"));
+        }
+        let used = self.inline_c.iter().any(|c| c.contains(name));
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(Declaration { name, span, parameter: false, used });
+        }
+    }
+
+    /// Adds a warning for every variable in scope that wasn't used
+    fn add_unused_warnings(&mut self, scope: Vec<Declaration<'a>>) {
+        for declared in scope.into_iter().filter(|declared| !declared.used) {
+            let message = format!("The variable {} is never used", declared.name);
+            self.warnings.push(Diagnostic::warning(ErrorKind::Semantic, message, declared.span).with_code("W0002"));
+        }
+    }
+
+    /// Marks the variables in expression as used
+    fn use_expression(&mut self, expression: &Expression) {
+        match *expression {
+            Expression::BinaryOp { ref left, ref right, .. } => {
+                self.use_expression(left);
+                self.use_expression(right);
+            },
+            Expression::UnaryOp { ref expression, .. } => self.use_expression(expression),
+            Expression::Call { ref arguments, .. } => {
+                for argument in arguments {
+                    self.use_expression(argument);
+                }
+            },
+            Expression::Ident(ref name) => self.use_variable(name),
+            Expression::Integer(_) | Expression::Text(_) => (),
+        }
+    }
+
+    /// Marks the innermost variable named name as used
+    fn use_variable(&mut self, name: &str) {
+        let declared = self.scopes.iter_mut().rev().flat_map(|scope| scope.iter_mut().rev()).find(|declared| declared.name == name);
+        if let Some(declared) = declared {
+            declared.used = true;
+        }
+    }
+}
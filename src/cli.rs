@@ -0,0 +1,545 @@
+/// src/cli.rs
+/// The command line interface of the haumea binary
+use std::fs;
+use std::path::PathBuf;
+use haumea::lints::{Level, LintLevels};
+use haumea::grammar::GrammarFormat;
+use haumea::parser;
+use haumea::options::{CompilerOptions, Emit, Target, Runtime, OutputKind, MessageFormat, OptLevel, STDIO_PATH};
+
+/// The most that --max-nesting can be, as the compiler needs more stack the deeper programs can nest
+pub const MAX_NESTING_LIMIT: usize = 10_000;
+
+/// The usage message printed by `haumea --help`
+pub const USAGE: &str = "\
+Usage: haumea <command> [options]
+
+Commands:
+    build <inputs>...      Compile a haumea program made of the files <inputs>
+                           (use - to read a file from stdin)
+    run <inputs>... [-- <arguments>...]
+                           Compile a haumea program and run it with <arguments>
+    test [<inputs>...]     Compile the test_ functions in <inputs> into a harness and run them
+                           (a directory is a project, whose src and tests are used;
+                           the default is the current directory)
+    bench [<inputs>...]    Compile the bench_ functions in <inputs> into a harness that times them
+                           (a directory is a project, whose src and benches are used)
+    watch <path>           Recompile the programs in <path> whenever they change
+                           (with --run, run them after they are compiled)
+    fmt <inputs>...        Rewrite the files <inputs> in the canonical format
+                           (with --check, only list the files that aren't formatted;
+                           - formats stdin to stdout)
+    lsp                    Run a language server for editors on stdin and stdout
+    debug                  Run a debug adapter for editors on stdin and stdout, which
+                           debugs programs with gdb
+    repl [<inputs>...]     Run code interactively, after loading the functions in <inputs>
+    new <path>             Create a new project in the directory <path>
+    doc <inputs>...        Generate a Markdown reference of the functions in <inputs>
+                           from their ## documentation comments (with --html, an HTML page)
+    explain <code>         Explain the error with <code>, like E0201, with an example
+    grammar [--format <format>]
+                           Print a grammar of haumea's tokens for editors to highlight it
+                           with: textmate (the default, as JSON) or tree-sitter (a grammar.js)
+
+Options:
+    -o, --output <file>    Write the output to <file> (use - for stdout)
+        --bin              Compile the program into an executable using $CC (or cc)
+        --lib              Compile the functions other than main into a static library
+                           using $CC and $AR (or ar), with a header that declares them
+        --shared           Compile the functions other than main into a shared library
+                           (a .so, or on macOS a .dylib), with a header that declares them
+        --cc <compiler>    Use <compiler> as the C compiler instead of $CC (or cc)
+    -O0, -O1, -O2          Optimize the program not at all, a little (the default: inline the
+                           functions marked @inline, fold and propagate constants and
+                           remove dead code), or also by eliminating common subexpressions,
+                           and pass the same level to the C compiler
+        --cflags <flags>   Pass the space separated <flags> to the C compiler (after -O)
+        --link <library>   Link executables with <library>, like m for the maths library
+    -L, --library-path <dir>
+                           Look for the libraries to link with in <dir>
+        --split            Generate a C file for each input, plus a shared header
+                           (the header is written to the output)
+        --emit <kind>      The kind of output to produce: tokens, ast, ir (the basic blocks
+                           that the functions are lowered to), c (default), asm, dot (a
+                           Graphviz graph of the AST), callgraph (a Graphviz graph of the
+                           calls between functions), callgraph-json, cfg (a Graphviz graph
+                           of the basic blocks of each function), or header (the
+                           prototypes of the functions, for calling them from C)
+        --target <target>  The backend to generate code for: c (default)
+        --runtime <kind>   The runtime library to use: minimal (in the C itself, with only
+                           display), full (compiled separately), or auto (the default,
+                           which is minimal if the program doesn't need full)
+        --safe             Make the program stop with an error and a backtrace on division
+                           by zero, instead of doing whatever the C compiler does
+        --temporaries      Break each expression up into assignments to temporaries with one
+                           operation each, in the order they happen, for C that is like the
+                           IR and easier to step through in a debugger
+        --allow-inline-c   Allow blocks of C in the program, like c { putchar('x'); }
+    -W, --warn <lint>      Warn about code that is allowed but easy to get wrong, where <lint> is
+                           short-circuit-call (a call on the right of and or or, which doesn't
+                           always happen), unused-variable, shadowing (a variable with the
+                           same name as one around it), deprecated (a call of a function
+                           marked @deprecated), or all
+    -A, --allow <lint>     Don't report <lint>, which is what every lint but deprecated starts as
+    -D, --deny <lint>      Report <lint> as an error (later flags win over earlier ones, so
+                           -D all -A shadowing denies every lint but shadowing)
+        --pedantic         The same as -W all
+        --deny-warnings    Make every warning an error, so that a program with warnings fails
+                           to compile (for CI and marking scripts)
+        --instrument-coverage
+                           Make the program count how many times each statement runs,
+                           and write the counts to $HAUMEA_COVERAGE (or haumea-coverage.txt)
+                           when it exits (with test, report which lines the tests ran)
+        --cache-dir <dir>  Keep the C of each function in <dir>, and only compile the functions
+                           that changed since the last build (not with --split, --lib, --shared,
+                           or --instrument-coverage)
+        --prolog-file <file>
+                           Put the C in <file> at the start of the generated C,
+                           after the runtime (for helper functions)
+        --max-nesting <n>  Reject programs that nest statements and expressions more than
+                           <n> deep (200 by default, and at most 10000)
+        --check            Only check the program for errors, without generating anything
+        --source-map       Also write <output>.map, a JSON map of which lines of the C
+                           each line of the program was compiled to (for debuggers)
+        --minify           Generate the C without comments, #line directives, or any
+                           whitespace that it doesn't need, all on one line apart from
+                           the preprocessor directives (for embedding it)
+    -v, --timings          Report how long each phase of the compilation takes
+        --message-format <format>
+                           Write errors and warnings as human (the default), json, which
+                           is a JSON object on a line of its own for each one, or sarif, which
+                           is a SARIF log of all of them for code scanning, written at the end
+    -q, --quiet            Print nothing but errors (and the output of run)
+    -h, --help             Print this message
+    -V, --version          Print the version of haumea
+
+Exit codes:
+    0      Success
+    1      Any other failure, like an unreadable file or a failing C compiler
+    2      Invalid command line arguments
+    3      The program has a lexical error
+    4      The program has a syntax error
+    5      The program has a semantic error, like a function defined twice
+    101    The compiler crashed (an internal error)
+";
+
+/// A command given to the haumea binary
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Compile a program
+    Build(CompilerOptions),
+    /// Compile a program into a temporary executable and run it with the arguments
+    Run {
+        options: CompilerOptions,
+        arguments: Vec<String>,
+    },
+    /// Compile the tests of a program into a temporary executable and run it
+    Test(CompilerOptions),
+    /// Compile the benchmarks of a program into a temporary executable and run it
+    Bench(CompilerOptions),
+    /// Recompile (and maybe rerun) the programs in the input of options whenever they change
+    Watch {
+        options: CompilerOptions,
+        run: bool,
+    },
+    /// Format the files, or just check that they are formatted if check is true
+    Fmt {
+        inputs: Vec<PathBuf>,
+        check: bool,
+    },
+    /// Run the language server
+    Lsp,
+    /// Run the debug adapter
+    Debug,
+    /// Run the REPL, after loading the functions in the files
+    Repl(Vec<PathBuf>),
+    /// Create a new project in the directory
+    New(PathBuf),
+    /// Generate a reference of the functions in the input of options, as HTML if html is true
+    Doc {
+        options: CompilerOptions,
+        html: bool,
+    },
+    /// Print the explanation of the error code
+    Explain(String),
+    /// Print a grammar of haumea for editors, in the format
+    Grammar(GrammarFormat),
+    /// Print the usage message
+    Help,
+    /// Print the version
+    Version,
+}
+
+impl Command {
+    /// Returns the options of the program that the command compiles, if it compiles one
+    pub fn options(&self) -> Option<&CompilerOptions> {
+        match *self {
+            Command::Build(ref options) | Command::Test(ref options) | Command::Bench(ref options) |
+            Command::Run { ref options, .. } | Command::Watch { ref options, .. } |
+            Command::Doc { ref options, .. } => Some(options),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the command line arguments (without the program name) into a Command
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let args = args.into_iter().collect::<Vec<_>>();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        return Ok(Command::Help);
+    }
+    let mut args = args.into_iter();
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Err("No command given".to_string()),
+    };
+    match &command[..] {
+        "build" => parse_build(args, None).map(Command::Build),
+        "run" => {
+            let mut args = args.collect::<Vec<_>>();
+            let arguments = match args.iter().position(|arg| arg == "--") {
+                Some(separator) => args.split_off(separator).into_iter().skip(1).collect(),
+                None => vec![],
+            };
+            let mut options = parse_build(args.into_iter(), None)?;
+            if options.emit != Emit::C {
+                return Err("run can't be used with --emit".to_string());
+            }
+            if options.check {
+                return Err("run can't be used with --check".to_string());
+            }
+            options.output_kind = OutputKind::Executable;
+            Ok(Command::Run {
+                options,
+                arguments,
+            })
+        },
+        "test" | "bench" => {
+            let mut options = parse_build(args, Some(PathBuf::from(".")))?;
+            if options.output.is_some() || options.split || options.emit != Emit::C || options.check {
+                return Err(format!("{} can't be used with --output, --split, --emit, or --check", command));
+            }
+            options.output_kind = OutputKind::Executable;
+            if command == "test" {
+                Ok(Command::Test(options))
+            } else {
+                Ok(Command::Bench(options))
+            }
+        },
+        "watch" => {
+            let (runs, args): (Vec<_>, Vec<_>) = args.partition(|arg| arg == "--run");
+            let mut options = parse_build(args.into_iter(), None)?;
+            if options.output.is_some() {
+                return Err("watch can't be used with --output".to_string());
+            }
+            if options.reads_stdin() {
+                return Err("watch can't read from stdin".to_string());
+            }
+            if options.inputs.len() > 1 || options.split {
+                return Err("watch only watches a single path".to_string());
+            }
+            let run = !runs.is_empty();
+            if run {
+                if options.check {
+                    return Err("watch --run can't be used with --check".to_string());
+                }
+                if options.emit != Emit::C {
+                    return Err("watch --run can't be used with --emit".to_string());
+                }
+                options.output_kind = OutputKind::Executable;
+            }
+            Ok(Command::Watch {
+                options,
+                run,
+            })
+        },
+        "fmt" => {
+            let mut inputs = vec![];
+            let mut check = false;
+            for arg in args {
+                if arg == "--check" {
+                    check = true;
+                } else if arg.starts_with('-') && arg != STDIO_PATH {
+                    return Err(format!("Unknown option '{}'", arg));
+                } else {
+                    inputs.push(PathBuf::from(arg));
+                }
+            }
+            if inputs.is_empty() {
+                return Err("No input file given".to_string());
+            }
+            Ok(Command::Fmt {
+                inputs,
+                check,
+            })
+        },
+        "lsp" => match args.next() {
+            Some(arg) => Err(format!("Unexpected argument '{}'", arg)),
+            None => Ok(Command::Lsp),
+        },
+        "debug" => match args.next() {
+            Some(arg) => Err(format!("Unexpected argument '{}'", arg)),
+            None => Ok(Command::Debug),
+        },
+        "repl" => {
+            let mut inputs = vec![];
+            for arg in args {
+                if arg.starts_with('-') {
+                    return Err(format!("Unknown option '{}'", arg));
+                }
+                inputs.push(PathBuf::from(arg));
+            }
+            Ok(Command::Repl(inputs))
+        },
+        "new" => match (args.next(), args.next()) {
+            (Some(ref arg), _) if arg.starts_with('-') => Err(format!("Unknown option '{}'", arg)),
+            (Some(path), None) => Ok(Command::New(PathBuf::from(path))),
+            (None, _) => Err("No project directory given".to_string()),
+            (Some(_), Some(arg)) => Err(format!("Unexpected argument '{}'", arg)),
+        },
+        "doc" => {
+            let (htmls, args): (Vec<_>, Vec<_>) = args.partition(|arg| arg == "--html");
+            let options = parse_build(args.into_iter(), None)?;
+            if options.emit != Emit::C || options.output_kind != OutputKind::Source || options.split || options.check {
+                return Err("doc can't be used with --emit, --bin, --split, or --check".to_string());
+            }
+            Ok(Command::Doc {
+                options,
+                html: !htmls.is_empty(),
+            })
+        },
+        "explain" => match (args.next(), args.next()) {
+            (Some(code), None) => Ok(Command::Explain(code)),
+            (None, _) => Err("No error code given".to_string()),
+            (Some(_), Some(arg)) => Err(format!("Unexpected argument '{}'", arg)),
+        },
+        "grammar" => {
+            let mut format = GrammarFormat::TextMate;
+            while let Some(arg) = args.next() {
+                match split_flag(&arg) {
+                    ("--format", inline_value) => {
+                        let name = flag_value("--format", inline_value, &mut args)?;
+                        format = match GrammarFormat::from_name(&name) {
+                            Some(format) => format,
+                            None => return Err(format!("Unknown grammar format '{}'", name)),
+                        };
+                    },
+                    (flag, _) if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+                    _ => return Err(format!("Unexpected argument '{}'", arg)),
+                }
+            }
+            Ok(Command::Grammar(format))
+        },
+        "help" => Ok(Command::Help),
+        "-V" | "--version" => Ok(Command::Version),
+        _ => Err(format!("Unknown command '{}'", command)),
+    }
+}
+
+/// Parses the arguments of the build and run commands into CompilerOptions
+///
+/// If no inputs are given default_input is used, or if that is None, it is an error
+fn parse_build<I: Iterator<Item = String>>(mut args: I, default_input: Option<PathBuf>)
+                                           -> Result<CompilerOptions, String> {
+    let mut inputs = vec![];
+    let mut output = None;
+    let mut emit = Emit::C;
+    let mut target = Target::C;
+    let mut runtime = Runtime::Auto;
+    let mut output_kind = OutputKind::Source;
+    let mut split = false;
+    let mut cc = None;
+    let mut opt_level = OptLevel::O1;
+    let mut cflags = vec![];
+    let mut libraries = vec![];
+    let mut library_paths = vec![];
+    let mut safe = false;
+    let mut temporaries = false;
+    let mut allow_inline_c = false;
+    let mut lints = LintLevels::default();
+    let mut deny_warnings = false;
+    let mut instrument_coverage = false;
+    let mut cache_dir = None;
+    let mut extra_prolog = String::new();
+    let mut check = false;
+    let mut timings = false;
+    let mut source_map = false;
+    let mut minify = false;
+    let mut max_nesting = parser::MAX_NESTING;
+    let mut message_format = MessageFormat::Human;
+    let mut quiet = false;
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = split_flag(&arg);
+        match flag {
+            "-o" | "--output" => {
+                output = Some(PathBuf::from(flag_value(flag, inline_value, &mut args)?));
+            },
+            "--emit" => {
+                let name = flag_value(flag, inline_value, &mut args)?;
+                emit = match Emit::from_name(&name) {
+                    Some(emit) => emit,
+                    None => return Err(format!("Unknown kind of output '{}'", name)),
+                };
+            },
+            "--target" => {
+                let name = flag_value(flag, inline_value, &mut args)?;
+                target = match Target::from_name(&name) {
+                    Some(target) => target,
+                    None => return Err(format!("Unknown target '{}'", name)),
+                };
+            },
+            "--runtime" => {
+                let name = flag_value(flag, inline_value, &mut args)?;
+                runtime = match Runtime::from_name(&name) {
+                    Some(runtime) => runtime,
+                    None => return Err(format!("Unknown runtime '{}'", name)),
+                };
+            },
+            "--cc" => cc = Some(flag_value(flag, inline_value, &mut args)?),
+            "-O" | "--opt-level" => {
+                let name = flag_value(flag, inline_value, &mut args)?;
+                opt_level = match OptLevel::from_name(&name) {
+                    Some(opt_level) => opt_level,
+                    None => return Err(format!("Unknown optimization level '{}'", name)),
+                };
+            },
+            "--cflags" => {
+                let flags = flag_value(flag, inline_value, &mut args)?;
+                cflags.extend(flags.split_whitespace().map(|flag| flag.to_string()));
+            },
+            "--link" => libraries.push(flag_value(flag, inline_value, &mut args)?),
+            "-L" | "--library-path" => library_paths.push(PathBuf::from(flag_value(flag, inline_value, &mut args)?)),
+            "--bin" => output_kind = OutputKind::Executable,
+            "--lib" => output_kind = OutputKind::StaticLibrary,
+            "--shared" => output_kind = OutputKind::SharedLibrary,
+            "--split" => split = true,
+            "--safe" => safe = true,
+            "--temporaries" => temporaries = true,
+            "--allow-inline-c" => allow_inline_c = true,
+            "--pedantic" => lints.set("all", Level::Warn)?,
+            "--deny-warnings" => deny_warnings = true,
+            "-W" | "--warn" => lints.set(&flag_value(flag, inline_value, &mut args)?, Level::Warn)?,
+            "-A" | "--allow" => lints.set(&flag_value(flag, inline_value, &mut args)?, Level::Allow)?,
+            "-D" | "--deny" => lints.set(&flag_value(flag, inline_value, &mut args)?, Level::Deny)?,
+            "--instrument-coverage" => instrument_coverage = true,
+            "--cache-dir" => cache_dir = Some(PathBuf::from(flag_value(flag, inline_value, &mut args)?)),
+            "--prolog-file" => {
+                let path = flag_value(flag, inline_value, &mut args)?;
+                let prolog = fs::read_to_string(&path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+                extra_prolog.push_str(&prolog);
+            },
+            "--max-nesting" => {
+                let value = flag_value(flag, inline_value, &mut args)?;
+                max_nesting = match value.parse() {
+                    Ok(max_nesting) if (1..=MAX_NESTING_LIMIT).contains(&max_nesting) => max_nesting,
+                    _ => return Err(format!("The nesting limit must be a number from 1 to {}, not '{}'",
+                                            MAX_NESTING_LIMIT, value)),
+                };
+            },
+            "--check" => check = true,
+            "--source-map" => source_map = true,
+            "--minify" => minify = true,
+            "-v" | "--verbose" | "--timings" => timings = true,
+            "--message-format" => {
+                let name = flag_value(flag, inline_value, &mut args)?;
+                message_format = match MessageFormat::from_name(&name) {
+                    Some(message_format) => message_format,
+                    None => return Err(format!("Unknown message format '{}'", name)),
+                };
+            },
+            "-q" | "--quiet" => quiet = true,
+            _ if flag.starts_with('-') && flag != STDIO_PATH => return Err(format!("Unknown option '{}'", flag)),
+            _ => inputs.push(PathBuf::from(arg.clone())),
+        }
+    }
+    if inputs.is_empty() {
+        match default_input {
+            Some(input) => inputs.push(input),
+            None => return Err("No input file given".to_string()),
+        }
+    }
+    if output_kind == OutputKind::Executable && emit != Emit::C {
+        return Err("--bin can't be used with --emit".to_string());
+    }
+    if split && (output_kind != OutputKind::Source || emit != Emit::C) {
+        return Err("--split can only be used to generate C".to_string());
+    }
+    let native = emit == Emit::C || emit == Emit::Asm;
+    if instrument_coverage && (split || !native || output_kind == OutputKind::StaticLibrary ||
+                               output_kind == OutputKind::SharedLibrary) {
+        return Err("--instrument-coverage can only be used to compile a program to C, assembly, or an executable"
+                   .to_string());
+    }
+    if cache_dir.is_some() && (split || instrument_coverage || output_kind == OutputKind::StaticLibrary ||
+                               output_kind == OutputKind::SharedLibrary) {
+        return Err("--cache-dir can't be used with --split, --lib, --shared, or --instrument-coverage".to_string());
+    }
+    if check && (output.is_some() || output_kind != OutputKind::Source || split || emit != Emit::C) {
+        return Err("--check can't be used with --output, --bin, --split, or --emit".to_string());
+    }
+    if source_map && (output_kind != OutputKind::Source || split || emit != Emit::C || check) {
+        return Err("--source-map can only be used to generate C into one file".to_string());
+    }
+    // A source map is made from the #line directives, which minified C doesn't have
+    if minify && (!native || source_map) {
+        return Err("--minify can only be used to generate C, assembly, or an executable, without --source-map"
+                   .to_string());
+    }
+    let mut options = CompilerOptions::new(inputs.remove(0));
+    options.inputs.extend(inputs);
+    options.output = output;
+    options.emit = emit;
+    options.target = target;
+    options.runtime = runtime;
+    options.output_kind = output_kind;
+    options.split = split;
+    options.cc = cc;
+    options.opt_level = opt_level;
+    options.cflags = cflags;
+    options.libraries = libraries;
+    options.library_paths = library_paths;
+    options.safe = safe;
+    options.temporaries = temporaries;
+    options.allow_inline_c = allow_inline_c;
+    options.lints = lints;
+    options.deny_warnings = deny_warnings;
+    options.instrument_coverage = instrument_coverage;
+    options.cache_dir = cache_dir;
+    options.extra_prolog = extra_prolog;
+    options.max_nesting = max_nesting;
+    options.check = check;
+    options.timings = timings;
+    options.source_map = source_map;
+    options.minify = minify;
+    options.message_format = message_format;
+    options.quiet = quiet;
+    Ok(options)
+}
+
+/// Splits a `--flag=value` argument into the flag and the value
+///
+/// `-L` can have its value straight after it too, like `-L/opt/lib` for the C compiler,
+/// and so can `-W`, `-A`, `-D` and `-O`, like `-Wall` and `-O2`
+fn split_flag(arg: &str) -> (&str, Option<&str>) {
+    if arg.starts_with("--") {
+        if let Some(index) = arg.find('=') {
+            return (&arg[..index], Some(&arg[index + 1..]));
+        }
+    }
+    if ["-L", "-W", "-A", "-D", "-O"].iter().any(|short| arg.starts_with(short)) && arg.len() > 2 {
+        return (&arg[..2], Some(&arg[2..]));
+    }
+    (arg, None)
+}
+
+/// Returns the value of a flag, taking it from the next argument if it wasn't given inline
+fn flag_value<I: Iterator<Item = String>>(flag: &str, inline_value: Option<&str>, args: &mut I)
+                                          -> Result<String, String> {
+    match inline_value {
+        Some(value) => Ok(value.to_string()),
+        None => match args.next() {
+            Some(value) => Ok(value),
+            None => Err(format!("Expected a value after '{}'", flag)),
+        },
+    }
+}
@@ -1,213 +1,1183 @@
 /// codegen.rs
 /// The code generator for the haumea language.
-use std::rc::Rc;
+use std::fmt::Write;
+use std::panic;
+use std::slice;
+use std::thread;
 use parser;
+use cache::{self, FunctionCache};
+use coverage;
+use emitter::CEmitter;
+use optimizer;
+use options::CompilerOptions;
+use runtime;
+use scanner::Span;
+use texts;
 
-const INDENT: &'static str = "    ";
-const NEW_LINE: &'static str = "\n";
-const PROLOG: &'static str = "
+/// The fewest functions that compiling on another thread is worth starting it for
+const FUNCTIONS_PER_THREAD: usize = 500;
+/// The fewest comparisons in a chain of ifs that it is compiled into a switch for
+const SWITCH_CASES: usize = 3;
+const PROLOG: &str = "
 /* Haumea prolog */
+";
+const END_PROLOG: &str = "/* End prolog */
+
+/* Start compiled program */
+";
+const EPILOG: &str = "
+/* End compiled program */
+";
+const TEST_HARNESS: &str = "
+/* Test harness */
 #include <stdio.h>
 
-long display(long n) {
-    printf(\"%ld\\n\", n);
-    return 0;
+/* Runs the test, returning 1 if it passes and 0 if it fails */
+static int haumea_run_test(const char *name, long (*test)(void)) {
+    jmp_buf failed;
+    haumea_test_failed = &failed;
+    if (setjmp(failed)) {
+        haumea_test_failed = NULL;
+#ifdef HAUMEA_BACKTRACES
+        /* The frames of the functions that the failure jumped out of are gone */
+        haumea_stack = NULL;
+#endif
+        printf(\"test %s ... FAILED\\n\", name);
+        return 0;
+    }
+    test();
+    haumea_test_failed = NULL;
+    printf(\"test %s ... ok\\n\", name);
+    return 1;
 }
+";
+const BRANCH_HINTS: &str = "
+/* Branch hints, which only GCC and Clang understand */
+#if defined(__GNUC__) || defined(__clang__)
+#define haumea_likely(condition) __builtin_expect(!!(condition), 1)
+#define haumea_unlikely(condition) __builtin_expect(!!(condition), 0)
+#else
+#define haumea_likely(condition) (condition)
+#define haumea_unlikely(condition) (condition)
+#endif
+";
+const COVERAGE_PROLOG: &str = "
+/* Coverage counters */
+#include <stdio.h>
+#include <stdlib.h>
 
-/* End prolog */
-
-/* Start compiled program */
+struct haumea_statement {
+    const char *file;
+    int line;
+};
+extern long haumea_coverage[];
+static void haumea_coverage_report(void);
 ";
-const EPILOG: &'static str = "
-/* End compiled program */
+const COVERAGE_REPORT: &str = "
+/* Writes how many times each statement ran to the file named by HAUMEA_COVERAGE_VARIABLE,
+   or HAUMEA_COVERAGE_FILE if it isn't set */
+static void haumea_coverage_report(void) {
+    const char *path = getenv(HAUMEA_COVERAGE_VARIABLE);
+    FILE *report = fopen(path != NULL && *path != '\\0' ? path : HAUMEA_COVERAGE_FILE, \"w\");
+    long i;
+    if (report == NULL) {
+        fprintf(stderr, \"could not write the coverage report\\n\");
+        return;
+    }
+    for (i = 0; i < haumea_coverage_count; i++) {
+        fprintf(report, \"%s:%d %ld\\n\", haumea_coverage_statements[i].file,
+                haumea_coverage_statements[i].line, haumea_coverage[i]);
+    }
+    fclose(report);
+}
 ";
+const BENCH_HARNESS: &str = "
+/* Benchmark harness */
+#include <stdio.h>
+#include <time.h>
 
-/// Compile an Program created by parser::parse into a C program
-pub fn compile_ast(mut out: &mut String, ast: parser::Program) {
-    out.push_str(PROLOG);
-    for func in ast {
-        compile_function(&mut out, func);
+/* How many times each benchmark is timed, and how many nanoseconds each time should take at least */
+#define HAUMEA_SAMPLES 10
+#define HAUMEA_SAMPLE_NS 10000000.0
+
+/* Where the results of benchmarks go, so that the C compiler can't leave out the calls */
+static volatile long haumea_bench_result;
+
+static double haumea_now_ns(void) {
+    struct timespec now;
+    clock_gettime(CLOCK_MONOTONIC, &now);
+    return now.tv_sec * 1e9 + now.tv_nsec;
+}
+
+/* Calls bench iterations times, returning the mean number of nanoseconds each call took */
+static double haumea_time_bench(long (*bench)(void), long iterations) {
+    long i;
+    double start = haumea_now_ns();
+    for (i = 0; i < iterations; i++) {
+        haumea_bench_result = bench();
     }
-    out.push_str(EPILOG);
+    return (haumea_now_ns() - start) / iterations;
 }
 
-/// Compiles a Function
-fn compile_function(mut out: &mut String, func: parser::Function) {
-    write_newline(&mut out);
-    out.push_str(if func.name == "main".to_string() { "int " } else { "long " });
-    out.push_str(&func.name);
-	out.push_str("(");
-	if let Some(sig) = func.signature {
-		if let Some((last_param, first_params)) = sig.split_last() {
-			for param in first_params {
-				out.push_str(&format!("long {:}, ", param));
+static void haumea_run_bench(const char *name, long (*bench)(void)) {
+    long iterations = 1;
+    double mean = 0, variance = 0, deviation;
+    double samples[HAUMEA_SAMPLES];
+    int i;
+    /* Find how many calls take long enough to be timed accurately */
+    while (haumea_time_bench(bench, iterations) * iterations < HAUMEA_SAMPLE_NS && iterations < (1L << 30)) {
+        iterations *= 2;
+    }
+    for (i = 0; i < HAUMEA_SAMPLES; i++) {
+        samples[i] = haumea_time_bench(bench, iterations);
+        mean += samples[i] / HAUMEA_SAMPLES;
+    }
+    for (i = 0; i < HAUMEA_SAMPLES; i++) {
+        variance += (samples[i] - mean) * (samples[i] - mean) / (HAUMEA_SAMPLES - 1);
+    }
+    /* The square root by Newton's method, so that the program doesn't need the maths library */
+    deviation = variance;
+    for (i = 0; i < 100 && deviation > 0; i++) {
+        deviation = (deviation + variance / deviation) / 2;
+    }
+    printf(\"bench %s ... %.1f ns/iter (+/- %.1f) from %d samples of %ld iterations\\n\",
+           name, mean, deviation, HAUMEA_SAMPLES, iterations);
+}
+";
+
+/// Compile an Program created by parser::parse into a C program
+///
+/// If the program needs the full runtime library it includes its header, and has to be linked with it.
+/// The runtime module has the header and source of the runtime.
+/// The minimal runtime is used when options.runtime allows it, and the full one otherwise.
+///
+/// The generated C contains #line directives that point back at the source named by options,
+/// so that errors from the C compiler refer to lines in the haumea source.
+///
+/// The program is only borrowed, so it can be compiled again, with other options or by another
+/// function of this module, without parsing it again. It is copied into a SourceFile
+/// named by options, which compile_files saves for programs that are already in one.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser;
+/// # use haumea::codegen::compile_ast;
+/// # use haumea::options::CompilerOptions;
+/// let program = parser::parse(Scanner::new("to main do display(6 / 2) end")).unwrap();
+/// let mut options = CompilerOptions::new("main.hm");
+/// let mut c = String::new();
+/// compile_ast(&mut c, &program, &options);
+/// options.safe = true;
+/// let mut safe_c = String::new();
+/// compile_ast(&mut safe_c, &program, &options);
+/// assert!(c.contains("display((6l / 2l));"));
+/// assert!(safe_c.contains("display(haumea_divide(6l, 2l, __FILE__, __LINE__));"));
+/// ```
+pub fn compile_ast(out: &mut String, ast: &parser::Program, options: &CompilerOptions) {
+	let files = [parser::SourceFile {
+		name: options.source_name(),
+		program: ast.clone(),
+	}];
+	let files = texts::compare_by_chars(&files);
+	let full_runtime = runtime::needs_full_runtime(&files, options.runtime).unwrap_or(true);
+	compile_files(out, &files, full_runtime, options);
+}
+
+/// Compiles the Programs of several source files into a single C program
+///
+/// With full_runtime the program uses the full runtime library, and otherwise the minimal one.
+/// files should already have been prepared with haumea::prepare, which makes their comparisons
+/// of texts into calls of same_text before they are optimized.
+/// With options.safe it checks for errors like division by zero,
+/// and options.extra_prolog and options.extra_epilog are put before and after the program.
+/// With options.instrument_coverage each statement counts how many times it runs,
+/// and the program writes the counts to a report when it exits, which the coverage module reads.
+///
+/// The functions of a large program are compiled on several threads, as many as
+/// options.codegen_threads allows, unless their statements are counted for coverage.
+///
+/// The C only depends on files, full_runtime, and options, so it is the same every time
+/// they are, which lets builds be cached. How many threads compile it doesn't change it.
+pub fn compile_files(out: &mut String, files: &[parser::SourceFile], full_runtime: bool,
+                     options: &CompilerOptions) {
+	emit_files(&mut emitter(out, options), files, full_runtime, options);
+}
+
+/// Returns an emitter that writes to out, which minifies the C with options.minify
+fn emitter<W: Write>(out: W, options: &CompilerOptions) -> CEmitter<W> {
+	if options.minify { CEmitter::minified(out) } else { CEmitter::new(out) }
+}
+
+/// Compiles files as compile_files does, keeping the C of each function in cache
+///
+/// The functions that are already in the cache aren't compiled again, so rebuilding a program
+/// only compiles the ones that changed. The C is the same as compile_files gives, unless
+/// options.instrument_coverage is set, in which case the cache isn't used.
+pub fn compile_files_cached(out: &mut String, files: &[parser::SourceFile], full_runtime: bool,
+                            options: &CompilerOptions, cache: &mut FunctionCache) {
+	emit_program(&mut emitter(out, options), files, full_runtime, options, Some(cache));
+}
+
+/// Compiles files as compile_files does, writing the C to emitter
+///
+/// This lets the C be written somewhere other than a String, like straight to a file
+pub fn emit_files<W: Write>(emitter: &mut CEmitter<W>, files: &[parser::SourceFile], full_runtime: bool,
+                            options: &CompilerOptions) {
+	emit_program(emitter, files, full_runtime, options, None);
+}
+
+fn emit_program<W: Write>(emitter: &mut CEmitter<W>, files: &[parser::SourceFile], full_runtime: bool,
+                          options: &CompilerOptions, cache: Option<&mut FunctionCache>) {
+	let mut coverage = Coverage::new(options.instrument_coverage);
+	compile_includes(emitter, files);
+	compile_prolog(emitter, files, full_runtime, options.safe, coverage.enabled, &options.extra_prolog);
+	compile_prototypes(emitter, files, false);
+	let functions = files.iter()
+		.flat_map(|file| file.program.iter().map(move |func| (func, &file.name[..])))
+		.collect::<Vec<_>>();
+	let threads = codegen_threads(options, functions.len());
+	// The counters of coverage are numbered in the order the statements are compiled
+	if coverage.enabled || (threads == 1 && cache.is_none()) {
+		for &(func, source_name) in &functions {
+			compile_function(emitter, func, source_name, full_runtime, options.safe, &mut coverage);
+		}
+	} else if let Some(cache) = cache {
+		for c_source in compile_with_cache(&functions, cache, full_runtime, options) {
+			emitter.raw(&c_source);
+		}
+	} else {
+		for c_source in compile_in_parallel(&functions, threads, full_runtime, options.safe) {
+			emitter.raw(&c_source);
+		}
+	}
+	coverage.compile_report(emitter);
+	compile_epilog(emitter, &options.extra_epilog);
+}
+
+/// Returns how many threads to compile a program of functions functions on, as allowed by options
+///
+/// Each thread gets at least FUNCTIONS_PER_THREAD of them, so small programs are compiled on one
+fn codegen_threads(options: &CompilerOptions, functions: usize) -> usize {
+	let most = options.codegen_threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+	most.min(functions / FUNCTIONS_PER_THREAD).max(1)
+}
+
+/// Compiles functions, each with the name of its source file, on threads threads
+///
+/// Returns the C of each function, in the order of the functions, so that joined together
+/// they are the C that compiling the functions one after another gives
+fn compile_in_parallel(functions: &[(&parser::Function, &str)], threads: usize, full_runtime: bool,
+                       safe: bool) -> Vec<String> {
+	let compile_chunk = |chunk: &[(&parser::Function, &str)]| {
+		chunk.iter().map(|&(func, source_name)| {
+			let mut out = String::new();
+			compile_function(&mut CEmitter::new(&mut out), func, source_name, full_runtime, safe,
+			                 &mut Coverage::new(false));
+			out
+		}).collect::<Vec<_>>()
+	};
+	if threads <= 1 || functions.is_empty() {
+		return compile_chunk(functions);
+	}
+	thread::scope(|scope| {
+		let compiling = functions.chunks(functions.len().div_ceil(threads))
+			.map(|chunk| scope.spawn(move || compile_chunk(chunk)))
+			.collect::<Vec<_>>();
+		compiling.into_iter()
+			.flat_map(|chunk| chunk.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+			.collect()
+	})
+}
+
+/// Returns the C of each of functions, taking it from cache for the ones that are in it,
+/// and compiling the others and keeping them in it
+fn compile_with_cache(functions: &[(&parser::Function, &str)], cache: &mut FunctionCache, full_runtime: bool,
+                      options: &CompilerOptions) -> Vec<String> {
+	let keys = functions.iter()
+		.map(|&(func, source_name)| cache::function_key(func, source_name, full_runtime, options.safe))
+		.collect::<Vec<_>>();
+	let cached = keys.iter().map(|&key| cache.get(key)).collect::<Vec<_>>();
+	let missing = functions.iter().zip(&cached)
+		.filter(|&(_, c_source)| c_source.is_none())
+		.map(|(&function, _)| function)
+		.collect::<Vec<_>>();
+	let threads = codegen_threads(options, missing.len());
+	let mut compiled = compile_in_parallel(&missing, threads, full_runtime, options.safe).into_iter();
+	cached.into_iter().zip(keys).map(|(c_source, key)| c_source.unwrap_or_else(|| {
+		let c_source = compiled.next().expect("a function that isn't cached wasn't compiled");
+		cache.insert(key, &c_source);
+		c_source
+	})).collect()
+}
+
+/// Compiles the Programs of several source files into the C of a library, which is every function but main
+///
+/// full_runtime and options are as for compile_files. The functions can be declared
+/// for the C code that uses the library with compile_interface. If any of them are exported,
+/// the others are static, so that they aren't symbols of the library.
+pub fn compile_library(out: &mut String, files: &[parser::SourceFile], full_runtime: bool,
+                       options: &CompilerOptions) {
+	let emitter = &mut emitter(out, options);
+	compile_includes(emitter, files);
+	compile_prolog(emitter, files, full_runtime, options.safe, false, &options.extra_prolog);
+	compile_prototypes(emitter, files, true);
+	for file in files {
+		for func in &file.program {
+			if func.name != "main" {
+				compile_function(emitter, func, &file.name, full_runtime, options.safe, &mut Coverage::new(false));
 			}
-			out.push_str(&format!("long {:}", last_param));
 		}
 	}
-	out.push_str(")");
-	compile_statement(&mut out, func.code, 0);
+	compile_epilog(emitter, &options.extra_epilog);
+}
+
+/// Returns true if func is a test, which means that it is named test_something or marked @test,
+/// and has no parameters
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser;
+/// # use haumea::codegen::is_test;
+/// let program = parser::parse(Scanner::new("to test_add do assert_equal(1 + 1, 2) end
+/// to test_with with (n) do return n end
+/// @test
+/// to adding_works do assert_equal(1 + 1, 2) end")).unwrap();
+/// assert!(is_test(&program[0]));
+/// assert!(!is_test(&program[1]));
+/// assert!(is_test(&program[2]));
+/// ```
+pub fn is_test(func: &parser::Function) -> bool {
+	let parameters = func.signature.as_ref().map_or(0, |signature| signature.len());
+	(func.name.starts_with("test_") || func.attribute("test").is_some()) && parameters == 0
+}
+
+/// Compiles the Programs of several source files into a C program that runs their tests
+///
+/// Every function for which is_test is true is run in turn, and the program reports
+/// which ones failed an assertion. It exits with 1 if any of them did.
+/// The main function of the program isn't compiled, as the harness has its own.
+/// With coverage, the statements that the tests run are counted as for compile_files,
+/// and with safe the functions check for errors as they do for compile_files.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::codegen::compile_tests;
+/// let files = [SourceFile {
+///     name: "half.hm".to_string(),
+///     program: parser::parse(Scanner::new("to test_half do assert_equal(4 / 2, 2) end")).unwrap(),
+/// }];
+/// let mut c = String::new();
+/// compile_tests(&mut c, &files, false, true);
+/// assert!(c.contains("assert_equal(haumea_divide(4l, 2l, __FILE__, __LINE__), 2l);"));
+/// assert!(c.contains("passed += haumea_run_test(\"test_half\", test_half);"));
+/// ```
+pub fn compile_tests(out: &mut String, files: &[parser::SourceFile], coverage: bool, safe: bool) {
+	let emitter = &mut CEmitter::new(out);
+	let tests = harness_functions(files, is_test);
+	compile_without_main(emitter, files, coverage, safe);
+	emitter.raw(TEST_HARNESS);
+	emitter.blank_line();
+	emitter.line("int main(void) {");
+	emitter.push_indent();
+	if coverage {
+		emitter.line("atexit(haumea_coverage_report);");
+	}
+	emitter.line("int passed = 0;");
+	emitter.line(format_args!("printf(\"running {} test{}\\n\");", tests.len(), if tests.len() == 1 { "" } else { "s" }));
+	for test in &tests {
+		emitter.line(format_args!("passed += haumea_run_test({}, {});", c_string_literal(test), test));
+	}
+	emitter.line("printf(\"\\ntest result: %s. %d passed; %d failed\\n\",");
+	emitter.push_indent();
+	emitter.line(format_args!("passed == {0} ? \"ok\" : \"FAILED\", passed, {0} - passed);", tests.len()));
+	emitter.pop_indent();
+	emitter.line(format_args!("return passed == {} ? 0 : 1;", tests.len()));
+	emitter.pop_indent();
+	emitter.line("}");
+}
+
+/// Returns true if func is a benchmark, which means that it is named bench_something and has no parameters
+pub fn is_bench(func: &parser::Function) -> bool {
+	let parameters = func.signature.as_ref().map_or(0, |signature| signature.len());
+	func.name.starts_with("bench_") && parameters == 0
+}
+
+/// Compiles the Programs of several source files into a C program that times their benchmarks
+///
+/// Each function for which is_bench is true is called over and over, timing it with clock_gettime.
+/// The program reports the mean and standard deviation of the time each call takes.
+/// The main function of the program isn't compiled, as the harness has its own.
+/// safe is as for compile_tests.
+pub fn compile_benches(out: &mut String, files: &[parser::SourceFile], safe: bool) {
+	let emitter = &mut CEmitter::new(out);
+	let benches = harness_functions(files, is_bench);
+	compile_without_main(emitter, files, false, safe);
+	emitter.raw(BENCH_HARNESS);
+	emitter.blank_line();
+	emitter.line("int main(void) {");
+	emitter.push_indent();
+	emitter.line(format_args!("printf(\"running {} benchmark{}\\n\");",
+	                          benches.len(), if benches.len() == 1 { "" } else { "s" }));
+	for bench in &benches {
+		emitter.line(format_args!("haumea_run_bench({}, {});", c_string_literal(bench), bench));
+	}
+	emitter.line("return 0;");
+	emitter.pop_indent();
+	emitter.line("}");
+}
+
+/// Returns the names of the functions in files that the harness runs, which are the ones that runs is true for
+fn harness_functions(files: &[parser::SourceFile], runs: fn(&parser::Function) -> bool) -> Vec<String> {
+	files.iter()
+		.flat_map(|file| file.program.iter())
+		.filter(|func| runs(func))
+		.map(|func| func.name.clone())
+		.collect()
+}
+
+/// Compiles every function in files except main, for a harness that has its own main
+///
+/// The harnesses need the full runtime. coverage and safe are as for compile_tests.
+fn compile_without_main<W: Write>(emitter: &mut CEmitter<W>, files: &[parser::SourceFile], coverage: bool, safe: bool) {
+	let mut coverage = Coverage::new(coverage);
+	compile_includes(emitter, files);
+	compile_prolog(emitter, files, true, safe, coverage.enabled, "");
+	compile_prototypes(emitter, files, false);
+	for file in files {
+		for func in &file.program {
+			if func.name != "main" {
+				compile_function(emitter, func, &file.name, true, safe, &mut coverage);
+			}
+		}
+	}
+	coverage.compile_report(emitter);
+	emitter.raw(EPILOG);
+}
+
+/// Returns the name of the include guard macro for the header named header_name
+///
+/// # Examples
+/// ```
+/// # use haumea::codegen::include_guard;
+/// assert_eq!(include_guard("math.h"), "MATH_H");
+/// assert_eq!(include_guard("my-lib.h"), "MY_LIB_H");
+/// ```
+pub fn include_guard(header_name: &str) -> String {
+	header_name.chars()
+		.map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+		.collect()
+}
+
+/// Compiles a C header with the prototypes of every function in files
+///
+/// guard is the name of the macro used for the include guard
+pub fn compile_header(out: &mut String, files: &[parser::SourceFile], guard: &str) {
+	let emitter = &mut CEmitter::new(out);
+	compile_guard_start(emitter, guard);
+	compile_includes(emitter, files);
+	compile_prototypes(emitter, files, false);
+	compile_guard_end(emitter, guard);
+}
+
+/// Compiles a C header that declares the functions defined in files, so that C code can call them
+///
+/// main and external functions are left out, as they aren't for C code to call, and if any
+/// of the functions are exported, with `exported to` or `@export`, only those are declared.
+/// guard is the name of the macro used for the include guard.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::codegen::compile_interface;
+/// let source = "external function putchar with (c)\nto add with (a, b) do return a + b end\nto main do end";
+/// let file = SourceFile {
+///     name: "math.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// let mut out = String::new();
+/// compile_interface(&mut out, &[file], "MATH_H");
+/// assert_eq!(out, "#ifndef MATH_H\n#define MATH_H\n\nlong add(long a, long b);\n\n#endif /* MATH_H */\n");
+///
+/// let source = "to helper do return 1 end\nexported to answer do return helper() * 42 end\n@export\nto twice with (n) is n * 2";
+/// let file = SourceFile {
+///     name: "answer.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// let mut out = String::new();
+/// compile_interface(&mut out, &[file], "ANSWER_H");
+/// assert_eq!(out, "#ifndef ANSWER_H\n#define ANSWER_H\n\nlong answer();\nlong twice(long n);\n\n#endif /* ANSWER_H */\n");
+/// ```
+pub fn compile_interface(out: &mut String, files: &[parser::SourceFile], guard: &str) {
+	let emitter = &mut CEmitter::new(out);
+	compile_guard_start(emitter, guard);
+	let exports = has_exports(files);
+	for func in files.iter().flat_map(|file| file.program.iter()) {
+		if func.name != "main" && func.code.is_some() && (func.is_exported() || !exports) {
+			emitter.line(format_args!("{};", c_signature(func)));
+		}
+	}
+	compile_guard_end(emitter, guard);
+}
+
+/// Compiles the start of the include guard of a header, which is named guard
+fn compile_guard_start<W: Write>(emitter: &mut CEmitter<W>, guard: &str) {
+	emitter.directive(format_args!("#ifndef {}", guard));
+	emitter.directive(format_args!("#define {}", guard));
+	emitter.blank_line();
+}
+
+/// Compiles the end of the include guard named guard
+fn compile_guard_end<W: Write>(emitter: &mut CEmitter<W>, guard: &str) {
+	emitter.blank_line();
+	emitter.directive(format_args!("#endif /* {} */", guard));
+}
+
+/// Compiles a single source file of a larger program into its own C file
+///
+/// The prototypes of the functions are taken from the header named header,
+/// which should be generated by compile_header. full_runtime and options are as for compile_files.
+pub fn compile_split_file(out: &mut String, file: &parser::SourceFile, header: &str, full_runtime: bool,
+                          options: &CompilerOptions) {
+	let emitter = &mut emitter(out, options);
+	compile_prolog(emitter, slice::from_ref(file), full_runtime, options.safe, false, &options.extra_prolog);
+	emitter.directive(format_args!("#include {}", c_string_literal(header)));
+	for func in &file.program {
+		compile_function(emitter, func, &file.name, full_runtime, options.safe, &mut Coverage::new(false));
+	}
+	compile_epilog(emitter, &options.extra_epilog);
+}
+
+/// Compiles the prolog, which is the runtime for the program
+///
+/// This is the include of the full runtime library with full_runtime, and the minimal runtime otherwise,
+/// followed by the checks with safe, the branch hints if the ifs in files use them, the coverage
+/// counters with coverage, and then extra, which is C that the user gave
+fn compile_prolog<W: Write>(emitter: &mut CEmitter<W>, files: &[parser::SourceFile], full_runtime: bool, safe: bool,
+                            coverage: bool, extra: &str) {
+	emitter.raw(PROLOG);
+	emitter.raw(if full_runtime { runtime::INCLUDE } else { runtime::MINIMAL });
+	if safe {
+		emitter.raw(runtime::CHECKS);
+	}
+	if has_branch_hints(files) {
+		emitter.raw(BRANCH_HINTS);
+	}
+	if coverage {
+		emitter.raw(COVERAGE_PROLOG);
+		emitter.directive(format_args!("#define HAUMEA_COVERAGE_VARIABLE {}", c_string_literal(coverage::FILE_VARIABLE)));
+		emitter.directive(format_args!("#define HAUMEA_COVERAGE_FILE {}", c_string_literal(coverage::DEFAULT_FILE)));
+	}
+	emitter.raw(extra);
+	emitter.raw(END_PROLOG);
+}
+
+/// Compiles the epilog, which ends the program, followed by extra, which is C that the user gave
+fn compile_epilog<W: Write>(emitter: &mut CEmitter<W>, extra: &str) {
+	emitter.raw(EPILOG);
+	emitter.raw(extra);
+	emitter.end();
+}
+
+/// Compiles the prototypes of the functions in files, so that they can be called
+/// before they are defined
+///
+/// A program that includes headers relies on them to declare its external functions,
+/// with their real types, so then those don't get prototypes.
+/// With hide_unexported, if any of the functions are exported the others are declared static.
+fn compile_prototypes<W: Write>(emitter: &mut CEmitter<W>, files: &[parser::SourceFile], hide_unexported: bool) {
+	let declare_externals = includes(files).is_empty();
+	let hide = hide_unexported && has_exports(files);
+	for func in files.iter().flat_map(|file| file.program.iter()) {
+		// main is never called by haumea code, so it doesn't need a prototype
+		if func.name != "main" && (func.code.is_some() || declare_externals) {
+			// A function declared static is static where it is defined too
+			let storage = if hide && func.code.is_some() && !func.is_exported() { "static " } else { "" };
+			emitter.line(format_args!("{}{};", storage, c_signature(func)));
+		}
+	}
+	compile_conversions(emitter, files);
+}
+
+/// Compiles a macro for each external function in files that takes or returns texts or takes functions,
+/// which turns the texts into the chars that C sees and back, and the functions into pointers
+///
+/// Every text ends with a 0 byte, so C can use them as they are. The texts belong to haumea,
+/// so C mustn't change or free them, and haumea never frees a text that C returns.
+/// The pointers to functions are void *, so that they can be given to C functions that expect
+/// any kind of pointer to a function, like the comparison of qsort. It is called with C's
+/// arguments as longs, and returns a long, which works as all of them are passed in registers the same way
+fn compile_conversions<W: Write>(emitter: &mut CEmitter<W>, files: &[parser::SourceFile]) {
+	for func in files.iter().flat_map(|file| file.program.iter()) {
+		let converts = !func.text_parameters.is_empty() || func.returns_text || !func.callback_parameters.is_empty();
+		if func.code.is_some() || !converts {
+			continue;
+		}
+		if !func.text_parameters.is_empty() || func.returns_text {
+			emitter.line(format_args!("/* {} is given texts as const char * that it mustn't change or free{} */",
+			                          func.name, if func.returns_text { ", and the text it returns is never freed" } else { "" }));
+		}
+		let parameters = func.signature.as_ref().map_or(&[][..], |signature| &signature[..]);
+		let arguments = parameters.iter().map(|parameter| {
+			if func.text_parameters.contains(parameter) {
+				format!("(char *)({})", parameter)
+			} else if func.callback_parameters.iter().any(|(name, _)| name == parameter) {
+				format!("(void *)({})", parameter)
+			} else {
+				format!("({})", parameter)
+			}
+		}).collect::<Vec<_>>();
+		// A macro isn't expanded inside itself, so the call in it is to the C function
+		let call = format!("{}({})", func.name, arguments.join(", "));
+		emitter.directive(format_args!("#define {}({}) {}", func.name, parameters.join(", "),
+		                               if func.returns_text { format!("((long){})", call) } else { call }));
+	}
+}
+
+/// Returns true if any of the functions in files are exported
+fn has_exports(files: &[parser::SourceFile]) -> bool {
+	files.iter().flat_map(|file| file.program.iter()).any(|func| func.is_exported())
+}
+
+/// Compiles an #include for each of the headers that the functions in files include
+fn compile_includes<W: Write>(emitter: &mut CEmitter<W>, files: &[parser::SourceFile]) {
+	for header in includes(files) {
+		emitter.directive(format_args!("#include <{}>", header));
+	}
+}
+
+/// Returns the headers included in files, each once, in the order they are first included
+fn includes(files: &[parser::SourceFile]) -> Vec<&str> {
+	let mut headers = vec![];
+	for header in files.iter().flat_map(|file| file.program.iter()).flat_map(|func| func.includes.iter()) {
+		if !headers.contains(&&header[..]) {
+			headers.push(&header[..]);
+		}
+	}
+	headers
+}
+
+/// Compiles a Function
+///
+/// With full_runtime, main is given the command line arguments and hands them to the runtime.
+/// safe is as for compile_files, and the statements are counted in coverage.
+/// With coverage enabled, main reports the counts when the program exits.
+///
+/// An external function is defined in C, so it only has a prototype
+fn compile_function<W: Write>(emitter: &mut CEmitter<W>, func: &parser::Function, source_name: &str,
+                              full_runtime: bool, safe: bool, coverage: &mut Coverage) {
+	let code = match func.code {
+		Some(ref code) => code,
+		None => return,
+	};
+	emitter.blank_line();
+	compile_line_directive(emitter, func.span, source_name);
+	if func.name == "main" && (full_runtime || coverage.enabled) {
+		emitter.line(if full_runtime { "int main(int argc, char **argv)" } else { "int main(void)" });
+		emitter.line("{");
+		emitter.push_indent();
+		if full_runtime {
+			emitter.line("haumea_set_arguments(argc, argv);");
+		}
+		if coverage.enabled {
+			emitter.line("atexit(haumea_coverage_report);");
+		}
+		compile_frame_start(emitter, func, safe);
+		compile_statement(emitter, code, source_name, safe, coverage, &mut vec![]);
+		compile_frame_end(emitter, safe);
+		emitter.pop_indent();
+		emitter.line("}");
+		return;
+	}
+	emitter.line(c_signature(func));
+	// A function whose body is a single statement, like one written with is, still needs braces in C,
+	// and with safe the frame of the function goes in them around its body
+	if let (parser::StatementKind::Do(_), false) = (&code.kind, safe) {
+		compile_statement(emitter, code, source_name, safe, coverage, &mut vec![]);
+	} else {
+		emitter.line("{");
+		emitter.push_indent();
+		compile_frame_start(emitter, func, safe);
+		compile_statement(emitter, code, source_name, safe, coverage, &mut vec![]);
+		compile_frame_end(emitter, safe);
+		emitter.pop_indent();
+		emitter.line("}");
+	}
+}
+
+/// Compiles the start of the frame of func on the stack of the functions that are running,
+/// with safe, which the C for --safe writes a backtrace from when the program stops with an error
+fn compile_frame_start<W: Write>(emitter: &mut CEmitter<W>, func: &parser::Function, safe: bool) {
+	if safe {
+		emitter.line(format_args!("struct haumea_frame haumea_frame = {{ {}, __FILE__, __LINE__, haumea_stack }};",
+		                          c_string_literal(&func.name)));
+		emitter.line("haumea_stack = &haumea_frame;");
+	}
+}
+
+/// Compiles taking the frame of the function off the stack with safe, which every return does too
+fn compile_frame_end<W: Write>(emitter: &mut CEmitter<W>, safe: bool) {
+	if safe {
+		emitter.line("haumea_stack = haumea_frame.caller;");
+	}
+}
+
+/// Returns the return type, name, and parameters of a Function in C
+///
+/// The texts that external functions take and return are const char *,
+/// and the functions that they take are pointers to functions that take and return longs
+fn c_signature(func: &parser::Function) -> String {
+	let return_type = if func.name == "main" { "int " } else if func.returns_text { "const char *" } else { "long " };
+	let mut params = vec![];
+	if let Some(ref sig) = func.signature {
+		let c_parameter = |param: &String| {
+			match func.callback_parameters.iter().find(|(name, _)| name == param) {
+				Some((_, params)) if params.is_empty() => format!("long (*{:})(void)", param),
+				Some((_, params)) => format!("long (*{:})({:})", param, vec!["long"; params.len()].join(", ")),
+				None if func.text_parameters.contains(param) => format!("const char *{:}", param),
+				None => format!("long {:}", param),
+			}
+		};
+		params = sig.iter().map(c_parameter).collect::<Vec<_>>();
+	}
+	format!("{}{}({})", return_type, func.name, params.join(", "))
 }
 
 /// Compiles a statement
-fn compile_statement(mut out: &mut String, statement: parser::Statement, indent: i32) {
-	use parser::Statement;
-	
-	match statement {
-		Statement::Return(exp) => {
-			out.push_str(&format!("{:}return {:};", 
-			                      replicate(INDENT, indent), 
-			                      compile_expression(exp)));
-		},
-		Statement::Do(block) => {
-			out.push_str(&format!("\n{:}{{\n", replicate(INDENT, indent)));
+///
+/// Every statement but a variable or a block is counted in coverage, so a block counts
+/// through the statements in it. loops is the labels of the loops that the statement is in,
+/// innermost last, with where each loop starts, which a break finds the loop it leaves by.
+fn compile_statement<'a, W: Write>(emitter: &mut CEmitter<W>, statement: &'a parser::Statement,
+                                   source_name: &str, safe: bool, coverage: &mut Coverage,
+                                   loops: &mut Vec<(&'a str, Span)>) {
+	use parser::StatementKind as Statement;
+
+	let counted = !matches!(statement.kind, Statement::Var(_) | Statement::Persistent(_) | Statement::Do(_));
+	// These go before the #line directive, so that the statement starts on the line it names
+	if counted && safe {
+		emitter.line(format_args!("haumea_frame.line = {};", statement.span.line));
+	}
+	if counted {
+		coverage.count(emitter, statement.span, source_name);
+	}
+	compile_line_directive(emitter, statement.span, source_name);
+	match statement.kind {
+		// The value is worked out before the frame comes off the stack, as it can call functions.
+		// The brace takes the line of the #line directive, so the value needs one of its own.
+		Statement::Return(ref exp) if safe => {
+			emitter.line("{");
+			emitter.push_indent();
+			compile_line_directive(emitter, statement.span, source_name);
+			emitter.line(format_args!("long haumea_result = {};", compile_expression(exp, safe)));
+			compile_frame_end(emitter, safe);
+			emitter.line("return haumea_result;");
+			emitter.pop_indent();
+			emitter.line("}");
+		},
+		Statement::Return(ref exp) => {
+			emitter.line(format_args!("return {};", compile_expression(exp, safe)));
+		},
+		Statement::Do(ref block) => {
+			emitter.line("{");
+			emitter.push_indent();
 			for sub_statement in block {
-				let sub = match Rc::try_unwrap(sub_statement) {
-					Ok(sub) => sub,
-					Err(_) => panic!("Could not compile!"),
-				};
-				compile_statement(&mut out, sub, indent+1);
+				compile_statement(emitter, sub_statement, source_name, safe, coverage, loops);
 			};
-			out.push_str(&format!("\n{:}}}\n", replicate(INDENT, indent)));
+			emitter.pop_indent();
+			emitter.line("}");
 		},
 		Statement::Call {
-			function: func,
-			arguments: args,
+			function: ref func,
+			arguments: ref args,
 		} => {
-			out.push_str(&format!("{:}{:}(", replicate(INDENT, indent), func));
-			let len = args.len();		
-			for (index, arg) in args.into_iter().enumerate() {
-				if index == len-1 {
-					out.push_str(&compile_expression(arg));
-				} else {
-					out.push_str(&format!("{:}, ", compile_expression(arg)));
-				}
-			}
-			out.push_str(");\n");
+			let args = args.iter().map(|arg| compile_expression(arg, safe)).collect::<Vec<_>>();
+			emitter.line(format_args!("{}({});", runtime::c_function_name(func), args.join(", ")));
+		},
+		// haumea starts a variable at 0, which C only does for static ones
+		Statement::Var(ref idents) => {
+			let idents = idents.iter().map(|ident| format!("{} = 0l", ident)).collect::<Vec<_>>();
+			emitter.line(format_args!("long {};", idents.join(", ")));
+		},
+		// Inline C goes in as it is, so the function's variables can be used in it
+		Statement::InlineC(ref c_source) => {
+			emitter.raw(c_source);
+		},
+		// C starts a static variable at 0, which is where haumea starts one
+		Statement::Persistent(ref ident) => {
+			emitter.line(format_args!("static long {};", ident));
+		},
+		Statement::Define(ref ident, ref expr) => {
+			emitter.line(format_args!("long {} = {};", ident, compile_expression(expr, safe)));
+		},
+		Statement::Set(ref ident, ref expr) => {
+			emitter.line(format_args!("{} = {};", ident, compile_expression(expr, safe)));
 		},
-		Statement::Var(ident) => {
-			out.push_str(&format!("{:}long {:};\n", replicate(INDENT, indent), ident));
+		Statement::Change(ref ident, parser::Expression::UnaryOp { operator: parser::Operator::Sub, ref expression }) => {
+			emitter.line(format_args!("{} -= {};", ident, compile_expression(expression, safe)));
 		},
-		Statement::Set(ident, expr) => {
-			out.push_str(&format!("{:}{:} = {:};\n", 
-			                      replicate(INDENT, indent), 
-			                      ident,
-							      compile_expression(expr)
-							  ));
+		// Which is what a decrease becomes once the optimizer has folded it
+		Statement::Change(ref ident, parser::Expression::Integer(i)) if i < 0 => {
+			emitter.line(format_args!("{} -= {}l;", ident, -i64::from(i)));
 		},
-		Statement::Change(ident, expr) => {
-			out.push_str(&format!("{:}{:} += {:};\n", 
-			                      replicate(INDENT, indent), 
-			                      ident,
-							      compile_expression(expr)
-							  ));
+		Statement::Change(ref ident, ref expr) => {
+			emitter.line(format_args!("{} += {};", ident, compile_expression(expr, safe)));
+		},
+		// Each if in the chain is counted in coverage, so then the chain stays as it is
+		Statement::If { .. } if !coverage.enabled && switch_cases(statement).is_some() => {
+			let (variable, cases, default) = switch_cases(statement).expect("the chain was just found");
+			emitter.line(format_args!("switch ({})", variable));
+			emitter.line("{");
+			for (value, span, clause) in cases {
+				compile_line_directive(emitter, span, source_name);
+				emitter.line(format_args!("case {:?}l:", value));
+				compile_case(emitter, clause, source_name, safe, coverage, loops);
+			}
+			if let Some(clause) = default {
+				emitter.line("default:");
+				compile_case(emitter, clause, source_name, safe, coverage, loops);
+			}
+			emitter.line("}");
 		},
 		Statement::If {
-			cond,
-			if_clause,
-			else_clause,
-		} => {	
-			out.push_str(&format!("{:}if ", replicate(INDENT, indent)));
-			out.push_str(&format!(" {:} ", compile_expression(cond)));
-			let if_clause = match Rc::try_unwrap(if_clause) {
-				Ok(if_clause) => if_clause,
-				Err(_) => panic!("Could not compile!"),
-			};
-			compile_statement(&mut out, if_clause, indent+1);
-			let else_clause = match Rc::try_unwrap(else_clause) {
-				Ok(else_clause) => else_clause,
-				Err(_) => panic!("Could not compile!"),
-			};
-			if let Some(else_) = else_clause {
-				out.push_str(&format!("{:}else ", replicate(INDENT, indent)));
-				compile_statement(&mut out, else_, indent+1);
+			ref cond,
+			ref if_clause,
+			ref else_clause,
+			ref attributes,
+		} => {
+			// Operations are compiled in brackets already, but C needs them around anything else too
+			let cond = compile_expression(cond, safe);
+			if let Some(hint) = branch_hint(attributes) {
+				emitter.line(format_args!("if (haumea_{}({}))", hint, cond));
+			} else if cond.starts_with('(') {
+				emitter.line(format_args!("if {}", cond));
+			} else {
+				emitter.line(format_args!("if ({})", cond));
+			}
+			compile_clause(emitter, if_clause, source_name, safe, coverage, loops);
+			if let Some(ref else_) = *else_clause {
+				emitter.line("else");
+				compile_clause(emitter, else_, source_name, safe, coverage, loops);
+			}
+		},
+		// Like a counter, the temporary has digits in its name, so it can't hide one of the variables
+		Statement::Swap(ref first, ref second) => {
+			let temporary = format!("haumea_swap_{}_{}", statement.span.line, statement.span.column);
+			emitter.line("{");
+			emitter.push_indent();
+			emitter.line(format_args!("long {} = {};", temporary, first));
+			emitter.line(format_args!("{} = {};", first, second));
+			emitter.line(format_args!("{} = {};", second, temporary));
+			emitter.pop_indent();
+			emitter.line("}");
+		},
+		// The counter has digits in its name, which haumea's identifiers can't, so it can't hide one
+		Statement::Repeat {
+			ref count,
+			ref label,
+			ref body,
+		} => {
+			let counter = format!("haumea_repeat_{}_{}", statement.span.line, statement.span.column);
+			emitter.line(format_args!("for (long {0} = {1}; {0} > 0; {0}--)", counter, compile_expression(count, safe)));
+			match *label {
+				Some(ref label) => {
+					loops.push((label, statement.span));
+					compile_clause(emitter, body, source_name, safe, coverage, loops);
+					loops.pop();
+					// A label has to be followed by a statement, so it gets an empty one
+					emitter.line(format_args!("{}: ;", c_break_label(label, statement.span)));
+				},
+				None => compile_clause(emitter, body, source_name, safe, coverage, loops),
+			}
+		},
+		// The checker makes sure that the loop is there
+		Statement::Break(ref label) => {
+			if let Some(&(_, span)) = loops.iter().rev().find(|&&(name, _)| name == label) {
+				emitter.line(format_args!("goto {};", c_break_label(label, span)));
 			}
 		},
 	}
 }
 
-fn compile_expression(expr: parser::Expression) -> String {
+/// Compiles the then or else clause of an if, or the body of a repeat, indented under it
+///
+/// With coverage or --safe, a clause that isn't a block is put in one, so that its count
+/// and the line of the frame are only made when it runs. So is a repeat with a label,
+/// which is followed by the label that break jumps to.
+fn compile_clause<'a, W: Write>(emitter: &mut CEmitter<W>, clause: &'a parser::Statement,
+                                source_name: &str, safe: bool, coverage: &mut Coverage,
+                                loops: &mut Vec<(&'a str, Span)>) {
+	let block = match clause.kind {
+		parser::StatementKind::Do(_) => false,
+		parser::StatementKind::Repeat { label: Some(_), .. } => true,
+		_ => coverage.enabled || safe,
+	};
+	if block {
+		emitter.line("{");
+	}
+	emitter.push_indent();
+	compile_statement(emitter, clause, source_name, safe, coverage, loops);
+	emitter.pop_indent();
+	if block {
+		emitter.line("}");
+	}
+}
+
+/// Returns likely or unlikely if attributes, which are those of an if, say that its then clause is
+///
+/// The checker makes sure that an if has at most one of them
+fn branch_hint(attributes: &[parser::Attribute]) -> Option<&str> {
+	attributes.iter().map(|attribute| &attribute.name[..]).find(|&name| name == "likely" || name == "unlikely")
+}
+
+/// Returns true if any of the ifs in files are marked @likely or @unlikely, so the C needs the hints
+fn has_branch_hints(files: &[parser::SourceFile]) -> bool {
+	fn hinted(statement: &parser::Statement) -> bool {
+		use parser::StatementKind;
+
+		match statement.kind {
+			StatementKind::If { ref if_clause, ref else_clause, ref attributes, .. } => {
+				branch_hint(attributes).is_some() || hinted(if_clause) || else_clause.as_ref().is_some_and(|clause| hinted(clause))
+			},
+			StatementKind::Repeat { ref body, .. } => hinted(body),
+			StatementKind::Do(ref block) => block.iter().any(hinted),
+			_ => false,
+		}
+	}
+	files.iter().flat_map(|file| file.program.iter()).filter_map(|func| func.code.as_ref()).any(hinted)
+}
+
+/// Compiles the clause of a case of a switch, indented under it and followed by a break
+///
+/// It is always in a block, as a declaration can't come straight after a case in C
+fn compile_case<'a, W: Write>(emitter: &mut CEmitter<W>, clause: &'a parser::Statement,
+                              source_name: &str, safe: bool, coverage: &mut Coverage,
+                              loops: &mut Vec<(&'a str, Span)>) {
+	let block = !matches!(clause.kind, parser::StatementKind::Do(_));
+	emitter.push_indent();
+	if block {
+		emitter.line("{");
+		emitter.push_indent();
+	}
+	compile_statement(emitter, clause, source_name, safe, coverage, loops);
+	if block {
+		emitter.pop_indent();
+		emitter.line("}");
+	}
+	emitter.line("break;");
+	emitter.pop_indent();
+}
+
+/// The cases of a chain of ifs that compares one variable with different numbers, which is
+/// compiled into a switch: the variable, then each number with where its if is and what it
+/// runs, then what runs if the variable is none of them
+type SwitchCases<'a> = (&'a str, Vec<(i32, Span, &'a parser::Statement)>, Option<&'a parser::Statement>);
+
+/// Returns the cases of the chain of ifs that statement starts, if it compares one variable
+/// with at least SWITCH_CASES numbers, each different, like `if x = 1 then ... else if x = 2 then ...`
+///
+/// A number can be any expression that always has the same value, like `x = 2 * 8`, which is
+/// worked out with the optimizer's folding so that the case is the same at every level
+/// of optimization. An if that compares with anything else ends the chain.
+fn switch_cases(statement: &parser::Statement) -> Option<SwitchCases<'_>> {
+	use parser::{Expression, Operator, StatementKind};
+
+	let mut variable = None;
+	let mut cases: Vec<(i32, Span, &parser::Statement)> = vec![];
+	let mut next = Some(statement);
+	while let Some(statement) = next {
+		// A switch has nowhere to put the hint of an if
+		let (cond, if_clause, else_clause) = match statement.kind {
+			StatementKind::If { ref cond, ref if_clause, ref else_clause, ref attributes } if attributes.is_empty() => {
+				(cond, if_clause, else_clause)
+			},
+			_ => break,
+		};
+		let (name, value) = match *cond {
+			Expression::BinaryOp { operator: Operator::Equals, ref left, ref right } => match (&**left, &**right) {
+				(Expression::Ident(name), value) | (value, Expression::Ident(name)) => match optimizer::constant_value(value) {
+					Some(value) => (name, value),
+					None => break,
+				},
+				_ => break,
+			},
+			_ => break,
+		};
+		// A switch can't have a case twice, and the if tests the variable again
+		if *variable.get_or_insert(name) != name || cases.iter().any(|&(case, _, _)| case == value) {
+			break;
+		}
+		cases.push((value, statement.span, if_clause));
+		next = else_clause.as_ref().map(|clause| &**clause);
+	}
+	match variable {
+		Some(variable) if cases.len() >= SWITCH_CASES => Some((variable, cases, next)),
+		_ => None,
+	}
+}
+
+/// The statements of a program compiled with --instrument-coverage, each of which has a counter
+///
+/// When it isn't enabled no statements are counted
+struct Coverage {
+	enabled: bool,
+	/// The source file and line of each counted statement, in the order of their counters
+	statements: Vec<(String, usize)>,
+}
+
+impl Coverage {
+	fn new(enabled: bool) -> Coverage {
+		Coverage {
+			enabled,
+			statements: vec![],
+		}
+	}
+
+	/// Compiles a count of the statement at span in source_name, if coverage is enabled
+	fn count<W: Write>(&mut self, emitter: &mut CEmitter<W>, span: Span, source_name: &str) {
+		if self.enabled {
+			emitter.line(format_args!("haumea_coverage[{}]++;", self.statements.len()));
+			self.statements.push((source_name.to_string(), span.line));
+		}
+	}
+
+	/// Compiles the counters and where their statements are, followed by the function that reports them
+	fn compile_report<W: Write>(&self, emitter: &mut CEmitter<W>) {
+		if !self.enabled {
+			return;
+		}
+		emitter.blank_line();
+		emitter.line("/* Coverage report */");
+		// C has no arrays with no elements
+		emitter.line(format_args!("long haumea_coverage[{}];", self.statements.len().max(1)));
+		emitter.line(format_args!("static const long haumea_coverage_count = {};", self.statements.len()));
+		emitter.line("static const struct haumea_statement haumea_coverage_statements[] = {");
+		emitter.push_indent();
+		if self.statements.is_empty() {
+			emitter.line("{NULL, 0},");
+		}
+		for (file, line) in &self.statements {
+			emitter.line(format_args!("{{{}, {}}},", c_string_literal(file), line));
+		}
+		emitter.pop_indent();
+		emitter.line("};");
+		emitter.raw(COVERAGE_REPORT);
+	}
+}
+
+/// Compiles an expression
+///
+/// With safe, divisions check that they aren't by zero
+///
+/// Machine-generated programs can nest expressions far deeper than the stack could recurse,
+/// so the expression is compiled with a stack of the work left to do instead of recursing
+fn compile_expression(expr: &parser::Expression, safe: bool) -> String {
 	use parser::Expression;
-	
-	match expr {
-		Expression::Integer(i) => format!("{:?}l", i),
-		Expression::Ident(name) => name,
-		Expression::BinaryOp {
-			operator: op,
-			left,
-			right,
-		} => {
-			let lh = match Rc::try_unwrap(left) {
-			    Ok(lh) => lh,
-				Err(_) => panic!("Could not compile!"),
-			};
-			let rh = match Rc::try_unwrap(right) {
-			    Ok(rh) => rh,
-				Err(_) => panic!("Could not compile!"),
-			};
-			format!("({:} {:} {:})", 
-			         compile_expression(lh),
-				     get_c_name(op),
-				     compile_expression(rh)
-				   )
-		},
-		Expression::Call {
-			function: func,
-			arguments: args,
-		} => {
-			let mut out = String::new();
-			out.push_str(&format!("{:}(", func));
-			let len = args.len();		
-			for (index, arg) in args.into_iter().enumerate() {
-				let arg = match Rc::try_unwrap(arg) {
-				    Ok(arg) => arg,
-					Err(_) => panic!("Could not compile!"),
-				};
-				if index == len-1 {
-					out.push_str(&compile_expression(arg));
+
+	let mut out = String::new();
+	// The work is popped off the end, so the pieces of an expression are pushed in reverse
+	let mut work = vec![Work::Compile(expr)];
+	while let Some(next) = work.pop() {
+		let expr = match next {
+			Work::Write(c) => {
+				out.push_str(c);
+				continue;
+			},
+			Work::Compile(expr) => expr,
+		};
+		match *expr {
+			Expression::Integer(i) => write!(out, "{:?}l", i).unwrap(),
+			// A text is a pointer to its chars, which is kept in a long like any other value
+			Expression::Text(ref s) => write!(out, "((long){:})", c_string_literal(s)).unwrap(),
+			Expression::Ident(ref name) => out.push_str(name),
+			Expression::BinaryOp {
+				operator: ref op,
+				left: ref lh,
+				right: ref rh,
+			} => {
+				if let (&parser::Operator::Div, true) = (op, safe) {
+					out.push_str("haumea_divide(");
+					work.extend([Work::Write(", __FILE__, __LINE__)"), Work::Compile(rh),
+					             Work::Write(", "), Work::Compile(lh)]);
 				} else {
-					out.push_str(&format!("{:}, ", compile_expression(arg)));
+					out.push('(');
+					work.extend([Work::Write(")"), Work::Compile(rh), Work::Write(" "),
+					             Work::Write(get_c_name(op)), Work::Write(" "), Work::Compile(lh)]);
 				}
+			},
+			Expression::Call {
+				function: ref func,
+				arguments: ref args,
+			} => {
+				write!(out, "{:}(", runtime::c_function_name(func)).unwrap();
+				work.push(Work::Write(")"));
+				for (index, arg) in args.iter().enumerate().rev() {
+					work.push(Work::Compile(arg));
+					if index > 0 {
+						work.push(Work::Write(", "));
+					}
+				}
+			},
+			Expression::UnaryOp {
+				operator: ref op,
+				expression: ref exp,
+			} => {
+				write!(out, "({:}", get_c_name(op)).unwrap();
+				work.extend([Work::Write(")"), Work::Compile(exp)]);
 			}
-			out.push_str(")");
-			out
-		},
-		Expression::UnaryOp {
-			operator: op,
-			expression: exp,
-		} => {
-			let exp = match Rc::try_unwrap(exp) {
-			    Ok(exp) => exp,
-				Err(_) => panic!("Could not compile!"),
-			};
-			format!("({:}{:})", 
-				     get_c_name(op),
-				     compile_expression(exp)
-				   )
 		}
 	}
+	out
+}
+
+/// A piece of the work left to do in compiling an expression
+enum Work<'a> {
+	/// Compile an expression
+	Compile(&'a parser::Expression),
+	/// Write C that is already compiled
+	Write(&'static str),
 }
 
 // Utility functions
 
-/// Writes a newline to out
-fn write_newline(mut out: &mut String) {
-    out.push_str(NEW_LINE);
+/// Returns the C label just after the loop with label that starts at span
+///
+/// The loop's span is in it, so that loops with the same label in one function get different
+/// ones, and it has digits in it, so it can't be the same as a name in the program
+fn c_break_label(label: &str, span: Span) -> String {
+	format!("haumea_break_{}_{}_{}", label, span.line, span.column)
 }
 
-/// Replicates a &str t times
-fn replicate(s: &str, t: i32) -> String {
-	if t == 0 {
-		"".to_string()
-	} else {
-		replicate(s, t-1) + s
+/// Compiles a #line directive that maps the C code that follows back to span in source_name
+fn compile_line_directive<W: Write>(emitter: &mut CEmitter<W>, span: Span, source_name: &str) {
+	emitter.directive(format_args!("#line {} {}", span.line, c_string_literal(source_name)));
+}
+
+/// Returns s as a C string literal
+fn c_string_literal(s: &str) -> String {
+	let mut literal = String::from("\"");
+	for c in s.chars() {
+		match c {
+			'"' => literal.push_str("\\\""),
+			'\\' => literal.push_str("\\\\"),
+			'\n' => literal.push_str("\\n"),
+			'\t' => literal.push_str("\\t"),
+			c if (c as u32) < 0x20 => literal.push_str(&format!("\\{:03o}", c as u32)),
+			_ => literal.push(c),
+		}
 	}
+	literal.push('"');
+	literal
 }
 
 /// Returns the C name of an operator
-fn get_c_name(op: parser::Operator) -> &'static str {
+fn get_c_name(op: &parser::Operator) -> &'static str {
 	use parser::Operator::*;
-	match op {
+	match *op {
 	    Add => "+",
 	    Sub => "-",
 	    Mul => "*",
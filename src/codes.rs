@@ -0,0 +1,501 @@
+/// src/codes.rs
+/// The codes of the errors and warnings that haumea reports, with the explanations that `haumea explain` prints
+use diagnostic::Diagnostic;
+
+/// Every code, with its explanation
+///
+/// The first line of an explanation says what the code is for. A code never changes its meaning,
+/// so one that isn't used any more stays here rather than being given to something else.
+/// Errors from the scanner are E00, from the parser E01, from the checker E02, and from running
+/// a program E03, and warnings start with W. Internal errors have no code, as they are bugs in haumea.
+pub const EXPLANATIONS: &[(&str, &str)] = &[
+    ("E0001", "\
+A character that can't start any token
+
+haumea only uses letters, digits, underscores, double quotes, # for comments, @ for
+attributes, and the operators + - * / = != < > <= >= ~ | & ( ) and the comma. Anything
+else is an error outside of a text or a comment.
+
+    to main do display(2 % 3) end
+
+There is no remainder operator, so it has to be worked out:
+
+    to main do display(2 - 2 / 3 * 3) end
+"),
+    ("E0002", "\
+A number that is too large
+
+Number literals have to fit in 32 bits, so the largest is 2147483647.
+
+    to main do display(3000000000) end
+
+A larger number can be made with arithmetic, as values are longs:
+
+    to main do display(3 * 1000000000) end
+"),
+    ("E0003", "\
+A text with no closing quote
+
+Texts have to end with a double quote on the same line as they start.
+
+    to main do display_text(\"hello) end
+
+Close the text:
+
+    to main do display_text(\"hello\") end
+"),
+    ("E0004", "\
+An escape in a text that haumea doesn't know
+
+The escapes are \\n, \\t, \\\" and \\\\. A backslash followed by anything else is an error.
+
+    to main do display_text(\"C:\\temp\") end
+
+A backslash is written twice:
+
+    to main do display_text(\"C:\\\\temp\") end
+"),
+    ("E0005", "\
+A block of inline C with no closing brace
+
+The C in c { ... } goes on until the brace that matches the first one.
+
+    to main do c { putchar('x'); end
+
+Close the block:
+
+    to main do c { putchar('x'); } end
+"),
+    ("E0101", "\
+A token that isn't what had to come next
+
+The message says what haumea was expecting and what it found instead. This is often a
+missing end, with, or closing bracket.
+
+    to main do
+        display(1
+    end
+
+Add what is missing:
+
+    to main do
+        display(1)
+    end
+"),
+    ("E0102", "\
+A program that is nested too deeply
+
+Blocks, statements and brackets can only be nested so deep in each other, so that the
+compiler doesn't run out of stack.
+
+Split the deeply nested code into functions, or work values out a piece at a time in variables.
+The limit is 200 unless it is changed with --max-nesting, which a compiler run as a service can
+lower to bound what a program can take, or a program that has to nest deeper can raise.
+"),
+    ("E0103", "\
+A name that is missing
+
+Functions, parameters, variables and labels need names, which are made of letters and
+underscores. Keywords like if and end can't be names.
+
+    to do display(1) end
+
+Give the function a name:
+
+    to main do display(1) end
+"),
+    ("E0104", "\
+An include that isn't followed by the name of a header
+
+The header goes in double quotes, like in C.
+
+    include stdio.h
+
+Quote it:
+
+    include \"stdio.h\"
+"),
+    ("E0105", "\
+Something that can't start a statement
+
+A statement starts with a keyword like return, set, if, or variable, or is a call of a
+function. An expression on its own isn't a statement.
+
+    to main do
+        1 + 2
+    end
+
+Do something with the value:
+
+    to main do
+        display(1 + 2)
+    end
+"),
+    ("E0106", "\
+An expression that is missing
+
+Something like a number, a text, a variable or a call has to come here.
+
+    to main do display(1 + ) end
+
+Finish the expression:
+
+    to main do display(1 + 2) end
+"),
+    ("E0201", "\
+A variable that is used without being declared
+
+A variable has to be declared with variable before it is set, changed, or used, in the
+block it is in or one around it. Variables in other functions can't be used.
+
+    to main do
+        set total to 1
+    end
+
+Declare it first:
+
+    to main do
+        variable total
+        set total to 1
+    end
+"),
+    ("E0202", "\
+A call of a function that isn't defined
+
+The function has to be defined with to in one of the files of the program, be declared
+as an external function, or be one of the builtins.
+
+    to main do dispaly(1) end
+
+Check the name of the function:
+
+    to main do display(1) end
+"),
+    ("E0203", "\
+A call with the wrong number of arguments
+
+A function has to be given exactly as many arguments as it has parameters.
+
+    to add with (a, b) do return a + b end
+    to main do display(add(1)) end
+
+Give it all of them:
+
+    to add with (a, b) do return a + b end
+    to main do display(add(1, 2)) end
+"),
+    ("E0204", "\
+A function that is defined more than once
+
+Every function has to have a name of its own, in all of the files of a program.
+
+    to helper do return 1 end
+    to helper do return 2 end
+
+Rename or remove one of them.
+"),
+    ("E0205", "\
+A main that is an external function
+
+main is where the program starts, so it has to be written in haumea.
+
+    external function main
+
+Define it instead:
+
+    to main do display(1) end
+"),
+    ("E0206", "\
+Inline C in a program compiled without --allow-inline-c
+
+Blocks of C can do anything, so they have to be allowed on the command line.
+
+    to main do c { putchar('x'); } end
+
+Compile with haumea build --allow-inline-c, or write the code in haumea.
+"),
+    ("E0207", "\
+A break out of a loop that it isn't in
+
+break out of <label> leaves the loop with that label, which has to be around the break in
+the same function.
+
+    repeat 3 times as rows do display(1) end
+    break out of rows
+
+Move the break into the loop:
+
+    repeat 3 times as rows do
+        display(1)
+        break out of rows
+    end
+"),
+    ("E0208", "\
+A variable whose starting value uses a variable of the same name
+
+In variable x is <value>, the value can't use x, as in C it would be the new variable,
+which has no value yet.
+
+    variable x is 1
+    do
+        variable x is x + 1
+    end
+
+Use a different name for one of them:
+
+    variable x is 1
+    do
+        variable y is x + 1
+    end
+"),
+    ("E0209", "\
+A number given to an external function for a text, or a text for a number
+
+The parameters of an external function declared with text take texts, and the others
+take numbers. 0 can be given for a text, as it is how C says that there is no text.
+
+    external function puts with (text s)
+    to main do puts(42) end
+
+Give it a text:
+
+    external function puts with (text s)
+    to main do puts(\"42\") end
+"),
+    ("E0210", "\
+A function parameter of an external function that isn't given a suitable function
+
+A parameter declared with function takes the name of a function written in haumea, for C
+to call, which has to take as many arguments as the parameter says.
+
+    external function each with (function f with (x))
+    to twice with (x, y) do return x + y end
+    to main do each(twice) end
+
+Give it a function that takes the right arguments:
+
+    external function each with (function f with (x))
+    to double with (x) do return x * 2 end
+    to main do each(double) end
+"),
+    ("E0211", "\
+A program with no main function
+
+A program starts by running main, so it has to define one.
+
+    to helper do return 1 end
+
+Add a main:
+
+    to helper do return 1 end
+    to main do display(helper()) end
+"),
+    ("E0212", "\
+A return outside of any function
+
+In the REPL, statements can be run on their own, but only a function can return.
+
+    > return 1
+
+Display the value instead:
+
+    > display(1)
+"),
+    ("E0213", "\
+An attribute that haumea doesn't know
+
+The attributes of functions are @inline, @test, @export, and @deprecated, which can be
+given a text that says what to use instead. An if can be marked @likely or @unlikely.
+
+    @tested
+    to adding_works do assert_equal(1 + 1, 2) end
+
+Check the name of the attribute:
+
+    @test
+    to adding_works do assert_equal(1 + 1, 2) end
+"),
+    ("E0214", "\
+An attribute that can't go on its function or if
+
+Only @deprecated can be given a text, and each attribute can only be given once. External
+functions are written in C, so they can only be deprecated, and a test is run without
+arguments, so it can't have parameters. An if can't be both @likely and @unlikely.
+
+    @test
+    to check_double with (n) do assert_equal(n * 2, n + n) end
+
+Give the test its values itself:
+
+    @test
+    to check_double do assert_equal(21 * 2, 21 + 21) end
+"),
+    ("E0301", "\
+Too many calls inside each other
+
+The interpreter only lets functions call each other so deep, which usually means that a
+recursive function never stops calling itself.
+
+    to forever with (n) do return forever(n + 1) end
+
+Give the recursion a case that stops it:
+
+    to count with (n) do
+        if n = 0 then return 0
+        return count(n - 1)
+    end
+"),
+    ("E0302", "\
+A call of an external function in the interpreter
+
+External functions are written in C, so only compiled programs can call them.
+
+Compile the program with haumea build or haumea run instead of running it in the REPL.
+"),
+    ("E0303", "\
+Inline C in the interpreter
+
+Blocks of C can only run in compiled programs.
+
+Compile the program with haumea build or haumea run instead of running it in the REPL.
+"),
+    ("E0304", "\
+A failed assertion
+
+assert_equal(left, right) stops the program when left isn't equal to right, and in a
+test, fails the test.
+
+    assert_equal(1 + 1, 3)
+
+Either the code or the assertion is wrong, and one of them has to be fixed.
+"),
+    ("E0305", "\
+A file or the output that couldn't be read or written
+
+The message has the reason that the system gave, like a full disk.
+"),
+    ("E0306", "\
+A file used in a way that it wasn't opened for, or that isn't open
+
+read_line needs a file opened with \"r\", write_line one opened with \"w\" or \"a\", and a
+file can't be used after it is closed. open returns 0 for a file that can't be opened,
+which has to be checked for.
+
+    variable file is open(\"out.txt\", \"r\")
+    write_line(file, \"hi\")
+
+Open it for writing:
+
+    variable file is open(\"out.txt\", \"w\")
+    write_line(file, \"hi\")
+"),
+    ("E0307", "\
+A number used as a text
+
+Every value is a number, and the builtins that take texts can only be given numbers that
+are texts, like those written in quotes or returned by other builtins.
+
+    display_text(42)
+
+Make a text from the number:
+
+    display_text(to_text(42))
+"),
+    ("E0308", "\
+A division by zero
+
+Dividing by 0 stops the program in the interpreter, and in programs compiled with --safe.
+
+    display(10 / 0)
+
+Check the divisor first:
+
+    if n != 0 then display(10 / n)
+"),
+    ("W0001", "\
+A call on the right of and or or, which might not happen
+
+and only works out its right side if its left side is true, and or only if its left
+side is false, so a call there isn't always made. This is the lint short-circuit-call, which
+is only reported with -W short-circuit-call, or -D to make it an error.
+
+    if ready() and record(1) then display(1)
+
+Make the call before, if it has to happen:
+
+    variable recorded is record(1)
+    if ready() and recorded then display(1)
+"),
+    ("W0002", "\
+A variable that is never used
+
+A variable that is declared but whose value is never worked out is usually a mistake, like
+a misspelling of another variable, or left over from code that was removed. Setting it
+doesn't count as using it. This is the lint unused-variable.
+
+    variable total is 0
+    variable count
+    set count to 3
+    display(total)
+
+Remove it, or use it:
+
+    variable total is 0
+    display(total)
+"),
+    ("W0003", "\
+A variable that has the same name as one around it
+
+While it is declared, the name means the new variable, so the one outside can't be set or
+used, which is easy to forget. This is the lint shadowing.
+
+    to scale with (n) do
+        variable n is 10
+        return n * 2
+    end
+
+Give it a name of its own:
+
+    to scale with (n) do
+        variable factor is 10
+        return n * factor
+    end
+"),
+    ("W0004", "\
+A call of a function that is marked @deprecated
+
+A function is deprecated when it is going to be removed, and the text given to
+@deprecated, if there is one, says what to use instead. Calls in functions that are
+deprecated themselves don't get warnings. This is the lint deprecated, which warns unless
+it is allowed with -A deprecated.
+
+    @deprecated(\"use add\")
+    to plus with (a, b) is a + b
+    to main do display(plus(1, 2)) end
+
+Call what replaces it:
+
+    to add with (a, b) is a + b
+    to main do display(add(1, 2)) end
+"),
+];
+
+/// Returns the explanation of code, like E0201, or None if there is no such code
+///
+/// # Examples
+/// ```
+/// # use haumea::codes;
+/// assert!(codes::explain("E0201").unwrap().starts_with("A variable that is used without being declared\n"));
+/// assert_eq!(codes::explain("e0201"), codes::explain("E0201"));
+/// assert_eq!(codes::explain("E9999"), None);
+/// ```
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS.iter().find(|&&(known, _)| known.eq_ignore_ascii_case(code)).map(|&(_, explanation)| explanation)
+}
+
+impl Diagnostic {
+    /// Returns the explanation of the code of this Diagnostic, if it has one
+    pub fn explanation(&self) -> Option<&'static str> {
+        self.code.and_then(explain)
+    }
+}
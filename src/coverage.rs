@@ -0,0 +1,77 @@
+/// src/coverage.rs
+/// Reads the coverage reports of programs compiled with --instrument-coverage
+use std::collections::BTreeMap;
+
+/// The environment variable that names the file a program writes its coverage report to
+pub const FILE_VARIABLE: &str = "HAUMEA_COVERAGE";
+
+/// The file that a program writes its coverage report to if FILE_VARIABLE isn't set
+pub const DEFAULT_FILE: &str = "haumea-coverage.txt";
+
+/// How many times the statements on a line of a haumea program ran
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineCoverage {
+    /// The name of the source file that the line is in
+    pub file: String,
+    /// The number of the line, counting from 1
+    pub line: usize,
+    /// How many times the statement on the line that ran the most ran
+    pub count: i64,
+}
+
+/// Parses a coverage report into the lines of the program, sorted by file and then line
+///
+/// Each line of a report is `file:line count` for a statement. A line with several statements
+/// counts as run as often as the one that ran the most, so a line counts as run
+/// if any of its statements did.
+///
+/// # Examples
+/// ```
+/// # use haumea::coverage::{parse_report, LineCoverage};
+/// let lines = parse_report("main.hm:3 2\nmain.hm:1 1\nmain.hm:3 0\n").unwrap();
+/// assert_eq!(lines, vec![
+///     LineCoverage { file: "main.hm".to_string(), line: 1, count: 1 },
+///     LineCoverage { file: "main.hm".to_string(), line: 3, count: 2 },
+/// ]);
+/// assert!(parse_report("main.hm 2").is_err());
+/// ```
+pub fn parse_report(report: &str) -> Result<Vec<LineCoverage>, String> {
+    let mut counts = BTreeMap::new();
+    for entry in report.lines().filter(|entry| !entry.is_empty()) {
+        let malformed = || format!("The coverage report has a malformed line: {}", entry);
+        let mut parts = entry.rsplitn(2, ' ');
+        let count = parts.next().and_then(|count| count.parse::<i64>().ok()).ok_or_else(malformed)?;
+        // File names can have colons and spaces in them, but the line number and count can't
+        let mut parts = parts.next().ok_or_else(malformed)?.rsplitn(2, ':');
+        let line = parts.next().and_then(|line| line.parse::<usize>().ok()).ok_or_else(malformed)?;
+        let file = parts.next().ok_or_else(malformed)?;
+        let most = counts.entry((file.to_string(), line)).or_insert(count);
+        *most = (*most).max(count);
+    }
+    Ok(counts.into_iter().map(|((file, line), count)| LineCoverage { file, line, count }).collect())
+}
+
+/// Summarises the coverage of lines, saying how many of them ran and which didn't
+///
+/// # Examples
+/// ```
+/// # use haumea::coverage::{parse_report, summary};
+/// let lines = parse_report("main.hm:1 1\nmain.hm:2 0\nmain.hm:4 0\nutil.hm:1 3\n").unwrap();
+/// assert_eq!(summary(&lines), "coverage: 2 of 4 lines run (50.0%)\nnot run: main.hm:2, 4\n");
+/// ```
+pub fn summary(lines: &[LineCoverage]) -> String {
+    let run = lines.iter().filter(|line| line.count > 0).count();
+    let percentage = if lines.is_empty() { 100.0 } else { run as f64 * 100.0 / lines.len() as f64 };
+    let mut out = format!("coverage: {} of {} lines run ({:.1}%)\n", run, lines.len(), percentage);
+    let mut not_run: Vec<(&str, Vec<String>)> = vec![];
+    for line in lines.iter().filter(|line| line.count == 0) {
+        match not_run.last_mut() {
+            Some(&mut (file, ref mut numbers)) if file == line.file => numbers.push(line.line.to_string()),
+            _ => not_run.push((&line.file, vec![line.line.to_string()])),
+        }
+    }
+    for (file, numbers) in not_run {
+        out.push_str(&format!("not run: {}:{}\n", file, numbers.join(", ")));
+    }
+    out
+}
@@ -0,0 +1,513 @@
+/// src/debug.rs
+/// A Debug Adapter Protocol server, so that editors can debug haumea programs with gdb
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use haumea::json::Json;
+use haumea::lsp::{read_message, write_message};
+use haumea::mi::{self, Record, field, number_field};
+use haumea::options::{CompilerOptions, OptLevel};
+use haumea::toolchain;
+
+/// The debugger that runs the program, unless the launch request names another one
+///
+/// It is driven through its machine interface (MI), so it can be any debugger that has one
+const DEFAULT_DEBUGGER: &str = "gdb";
+/// The id of the thread of the program, which is the only one it has
+const THREAD_ID: i64 = 1;
+
+/// Runs the debug adapter on stdin and stdout until the client disconnects
+///
+/// The program named by the launch request is compiled without optimizations and with
+/// debugging information, and run in the debugger. The #line directives in its C are what let
+/// the debugger put breakpoints on the lines of the haumea source and step through them.
+pub fn serve() -> Result<(), String> {
+    let (sender, inputs) = mpsc::channel();
+    let client = sender.clone();
+    // The client and the debugger can both say something at any time, so each is read on a thread
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        loop {
+            let message = read_message(&mut input);
+            let finished = !matches!(message, Ok(Some(_)));
+            if client.send(Input::Client(message)).is_err() || finished {
+                return;
+            }
+        }
+    });
+    let stdout = io::stdout();
+    let mut adapter = Adapter {
+        output: stdout.lock(),
+        seq: 0,
+        sender,
+        inputs,
+        pending: VecDeque::new(),
+        debugger: None,
+        token: 0,
+        stop_on_entry: false,
+        breakpoints: BTreeMap::new(),
+    };
+    let result = adapter.run();
+    adapter.quit();
+    result
+}
+
+/// Something that the adapter has to handle, from the client or from the debugger
+enum Input {
+    /// A message from the client, or None if it closed the connection
+    Client(Result<Option<Json>, String>),
+    /// A line that the debugger wrote to stdout, which is either an MI record or something
+    /// that the program wrote
+    Debugger(String),
+    /// A line that the debugger or the program wrote to stderr
+    Stderr(String),
+    /// The debugger closed its stdout, because it exited
+    DebuggerExited,
+}
+
+/// A debugger running the program
+struct Debugger {
+    child: Child,
+    /// Where MI commands are written to
+    stdin: ChildStdin,
+    /// The executable that the program was compiled into, which is removed when the debugger exits
+    executable: PathBuf,
+}
+
+/// The state of the debug adapter
+struct Adapter<W: Write> {
+    /// Where messages to the client are written to
+    output: W,
+    /// The seq of the last message sent to the client
+    seq: i64,
+    /// Sends inputs to the adapter, for the threads that read from the debugger
+    sender: Sender<Input>,
+    inputs: Receiver<Input>,
+    /// Inputs that came while the adapter was waiting for the result of an MI command
+    pending: VecDeque<Input>,
+    debugger: Option<Debugger>,
+    /// The token of the last MI command, which its result has too
+    token: u64,
+    /// Whether the program should stop at the start of main, from the launch request
+    stop_on_entry: bool,
+    /// The numbers of the debugger's breakpoints in each source file, by its path
+    breakpoints: BTreeMap<String, Vec<String>>,
+}
+
+impl<W: Write> Adapter<W> {
+    /// Handles inputs until the client disconnects
+    fn run(&mut self) -> Result<(), String> {
+        loop {
+            match self.next_input()? {
+                Input::Client(message) => {
+                    let message = match message? {
+                        Some(message) => message,
+                        None => return Err("The client closed the connection without disconnecting".to_string()),
+                    };
+                    if message.get("type").and_then(Json::as_str) != Some("request") {
+                        continue;
+                    }
+                    let command = message.get("command").and_then(Json::as_str).unwrap_or("").to_string();
+                    let arguments = message.get("arguments").cloned().unwrap_or(Json::Null);
+                    if command == "disconnect" {
+                        self.quit();
+                        return self.respond(&message, Ok(Json::Null));
+                    }
+                    let result = self.request(&command, &arguments);
+                    let launched = command == "launch" && result.is_ok();
+                    self.respond(&message, result)?;
+                    // Breakpoints can be set once the debugger has the program
+                    if launched {
+                        self.event("initialized", Json::Null)?;
+                    }
+                },
+                Input::Debugger(line) => self.record(&line)?,
+                Input::Stderr(line) => self.output("stderr", &format!("{}\n", line))?,
+                Input::DebuggerExited => {
+                    self.quit();
+                    self.event("terminated", Json::Null)?;
+                },
+            }
+        }
+    }
+
+    /// Returns the next input to handle, starting with the ones that came while waiting for a result
+    fn next_input(&mut self) -> Result<Input, String> {
+        match self.pending.pop_front() {
+            Some(input) => Ok(input),
+            None => self.inputs.recv().map_err(|_| "The debug adapter stopped getting input".to_string()),
+        }
+    }
+
+    /// Handles a request with arguments, returning the body of the response or why it failed
+    fn request(&mut self, command: &str, arguments: &Json) -> Result<Json, String> {
+        match command {
+            "initialize" => Ok(Json::object(vec![
+                ("supportsConfigurationDoneRequest", Json::from(true)),
+                ("supportsEvaluateForHovers", Json::from(true)),
+            ])),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            // There are no exceptions in haumea to break on
+            "setExceptionBreakpoints" => Ok(Json::Null),
+            "configurationDone" => {
+                let run = if self.stop_on_entry { "-exec-run --start" } else { "-exec-run" };
+                self.command(run).map(|_| Json::Null)
+            },
+            "threads" => Ok(Json::object(vec![
+                ("threads", Json::Array(vec![Json::object(vec![
+                    ("id", Json::from(THREAD_ID)),
+                    ("name", Json::from("main")),
+                ])])),
+            ])),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => {
+                let frame = arguments.get("frameId").and_then(Json::as_i64).ok_or("The request has no frameId")?;
+                Ok(Json::object(vec![
+                    ("scopes", Json::Array(vec![Json::object(vec![
+                        ("name", Json::from("Locals")),
+                        ("presentationHint", Json::from("locals")),
+                        ("variablesReference", Json::from(frame)),
+                        ("expensive", Json::from(false)),
+                    ])])),
+                ]))
+            },
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => self.command("-exec-continue").map(|_| Json::object(vec![
+                ("allThreadsContinued", Json::from(true)),
+            ])),
+            "next" => self.command("-exec-next").map(|_| Json::Null),
+            "stepIn" => self.command("-exec-step").map(|_| Json::Null),
+            "stepOut" => self.command("-exec-finish").map(|_| Json::Null),
+            "pause" => self.command("-exec-interrupt").map(|_| Json::Null),
+            _ => Err(format!("The debug adapter doesn't support {} requests", command)),
+        }
+    }
+
+    /// Compiles the program in the arguments of a launch request and loads it into the debugger
+    fn launch(&mut self, arguments: &Json) -> Result<Json, String> {
+        if self.debugger.is_some() {
+            return Err("The program has already been launched".to_string());
+        }
+        let program = arguments.get("program").and_then(Json::as_str).ok_or("The launch request has no program")?;
+        // The #line directives have the whole path, so the debugger can find the source wherever it runs
+        let program = fs::canonicalize(program).map_err(|e| format!("Could not find {}: {}", program, e))?;
+        let mut options = CompilerOptions::new(program.clone());
+        options.opt_level = OptLevel::O0;
+        options.cflags = vec!["-g".to_string()];
+        let c = super::compile(&options).map_err(|error| error.to_string())?;
+        let executable = toolchain::temp_executable(&program);
+        toolchain::build_executable(&c, &executable, &options)?;
+        let debugger = arguments.get("debugger").and_then(Json::as_str).unwrap_or(DEFAULT_DEBUGGER);
+        let child = Command::new(debugger)
+            .arg("--interpreter=mi2")
+            .arg("--quiet")
+            .arg(&executable)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = fs::remove_file(&executable);
+                return Err(format!("Could not run the debugger ({}): {}", debugger, e));
+            },
+        };
+        let stdin = child.stdin.take().expect("The debugger's stdin is piped");
+        forward(child.stdout.take().expect("The debugger's stdout is piped"), self.sender.clone(),
+                Input::Debugger, Some(Input::DebuggerExited));
+        forward(child.stderr.take().expect("The debugger's stderr is piped"), self.sender.clone(),
+                Input::Stderr, None);
+        self.debugger = Some(Debugger { child, stdin, executable });
+        self.stop_on_entry = arguments.get("stopOnEntry") == Some(&Json::Bool(true));
+        // Without this the debugger can't be told anything, like to pause, while the program runs
+        self.command("-gdb-set mi-async on")?;
+        let program_arguments = arguments.get("args").and_then(Json::as_array).unwrap_or(&[]);
+        let mut words = program_arguments.iter().filter_map(Json::as_str).map(shell_quote).collect::<Vec<_>>();
+        // The debugger's stdin is where the commands come from, so the program can't share it
+        words.push("< /dev/null".to_string());
+        self.command(&format!("-exec-arguments {}", words.join(" ")))?;
+        Ok(Json::Null)
+    }
+
+    /// Replaces the breakpoints in a source file with the ones in the arguments of a setBreakpoints request
+    fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let path = arguments.get("source").and_then(|source| source.get("path")).and_then(Json::as_str)
+            .ok_or("The breakpoints have no source path")?
+            .to_string();
+        let lines = arguments.get("breakpoints").and_then(Json::as_array).unwrap_or(&[]).iter()
+            .filter_map(|breakpoint| breakpoint.get("line").and_then(Json::as_i64))
+            .collect::<Vec<_>>();
+        if let Some(numbers) = self.breakpoints.remove(&path) {
+            if !numbers.is_empty() {
+                self.command(&format!("-break-delete {}", numbers.join(" ")))?;
+            }
+        }
+        let mut numbers = vec![];
+        let mut breakpoints = vec![];
+        for line in lines {
+            let breakpoint = match self.command(&format!("-break-insert {}", mi::quote(&format!("{}:{}", path, line)))) {
+                Ok(results) => {
+                    let number = field(&results, &["bkpt", "number"]).unwrap_or("").to_string();
+                    // A breakpoint on a line without code is moved to the next line that has some
+                    let line = number_field(&results, &["bkpt", "line"]).unwrap_or(line);
+                    let breakpoint = Json::object(vec![
+                        ("id", Json::from(number.parse::<i64>().unwrap_or(0))),
+                        ("verified", Json::from(true)),
+                        ("line", Json::from(line)),
+                    ]);
+                    numbers.push(number);
+                    breakpoint
+                },
+                Err(message) => Json::object(vec![
+                    ("verified", Json::from(false)),
+                    ("line", Json::from(line)),
+                    ("message", Json::from(message)),
+                ]),
+            };
+            breakpoints.push(breakpoint);
+        }
+        self.breakpoints.insert(path, numbers);
+        Ok(Json::object(vec![("breakpoints", Json::Array(breakpoints))]))
+    }
+
+    /// Returns the body of the response to a stackTrace request, with the frames of the stopped program
+    ///
+    /// The id of each frame is one more than its level, so that none of them are 0, which the
+    /// protocol uses for nothing. The frames of the functions in the prolog, like display,
+    /// have no haumea source, so they are shown as less important.
+    fn stack_trace(&mut self) -> Result<Json, String> {
+        let results = self.command("-stack-list-frames")?;
+        let frames = results.get("stack").and_then(Json::as_array).unwrap_or(&[]).iter().map(|frame| {
+            let mut members = vec![
+                ("id", Json::from(number_field(frame, &["level"]).unwrap_or(0) + 1)),
+                ("name", Json::from(field(frame, &["func"]).unwrap_or("??"))),
+            ];
+            match field(frame, &["fullname"]) {
+                Some(path) if field(frame, &["line"]).is_some() => {
+                    let name = path.rsplit('/').next().unwrap_or(path);
+                    members.push(("source", Json::object(vec![("name", Json::from(name)), ("path", Json::from(path))])));
+                    members.push(("line", Json::from(number_field(frame, &["line"]).unwrap_or(0))));
+                    members.push(("column", Json::from(1)));
+                },
+                _ => {
+                    members.push(("line", Json::from(0)));
+                    members.push(("column", Json::from(0)));
+                    members.push(("presentationHint", Json::from("subtle")));
+                },
+            }
+            Json::object(members)
+        }).collect::<Vec<_>>();
+        let total = frames.len();
+        Ok(Json::object(vec![("stackFrames", Json::Array(frames)), ("totalFrames", Json::from(total))]))
+    }
+
+    /// Returns the body of the response to a variables request, with the parameters and the
+    /// variables of the frame whose locals are its variablesReference
+    fn variables(&mut self, arguments: &Json) -> Result<Json, String> {
+        let frame = arguments.get("variablesReference").and_then(Json::as_i64).ok_or("The request has no variablesReference")?;
+        let results = self.command(&format!("-stack-list-variables --thread {} --frame {} --simple-values", THREAD_ID, frame - 1))?;
+        let variables = results.get("variables").and_then(Json::as_array).unwrap_or(&[]).iter().map(|variable| {
+            Json::object(vec![
+                ("name", Json::from(field(variable, &["name"]).unwrap_or(""))),
+                ("value", Json::from(field(variable, &["value"]).unwrap_or(""))),
+                ("type", Json::from(field(variable, &["type"]).unwrap_or(""))),
+                // Numbers and texts have nothing in them to expand
+                ("variablesReference", Json::from(0)),
+            ])
+        }).collect();
+        Ok(Json::object(vec![("variables", Json::Array(variables))]))
+    }
+
+    /// Returns the body of the response to an evaluate request, which works out an expression in
+    /// a frame, for watches and hovers
+    fn evaluate(&mut self, arguments: &Json) -> Result<Json, String> {
+        let expression = arguments.get("expression").and_then(Json::as_str).ok_or("The request has no expression")?;
+        let frame = match arguments.get("frameId").and_then(Json::as_i64) {
+            Some(frame) => format!("--thread {} --frame {} ", THREAD_ID, frame - 1),
+            None => String::new(),
+        };
+        let results = self.command(&format!("-data-evaluate-expression {}{}", frame, mi::quote(expression)))?;
+        Ok(Json::object(vec![
+            ("result", Json::from(field(&results, &["value"]).unwrap_or(""))),
+            ("variablesReference", Json::from(0)),
+        ]))
+    }
+
+    /// Sends an MI command to the debugger and waits for its result, returning its results or
+    /// the message that it failed with
+    ///
+    /// What the debugger says in the meantime is handled as it comes, and what the client says
+    /// is kept until afterwards.
+    fn command(&mut self, command: &str) -> Result<Json, String> {
+        self.token += 1;
+        let token = self.token;
+        match self.debugger {
+            Some(ref mut debugger) => writeln!(debugger.stdin, "{}{}", token, command)
+                .and_then(|_| debugger.stdin.flush())
+                .map_err(|e| format!("Could not send a command to the debugger: {}", e))?,
+            None => return Err("The program hasn't been launched".to_string()),
+        }
+        loop {
+            let line = match self.inputs.recv() {
+                Ok(Input::Debugger(line)) => line,
+                Ok(Input::DebuggerExited) => {
+                    self.pending.push_back(Input::DebuggerExited);
+                    return Err("The debugger exited".to_string());
+                },
+                Ok(input) => {
+                    self.pending.push_back(input);
+                    continue;
+                },
+                Err(_) => return Err("The debug adapter stopped getting input".to_string()),
+            };
+            match Record::parse(&line) {
+                Record::Result { token: Some(result_token), ref class, ref results } if result_token == token => {
+                    return if class == "error" {
+                        Err(field(results, &["msg"]).unwrap_or("The debugger failed").to_string())
+                    } else {
+                        Ok(results.clone())
+                    };
+                },
+                _ => self.record(&line)?,
+            }
+        }
+    }
+
+    /// Handles a line that the debugger wrote, telling the client about anything that it needs to know
+    fn record(&mut self, line: &str) -> Result<(), String> {
+        match Record::parse(line) {
+            Record::Exec { ref class, ref results } if class == "stopped" => self.stopped(results),
+            Record::Stream('~', ref text) => self.output("console", text),
+            Record::Stream('@', ref text) => self.output("stdout", text),
+            Record::Program(ref text) => self.output("stdout", &format!("{}\n", text)),
+            // The results of commands are waited for by command, and the debugger's log is only
+            // the commands it was given and why they failed, which the responses say
+            Record::Result { .. } | Record::Exec { .. } | Record::Notify | Record::Stream(..) | Record::Prompt => Ok(()),
+        }
+    }
+
+    /// Tells the client that the program stopped, or that it exited, from the results of a *stopped record
+    fn stopped(&mut self, results: &Json) -> Result<(), String> {
+        let reason = field(results, &["reason"]).unwrap_or("");
+        let (reason, description) = match reason {
+            "exited-normally" | "exited" | "exited-signalled" => {
+                // The debugger writes the exit code in octal, and a program killed by a signal has
+                // none, so that is reported as a failure
+                let code = match field(results, &["exit-code"]) {
+                    Some(code) => i64::from_str_radix(code, 8).unwrap_or(1),
+                    None if reason == "exited-normally" => 0,
+                    None => 1,
+                };
+                self.event("exited", Json::object(vec![("exitCode", Json::from(code))]))?;
+                return self.event("terminated", Json::Null);
+            },
+            // The temporary breakpoint of -exec-run --start is deleted once it is hit
+            "breakpoint-hit" if field(results, &["disp"]) == Some("del") => ("entry", None),
+            "breakpoint-hit" => ("breakpoint", None),
+            "signal-received" if field(results, &["signal-name"]) == Some("SIGINT") => ("pause", None),
+            "signal-received" => ("exception", field(results, &["signal-meaning"]).or_else(|| field(results, &["signal-name"]))),
+            "end-stepping-range" | "function-finished" | "location-reached" => ("step", None),
+            _ => ("pause", None),
+        };
+        let mut body = vec![
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(THREAD_ID)),
+            ("allThreadsStopped", Json::from(true)),
+        ];
+        if let Some(description) = description {
+            body.push(("description", Json::from(description)));
+        }
+        self.event("stopped", Json::object(body))
+    }
+
+    /// Tells the client to show text in the output of category, like stdout or console
+    fn output(&mut self, category: &str, text: &str) -> Result<(), String> {
+        self.event("output", Json::object(vec![("category", Json::from(category)), ("output", Json::from(text))]))
+    }
+
+    /// Sends the response to request, with the body that it returned or the message that it failed with
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) -> Result<(), String> {
+        let mut members = vec![
+            ("type", Json::from("response")),
+            ("request_seq", request.get("seq").cloned().unwrap_or(Json::Null)),
+            ("command", request.get("command").cloned().unwrap_or(Json::Null)),
+        ];
+        match result {
+            Ok(body) => {
+                members.push(("success", Json::from(true)));
+                if body != Json::Null {
+                    members.push(("body", body));
+                }
+            },
+            Err(message) => {
+                members.push(("success", Json::from(false)));
+                members.push(("message", Json::from(message)));
+            },
+        }
+        self.send(members)
+    }
+
+    /// Sends an event to the client, with body unless it is null
+    fn event(&mut self, event: &str, body: Json) -> Result<(), String> {
+        let mut members = vec![("type", Json::from("event")), ("event", Json::from(event))];
+        if body != Json::Null {
+            members.push(("body", body));
+        }
+        self.send(members)
+    }
+
+    /// Sends a message with members to the client, giving it the next seq
+    fn send(&mut self, mut members: Vec<(&str, Json)>) -> Result<(), String> {
+        self.seq += 1;
+        members.insert(0, ("seq", Json::from(self.seq)));
+        write_message(&mut self.output, &Json::object(members))
+    }
+
+    /// Tells the debugger to exit, which kills the program if it is still running, and removes the executable
+    fn quit(&mut self) {
+        if let Some(mut debugger) = self.debugger.take() {
+            let _ = writeln!(debugger.stdin, "-gdb-exit").and_then(|_| debugger.stdin.flush());
+            drop(debugger.stdin);
+            let _ = debugger.child.wait();
+            let _ = fs::remove_file(&debugger.executable);
+        }
+    }
+}
+
+/// Reads lines from reader on a thread of its own, sending each of them to the adapter as the
+/// input that input makes of it, and then finished if there is one
+fn forward<R: Read + Send + 'static>(reader: R, sender: Sender<Input>, input: fn(String) -> Input,
+                                     finished: Option<Input>) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        loop {
+            let mut line = vec![];
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    // The program can write anything, even text that isn't UTF-8
+                    let line = String::from_utf8_lossy(&line).trim_end_matches(&['\r', '\n'][..]).to_string();
+                    if sender.send(input(line)).is_err() {
+                        return;
+                    }
+                },
+            }
+        }
+        if let Some(finished) = finished {
+            let _ = sender.send(finished);
+        }
+    });
+}
+
+/// Returns word quoted for the shell that the debugger starts the program with
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
@@ -0,0 +1,240 @@
+/// src/diagnostic.rs
+/// The errors reported by the haumea compiler
+use std::fmt;
+use json::Json;
+use scanner::Span;
+
+/// The phase of the compiler that found an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorKind {
+    /// The scanner read something that isn't a token
+    Lex,
+    /// The tokens don't form a valid program
+    Parse,
+    /// The program is well formed but doesn't make sense,
+    /// like calling a function that doesn't exist
+    Semantic,
+    /// The program went wrong while it was being interpreted, like dividing by zero
+    Runtime,
+    /// The compiler itself went wrong
+    Internal,
+}
+
+impl ErrorKind {
+    /// Returns the exit code that the haumea binary exits with for this kind of error
+    ///
+    /// Exit code 1 is used for all other failures, like files that can't be read,
+    /// and 2 for invalid command line arguments
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::diagnostic::ErrorKind;
+    /// assert_eq!(ErrorKind::Lex.exit_code(), 3);
+    /// assert_eq!(ErrorKind::Internal.exit_code(), 101);
+    /// ```
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            ErrorKind::Lex => 3,
+            ErrorKind::Parse => 4,
+            ErrorKind::Semantic => 5,
+            ErrorKind::Runtime => 6,
+            // The same exit code as a Rust panic, which is the usual cause of internal errors
+            ErrorKind::Internal => 101,
+        }
+    }
+
+    /// Returns the name of the kind, as it is written in JSON diagnostics
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ErrorKind::Lex => "lex",
+            ErrorKind::Parse => "parse",
+            ErrorKind::Semantic => "semantic",
+            ErrorKind::Runtime => "runtime",
+            ErrorKind::Internal => "internal",
+        }
+    }
+}
+
+/// Whether a Diagnostic stops the program from being compiled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The program can't be compiled
+    Error,
+    /// The program can be compiled, but probably doesn't do what was meant
+    Warning,
+}
+
+impl Severity {
+    /// Returns the name of the severity, which starts the message of a Diagnostic
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// An error or warning in a haumea program, along with where it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The phase that found the error
+    pub kind: ErrorKind,
+    /// Whether it is an error or a warning
+    pub severity: Severity,
+    /// The code that `haumea explain` explains the error with, like E0201
+    pub code: Option<&'static str>,
+    /// A description of the error
+    pub message: String,
+    /// Where the error is in the source, if it is anywhere in particular
+    pub span: Option<Span>,
+    /// The name of the source file the error is in, if it is known
+    pub file: Option<String>,
+    /// A suggestion of how to fix the error, if there is one
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    /// Constructs a new error at span
+    pub fn new<S: Into<String>>(kind: ErrorKind, message: S, span: Span) -> Diagnostic {
+        Diagnostic {
+            kind,
+            severity: Severity::Error,
+            code: None,
+            message: message.into(),
+            span: Some(span),
+            file: None,
+            help: None,
+        }
+    }
+
+    /// Constructs a new error about the whole program, which isn't anywhere in particular in it
+    pub fn of_program<S: Into<String>>(kind: ErrorKind, message: S) -> Diagnostic {
+        Diagnostic {
+            kind,
+            severity: Severity::Error,
+            code: None,
+            message: message.into(),
+            span: None,
+            file: None,
+            help: None,
+        }
+    }
+
+    /// Constructs a new warning at span
+    pub fn warning<S: Into<String>>(kind: ErrorKind, message: S, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::new(kind, message, span)
+        }
+    }
+
+    /// Returns this Diagnostic as an error, if it is a warning
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::diagnostic::{Diagnostic, ErrorKind, Severity};
+    /// # use haumea::scanner::Span;
+    /// let warning = Diagnostic::warning(ErrorKind::Semantic, "x is never used", Span { line: 2, column: 5 });
+    /// assert_eq!(warning.denied().severity, Severity::Error);
+    /// ```
+    pub fn denied(self) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, ..self }
+    }
+
+    /// Returns this Diagnostic with code as its code
+    pub fn with_code(mut self, code: &'static str) -> Diagnostic {
+        self.code = Some(code);
+        self
+    }
+
+    /// Returns this Diagnostic with help as its suggestion of how to fix it
+    pub fn with_help<S: Into<String>>(mut self, help: S) -> Diagnostic {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Returns this Diagnostic with its file set to file, unless it already has a file
+    pub fn in_file(mut self, file: &str) -> Diagnostic {
+        if self.file.is_none() {
+            self.file = Some(file.to_string());
+        }
+        self
+    }
+
+    /// Returns the Diagnostic as a JSON object, for `--message-format json`
+    ///
+    /// The members are always there, with null for a missing code or file. spans has where the
+    /// Diagnostic is, if it is anywhere, and suggestions has any help.
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::diagnostic::{Diagnostic, ErrorKind};
+    /// # use haumea::json::Json;
+    /// # use haumea::scanner::Span;
+    /// let d = Diagnostic::new(ErrorKind::Semantic, "The variable totl is not declared", Span { line: 3, column: 5 })
+    ///     .with_code("E0201")
+    ///     .with_help("did you mean 'total'?")
+    ///     .in_file("main.hm");
+    /// let json = d.to_json();
+    /// assert_eq!(json.get("code").and_then(Json::as_str), Some("E0201"));
+    /// assert_eq!(json.get("spans").unwrap().to_string(), r#"[{"line":3,"column":5}]"#);
+    /// assert_eq!(json.get("suggestions").unwrap().to_string(), r#"["did you mean 'total'?"]"#);
+    /// assert_eq!(Diagnostic { span: None, ..d }.to_json().get("spans").unwrap().to_string(), "[]");
+    /// ```
+    pub fn to_json(&self) -> Json {
+        let spans = self.span.iter().map(|span| {
+            Json::object(vec![("line", Json::from(span.line)), ("column", Json::from(span.column))])
+        }).collect::<Vec<_>>();
+        let suggestions = self.help.iter().map(|help| Json::from(&help[..])).collect::<Vec<_>>();
+        Json::object(vec![
+            ("severity", Json::from(self.severity.name())),
+            ("kind", Json::from(self.kind.name())),
+            ("code", Json::from(self.code)),
+            ("message", Json::from(&self.message[..])),
+            ("file", Json::from(self.file.as_ref().map(|file| &file[..]))),
+            ("spans", Json::from(spans)),
+            ("suggestions", Json::from(suggestions)),
+        ])
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    /// Formats the Diagnostic the way C compilers do, as `file:line:column: error: message`,
+    /// or with warning instead of error for a warning, and any help on a line of its own.
+    /// A code goes after the severity in brackets, like `error[E0201]`, as in rustc.
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::diagnostic::{Diagnostic, ErrorKind};
+    /// # use haumea::scanner::Span;
+    /// let d = Diagnostic::new(ErrorKind::Parse, "Expected an expression", Span { line: 3, column: 7 });
+    /// assert_eq!(d.to_string(), "3:7: error: Expected an expression");
+    /// assert_eq!(d.in_file("main.hm").to_string(), "main.hm:3:7: error: Expected an expression");
+    /// let w = Diagnostic::warning(ErrorKind::Semantic, "This might not run", Span { line: 1, column: 1 });
+    /// assert_eq!(w.to_string(), "1:1: warning: This might not run");
+    /// let w = w.with_help("run it first");
+    /// assert_eq!(w.to_string(), "1:1: warning: This might not run\nhelp: run it first");
+    /// let e = Diagnostic::new(ErrorKind::Semantic, "x is not declared", Span { line: 2, column: 1 }).with_code("E0201");
+    /// assert_eq!(e.to_string(), "2:1: error[E0201]: x is not declared");
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}:", file)?;
+        }
+        if let Some(span) = self.span {
+            write!(f, "{}:{}:", span.line, span.column)?;
+        }
+        if self.file.is_some() || self.span.is_some() {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.severity.name())?;
+        if let Some(code) = self.code {
+            write!(f, "[{}]", code)?;
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(ref help) = self.help {
+            write!(f, "\nhelp: {}", help)?;
+        }
+        Ok(())
+    }
+}
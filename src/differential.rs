@@ -0,0 +1,84 @@
+/// src/differential.rs
+/// Runs haumea programs with both the interpreter and the C backend, to find where they disagree
+use std::slice;
+use interpreter::Interpreter;
+use options::CompilerOptions;
+use parser::{self, SourceFile};
+use scanner::Scanner;
+use diagnostic::Severity;
+use testing;
+
+/// What a program did when it was run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    /// What the program wrote to stdout
+    pub output: String,
+    /// The exit code of the program, which is the low byte of what main returned
+    pub exit_code: i32,
+}
+
+/// Runs the program in source, which is named name, with the interpreter
+///
+/// # Examples
+/// ```
+/// # use haumea::differential::interpret;
+/// let outcome = interpret("main.hm", "to main do\n display(6 * 7)\n return 3\nend").unwrap();
+/// assert_eq!(outcome.output, "42\n");
+/// assert_eq!(outcome.exit_code, 3);
+/// ```
+pub fn interpret(name: &str, source: &str) -> Result<Outcome, String> {
+    let file = parse_checked(name, source)?;
+    let mut interpreter = Interpreter::new(vec![]);
+    interpreter.define_all(file.program);
+    let value = interpreter.run_main().map_err(|e| e.in_file(name).to_string())?;
+    Ok(Outcome {
+        output: String::from_utf8_lossy(&interpreter.into_output()).into_owned(),
+        // A process can only exit with a byte, so that is all of the value of main that is kept
+        exit_code: (value & 0xff) as i32,
+    })
+}
+
+/// Compiles the program in source, which is named name, to C and runs it,
+/// using the C compiler and flags in options
+pub fn compile_and_run(name: &str, source: &str, options: &CompilerOptions) -> Result<Outcome, String> {
+    let output = testing::run_program_with(name, source, "", options)?;
+    Ok(Outcome {
+        output: output.stdout,
+        exit_code: output.exit_code,
+    })
+}
+
+/// Runs the program in source, which is named name, with both the interpreter and the C backend
+///
+/// Returns what it did if they agree, and otherwise an error that says how they differ.
+/// options are as for compile_and_run.
+pub fn compare(name: &str, source: &str, options: &CompilerOptions) -> Result<Outcome, String> {
+    let interpreted = interpret(name, source)?;
+    let compiled = compile_and_run(name, source, options)?;
+    if interpreted == compiled {
+        return Ok(interpreted);
+    }
+    let mut difference = format!("{}: the interpreter and the C backend disagree\n", name);
+    if interpreted.output != compiled.output {
+        difference.push_str(&format!("interpreted output:\n{}\ncompiled output:\n{}\n",
+                                     interpreted.output, compiled.output));
+    }
+    if interpreted.exit_code != compiled.exit_code {
+        difference.push_str(&format!("interpreted exit code: {}, compiled exit code: {}\n",
+                                     interpreted.exit_code, compiled.exit_code));
+    }
+    Err(difference)
+}
+
+/// Parses and checks the program in source, which is named name, as haumea::compile checks it
+fn parse_checked(name: &str, source: &str) -> Result<SourceFile, String> {
+    let program = parser::parse(Scanner::new(source)).map_err(|e| e.in_file(name).to_string())?;
+    let file = SourceFile { name: name.to_string(), program };
+    let options = CompilerOptions::new(name);
+    let mut errors = ::check(slice::from_ref(&file), &options).into_iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error);
+    match errors.next() {
+        Some(error) => Err(error.to_string()),
+        None => Ok(file),
+    }
+}
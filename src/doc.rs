@@ -0,0 +1,80 @@
+/// src/doc.rs
+/// Generates a reference of the functions in haumea programs from their documentation comments
+use parser::SourceFile;
+use pretty::function_header;
+
+/// Generates a Markdown reference of the functions in files
+///
+/// Each file gets a section, with a heading for each of its functions
+/// followed by the function's documentation comments
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::doc::markdown;
+/// let source = "## Doubles n\nto double with (n) do return n * 2 end";
+/// let file = SourceFile {
+///     name: "math.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// assert_eq!(markdown(&[file]), "# Function reference\n\n## math.hm\n\n### `to double with (n)`\n\nDoubles n\n");
+/// ```
+pub fn markdown(files: &[SourceFile]) -> String {
+    let mut out = "# Function reference\n".to_string();
+    for file in files {
+        out.push_str(&format!("\n## {}\n", file.name));
+        for func in &file.program {
+            out.push_str(&format!("\n### `{}`\n", function_header(func)));
+            if !func.doc.is_empty() {
+                out.push('\n');
+                for line in &func.doc {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Generates a standalone HTML page with a reference of the functions in files
+///
+/// Blank lines in documentation comments separate paragraphs
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::doc::html;
+/// let source = "## Returns a < b\nto less with (a, b) do return a < b end";
+/// let file = SourceFile {
+///     name: "math.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// let page = html(&[file]);
+/// assert!(page.contains("<h3 id=\"less\"><code>to less with (a, b)</code></h3>"));
+/// assert!(page.contains("<p>Returns a &lt; b</p>"));
+/// ```
+pub fn html(files: &[SourceFile]) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<title>Function reference</title>\n</head>\n<body>\n");
+    out.push_str("<h1>Function reference</h1>\n");
+    for file in files {
+        out.push_str(&format!("<h2>{}</h2>\n", escape(&file.name)));
+        for func in &file.program {
+            out.push_str(&format!("<h3 id=\"{}\"><code>{}</code></h3>\n", escape(&func.name), escape(&function_header(func))));
+            for paragraph in func.doc.split(|line| line.trim().is_empty()).filter(|lines| !lines.is_empty()) {
+                out.push_str(&format!("<p>{}</p>\n", escape(&paragraph.join("\n"))));
+            }
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Escapes the characters in s that have a meaning in HTML
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
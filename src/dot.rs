@@ -0,0 +1,185 @@
+/// src/dot.rs
+/// Renders parsed haumea programs as Graphviz graphs
+use parser::{Expression, Function, SourceFile, Statement, StatementKind};
+use pretty::{function_header, operator_name, text_literal};
+
+/// Renders the functions in files as a Graphviz digraph
+///
+/// Every function, statement, and expression is a node, with an edge to each of its parts
+/// that is labelled with the part's role. Each file is drawn in a box of its own.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::dot::program_to_dot;
+/// let file = SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new("to main do display(1 + 2) end")).unwrap(),
+/// };
+/// let dot = program_to_dot(&[file]);
+/// assert!(dot.starts_with("digraph program {"));
+/// assert!(dot.contains("n0 [label=\"to main\", shape=box, style=bold];"));
+/// assert!(dot.contains("n3 -> n4 [label=\"left\"];"));
+/// ```
+pub fn program_to_dot(files: &[SourceFile]) -> String {
+    let mut graph = Graph {
+        out: String::new(),
+        nodes: 0,
+    };
+    graph.out.push_str("digraph program {\n");
+    graph.out.push_str("    node [fontname=\"monospace\"];\n");
+    for (index, file) in files.iter().enumerate() {
+        graph.out.push_str(&format!("    subgraph cluster_{} {{\n", index));
+        graph.out.push_str(&format!("        label={};\n", quote(&file.name)));
+        for func in &file.program {
+            graph.function(func);
+        }
+        graph.out.push_str("    }\n");
+    }
+    graph.out.push_str("}\n");
+    graph.out
+}
+
+/// A graph that is being written
+struct Graph {
+    /// The graph written so far
+    out: String,
+    /// How many nodes have been written, which is also the number of the next node
+    nodes: usize,
+}
+
+impl Graph {
+    /// Writes a node with label and the extra attributes, returning its name
+    fn node(&mut self, label: &str, attributes: &str) -> String {
+        let name = format!("n{}", self.nodes);
+        self.nodes += 1;
+        self.out.push_str(&format!("        {} [label={}{}];\n", name, quote(label), attributes));
+        name
+    }
+
+    /// Writes an edge from the node named from to the node named to
+    fn edge(&mut self, from: &str, to: &str, label: &str) {
+        self.out.push_str(&format!("        {} -> {} [label={}];\n", from, to, quote(label)));
+    }
+
+    fn function(&mut self, func: &Function) {
+        let name = self.node(&function_header(func), ", shape=box, style=bold");
+        if let Some(ref code) = func.code {
+            let code = self.statement(code);
+            self.edge(&name, &code, "body");
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) -> String {
+        let attributes = ", shape=box";
+        match statement.kind {
+            StatementKind::Return(ref expr) => {
+                let name = self.node("return", attributes);
+                let value = self.expression(expr);
+                self.edge(&name, &value, "value");
+                name
+            },
+            StatementKind::Var(ref idents) => {
+                let keyword = if idents.len() == 1 { "variable" } else { "variables" };
+                self.node(&format!("{} {}", keyword, idents.join(", ")), attributes)
+            },
+            StatementKind::Break(ref label) => self.node(&format!("break out of {}", label), attributes),
+            StatementKind::InlineC(ref c_source) => self.node(&format!("c {{{}}}", c_source), attributes),
+            StatementKind::Persistent(ref ident) => self.node(&format!("persistent variable {}", ident), attributes),
+            StatementKind::Define(ref ident, ref expr) => {
+                let name = self.node(&format!("variable {}", ident), attributes);
+                let value = self.expression(expr);
+                self.edge(&name, &value, "is");
+                name
+            },
+            StatementKind::Swap(ref first, ref second) => self.node(&format!("swap {} and {}", first, second), attributes),
+            StatementKind::Set(ref ident, ref expr) => {
+                let name = self.node(&format!("set {}", ident), attributes);
+                let value = self.expression(expr);
+                self.edge(&name, &value, "to");
+                name
+            },
+            StatementKind::Change(ref ident, ref expr) => {
+                let name = self.node(&format!("change {}", ident), attributes);
+                let value = self.expression(expr);
+                self.edge(&name, &value, "by");
+                name
+            },
+            StatementKind::If { ref cond, ref if_clause, ref else_clause, .. } => {
+                let name = self.node("if", attributes);
+                let cond = self.expression(cond);
+                self.edge(&name, &cond, "cond");
+                let if_clause = self.statement(if_clause);
+                self.edge(&name, &if_clause, "then");
+                if let Some(ref else_clause) = *else_clause {
+                    let else_clause = self.statement(else_clause);
+                    self.edge(&name, &else_clause, "else");
+                }
+                name
+            },
+            StatementKind::Repeat { ref count, ref label, ref body } => {
+                let name = match *label {
+                    Some(ref label) => self.node(&format!("repeat as {}", label), attributes),
+                    None => self.node("repeat", attributes),
+                };
+                let count = self.expression(count);
+                self.edge(&name, &count, "times");
+                let body = self.statement(body);
+                self.edge(&name, &body, "body");
+                name
+            },
+            StatementKind::Do(ref block) => {
+                let name = self.node("do", attributes);
+                for (index, statement) in block.iter().enumerate() {
+                    let statement = self.statement(statement);
+                    self.edge(&name, &statement, &(index + 1).to_string());
+                }
+                name
+            },
+            StatementKind::Call { ref function, ref arguments } => {
+                let name = self.node(&format!("call {}", function), attributes);
+                for (index, argument) in arguments.iter().enumerate() {
+                    let argument = self.expression(argument);
+                    self.edge(&name, &argument, &format!("arg {}", index + 1));
+                }
+                name
+            },
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) -> String {
+        match *expression {
+            Expression::Integer(i) => self.node(&i.to_string(), ""),
+            Expression::Text(ref s) => self.node(&text_literal(s), ""),
+            Expression::Ident(ref ident) => self.node(ident, ""),
+            Expression::BinaryOp { ref operator, ref left, ref right } => {
+                let name = self.node(operator_name(operator), "");
+                let left = self.expression(left);
+                self.edge(&name, &left, "left");
+                let right = self.expression(right);
+                self.edge(&name, &right, "right");
+                name
+            },
+            Expression::UnaryOp { ref operator, ref expression } => {
+                let name = self.node(operator_name(operator), "");
+                let operand = self.expression(expression);
+                self.edge(&name, &operand, "operand");
+                name
+            },
+            Expression::Call { ref function, ref arguments } => {
+                let name = self.node(&format!("{}()", function), "");
+                for (index, argument) in arguments.iter().enumerate() {
+                    let argument = self.expression(argument);
+                    self.edge(&name, &argument, &format!("arg {}", index + 1));
+                }
+                name
+            },
+        }
+    }
+}
+
+/// Returns s as a quoted DOT string
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
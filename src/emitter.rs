@@ -0,0 +1,355 @@
+/// src/emitter.rs
+/// Writes generated C line by line, keeping track of how deeply the lines are indented
+use std::fmt::{self, Write};
+use std::mem;
+
+/// The indentation of one level of generated C
+pub const INDENT: &str = "    ";
+
+/// Writes the lines of generated C to out, which can be a String or anything else that is fmt::Write
+///
+/// Lines are indented by one INDENT for each push_indent that hasn't been popped. Writing to
+/// a String can't fail, but if writing to out does, the first error is kept, nothing more is
+/// written, and finish returns the error.
+///
+/// # Examples
+/// ```
+/// # use haumea::emitter::CEmitter;
+/// let mut emitter = CEmitter::new(String::new());
+/// emitter.line("int main(void)");
+/// emitter.line("{");
+/// emitter.push_indent();
+/// emitter.directive("#line 1 \"main.hm\"");
+/// emitter.line(format_args!("return {};", 0));
+/// emitter.pop_indent();
+/// emitter.line("}");
+/// assert_eq!(emitter.finish().unwrap(), "int main(void)\n{\n#line 1 \"main.hm\"\n    return 0;\n}\n");
+/// ```
+pub struct CEmitter<W> {
+    out: W,
+    /// How many levels the lines are indented by
+    indent: usize,
+    /// Whether nothing has been written since the end of the last line
+    at_line_start: bool,
+    /// The result of the writes so far, which is the first error once one has failed
+    result: fmt::Result,
+    /// What minifies the C as it is written, if it is minified
+    minifier: Option<Minifier>,
+}
+
+impl<W: Write> CEmitter<W> {
+    pub fn new(out: W) -> CEmitter<W> {
+        CEmitter {
+            out,
+            indent: 0,
+            at_line_start: true,
+            result: Ok(()),
+            minifier: None,
+        }
+    }
+
+    /// Returns a CEmitter that writes the C as small as it can be instead of laying it out
+    ///
+    /// Comments, #line directives and indentation are left out, and spaces and line breaks
+    /// are only kept where C needs them, so everything but the other preprocessor directives
+    /// ends up on one line. C that is written raw is minified too.
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::emitter::CEmitter;
+    /// let mut emitter = CEmitter::minified(String::new());
+    /// emitter.raw("/* The runtime */\n#include <stdio.h>\n");
+    /// emitter.line("long answer(void)");
+    /// emitter.line("{");
+    /// emitter.push_indent();
+    /// emitter.directive("#line 1 \"main.hm\"");
+    /// emitter.line(format_args!("return 6 * 7; // {}", "the answer"));
+    /// emitter.line("printf(\"%s  is  it\\n\", x - -1);");
+    /// emitter.pop_indent();
+    /// emitter.line("}");
+    /// assert_eq!(emitter.finish().unwrap(), "#include <stdio.h>\nlong answer(void){return 6*7;printf(\"%s  is  it\\n\",x- -1);}\n");
+    /// ```
+    pub fn minified(out: W) -> CEmitter<W> {
+        CEmitter {
+            minifier: Some(Minifier::default()),
+            ..CEmitter::new(out)
+        }
+    }
+
+    /// Writes text on a line of its own, indented by the current indentation
+    pub fn line<T: fmt::Display>(&mut self, text: T) {
+        if self.minifier.is_some() {
+            return self.minify(format_args!("{}\n", text));
+        }
+        self.end_line();
+        let indent = Indent(self.indent);
+        self.write(format_args!("{}{}\n", indent, text));
+        self.at_line_start = true;
+    }
+
+    /// Writes text on a line of its own without indenting it, as preprocessor directives start lines
+    pub fn directive<T: fmt::Display>(&mut self, text: T) {
+        if self.minifier.is_some() {
+            return self.minify(format_args!("\n{}\n", text));
+        }
+        self.end_line();
+        self.write(format_args!("{}\n", text));
+        self.at_line_start = true;
+    }
+
+    /// Writes a line with nothing on it
+    pub fn blank_line(&mut self) {
+        if self.minifier.is_some() {
+            return;
+        }
+        self.end_line();
+        self.write(format_args!("\n"));
+    }
+
+    /// Writes c as it is, like C that is already laid out, without indenting it
+    ///
+    /// c doesn't have to end a line, but the next line starts on a new one anyway
+    pub fn raw(&mut self, c: &str) {
+        if self.minifier.is_some() {
+            return self.minify(format_args!("{}", c));
+        }
+        if !c.is_empty() {
+            self.write(format_args!("{}", c));
+            self.at_line_start = c.ends_with('\n');
+        }
+    }
+
+    /// Indents the lines after this by one more level
+    pub fn push_indent(&mut self) {
+        self.indent += 1;
+    }
+
+    /// Indents the lines after this by one less level, undoing a push_indent
+    pub fn pop_indent(&mut self) {
+        self.indent = self.indent.saturating_sub(1);
+    }
+
+    /// Ends the line that raw left unfinished, returning out, or the error that writing to it gave
+    pub fn finish(mut self) -> Result<W, fmt::Error> {
+        self.end();
+        self.result.map(|_| self.out)
+    }
+
+    /// Ends the C, writing the end of the line that raw left unfinished, and anything that
+    /// minifying it held back
+    ///
+    /// More C can be written after it, but that C starts a new line.
+    pub fn end(&mut self) {
+        let minified = match self.minifier {
+            Some(ref mut minifier) => {
+                minifier.finish();
+                mem::take(&mut minifier.out)
+            },
+            None => return self.end_line(),
+        };
+        self.write(format_args!("{}", minified));
+    }
+
+    /// Minifies the C in args, writing what is ready of it
+    fn minify(&mut self, args: fmt::Arguments) {
+        let minified = match self.minifier {
+            Some(ref mut minifier) => {
+                for c in args.to_string().chars() {
+                    minifier.push(c);
+                }
+                mem::take(&mut minifier.out)
+            },
+            None => return,
+        };
+        self.write(format_args!("{}", minified));
+    }
+
+    /// Ends the line that raw left unfinished, if there is one
+    fn end_line(&mut self) {
+        if !self.at_line_start {
+            self.write(format_args!("\n"));
+            self.at_line_start = true;
+        }
+    }
+
+    fn write(&mut self, args: fmt::Arguments) {
+        if self.result.is_ok() {
+            self.result = self.out.write_fmt(args);
+        }
+    }
+}
+
+/// Where a Minifier is in the C it is given
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Place {
+    /// In code, where whitespace and comments can go
+    #[default]
+    Code,
+    /// After a / in code, which could start a comment
+    Slash,
+    /// In a comment like this one
+    BlockComment,
+    /// In a block comment, after a * that could end it
+    BlockCommentStar,
+    /// In a comment that goes to the end of the line
+    LineComment,
+    /// In a string or character literal that ends with the quote
+    Literal(char),
+    /// In a literal, after a backslash, so the next character doesn't end it
+    Escape(char),
+    /// In a preprocessor directive, which goes to the end of the line
+    Directive,
+}
+
+/// Minifies C a character at a time, as it is written
+///
+/// It only has to know enough of C to tell comments, literals and directives from the rest,
+/// so that the whitespace in literals and the line breaks after directives are kept.
+#[derive(Debug, Default)]
+struct Minifier {
+    /// The minified C that hasn't been written yet
+    out: String,
+    place: Place,
+    /// The last character of code written on the line, if there is one
+    last: Option<char>,
+    /// Whether there was whitespace or a comment since the last character of code
+    space: bool,
+    /// Whether nothing but whitespace has been given since the last line break
+    line_start: bool,
+    /// The directive that is being given, which is only written once it is known not to be #line
+    directive: String,
+}
+
+impl Minifier {
+    fn push(&mut self, c: char) {
+        match self.place {
+            Place::Code => self.code(c),
+            Place::Slash => match c {
+                '*' => {
+                    self.place = Place::BlockComment;
+                    self.space = true;
+                },
+                '/' => {
+                    self.place = Place::LineComment;
+                    self.space = true;
+                },
+                _ => {
+                    self.place = Place::Code;
+                    self.token('/');
+                    self.code(c);
+                },
+            },
+            Place::BlockComment if c == '*' => self.place = Place::BlockCommentStar,
+            Place::BlockComment => (),
+            Place::BlockCommentStar => self.place = match c {
+                '/' => Place::Code,
+                '*' => Place::BlockCommentStar,
+                _ => Place::BlockComment,
+            },
+            Place::LineComment if c == '\n' => {
+                self.place = Place::Code;
+                self.code(c);
+            },
+            Place::LineComment => (),
+            Place::Literal(quote) => {
+                self.out.push(c);
+                if c == '\\' {
+                    self.place = Place::Escape(quote);
+                } else if c == quote {
+                    self.place = Place::Code;
+                }
+            },
+            Place::Escape(quote) => {
+                self.out.push(c);
+                self.place = Place::Literal(quote);
+            },
+            Place::Directive if c == '\n' => {
+                let directive = mem::take(&mut self.directive);
+                let directive = directive.trim();
+                // A #line directive only says where the C came from, which needs lines
+                if !directive.starts_with("#line") {
+                    if self.last.is_some() {
+                        self.out.push('\n');
+                    }
+                    self.out.push_str(directive);
+                    self.out.push('\n');
+                    self.last = None;
+                }
+                self.place = Place::Code;
+                // A #line that was left out still kept what was before it apart from what is after
+                self.space = true;
+                self.line_start = true;
+            },
+            Place::Directive => self.directive.push(c),
+        }
+    }
+
+    /// Minifies c, which is in code
+    fn code(&mut self, c: char) {
+        match c {
+            '\n' => {
+                self.space = true;
+                self.line_start = true;
+            },
+            _ if c.is_whitespace() => self.space = true,
+            '#' if self.line_start => {
+                self.place = Place::Directive;
+                self.directive.push(c);
+            },
+            '/' => {
+                self.place = Place::Slash;
+                self.line_start = false;
+            },
+            '"' | '\'' => {
+                self.token(c);
+                self.place = Place::Literal(c);
+            },
+            _ => self.token(c),
+        }
+    }
+
+    /// Writes c, which is code and not whitespace, with a space before it if it needs one
+    ///
+    /// Two characters that could be in the same token, like the letters of two names or the
+    /// minuses of `x - -1`, are kept apart.
+    fn token(&mut self, c: char) {
+        let joins = |c: char| c.is_alphanumeric() || c == '_';
+        let operator = |c: char| "+-*/%&|^<>=!.".contains(c);
+        if let Some(last) = self.last {
+            if self.space && ((joins(last) && joins(c)) || (operator(last) && operator(c))) {
+                self.out.push(' ');
+            }
+        }
+        self.out.push(c);
+        self.last = Some(c);
+        self.space = false;
+        self.line_start = false;
+    }
+
+    /// Finishes the C, ending it with a line break
+    fn finish(&mut self) {
+        match self.place {
+            Place::Slash => self.token('/'),
+            Place::Directive => self.push('\n'),
+            _ => (),
+        }
+        if self.last.is_some() {
+            self.out.push('\n');
+            self.last = None;
+        }
+    }
+}
+
+/// The indentation of a line that is nested some number of levels deep
+///
+/// It writes INDENT once per level when it is formatted, so indenting a line doesn't build a String
+struct Indent(usize);
+
+impl fmt::Display for Indent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for _ in 0..self.0 {
+            f.write_str(INDENT)?;
+        }
+        Ok(())
+    }
+}
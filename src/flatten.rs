@@ -0,0 +1,215 @@
+/// src/flatten.rs
+/// Breaks expressions up into assignments to temporaries, for C that is easier to follow and step through
+use std::mem;
+use parser::{Comments, Expression, Operator, SourceFile, Statement, StatementKind};
+use scanner::Span;
+
+/// The name that the temporaries start with, before their number
+///
+/// It starts with haumea_ like the other names that haumea makes up in the C
+const TEMPORARY_PREFIX: &str = "haumea_temporary";
+
+/// Returns files with each expression broken up so that it has at most one operation,
+/// whose operands are variables, numbers or texts
+///
+/// Each operation that was in another is worked out into a temporary of its own just before
+/// the statement, in the order the interpreter works them out, so the C is like the IR that
+/// the function would be lowered to. The right side of and and or is only worked out in an
+/// if on the left side, so it still only happens when it would have. The temporaries are
+/// numbered from 1 in each function.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::{flatten, pretty};
+/// let source = "to main do\n    display(f(1 + 2) * 3 - x)\nend";
+/// let file = SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// let flattened = flatten::flatten_expressions(&[file]);
+/// let mut out = String::new();
+/// pretty::print_function(&mut out, &flattened[0].program[0]);
+/// assert_eq!(out, "to main do
+///     variable haumea_temporary1 is 1 + 2
+///     variable haumea_temporary2 is f(haumea_temporary1)
+///     variable haumea_temporary3 is haumea_temporary2 * 3
+///     variable haumea_temporary4 is haumea_temporary3 - x
+///     display(haumea_temporary4)
+/// end
+/// ");
+/// ```
+pub fn flatten_expressions(files: &[SourceFile]) -> Vec<SourceFile> {
+    let mut files = files.to_vec();
+    for func in files.iter_mut().flat_map(|file| file.program.iter_mut()) {
+        if let Some(ref mut code) = func.code {
+            let mut flattener = Flattener { count: 0, span: code.span };
+            flattener.clause(code);
+        }
+    }
+    files
+}
+
+/// Returns true if expression is a variable, a number or a text, which can be an operand
+fn is_operand(expression: &Expression) -> bool {
+    matches!(*expression, Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_))
+}
+
+/// Flattens the expressions in the body of a function
+struct Flattener {
+    /// How many temporaries have been made in the function
+    count: usize,
+    /// Where the statement whose expressions are being flattened starts, which the statements
+    /// that work out its temporaries are given
+    span: Span,
+}
+
+impl Flattener {
+    /// Flattens the expressions in statement, which isn't in a block, so the temporaries go
+    /// in a new block along with it
+    fn clause(&mut self, statement: &mut Statement) {
+        if let StatementKind::Do(ref mut block) = statement.kind {
+            return self.block(block);
+        }
+        let mut before = self.statement(statement);
+        if !before.is_empty() {
+            let span = statement.span;
+            let clause = mem::replace(statement, Statement {
+                kind: StatementKind::Do(vec![]),
+                span,
+                comments: Comments::default(),
+            });
+            before.push(clause);
+            statement.kind = StatementKind::Do(before);
+        }
+    }
+
+    /// Flattens the expressions in the statements of block, working out the temporaries
+    /// just before the statements that use them
+    fn block(&mut self, block: &mut Vec<Statement>) {
+        let mut index = 0;
+        while index < block.len() {
+            let before = self.statement(&mut block[index]);
+            let count = before.len();
+            block.splice(index..index, before);
+            index += count + 1;
+        }
+    }
+
+    /// Flattens the expressions in statement, returning the statements that have to come
+    /// before it to work out its temporaries
+    fn statement(&mut self, statement: &mut Statement) -> Vec<Statement> {
+        let mut before = vec![];
+        self.span = statement.span;
+        match statement.kind {
+            StatementKind::Return(ref mut expression) |
+            StatementKind::Set(_, ref mut expression) |
+            StatementKind::Define(_, ref mut expression) |
+            StatementKind::Change(_, ref mut expression) => self.simplify(expression, &mut before),
+            StatementKind::If { ref mut cond, ref mut if_clause, ref mut else_clause, .. } => {
+                self.simplify(cond, &mut before);
+                self.clause(if_clause);
+                if let Some(ref mut else_clause) = *else_clause {
+                    self.clause(else_clause);
+                }
+            },
+            StatementKind::Repeat { ref mut count, ref mut body, .. } => {
+                self.simplify(count, &mut before);
+                self.clause(body);
+            },
+            StatementKind::Do(ref mut block) => self.block(block),
+            StatementKind::Call { ref mut arguments, .. } => {
+                for argument in arguments {
+                    *argument = self.operand(mem::replace(argument, Expression::Integer(0)), &mut before);
+                }
+            },
+            StatementKind::Var(_) | StatementKind::Persistent(_) | StatementKind::Swap(..) |
+            StatementKind::InlineC(_) | StatementKind::Break(_) => (),
+        }
+        before
+    }
+
+    /// Replaces expression with one that only has one operation, adding the statements that
+    /// work out its operands to before
+    fn simplify(&mut self, expression: &mut Expression, before: &mut Vec<Statement>) {
+        *expression = self.simple(mem::replace(expression, Expression::Integer(0)), before);
+    }
+
+    /// Returns expression with operands in place of the operations in it, adding the
+    /// statements that work them out to before
+    fn simple(&mut self, expression: Expression, before: &mut Vec<Statement>) -> Expression {
+        match expression {
+            Expression::BinaryOp { operator, left, right } => {
+                let left = self.operand(*left, before);
+                if operator != Operator::LogicalAnd && operator != Operator::LogicalOr {
+                    let right = self.operand(*right, before);
+                    return Expression::BinaryOp { operator, left: Box::new(left), right: Box::new(right) };
+                }
+                let mut right_before = vec![];
+                let right = self.operand(*right, &mut right_before);
+                // An operand can't fail or change anything, so it can be worked out either way
+                if right_before.is_empty() {
+                    return Expression::BinaryOp { operator, left: Box::new(left), right: Box::new(right) };
+                }
+                let name = self.temporary();
+                before.push(self.here(StatementKind::Define(name.clone(), truth(left))));
+                right_before.push(self.here(StatementKind::Set(name.clone(), truth(right))));
+                let cond = if operator == Operator::LogicalAnd {
+                    Expression::Ident(name.clone())
+                } else {
+                    Expression::BinaryOp {
+                        operator: Operator::Equals,
+                        left: Box::new(Expression::Ident(name.clone())),
+                        right: Box::new(Expression::Integer(0)),
+                    }
+                };
+                let if_clause = self.here(StatementKind::Do(right_before));
+                before.push(self.here(StatementKind::If {
+                    cond,
+                    if_clause: Box::new(if_clause),
+                    else_clause: None,
+                    attributes: vec![],
+                }));
+                Expression::Ident(name)
+            },
+            Expression::UnaryOp { operator, expression } => Expression::UnaryOp {
+                operator,
+                expression: Box::new(self.operand(*expression, before)),
+            },
+            Expression::Call { function, arguments } => Expression::Call {
+                function,
+                arguments: arguments.into_iter().map(|argument| self.operand(argument, before)).collect(),
+            },
+            Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) => expression,
+        }
+    }
+
+    /// Returns an operand with the value of expression, which is a temporary if it isn't one
+    /// already, adding the statements that work it out to before
+    fn operand(&mut self, expression: Expression, before: &mut Vec<Statement>) -> Expression {
+        let simple = self.simple(expression, before);
+        if is_operand(&simple) {
+            return simple;
+        }
+        let name = self.temporary();
+        before.push(self.here(StatementKind::Define(name.clone(), simple)));
+        Expression::Ident(name)
+    }
+
+    /// Returns the name of a new temporary
+    fn temporary(&mut self) -> String {
+        self.count += 1;
+        format!("{}{}", TEMPORARY_PREFIX, self.count)
+    }
+
+    /// Returns a statement of kind where the statement being flattened is
+    fn here(&self, kind: StatementKind) -> Statement {
+        Statement { kind, span: self.span, comments: Comments::default() }
+    }
+}
+
+/// Returns 1 if operand isn't 0 and 0 if it is, as and and or give
+fn truth(operand: Expression) -> Expression {
+    Expression::BinaryOp { operator: Operator::NotEquals, left: Box::new(operand), right: Box::new(Expression::Integer(0)) }
+}
@@ -0,0 +1,254 @@
+/// src/grammar.rs
+/// Generates grammars for editors from the tokens that the scanner reads, for syntax highlighting
+use checker::BUILTINS;
+use json::Json;
+use options::SOURCE_EXTENSIONS;
+use scanner::{ESCAPES, IDENT_CHARS, INLINE_C, KEYWORDS, OPERATORS, OPERATOR_CHARS};
+
+/// The TextMate scope of haumea source, which the scopes of its tokens end with
+const SCOPE: &str = "source.haumea";
+
+/// The kind of grammar to generate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GrammarFormat {
+    /// A TextMate grammar, as JSON, for editors like VS Code, Sublime Text and Atom
+    TextMate,
+    /// The grammar.js of a tree-sitter grammar, for editors like Neovim, Helix and Zed
+    TreeSitter,
+}
+
+impl GrammarFormat {
+    /// Looks up a GrammarFormat by the name used on the command line
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::grammar::GrammarFormat;
+    /// assert_eq!(GrammarFormat::from_name("tree-sitter"), Some(GrammarFormat::TreeSitter));
+    /// assert_eq!(GrammarFormat::from_name("vim"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<GrammarFormat> {
+        match name {
+            "textmate" => Some(GrammarFormat::TextMate),
+            "tree-sitter" => Some(GrammarFormat::TreeSitter),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the grammar in format
+pub fn generate(format: GrammarFormat) -> String {
+    match format {
+        GrammarFormat::TextMate => textmate(),
+        GrammarFormat::TreeSitter => tree_sitter(),
+    }
+}
+
+/// Returns a TextMate grammar of haumea, as JSON
+///
+/// Its comments, texts, numbers, keywords, operators, names and attributes are the tokens that
+/// the scanner reads, with the same chars in them, so what it highlights is what haumea reads.
+/// The calls of builtins are highlighted differently from the calls of other functions, and
+/// inline C is highlighted as C.
+///
+/// # Examples
+/// ```
+/// # use haumea::grammar;
+/// # use haumea::json::Json;
+/// let grammar = Json::parse(&grammar::textmate()).unwrap();
+/// assert_eq!(grammar.get("scopeName").and_then(Json::as_str), Some("source.haumea"));
+/// let keywords = grammar.get("repository").and_then(|r| r.get("keywords")).and_then(|k| k.get("match")).unwrap();
+/// assert!(keywords.as_str().unwrap().contains("|exported|"));
+/// ```
+pub fn textmate() -> String {
+    let name = char_class(IDENT_CHARS);
+    let word = |words: &[&str]| format!("(?<!{0})(?:{1})(?!{0})", name, words.join("|"));
+    let builtins = BUILTINS.iter().map(|&(builtin, _)| builtin).collect::<Vec<_>>();
+    let escapes = ESCAPES.iter().map(|&(escape, _)| escape).collect::<String>();
+    let rules = vec![
+        ("comments", Json::object(vec![
+            ("name", scope("comment.line.number-sign")),
+            ("match", Json::from("#.*$")),
+        ])),
+        ("inline-c", Json::object(vec![
+            ("begin", Json::from(format!("(?<!{0})({1})\\s*(\\{{)", name, INLINE_C))),
+            ("beginCaptures", captures(&["keyword.other.inline-c", "punctuation.section.block.begin"])),
+            ("end", Json::from("\\}")),
+            ("endCaptures", captures(&["punctuation.section.block.end"])),
+            ("contentName", Json::from("source.c")),
+            ("patterns", includes(&["#c-braces", "source.c"])),
+        ])),
+        ("c-braces", Json::object(vec![
+            ("begin", Json::from("\\{")),
+            ("end", Json::from("\\}")),
+            ("patterns", includes(&["#c-braces", "source.c"])),
+        ])),
+        ("texts", Json::object(vec![
+            ("name", scope("string.quoted.double")),
+            ("begin", Json::from("\"")),
+            // A text can't go past the end of its line
+            ("end", Json::from("\"|$")),
+            ("patterns", Json::Array(vec![
+                Json::object(vec![
+                    ("name", scope("constant.character.escape")),
+                    ("match", Json::from(format!("\\\\{}", char_class(&escapes)))),
+                ]),
+                Json::object(vec![
+                    ("name", scope("invalid.illegal.escape")),
+                    ("match", Json::from("\\\\.")),
+                ]),
+            ])),
+        ])),
+        ("numbers", Json::object(vec![
+            ("name", scope("constant.numeric.integer")),
+            ("match", Json::from("[0-9]+")),
+        ])),
+        ("attributes", Json::object(vec![
+            ("match", Json::from(format!("(@)\\s*({}+)", name))),
+            ("captures", captures(&["punctuation.definition.attribute", "entity.other.attribute-name"])),
+        ])),
+        ("definitions", Json::object(vec![
+            ("match", Json::from(format!("({})\\s+({}+)", word(&["to"]), name))),
+            ("captures", captures(&["keyword.control", "entity.name.function"])),
+        ])),
+        ("keywords", Json::object(vec![
+            ("name", scope("keyword.control")),
+            ("match", Json::from(word(KEYWORDS))),
+        ])),
+        ("word-operators", Json::object(vec![
+            ("name", scope("keyword.operator.word")),
+            ("match", Json::from(word(&word_operators()))),
+        ])),
+        ("operators", Json::object(vec![
+            ("name", scope("keyword.operator")),
+            ("match", Json::from(format!("{}+", char_class(&OPERATOR_CHARS.iter().collect::<String>())))),
+        ])),
+        ("builtin-calls", Json::object(vec![
+            ("match", Json::from(format!("({})\\s*(?=\\()", word(&builtins)))),
+            ("captures", captures(&["support.function.builtin"])),
+        ])),
+        ("calls", Json::object(vec![
+            ("match", Json::from(format!("({}+)\\s*(?=\\()", name))),
+            ("captures", captures(&["entity.name.function"])),
+        ])),
+        ("names", Json::object(vec![
+            ("name", scope("variable.other")),
+            ("match", Json::from(format!("{}+", name))),
+        ])),
+        ("punctuation", Json::object(vec![
+            ("name", scope("punctuation.separator")),
+            ("match", Json::from("[(),]")),
+        ])),
+    ];
+    // The definitions come before the keywords so that the name after to is found with it,
+    // and braces are only matched in inline C
+    let order = rules.iter().filter(|&&(rule, _)| rule != "c-braces").map(|&(rule, _)| format!("#{}", rule)).collect::<Vec<_>>();
+    let order = order.iter().map(|rule| &rule[..]).collect::<Vec<_>>();
+    let grammar = Json::object(vec![
+        ("name", Json::from("haumea")),
+        ("scopeName", Json::from(SCOPE)),
+        ("fileTypes", Json::Array(SOURCE_EXTENSIONS.iter().map(|&extension| Json::from(extension)).collect())),
+        ("patterns", includes(&order)),
+        ("repository", Json::Object(rules.into_iter().map(|(rule, json)| (rule.to_string(), json)).collect())),
+    ]);
+    format!("{}\n", grammar)
+}
+
+/// Returns the grammar.js of a tree-sitter grammar of haumea
+///
+/// The grammar is of the tokens of haumea, like the TextMate one, so that highlighting doesn't
+/// depend on a program being complete. Its nodes are named like the captures of tree-sitter's
+/// highlight queries, so `(keyword) @keyword` highlights the keywords.
+///
+/// # Examples
+/// ```
+/// # use haumea::grammar;
+/// let grammar = grammar::tree_sitter();
+/// assert!(grammar.starts_with("// The tokens of haumea"));
+/// assert!(grammar.contains("    keyword: $ => choice('to', 'with', 'is',"));
+/// assert!(grammar.contains("    number: $ => /[0-9]+/,\n"));
+/// ```
+pub fn tree_sitter() -> String {
+    let name = char_class(IDENT_CHARS);
+    let quote = |words: &[&str]| words.iter().map(|word| format!("'{}'", word)).collect::<Vec<_>>().join(", ");
+    let escapes = ESCAPES.iter().map(|&(escape, _)| escape).collect::<String>();
+    let mut out = String::new();
+    out.push_str("// The tokens of haumea, generated by `haumea grammar --format=tree-sitter`\n");
+    out.push_str("module.exports = grammar({\n");
+    out.push_str("  name: 'haumea',\n");
+    out.push_str("  extras: $ => [/\\s/, $.comment],\n");
+    out.push_str("  word: $ => $.identifier,\n");
+    out.push_str("  rules: {\n");
+    out.push_str("    source_file: $ => repeat($._token),\n");
+    out.push_str("    _token: $ => choice($.inline_c, $.text, $.number, $.attribute, $.keyword, $.operator,\n");
+    out.push_str("                        $.identifier, '(', ')', ','),\n");
+    out.push_str("    comment: $ => token(seq('#', /.*/)),\n");
+    out.push_str(&format!("    inline_c: $ => seq(token(prec(1, /{}\\s*\\{{/)), optional($.c_code), '}}'),\n", INLINE_C));
+    out.push_str("    c_code: $ => repeat1(choice(/[^{}]+/, seq('{', optional($.c_code), '}'))),\n");
+    out.push_str("    text: $ => seq('\"', repeat(choice($.escape_sequence, token.immediate(/[^\"\\\\\\n]+/))),\n");
+    out.push_str("                   token.immediate('\"')),\n");
+    out.push_str(&format!("    escape_sequence: $ => token.immediate(/\\\\{}/),\n", char_class(&escapes)));
+    out.push_str("    number: $ => /[0-9]+/,\n");
+    out.push_str("    attribute: $ => seq('@', $.identifier),\n");
+    out.push_str(&format!("    keyword: $ => choice({}),\n", quote(KEYWORDS)));
+    out.push_str(&format!("    operator: $ => choice({}, /{}+/),\n",
+                          quote(&word_operators()), char_class(&OPERATOR_CHARS.iter().collect::<String>())));
+    out.push_str(&format!("    identifier: $ => /{}+/,\n", name));
+    out.push_str("  },\n");
+    out.push_str("});\n");
+    out
+}
+
+/// Returns the operators that are spelt like names, like and
+fn word_operators() -> Vec<&'static str> {
+    OPERATORS.iter().cloned().filter(|operator| operator.chars().all(|c| IDENT_CHARS.contains(c))).collect()
+}
+
+/// Returns a regex character class that matches the chars in chars, with runs like a to z as ranges
+fn char_class(chars: &str) -> String {
+    let mut chars = chars.chars().collect::<Vec<_>>();
+    chars.sort();
+    chars.dedup();
+    let mut class = String::from("[");
+    let mut index = 0;
+    while index < chars.len() {
+        let mut end = index;
+        while end + 1 < chars.len() && chars[end + 1] as u32 == chars[end] as u32 + 1 {
+            end += 1;
+        }
+        push_class_char(&mut class, chars[index]);
+        if end > index + 1 {
+            class.push('-');
+        }
+        if end > index {
+            push_class_char(&mut class, chars[end]);
+        }
+        index = end + 1;
+    }
+    class.push(']');
+    class
+}
+
+/// Adds c to a character class, escaping it if it means something in one
+fn push_class_char(class: &mut String, c: char) {
+    if "\\]^-/".contains(c) {
+        class.push('\\');
+    }
+    class.push(c);
+}
+
+/// Returns the TextMate scope name, which ends with the scope of haumea
+fn scope(name: &str) -> Json {
+    Json::from(format!("{}.haumea", name))
+}
+
+/// Returns the captures of a match, which are given the scopes of names in order
+fn captures(names: &[&str]) -> Json {
+    Json::Object(names.iter().enumerate().map(|(index, name)| {
+        ((index + 1).to_string(), Json::object(vec![("name", scope(name))]))
+    }).collect())
+}
+
+/// Returns the patterns of a rule, which include each of rules
+fn includes(rules: &[&str]) -> Json {
+    Json::Array(rules.iter().map(|&rule| Json::object(vec![("include", Json::from(rule))])).collect())
+}
@@ -0,0 +1,191 @@
+/// src/inliner.rs
+/// Replaces the calls of functions marked @inline with the expressions that they return
+use std::collections::HashMap;
+use parser::{self, Callbacks, Expression, Function, SourceFile, Statement, StatementKind};
+
+/// The functions that can be inlined, with their parameters and the expression that they return
+type Inlinable<'a> = HashMap<&'a str, (&'a [String], &'a Expression)>;
+
+/// Returns files with the calls of the functions marked @inline replaced by what they return,
+/// wherever that can be done
+///
+/// A function can be inlined if all it does is return an expression, like `to square with (x) is x * x`.
+/// A call of it is inlined if each argument is a number, a text, or a variable, so that nothing
+/// is worked out more or fewer times than the call would work it out. The functions are still
+/// compiled, for the calls that aren't inlined and for C code to call. Calls in what was inlined
+/// are inlined too, apart from the ones of a function in what was inlined from it, so that a
+/// function that calls itself is only inlined once.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::{inliner, pretty};
+/// let source = "@inline\nto square with (x) is x * x\nto main do display(square(3) + square(1 + 2)) end";
+/// let file = SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// let inlined = inliner::inline_functions(&[file]);
+/// let mut out = String::new();
+/// pretty::print_function(&mut out, &inlined[0].program[1]);
+/// assert_eq!(out, "to main do\n    display(3 * 3 + square(1 + 2))\nend\n");
+/// ```
+pub fn inline_functions(files: &[SourceFile]) -> Vec<SourceFile> {
+    let mut inlinable = Inlinable::new();
+    for func in files.iter().flat_map(|file| file.program.iter()).filter(|func| func.attribute("inline").is_some()) {
+        if let Some(expression) = func.code.as_ref().and_then(returned_expression) {
+            inlinable.entry(&func.name).or_insert((parameters(func), expression));
+        }
+    }
+    if inlinable.is_empty() {
+        return files.to_vec();
+    }
+    let callbacks = parser::callbacks(files);
+    let inliner = Inliner { inlinable: &inlinable, callbacks: &callbacks };
+    files.iter().map(|file| SourceFile {
+        name: file.name.clone(),
+        program: file.program.iter().map(|func| {
+            let mut func = func.clone();
+            if let Some(ref mut code) = func.code {
+                inliner.inline_statement(code);
+            }
+            func
+        }).collect(),
+    }).collect()
+}
+
+/// Returns the expression that statement returns, if all it does is return one
+fn returned_expression(statement: &Statement) -> Option<&Expression> {
+    match statement.kind {
+        StatementKind::Return(ref expression) => Some(expression),
+        StatementKind::Do(ref block) if block.len() == 1 => returned_expression(&block[0]),
+        _ => None,
+    }
+}
+
+/// Returns the names of the parameters of func
+fn parameters(func: &Function) -> &[String] {
+    match func.signature {
+        Some(ref signature) => signature,
+        None => &[],
+    }
+}
+
+/// Returns true if expression can be put in the place of a parameter as many times as it is used
+fn is_simple(expression: &Expression) -> bool {
+    matches!(*expression, Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_))
+}
+
+/// Inlines the calls in the body of a function
+struct Inliner<'a> {
+    inlinable: &'a Inlinable<'a>,
+    callbacks: &'a Callbacks<'a>,
+}
+
+impl<'a> Inliner<'a> {
+    fn inline_statement(&self, statement: &mut Statement) {
+        match statement.kind {
+            StatementKind::Return(ref mut expression) |
+            StatementKind::Set(_, ref mut expression) |
+            StatementKind::Define(_, ref mut expression) |
+            StatementKind::Change(_, ref mut expression) => self.inline_expression(expression, &mut vec![]),
+            StatementKind::If { ref mut cond, ref mut if_clause, ref mut else_clause, .. } => {
+                self.inline_expression(cond, &mut vec![]);
+                self.inline_statement(if_clause);
+                if let Some(ref mut else_clause) = *else_clause {
+                    self.inline_statement(else_clause);
+                }
+            },
+            StatementKind::Repeat { ref mut count, ref mut body, .. } => {
+                self.inline_expression(count, &mut vec![]);
+                self.inline_statement(body);
+            },
+            StatementKind::Do(ref mut block) => {
+                for statement in block {
+                    self.inline_statement(statement);
+                }
+            },
+            StatementKind::Call { ref function, ref mut arguments } => self.inline_arguments(function, arguments, &mut vec![]),
+            StatementKind::Var(_) | StatementKind::Persistent(_) | StatementKind::Swap(..) |
+            StatementKind::InlineC(_) | StatementKind::Break(_) => (),
+        }
+    }
+
+    /// Inlines the calls in expression, which is in what was inlined from the functions in stack
+    fn inline_expression(&self, expression: &mut Expression, stack: &mut Vec<&'a str>) {
+        let inlined = match *expression {
+            Expression::BinaryOp { ref mut left, ref mut right, .. } => {
+                self.inline_expression(left, stack);
+                self.inline_expression(right, stack);
+                None
+            },
+            Expression::UnaryOp { ref mut expression, .. } => {
+                self.inline_expression(expression, stack);
+                None
+            },
+            Expression::Call { ref function, ref mut arguments } => {
+                self.inline_arguments(function, arguments, stack);
+                match self.inlinable.get_key_value(&function[..]) {
+                    Some((&name, &(parameters, body))) if !stack.contains(&name) && parameters.len() == arguments.len()
+                                                          && arguments.iter().all(is_simple) => {
+                        let mut inlined = self.substitute(body, parameters, arguments);
+                        stack.push(name);
+                        self.inline_expression(&mut inlined, stack);
+                        stack.pop();
+                        Some(inlined)
+                    },
+                    _ => None,
+                }
+            },
+            Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) => None,
+        };
+        if let Some(inlined) = inlined {
+            *expression = inlined;
+        }
+    }
+
+    /// Inlines the calls in the arguments of a call of function, leaving the functions given to C alone
+    fn inline_arguments(&self, function: &str, arguments: &mut [Expression], stack: &mut Vec<&'a str>) {
+        let callbacks = self.callbacks.get(function).map_or(&[][..], |positions| &positions[..]);
+        for (index, argument) in arguments.iter_mut().enumerate() {
+            if !callbacks.contains(&index) {
+                self.inline_expression(argument, stack);
+            }
+        }
+    }
+
+    /// Returns body with each of parameters replaced by the argument in the same place in arguments
+    fn substitute(&self, body: &Expression, parameters: &[String], arguments: &[Expression]) -> Expression {
+        match *body {
+            Expression::BinaryOp { ref operator, ref left, ref right } => Expression::BinaryOp {
+                operator: operator.clone(),
+                left: Box::new(self.substitute(left, parameters, arguments)),
+                right: Box::new(self.substitute(right, parameters, arguments)),
+            },
+            Expression::UnaryOp { ref operator, ref expression } => Expression::UnaryOp {
+                operator: operator.clone(),
+                expression: Box::new(self.substitute(expression, parameters, arguments)),
+            },
+            Expression::Call { ref function, arguments: ref call_arguments } => {
+                let callbacks = self.callbacks.get(&function[..]).map_or(&[][..], |positions| &positions[..]);
+                Expression::Call {
+                    function: function.clone(),
+                    arguments: call_arguments.iter().enumerate().map(|(index, argument)| {
+                        // A function given to C is named by its name, even if a parameter has it too
+                        if callbacks.contains(&index) {
+                            argument.clone()
+                        } else {
+                            self.substitute(argument, parameters, arguments)
+                        }
+                    }).collect(),
+                }
+            },
+            Expression::Ident(ref name) => match parameters.iter().position(|parameter| parameter == name) {
+                Some(index) => arguments[index].clone(),
+                None => body.clone(),
+            },
+            Expression::Integer(_) | Expression::Text(_) => body.clone(),
+        }
+    }
+}
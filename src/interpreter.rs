@@ -0,0 +1,665 @@
+/// src/interpreter.rs
+/// An interpreter that runs parsed haumea programs directly, without compiling them to C
+use std::collections::HashMap;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::cell::Cell;
+use std::mem;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use parser::{Expression, Function, Ident, Operator, Program, Statement, StatementKind};
+use scanner::Span;
+use texts::{self, TEXT_BUILTINS};
+use diagnostic::{Diagnostic, ErrorKind};
+
+/// Every haumea value is an integer, which is a `long` in the generated C
+///
+/// A text or a file is a number that identifies it among the ones that the Interpreter knows,
+/// like the generated C uses its address. As in C, 0 is never a text or a file.
+pub type Value = i64;
+
+/// How deeply functions can call each other before the interpreter gives up
+const MAX_CALL_DEPTH: usize = 1000;
+
+/// The variables that are visible at some point in a program
+///
+/// Each `do` block gets a new scope, which is dropped at its `end`. A variable is shared,
+/// so that a persistent one can be in the frame of every call of its function.
+#[derive(Debug)]
+struct Frame {
+    scopes: Vec<HashMap<Ident, Rc<Cell<Value>>>>,
+}
+
+impl Frame {
+    /// Constructs a Frame with a single empty scope
+    fn new() -> Frame {
+        Frame {
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    /// Declares a variable in the innermost scope, starting at 0
+    fn declare(&mut self, name: &str) {
+        self.bind(name, Rc::new(Cell::new(0)));
+    }
+
+    /// Declares a variable in the innermost scope that is the same one as variable
+    fn bind(&mut self, name: &str, variable: Rc<Cell<Value>>) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), variable);
+        }
+    }
+
+    /// Returns the variable named name in the innermost scope that declares it
+    fn get(&self, name: &str) -> Option<&Cell<Value>> {
+        self.scopes.iter().rev().filter_map(|scope| scope.get(name)).next().map(|variable| &**variable)
+    }
+}
+
+/// A file that a program opened
+#[derive(Debug)]
+enum OpenFile {
+    Reader(BufReader<File>),
+    Writer(File),
+}
+
+/// What happens after a statement is executed
+enum Flow {
+    /// The next statement is executed
+    Next,
+    /// The function returns the value
+    Return(Value),
+    /// The loop with the label is left, by the break at the span
+    Break(Ident, Span),
+}
+
+/// Runs haumea code, writing anything it displays to out
+///
+/// The Interpreter remembers the functions that are defined on it and the variables declared
+/// by statements that are executed outside of any function, so that code can be given to it
+/// a piece at a time, like in the REPL
+///
+/// Every haumea call is a few nested Rust calls, so code that recurses deeply
+/// should be run on a thread with a bigger stack than usual
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser;
+/// # use haumea::interpreter::Interpreter;
+/// let program = parser::parse(Scanner::new("
+/// to square with (n) do return n * n end
+/// to main do display(square(7)) end")).unwrap();
+/// let mut interpreter = Interpreter::new(vec![]);
+/// interpreter.define_all(program);
+/// assert_eq!(interpreter.run_main().unwrap(), 0);
+/// assert_eq!(interpreter.into_output(), b"49\n");
+/// ```
+#[derive(Debug)]
+pub struct Interpreter<W: Write> {
+    /// The functions that have been defined
+    functions: HashMap<Ident, Rc<Function>>,
+    /// The variables declared outside of any function
+    globals: Frame,
+    /// How many function calls are currently running
+    depth: usize,
+    /// Every text that has been made, where the value of a text is one more than its index
+    texts: Vec<String>,
+    /// The values of the text literals, which are made once each, as the C compiler makes
+    /// one copy of the chars of each literal
+    literals: HashMap<String, Value>,
+    /// The persistent variables of each function, by where they are declared in it
+    persistent: HashMap<Ident, HashMap<Span, Rc<Cell<Value>>>>,
+    /// The function that is running, which is None outside of any function
+    function: Option<Rc<Function>>,
+    /// Every file that has been opened, which is None once it is closed
+    ///
+    /// Like texts, the value of a file is one more than its index
+    files: Vec<Option<OpenFile>>,
+    /// The command line arguments of the program, without its name
+    arguments: Vec<String>,
+    /// Where display writes to
+    out: W,
+}
+
+impl<W: Write> Interpreter<W> {
+    /// Constructs an Interpreter with no functions or variables
+    pub fn new(out: W) -> Interpreter<W> {
+        Interpreter {
+            functions: HashMap::new(),
+            globals: Frame::new(),
+            depth: 0,
+            texts: vec![],
+            literals: HashMap::new(),
+            persistent: HashMap::new(),
+            function: None,
+            files: vec![],
+            arguments: vec![],
+            out,
+        }
+    }
+
+    /// Sets the command line arguments that the program sees, which shouldn't include its name
+    pub fn set_arguments(&mut self, arguments: Vec<String>) {
+        self.arguments = arguments;
+    }
+
+    /// Defines func, replacing any function with the same name
+    ///
+    /// The persistent variables of the function it replaces are forgotten
+    pub fn define(&mut self, func: Function) {
+        self.persistent.remove(&func.name);
+        self.functions.insert(func.name.clone(), Rc::new(func));
+    }
+
+    /// Defines every function in program
+    pub fn define_all(&mut self, program: Program) {
+        for func in program {
+            self.define(func);
+        }
+    }
+
+    /// Returns the names of the functions that have been defined, in alphabetical order
+    pub fn function_names(&self) -> Vec<&str> {
+        let mut names = self.functions.keys().map(|name| &name[..]).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Consumes the Interpreter, returning what was displayed
+    pub fn into_output(self) -> W {
+        self.out
+    }
+
+    /// Runs the main function, returning its exit code
+    pub fn run_main(&mut self) -> Result<Value, Diagnostic> {
+        let span = match self.functions.get("main") {
+            Some(main) => main.span,
+            None => return Err(Diagnostic::new(ErrorKind::Semantic,
+                                               "There is no main function",
+                                               Span { line: 1, column: 1 }).with_code("E0211")),
+        };
+        self.call("main", vec![], span)
+    }
+
+    /// Executes statement outside of any function
+    ///
+    /// Variables declared by it are kept for later statements and expressions
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::scanner::Scanner;
+    /// # use haumea::parser::{self, Entry};
+    /// # use haumea::interpreter::Interpreter;
+    /// let mut interpreter = Interpreter::new(vec![]);
+    /// for line in &["variable x", "set x to 4", "change x by 1", "if x = 5 then display(x * 2)"] {
+    ///     match parser::parse_entry(Scanner::new(line).tokenize().unwrap()).unwrap() {
+    ///         Entry::Statement(statement) => interpreter.execute(&statement).unwrap(),
+    ///         _ => unreachable!(),
+    ///     }
+    /// }
+    /// assert_eq!(interpreter.into_output(), b"10\n");
+    /// ```
+    pub fn execute(&mut self, statement: &Statement) -> Result<(), Diagnostic> {
+        let mut globals = mem::replace(&mut self.globals, Frame::new());
+        let result = self.execute_in(statement, &mut globals);
+        self.globals = globals;
+        match result? {
+            Flow::Next => Ok(()),
+            Flow::Return(_) => Err(Diagnostic::new(ErrorKind::Semantic,
+                                                   "return can only be used inside a function",
+                                                   statement.span).with_code("E0212")),
+            Flow::Break(label, span) => Err(no_loop_called(&label, span)),
+        }
+    }
+
+    /// Evaluates expression outside of any function, using the variables declared so far
+    ///
+    /// span is where the expression starts, which is used for errors
+    pub fn evaluate(&mut self, expression: &Expression, span: Span) -> Result<Value, Diagnostic> {
+        let mut globals = mem::replace(&mut self.globals, Frame::new());
+        let result = self.evaluate_in(expression, span, &mut globals);
+        self.globals = globals;
+        result
+    }
+
+    /// Calls the function named name with the arguments
+    fn call(&mut self, name: &str, arguments: Vec<Value>, span: Span) -> Result<Value, Diagnostic> {
+        if let Some(result) = self.call_builtin(name, &arguments, span) {
+            return result;
+        }
+        let func = match self.functions.get(name) {
+            Some(func) => func.clone(),
+            None => return Err(Diagnostic::new(ErrorKind::Semantic,
+                                               format!("The function {} is not defined", name),
+                                               span).with_code("E0202")),
+        };
+        let parameters = match func.signature {
+            Some(ref signature) => &signature[..],
+            None => &[],
+        };
+        if parameters.len() != arguments.len() {
+            return Err(Diagnostic::new(ErrorKind::Semantic,
+                                       format!("{} takes {} arguments, but was given {}",
+                                               name, parameters.len(), arguments.len()),
+                                       span).with_code("E0203"));
+        }
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(Diagnostic::new(ErrorKind::Runtime,
+                                       format!("Too many nested calls (more than {})", MAX_CALL_DEPTH),
+                                       span).with_code("E0301"));
+        }
+        let mut frame = Frame::new();
+        for (parameter, argument) in parameters.iter().zip(arguments) {
+            frame.bind(parameter, Rc::new(Cell::new(argument)));
+        }
+        let code = match func.code {
+            Some(ref code) => code,
+            None => return Err(Diagnostic::new(ErrorKind::Runtime,
+                                               format!("{} is an external function, which only compiled programs can call", name),
+                                               span).with_code("E0302")),
+        };
+        self.depth += 1;
+        let caller = self.function.replace(func.clone());
+        let result = self.execute_in(code, &mut frame);
+        self.function = caller;
+        self.depth -= 1;
+        // Like the functions without a signature, a function that doesn't return anything returns 0
+        match result? {
+            Flow::Next => Ok(0),
+            Flow::Return(value) => Ok(value),
+            Flow::Break(label, span) => Err(no_loop_called(&label, span)),
+        }
+    }
+
+    /// Executes statement with the variables in frame
+    fn execute_in(&mut self, statement: &Statement, frame: &mut Frame) -> Result<Flow, Diagnostic> {
+        let span = statement.span;
+        match statement.kind {
+            StatementKind::Return(ref expr) => Ok(Flow::Return(self.evaluate_in(expr, span, frame)?)),
+            StatementKind::Var(ref names) => {
+                for name in names {
+                    frame.declare(name);
+                }
+                Ok(Flow::Next)
+            },
+            StatementKind::InlineC(_) => {
+                Err(Diagnostic::new(ErrorKind::Runtime, "Inline C only runs in compiled programs", span).with_code("E0303"))
+            },
+            StatementKind::Persistent(ref name) => {
+                let function = self.function.as_ref().map_or("", |function| &function.name[..]);
+                if !self.persistent.contains_key(function) {
+                    self.persistent.insert(function.to_string(), HashMap::new());
+                }
+                let variables = self.persistent.get_mut(function).unwrap();
+                frame.bind(name, variables.entry(span).or_insert_with(|| Rc::new(Cell::new(0))).clone());
+                Ok(Flow::Next)
+            },
+            StatementKind::Define(ref name, ref expr) => {
+                let value = self.evaluate_in(expr, span, frame)?;
+                frame.bind(name, Rc::new(Cell::new(value)));
+                Ok(Flow::Next)
+            },
+            StatementKind::Swap(ref first, ref second) => {
+                variable(frame, first, span)?.swap(variable(frame, second, span)?);
+                Ok(Flow::Next)
+            },
+            StatementKind::Set(ref name, ref expr) => {
+                let value = self.evaluate_in(expr, span, frame)?;
+                variable(frame, name, span)?.set(value);
+                Ok(Flow::Next)
+            },
+            StatementKind::Change(ref name, ref expr) => {
+                let value = self.evaluate_in(expr, span, frame)?;
+                let variable = variable(frame, name, span)?;
+                variable.set(variable.get().wrapping_add(value));
+                Ok(Flow::Next)
+            },
+            StatementKind::If { ref cond, ref if_clause, ref else_clause, .. } => {
+                if self.evaluate_in(cond, span, frame)? != 0 {
+                    self.execute_in(if_clause, frame)
+                } else if let Some(ref else_clause) = *else_clause {
+                    self.execute_in(else_clause, frame)
+                } else {
+                    Ok(Flow::Next)
+                }
+            },
+            StatementKind::Repeat { ref count, ref label, ref body } => {
+                for _ in 0..self.evaluate_in(count, span, frame)?.max(0) {
+                    match self.execute_in(body, frame)? {
+                        Flow::Next => (),
+                        Flow::Break(ref left, _) if Some(left) == label.as_ref() => break,
+                        flow => return Ok(flow),
+                    }
+                }
+                Ok(Flow::Next)
+            },
+            StatementKind::Break(ref label) => Ok(Flow::Break(label.clone(), span)),
+            StatementKind::Do(ref block) => {
+                frame.scopes.push(HashMap::new());
+                let mut flow = Ok(Flow::Next);
+                for statement in block {
+                    flow = self.execute_in(statement, frame);
+                    match flow {
+                        Ok(Flow::Next) => (),
+                        _ => break,
+                    }
+                }
+                frame.scopes.pop();
+                flow
+            },
+            StatementKind::Call { ref function, ref arguments } => {
+                let mut values = vec![];
+                for argument in arguments {
+                    values.push(self.evaluate_in(argument, span, frame)?);
+                }
+                self.call(function, values, span)?;
+                Ok(Flow::Next)
+            },
+        }
+    }
+
+    /// Calls the builtin named name with the arguments, or returns None if there isn't one
+    fn call_builtin(&mut self, name: &str, arguments: &[Value], span: Span) -> Option<Result<Value, Diagnostic>> {
+        let result = match (name, arguments) {
+            ("display", &[n]) => self.display(&n.to_string(), span),
+            ("assert_equal", &[left, right]) => {
+                if left == right {
+                    Ok(0)
+                } else {
+                    Err(Diagnostic::new(ErrorKind::Runtime,
+                                        format!("Assertion failed: {} is not equal to {}", left, right),
+                                        span).with_code("E0304"))
+                }
+            },
+            ("display_text", &[text]) => {
+                let text = self.text(text, span).map(|text| text.to_string());
+                text.and_then(|text| self.display(&text, span))
+            },
+            // Positions count bytes, like in the C runtime
+            ("substring", &[text, start, length]) => self.text(text, span).map(|text| {
+                let bytes = text.as_bytes();
+                let start = start.max(0).min(bytes.len() as Value) as usize;
+                let length = length.max(0).min((bytes.len() - start) as Value) as usize;
+                String::from_utf8_lossy(&bytes[start..start + length]).into_owned()
+            }).map(|part| self.new_text(part)),
+            ("index_of", &[text, part]) => {
+                self.text(part, span).map(|part| part.to_string()).and_then(|part| {
+                    self.text(text, span).map(|text| text.find(&part[..]).map_or(-1, |index| index as Value))
+                })
+            },
+            ("same_text", &[text, other]) => {
+                self.text(other, span).map(|other| other.to_string()).and_then(|other| {
+                    self.text(text, span).map(|text| Value::from(text == other))
+                })
+            },
+            ("to_text", &[n]) => Ok(self.new_text(n.to_string())),
+            // Texts that aren't numbers are 0
+            ("to_number", &[text]) => self.text(text, span).map(|text| text.trim_start().parse().unwrap_or(0)),
+            ("uppercase", &[text]) => {
+                self.text(text, span).map(|text| text.to_ascii_uppercase()).map(|text| self.new_text(text))
+            },
+            ("lowercase", &[text]) => {
+                self.text(text, span).map(|text| text.to_ascii_lowercase()).map(|text| self.new_text(text))
+            },
+            // Files that can't be opened are 0
+            ("open", &[path, mode]) => {
+                let path = self.text(path, span).map(|path| path.to_string());
+                let mode = self.text(mode, span).map(|mode| mode.to_string());
+                path.and_then(|path| mode.map(|mode| self.open(&path, &mode)))
+            },
+            // The end of the file is 0
+            ("read_line", &[file]) => {
+                let line = match self.file(file, span) {
+                    Ok(&mut OpenFile::Reader(ref mut reader)) => {
+                        let mut line = String::new();
+                        match reader.read_line(&mut line) {
+                            Ok(0) => Ok(None),
+                            Ok(_) => Ok(Some(line)),
+                            Err(e) => Err(Diagnostic::new(ErrorKind::Runtime, format!("Could not read a line: {}", e), span).with_code("E0305")),
+                        }
+                    },
+                    Ok(&mut OpenFile::Writer(_)) => {
+                        Err(Diagnostic::new(ErrorKind::Runtime, format!("{} was not opened for reading", file), span).with_code("E0306"))
+                    },
+                    Err(e) => Err(e),
+                };
+                line.map(|line| match line {
+                    Some(mut line) => {
+                        if line.ends_with('\n') {
+                            line.pop();
+                        }
+                        self.new_text(line)
+                    },
+                    None => 0,
+                })
+            },
+            ("write_line", &[file, text]) => {
+                let text = self.text(text, span).map(|text| text.to_string());
+                text.and_then(|text| match self.file(file, span) {
+                    Ok(&mut OpenFile::Writer(ref mut writer)) => match writeln!(writer, "{}", text) {
+                        Ok(()) => Ok(0),
+                        Err(e) => Err(Diagnostic::new(ErrorKind::Runtime, format!("Could not write a line: {}", e), span).with_code("E0305")),
+                    },
+                    Ok(&mut OpenFile::Reader(_)) => {
+                        Err(Diagnostic::new(ErrorKind::Runtime, format!("{} was not opened for writing", file), span).with_code("E0306"))
+                    },
+                    Err(e) => Err(e),
+                })
+            },
+            ("close", &[file]) => {
+                let closed = self.file(file, span).map(|_| ());
+                closed.map(|()| {
+                    self.files[file as usize - 1] = None;
+                    0
+                })
+            },
+            ("argument_count", &[]) => Ok(self.arguments.len() as Value),
+            // Arguments that don't exist are 0, which the text builtins take as the empty text
+            ("argument_at", &[index]) => {
+                let argument = if index >= 0 { self.arguments.get(index as usize).cloned() } else { None };
+                Ok(argument.map_or(0, |argument| self.new_text(argument)))
+            },
+            ("current_time", &[]) => Ok(unix_time().as_secs() as Value),
+            ("current_milliseconds", &[]) => Ok(unix_time().as_millis() as Value),
+            // since is a time from current_milliseconds
+            ("elapsed_milliseconds", &[since]) => Ok(unix_time().as_millis() as Value - since),
+            // Variables that aren't set are 0
+            ("environment_value", &[name]) => self.text(name, span).map(|name| env::var(name).ok()).map(|value| {
+                value.map_or(0, |value| self.new_text(value))
+            }),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /// Writes s on a line of its own to the output
+    fn display(&mut self, s: &str, span: Span) -> Result<Value, Diagnostic> {
+        match writeln!(self.out, "{}", s) {
+            Ok(()) => Ok(0),
+            Err(e) => Err(Diagnostic::new(ErrorKind::Runtime, format!("Could not display: {}", e), span).with_code("E0305")),
+        }
+    }
+
+    /// Makes the text s, returning its value
+    ///
+    /// Every text gets a value of its own, even if another text has the same chars, as every
+    /// text that the generated C makes at run time has an address of its own
+    fn new_text(&mut self, s: String) -> Value {
+        self.texts.push(s);
+        self.texts.len() as Value
+    }
+
+    /// Returns true if = or != between left and right compares the chars of texts
+    fn compares_texts(&self, left: &Expression, right: &Expression) -> bool {
+        texts::compares_texts(left, right, &|name| {
+            TEXT_BUILTINS.contains(&name) ||
+                self.functions.get(name).is_some_and(|func| func.code.is_none() && func.returns_text)
+        })
+    }
+
+    /// Returns the value of the text literal s, making it the first time that it is used
+    fn literal(&mut self, s: &str) -> Value {
+        if let Some(&value) = self.literals.get(s) {
+            return value;
+        }
+        let value = self.new_text(s.to_string());
+        self.literals.insert(s.to_string(), value);
+        value
+    }
+
+    /// Returns the text whose value is value, or an error at span if there isn't one
+    ///
+    /// 0 is the empty text, as it is what the builtins give for a text that isn't there
+    fn text(&self, value: Value, span: Span) -> Result<&str, Diagnostic> {
+        if value == 0 {
+            return Ok("");
+        }
+        let text = if value > 0 { self.texts.get(value as usize - 1) } else { None };
+        match text {
+            Some(text) => Ok(text),
+            None => Err(Diagnostic::new(ErrorKind::Runtime, format!("{} is not a text", value), span).with_code("E0307")),
+        }
+    }
+
+    /// Opens the file at path with mode, which is "r", "w", or "a" like in C, returning its value
+    ///
+    /// Returns 0 if the file can't be opened
+    fn open(&mut self, path: &str, mode: &str) -> Value {
+        let file = match mode {
+            "r" => File::open(path).map(|file| OpenFile::Reader(BufReader::new(file))),
+            "w" => File::create(path).map(OpenFile::Writer),
+            "a" => OpenOptions::new().append(true).create(true).open(path).map(OpenFile::Writer),
+            _ => return 0,
+        };
+        match file {
+            Ok(file) => {
+                self.files.push(Some(file));
+                self.files.len() as Value
+            },
+            Err(_) => 0,
+        }
+    }
+
+    /// Returns the open file whose value is value, or an error at span if there isn't one
+    fn file(&mut self, value: Value, span: Span) -> Result<&mut OpenFile, Diagnostic> {
+        let file = if value > 0 { self.files.get_mut(value as usize - 1) } else { None };
+        match file {
+            Some(&mut Some(ref mut file)) => Ok(file),
+            _ => Err(Diagnostic::new(ErrorKind::Runtime, format!("{} is not an open file", value), span).with_code("E0306")),
+        }
+    }
+
+    /// Evaluates expression with the variables in frame
+    ///
+    /// span is where the statement that contains the expression starts
+    fn evaluate_in(&mut self, expression: &Expression, span: Span, frame: &mut Frame)
+                   -> Result<Value, Diagnostic> {
+        match *expression {
+            Expression::Integer(i) => Ok(Value::from(i)),
+            Expression::Text(ref s) => Ok(self.literal(s)),
+            Expression::Ident(ref name) => Ok(variable(frame, name, span)?.get()),
+            // and and or only evaluate their right hand side if they need to, like in C
+            Expression::BinaryOp { operator: Operator::LogicalAnd, ref left, ref right } => {
+                Ok(if self.evaluate_in(left, span, frame)? == 0 {
+                    0
+                } else {
+                    truth(self.evaluate_in(right, span, frame)? != 0)
+                })
+            },
+            Expression::BinaryOp { operator: Operator::LogicalOr, ref left, ref right } => {
+                Ok(if self.evaluate_in(left, span, frame)? != 0 {
+                    1
+                } else {
+                    truth(self.evaluate_in(right, span, frame)? != 0)
+                })
+            },
+            Expression::BinaryOp { ref operator, ref left, ref right }
+                if (*operator == Operator::Equals || *operator == Operator::NotEquals) &&
+                   self.compares_texts(left, right) => {
+                let left = self.evaluate_in(left, span, frame)?;
+                let right = self.evaluate_in(right, span, frame)?;
+                let right = self.text(right, span)?.to_string();
+                let same = *self.text(left, span)? == right;
+                Ok(truth(same == (*operator == Operator::Equals)))
+            },
+            Expression::BinaryOp { ref operator, ref left, ref right } => {
+                let left = self.evaluate_in(left, span, frame)?;
+                let right = self.evaluate_in(right, span, frame)?;
+                binary_op(operator, left, right, span)
+            },
+            Expression::UnaryOp { ref operator, ref expression } => {
+                let value = self.evaluate_in(expression, span, frame)?;
+                match *operator {
+                    Operator::Sub | Operator::Negate => Ok(value.wrapping_neg()),
+                    Operator::LogicalNot => Ok(truth(value == 0)),
+                    Operator::BinaryNot => Ok(!value),
+                    ref operator => Err(Diagnostic::new(ErrorKind::Internal,
+                                                        format!("{:?} is not a unary operator", operator),
+                                                        span)),
+                }
+            },
+            Expression::Call { ref function, ref arguments } => {
+                let mut values = vec![];
+                for argument in arguments {
+                    values.push(self.evaluate_in(argument, span, frame)?);
+                }
+                self.call(function, values, span)
+            },
+        }
+    }
+}
+
+/// Returns the variable named name in frame, or an error at span if it isn't declared
+fn variable<'a>(frame: &'a Frame, name: &str, span: Span) -> Result<&'a Cell<Value>, Diagnostic> {
+    match frame.get(name) {
+        Some(value) => Ok(value),
+        None => Err(Diagnostic::new(ErrorKind::Semantic,
+                                    format!("The variable {} is not declared", name),
+                                    span).with_code("E0201")),
+    }
+}
+
+/// Returns the error for a break out of the loop called label, at span, that isn't in one
+fn no_loop_called(label: &str, span: Span) -> Diagnostic {
+    Diagnostic::new(ErrorKind::Semantic, format!("There is no loop called {} around this break", label), span).with_code("E0207")
+}
+
+/// Returns the value of a condition, which is 1 for true and 0 for false like in C
+fn truth(condition: bool) -> Value {
+    if condition { 1 } else { 0 }
+}
+
+/// Applies a binary operator to two values, the way the generated C does
+fn binary_op(operator: &Operator, left: Value, right: Value, span: Span) -> Result<Value, Diagnostic> {
+    Ok(match *operator {
+        Operator::Add => left.wrapping_add(right),
+        Operator::Sub => left.wrapping_sub(right),
+        Operator::Mul => left.wrapping_mul(right),
+        Operator::Div => {
+            if right == 0 {
+                return Err(Diagnostic::new(ErrorKind::Runtime, "Division by zero", span).with_code("E0308"));
+            }
+            left.wrapping_div(right)
+        },
+        Operator::Equals => truth(left == right),
+        Operator::NotEquals => truth(left != right),
+        Operator::Gt => truth(left > right),
+        Operator::Lt => truth(left < right),
+        Operator::Gte => truth(left >= right),
+        Operator::Lte => truth(left <= right),
+        Operator::LogicalAnd => truth(left != 0 && right != 0),
+        Operator::LogicalOr => truth(left != 0 || right != 0),
+        Operator::BinaryAnd => left & right,
+        Operator::BinaryOr => left | right,
+        ref operator => return Err(Diagnostic::new(ErrorKind::Internal,
+                                                   format!("{:?} is not a binary operator", operator),
+                                                   span)),
+    })
+}
+
+/// Returns how long it has been since the Unix epoch
+fn unix_time() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
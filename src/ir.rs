@@ -0,0 +1,621 @@
+/// src/ir.rs
+/// The intermediate representation that functions are lowered to, of basic blocks of simple instructions
+use std::collections::HashSet;
+use std::fmt;
+use parser::{self, Callbacks, Expression, Operator, SourceFile, Statement, StatementKind};
+use pretty;
+use scanner::Span;
+use texts;
+
+/// The index of a basic block in Function::blocks
+pub type BlockId = usize;
+
+/// The index of a temporary in Function::temporaries
+pub type Temporary = usize;
+
+/// The index of a variable in Function::locals
+pub type LocalId = usize;
+
+/// What a temporary holds
+///
+/// Every value is a long, but a text is a pointer to its chars kept in one, which a backend
+/// can keep as a pointer instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    /// A number
+    Number,
+    /// A text
+    Text,
+}
+
+/// A value that an instruction uses
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Operand {
+    /// A number
+    Constant(i64),
+    /// A text literal
+    Text(String),
+    /// What is in a temporary
+    Temporary(Temporary),
+    /// What is in a variable
+    Local(LocalId),
+    /// The name of a haumea function, given to an external function for C to call
+    Function(String),
+}
+
+/// A variable of a function, which each parameter and declaration has one of its own of
+///
+/// Declarations of the same name in different blocks are different variables, so what a
+/// name means doesn't depend on where it is any more.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Local {
+    /// The name of the variable in the source
+    pub name: String,
+    /// Whether it keeps its value from one call of the function to the next, starting at 0
+    pub persistent: bool,
+}
+
+/// An instruction, along with the statement in the source it was lowered from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Instruction {
+    /// What the instruction does
+    pub kind: InstructionKind,
+    /// Where the statement it was lowered from starts
+    pub span: Span,
+}
+
+/// The different kinds of instructions, which each do one thing
+///
+/// The operators of binary instructions always work out both of their sides, as and and or
+/// are lowered to branches instead
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum InstructionKind {
+    /// Puts value in a variable
+    Assign { local: LocalId, value: Operand },
+    /// Puts value in a temporary
+    Copy { dest: Temporary, value: Operand },
+    /// Puts what operator gives for left and right in a temporary
+    Binary { dest: Temporary, operator: Operator, left: Operand, right: Operand },
+    /// Puts what operator gives for operand in a temporary
+    Unary { dest: Temporary, operator: Operator, operand: Operand },
+    /// Calls function, putting what it returns in dest if it is used
+    Call { dest: Option<Temporary>, function: String, arguments: Vec<Operand> },
+    /// A block of inline C, which uses the variables by their names in the source
+    InlineC(String),
+}
+
+/// How a basic block ends, which is the only place that it can go to another one
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Terminator {
+    /// Goes to a block
+    Jump(BlockId),
+    /// Goes to then if cond isn't 0, and to otherwise if it is
+    Branch { cond: Operand, then: BlockId, otherwise: BlockId },
+    /// Returns from the function
+    Return(Operand),
+}
+
+/// A run of instructions that always run one after the other, from the first to the terminator
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BasicBlock {
+    /// The instructions, in order
+    pub instructions: Vec<Instruction>,
+    /// Where the block goes after them
+    pub terminator: Terminator,
+    /// Where the statement the terminator was lowered from starts, or the function if it has none
+    pub span: Span,
+}
+
+/// A function lowered to basic blocks
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Function {
+    /// The name of the function
+    pub name: String,
+    /// How many parameters the function has, which are its first locals
+    pub parameters: usize,
+    /// The variables of the function
+    pub locals: Vec<Local>,
+    /// What each temporary holds
+    pub temporaries: Vec<Type>,
+    /// The basic blocks, starting with the one that the function starts at
+    ///
+    /// Every block can be reached from the first one
+    pub blocks: Vec<BasicBlock>,
+    /// Where the function starts in the source
+    pub span: Span,
+}
+
+impl Terminator {
+    /// Returns the blocks that this can go to, in order
+    pub fn successors(&self) -> Vec<BlockId> {
+        match *self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch { then, otherwise, .. } => vec![then, otherwise],
+            Terminator::Return(_) => vec![],
+        }
+    }
+}
+
+/// Lowers each function written in haumea in files, in order
+///
+/// The instructions work values out in the order the interpreter does: the arguments of a
+/// call from left to right, and the right side of and and or only when it is needed. A
+/// variable declared without a value starts at 0, and a function that doesn't return
+/// anything returns 0. Code that can never run, like statements after a return, isn't
+/// in any block.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::ir;
+/// let source = "to clamp with (n) do\n    if n > 9 and n < 100 then return 9\n    return n\nend";
+/// let file = SourceFile { name: "main.hm".to_string(), program: parser::parse(Scanner::new(source)).unwrap() };
+/// let functions = ir::lower(&[file]);
+/// assert_eq!(functions[0].to_string(), "\
+/// to clamp with (n)
+/// b0:
+///     t1 = n > 9
+///     if t1 then b1 else b2
+/// b1:
+///     t2 = n < 100
+///     t0 = t2 != 0
+///     jump b3
+/// b2:
+///     t0 = 0
+///     jump b3
+/// b3:
+///     if t0 then b4 else b5
+/// b4:
+///     return 9
+/// b5:
+///     return n
+/// ");
+/// ```
+pub fn lower(files: &[SourceFile]) -> Vec<Function> {
+    let text_functions = texts::text_functions(files);
+    let callbacks = parser::callbacks(files);
+    files.iter()
+        .flat_map(|file| file.program.iter())
+        .filter_map(|func| func.code.as_ref().map(|code| (func, code)))
+        .map(|(func, code)| {
+            let mut lowerer = Lowerer::new(func, &text_functions, &callbacks);
+            lowerer.statement(code);
+            lowerer.finish()
+        })
+        .collect()
+}
+
+/// A basic block that is still being lowered, which has no terminator until it is finished
+struct PartialBlock {
+    instructions: Vec<Instruction>,
+    terminator: Option<(Terminator, Span)>,
+}
+
+/// Lowers the code of a function into basic blocks, one statement at a time
+struct Lowerer<'a> {
+    name: String,
+    parameters: usize,
+    locals: Vec<Local>,
+    temporaries: Vec<Type>,
+    blocks: Vec<PartialBlock>,
+    /// The block that instructions are added to
+    current: BlockId,
+    /// The variables declared in each block around the statement being lowered, innermost last
+    scopes: Vec<Vec<(&'a str, LocalId)>>,
+    /// The label of each loop around the statement being lowered, with the block after it
+    loops: Vec<(Option<&'a str>, BlockId)>,
+    span: Span,
+    function_span: Span,
+    text_functions: &'a HashSet<&'a str>,
+    callbacks: &'a Callbacks<'a>,
+}
+
+impl<'a> Lowerer<'a> {
+    fn new(func: &'a parser::Function, text_functions: &'a HashSet<&'a str>, callbacks: &'a Callbacks<'a>)
+           -> Lowerer<'a> {
+        let parameters = func.signature.as_ref().map_or(&[][..], |signature| &signature[..]);
+        let mut lowerer = Lowerer {
+            name: func.name.clone(),
+            parameters: parameters.len(),
+            locals: vec![],
+            temporaries: vec![],
+            blocks: vec![PartialBlock { instructions: vec![], terminator: None }],
+            current: 0,
+            scopes: vec![vec![]],
+            loops: vec![],
+            span: func.span,
+            function_span: func.span,
+            text_functions,
+            callbacks,
+        };
+        for parameter in parameters {
+            lowerer.declare(parameter, false);
+        }
+        lowerer
+    }
+
+    /// Returns the function, with a return of 0 at the end of the block the code ended in,
+    /// and without the blocks that can't be reached, which the rest are renumbered around
+    fn finish(mut self) -> Function {
+        let span = self.function_span;
+        self.terminate(Terminator::Return(Operand::Constant(0)), span);
+        let mut numbers: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        let mut order = vec![0];
+        numbers[0] = Some(0);
+        let mut index = 0;
+        while index < order.len() {
+            let block = &self.blocks[order[index]];
+            let (ref terminator, _) = *block.terminator.as_ref().expect("every block is finished");
+            for target in terminator.successors() {
+                if numbers[target].is_none() {
+                    numbers[target] = Some(order.len());
+                    order.push(target);
+                }
+            }
+            index += 1;
+        }
+        // The blocks keep the order they were made in, which is the order of the source
+        order.sort();
+        for (number, &old) in order.iter().enumerate() {
+            numbers[old] = Some(number);
+        }
+        let renumber = |id: BlockId| numbers[id].expect("the target of a reachable block is reachable");
+        let mut blocks = self.blocks.into_iter().map(Some).collect::<Vec<_>>();
+        let blocks = order.iter().map(|&old| {
+            let block = blocks[old].take().expect("each block is taken once");
+            let (terminator, span) = block.terminator.expect("every block is finished");
+            let terminator = match terminator {
+                Terminator::Jump(target) => Terminator::Jump(renumber(target)),
+                Terminator::Branch { cond, then, otherwise } => Terminator::Branch {
+                    cond,
+                    then: renumber(then),
+                    otherwise: renumber(otherwise),
+                },
+                Terminator::Return(value) => Terminator::Return(value),
+            };
+            BasicBlock { instructions: block.instructions, terminator, span }
+        }).collect();
+        Function {
+            name: self.name,
+            parameters: self.parameters,
+            locals: self.locals,
+            temporaries: self.temporaries,
+            blocks,
+            span,
+        }
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(PartialBlock { instructions: vec![], terminator: None });
+        self.blocks.len() - 1
+    }
+
+    fn new_temporary(&mut self, kind: Type) -> Temporary {
+        self.temporaries.push(kind);
+        self.temporaries.len() - 1
+    }
+
+    fn declare(&mut self, name: &'a str, persistent: bool) -> LocalId {
+        self.locals.push(Local { name: name.to_string(), persistent });
+        let id = self.locals.len() - 1;
+        self.scopes.last_mut().expect("there is always a scope").push((name, id));
+        id
+    }
+
+    /// Returns the variable that name means where the statement being lowered is, if it is one
+    fn lookup(&self, name: &str) -> Option<LocalId> {
+        self.scopes.iter().rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|&&(known, _)| known == name)
+            .map(|&(_, id)| id)
+    }
+
+    fn emit(&mut self, kind: InstructionKind) {
+        let span = self.span;
+        self.blocks[self.current].instructions.push(Instruction { kind, span });
+    }
+
+    /// Ends the current block with terminator, unless it has ended already
+    fn terminate(&mut self, terminator: Terminator, span: Span) {
+        let block = &mut self.blocks[self.current];
+        if block.terminator.is_none() {
+            block.terminator = Some((terminator, span));
+        }
+    }
+
+    /// Ends the current block with terminator, going on in a block that nothing goes to
+    /// for any statements after it
+    fn leave(&mut self, terminator: Terminator) {
+        let span = self.span;
+        self.terminate(terminator, span);
+        self.current = self.new_block();
+    }
+
+    /// Ends the current block by going to target, and goes on in target
+    fn go_to(&mut self, target: BlockId) {
+        let span = self.span;
+        self.terminate(Terminator::Jump(target), span);
+        self.current = target;
+    }
+
+    /// Lowers statement in a scope of its own, as a clause of an if or the body of a loop is
+    fn scoped(&mut self, statement: &'a Statement) {
+        self.scopes.push(vec![]);
+        self.statement(statement);
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, statement: &'a Statement) {
+        self.span = statement.span;
+        match statement.kind {
+            StatementKind::Return(ref expression) => {
+                let value = self.expression(expression);
+                self.leave(Terminator::Return(value));
+            },
+            StatementKind::Var(ref names) => {
+                for name in names {
+                    let local = self.declare(name, false);
+                    self.emit(InstructionKind::Assign { local, value: Operand::Constant(0) });
+                }
+            },
+            StatementKind::Persistent(ref name) => {
+                self.declare(name, true);
+            },
+            StatementKind::Define(ref name, ref expression) => {
+                let value = self.expression(expression);
+                let local = self.declare(name, false);
+                self.emit(InstructionKind::Assign { local, value });
+            },
+            StatementKind::Set(ref name, ref expression) => {
+                let value = self.expression(expression);
+                if let Some(local) = self.lookup(name) {
+                    self.emit(InstructionKind::Assign { local, value });
+                }
+            },
+            StatementKind::Change(ref name, ref expression) => {
+                let value = self.expression(expression);
+                if let Some(local) = self.lookup(name) {
+                    let dest = self.new_temporary(Type::Number);
+                    self.emit(InstructionKind::Binary { dest, operator: Operator::Add, left: Operand::Local(local), right: value });
+                    self.emit(InstructionKind::Assign { local, value: Operand::Temporary(dest) });
+                }
+            },
+            StatementKind::Swap(ref first, ref second) => {
+                if let (Some(first), Some(second)) = (self.lookup(first), self.lookup(second)) {
+                    let dest = self.new_temporary(Type::Number);
+                    self.emit(InstructionKind::Copy { dest, value: Operand::Local(first) });
+                    self.emit(InstructionKind::Assign { local: first, value: Operand::Local(second) });
+                    self.emit(InstructionKind::Assign { local: second, value: Operand::Temporary(dest) });
+                }
+            },
+            StatementKind::If { ref cond, ref if_clause, ref else_clause, .. } => {
+                let cond = self.expression(cond);
+                let then = self.new_block();
+                let otherwise = self.new_block();
+                let end = if else_clause.is_some() { self.new_block() } else { otherwise };
+                self.terminate(Terminator::Branch { cond, then, otherwise }, statement.span);
+                self.current = then;
+                self.scoped(if_clause);
+                self.go_to(end);
+                if let Some(ref else_clause) = *else_clause {
+                    self.current = otherwise;
+                    self.scoped(else_clause);
+                    self.go_to(end);
+                }
+            },
+            StatementKind::Repeat { ref count, ref label, ref body } => {
+                let count = self.expression(count);
+                let counter = self.new_temporary(Type::Number);
+                self.emit(InstructionKind::Copy { dest: counter, value: count });
+                let head = self.new_block();
+                self.go_to(head);
+                let more = self.new_temporary(Type::Number);
+                self.emit(InstructionKind::Binary {
+                    dest: more,
+                    operator: Operator::Gt,
+                    left: Operand::Temporary(counter),
+                    right: Operand::Constant(0),
+                });
+                let body_block = self.new_block();
+                let exit = self.new_block();
+                self.terminate(Terminator::Branch { cond: Operand::Temporary(more), then: body_block, otherwise: exit },
+                               statement.span);
+                self.current = body_block;
+                self.loops.push((label.as_ref().map(|label| &label[..]), exit));
+                self.scoped(body);
+                self.loops.pop();
+                self.span = statement.span;
+                self.emit(InstructionKind::Binary {
+                    dest: counter,
+                    operator: Operator::Sub,
+                    left: Operand::Temporary(counter),
+                    right: Operand::Constant(1),
+                });
+                self.go_to(head);
+                self.current = exit;
+            },
+            StatementKind::Break(ref label) => {
+                let exit = self.loops.iter().rev().find(|&&(known, _)| known == Some(&label[..])).map(|&(_, exit)| exit);
+                if let Some(exit) = exit {
+                    self.leave(Terminator::Jump(exit));
+                }
+            },
+            StatementKind::Do(ref block) => {
+                self.scopes.push(vec![]);
+                for statement in block {
+                    self.statement(statement);
+                }
+                self.scopes.pop();
+            },
+            StatementKind::Call { ref function, ref arguments } => {
+                let arguments = self.arguments(function, arguments);
+                self.emit(InstructionKind::Call { dest: None, function: function.clone(), arguments });
+            },
+            StatementKind::InlineC(ref c_source) => self.emit(InstructionKind::InlineC(c_source.clone())),
+        }
+    }
+
+    /// Lowers expression, returning the operand that has its value
+    fn expression(&mut self, expression: &'a Expression) -> Operand {
+        match *expression {
+            Expression::Integer(value) => Operand::Constant(value.into()),
+            Expression::Text(ref text) => Operand::Text(text.clone()),
+            Expression::Ident(ref name) => match self.lookup(name) {
+                Some(local) => Operand::Local(local),
+                None => Operand::Function(name.clone()),
+            },
+            Expression::BinaryOp { operator: Operator::LogicalAnd, ref left, ref right } => self.short_circuit(left, right, true),
+            Expression::BinaryOp { operator: Operator::LogicalOr, ref left, ref right } => self.short_circuit(left, right, false),
+            Expression::BinaryOp { ref operator, ref left, ref right } => {
+                let left = self.expression(left);
+                let right = self.expression(right);
+                let dest = self.new_temporary(Type::Number);
+                self.emit(InstructionKind::Binary { dest, operator: operator.clone(), left, right });
+                Operand::Temporary(dest)
+            },
+            Expression::UnaryOp { ref operator, ref expression } => {
+                let operand = self.expression(expression);
+                let dest = self.new_temporary(Type::Number);
+                self.emit(InstructionKind::Unary { dest, operator: operator.clone(), operand });
+                Operand::Temporary(dest)
+            },
+            Expression::Call { ref function, ref arguments } => {
+                let arguments = self.arguments(function, arguments);
+                let kind = if self.text_functions.contains(&function[..]) { Type::Text } else { Type::Number };
+                let dest = self.new_temporary(kind);
+                self.emit(InstructionKind::Call { dest: Some(dest), function: function.clone(), arguments });
+                Operand::Temporary(dest)
+            },
+        }
+    }
+
+    /// Lowers left and right, or left or right, into a branch on left, giving 1 or 0
+    fn short_circuit(&mut self, left: &'a Expression, right: &'a Expression, and: bool) -> Operand {
+        let dest = self.new_temporary(Type::Number);
+        let left = self.expression(left);
+        let right_block = self.new_block();
+        let known = self.new_block();
+        let end = self.new_block();
+        let span = self.span;
+        let (then, otherwise) = if and { (right_block, known) } else { (known, right_block) };
+        self.terminate(Terminator::Branch { cond: left, then, otherwise }, span);
+        self.current = right_block;
+        let right = self.expression(right);
+        self.emit(InstructionKind::Binary { dest, operator: Operator::NotEquals, left: right, right: Operand::Constant(0) });
+        self.go_to(end);
+        self.current = known;
+        self.emit(InstructionKind::Copy { dest, value: Operand::Constant(if and { 0 } else { 1 }) });
+        self.go_to(end);
+        Operand::Temporary(dest)
+    }
+
+    /// Lowers the arguments of a call of function, in order
+    fn arguments(&mut self, function: &str, arguments: &'a [Expression]) -> Vec<Operand> {
+        let callbacks = self.callbacks.get(function).map_or(&[][..], |positions| &positions[..]);
+        arguments.iter().enumerate().map(|(index, argument)| match *argument {
+            // A function given to C is named by its name, even if a variable has it too
+            Expression::Ident(ref name) if callbacks.contains(&index) => Operand::Function(name.clone()),
+            _ => self.expression(argument),
+        }).collect()
+    }
+}
+
+impl Function {
+    /// Returns the name that local is written with, which has a number after it if an
+    /// earlier local has the same name
+    pub fn local_name(&self, local: LocalId) -> String {
+        let name = &self.locals[local].name;
+        match self.locals[..local].iter().filter(|earlier| earlier.name == *name).count() {
+            0 => name.clone(),
+            earlier => format!("{}.{}", name, earlier),
+        }
+    }
+
+    /// Returns operand written as it is in the text of the function
+    pub fn operand_to_string(&self, operand: &Operand) -> String {
+        match *operand {
+            Operand::Constant(value) => value.to_string(),
+            Operand::Text(ref text) => pretty::text_literal(text),
+            Operand::Temporary(temporary) => format!("t{}", temporary),
+            Operand::Local(local) => self.local_name(local),
+            Operand::Function(ref name) => name.clone(),
+        }
+    }
+
+    /// Returns instruction written as it is in the text of the function, like `t1 = n > 9`
+    pub fn instruction_to_string(&self, instruction: &Instruction) -> String {
+        let operand = |operand: &Operand| self.operand_to_string(operand);
+        match instruction.kind {
+            InstructionKind::Assign { local, ref value } => format!("{} = {}", self.local_name(local), operand(value)),
+            InstructionKind::Copy { dest, ref value } => format!("{} = {}", self.dest_to_string(dest), operand(value)),
+            InstructionKind::Binary { dest, ref operator, ref left, ref right } => {
+                format!("{} = {} {} {}", self.dest_to_string(dest), operand(left), operator_name(operator), operand(right))
+            },
+            InstructionKind::Unary { dest, ref operator, operand: ref value } => {
+                format!("{} = {}{}", self.dest_to_string(dest), operator_name(operator), operand(value))
+            },
+            InstructionKind::Call { dest, ref function, ref arguments } => {
+                let arguments = arguments.iter().map(operand).collect::<Vec<_>>().join(", ");
+                match dest {
+                    Some(dest) => format!("{} = {}({})", self.dest_to_string(dest), function, arguments),
+                    None => format!("{}({})", function, arguments),
+                }
+            },
+            InstructionKind::InlineC(ref c_source) => format!("c {{{}}}", c_source),
+        }
+    }
+
+    /// Returns terminator written as it is in the text of the function, like `jump b2`
+    pub fn terminator_to_string(&self, terminator: &Terminator) -> String {
+        match *terminator {
+            Terminator::Jump(target) => format!("jump b{}", target),
+            Terminator::Branch { ref cond, then, otherwise } => {
+                format!("if {} then b{} else b{}", self.operand_to_string(cond), then, otherwise)
+            },
+            Terminator::Return(ref value) => format!("return {}", self.operand_to_string(value)),
+        }
+    }
+
+    /// Returns the temporary dest written as the place an instruction puts a value,
+    /// with its type if it is a text
+    fn dest_to_string(&self, dest: Temporary) -> String {
+        match self.temporaries[dest] {
+            Type::Number => format!("t{}", dest),
+            Type::Text => format!("t{}: text", dest),
+        }
+    }
+}
+
+/// Writes the function as text, like `t1 = n > 9`, which `--emit ir` prints
+///
+/// Persistent variables are listed after the signature.
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "to {}", self.name)?;
+        if self.parameters > 0 {
+            let parameters = (0..self.parameters).map(|local| self.local_name(local)).collect::<Vec<_>>();
+            write!(f, " with ({})", parameters.join(", "))?;
+        }
+        writeln!(f)?;
+        for (local, _) in self.locals.iter().enumerate().filter(|(_, local)| local.persistent) {
+            writeln!(f, "    persistent variable {}", self.local_name(local))?;
+        }
+        for (id, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "b{}:", id)?;
+            for instruction in &block.instructions {
+                writeln!(f, "    {}", self.instruction_to_string(instruction))?;
+            }
+            writeln!(f, "    {}", self.terminator_to_string(&block.terminator))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns how operator is written in the text of a function, which is how it is in haumea
+fn operator_name(operator: &Operator) -> &'static str {
+    match *operator {
+        Operator::LogicalNot => "not ",
+        ref operator => pretty::operator_name(operator),
+    }
+}
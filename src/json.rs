@@ -0,0 +1,345 @@
+/// src/json.rs
+/// A small JSON value type, for the tools that talk to editors and other programs
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A JSON value
+///
+/// The members of an object are kept in the order they were added, so output is predictable
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Constructs an object from its members
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::json::Json;
+    /// let json = Json::object(vec![("name", Json::from("main")), ("line", Json::from(3))]);
+    /// assert_eq!(json.to_string(), r#"{"name":"main","line":3}"#);
+    /// ```
+    pub fn object<'a, I: IntoIterator<Item = (&'a str, Json)>>(members: I) -> Json {
+        Json::Object(members.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    /// Parses a JSON value from text
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::json::Json;
+    /// let json = Json::parse(r#"{"id": 1, "params": {"uri": "file:///a.hm", "ok": [true, null]}}"#).unwrap();
+    /// assert_eq!(json.get("id").and_then(Json::as_i64), Some(1));
+    /// assert_eq!(json.get("params").and_then(|p| p.get("uri")).and_then(Json::as_str), Some("file:///a.hm"));
+    /// assert!(Json::parse("[1, 2").is_err());
+    /// ```
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            chars: text.chars().peekable(),
+        };
+        let value = parser.parse_value()?;
+        parser.skip_white();
+        match parser.chars.next() {
+            None => Ok(value),
+            Some(c) => Err(format!("Unexpected '{}' after the JSON value", c)),
+        }
+    }
+
+    /// Returns the member named key, if this is an object that has one
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => members.iter().find(|member| member.0 == key).map(|member| &member.1),
+            _ => None,
+        }
+    }
+
+    /// Returns the string, if this is a string
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the number, if this is a number
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Json::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Returns the number, if this is a number that is an integer
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Json::Number(n) if n.fract() == 0.0 => Some(n as i64),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements, if this is an array
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match *self {
+            Json::Array(ref elements) => Some(elements),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Json {
+        Json::Bool(b)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(n: i64) -> Json {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(n: i32) -> Json {
+        Json::Number(f64::from(n))
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Json {
+        Json::Number(n as f64)
+    }
+}
+
+impl<'a> From<&'a str> for Json {
+    fn from(s: &'a str) -> Json {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        Json::String(s)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(elements: Vec<Json>) -> Json {
+        Json::Array(elements)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Json {
+        match value {
+            Some(value) => value.into(),
+            None => Json::Null,
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    /// Writes the value as compact JSON, with no whitespace
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            // JSON has no infinities or NaNs
+            Json::Number(n) if !n.is_finite() => write!(f, "null"),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(ref s) => write_string(f, s),
+            Json::Array(ref elements) => {
+                write!(f, "[")?;
+                for (index, element) in elements.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(ref members) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+/// Writes s as a JSON string literal
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Reads a JSON value from some text
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn parse_value(&mut self) -> Result<Json, String> {
+        self.skip_white();
+        match self.chars.peek().cloned() {
+            Some('n') => self.parse_word("null", Json::Null),
+            Some('t') => self.parse_word("true", Json::Bool(true)),
+            Some('f') => self.parse_word("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.parse_string()?)),
+            Some('[') => {
+                self.chars.next();
+                let mut elements = vec![];
+                self.skip_white();
+                if self.chars.peek() == Some(&']') {
+                    self.chars.next();
+                    return Ok(Json::Array(elements));
+                }
+                loop {
+                    elements.push(self.parse_value()?);
+                    self.skip_white();
+                    match self.chars.next() {
+                        Some(',') => (),
+                        Some(']') => return Ok(Json::Array(elements)),
+                        _ => return Err("Expected ',' or ']' in an array".to_string()),
+                    }
+                }
+            },
+            Some('{') => {
+                self.chars.next();
+                let mut members = vec![];
+                self.skip_white();
+                if self.chars.peek() == Some(&'}') {
+                    self.chars.next();
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_white();
+                    if self.chars.peek() != Some(&'"') {
+                        return Err("Expected a string as the key of an object member".to_string());
+                    }
+                    let key = self.parse_string()?;
+                    self.skip_white();
+                    if self.chars.next() != Some(':') {
+                        return Err("Expected ':' after the key of an object member".to_string());
+                    }
+                    members.push((key, self.parse_value()?));
+                    self.skip_white();
+                    match self.chars.next() {
+                        Some(',') => (),
+                        Some('}') => return Ok(Json::Object(members)),
+                        _ => return Err("Expected ',' or '}' in an object".to_string()),
+                    }
+                }
+            },
+            Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+            Some(c) => Err(format!("Unexpected '{}' at the start of a JSON value", c)),
+            None => Err("Unexpected end of JSON".to_string()),
+        }
+    }
+
+    /// Reads the literal word, which stands for value
+    fn parse_word(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err(format!("Expected {}", word));
+            }
+        }
+        Ok(value)
+    }
+
+    fn parse_number(&mut self) -> Result<Json, String> {
+        let mut text = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' {
+                text.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        text.parse::<f64>().map(Json::Number).map_err(|_| format!("{} is not a valid number", text))
+    }
+
+    /// Reads a string literal, starting at its opening quote
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.chars.next();
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.chars.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => {
+                        let first = self.parse_hex()?;
+                        // Characters outside the Basic Multilingual Plane are written as surrogate pairs
+                        let code = if (0xD800..0xDC00).contains(&first) {
+                            if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+                                return Err("Expected the second half of a surrogate pair".to_string());
+                            }
+                            let second = self.parse_hex()?;
+                            0x10000 + ((first - 0xD800) << 10) + (second.wrapping_sub(0xDC00) & 0x3FF)
+                        } else {
+                            first
+                        };
+                        s.push(::std::char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    },
+                    _ => return Err("Invalid escape in a string".to_string()),
+                },
+                Some(c) => s.push(c),
+                None => return Err("Unterminated string".to_string()),
+            }
+        }
+    }
+
+    /// Reads the four hexadecimal digits of a \u escape
+    fn parse_hex(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.chars.next().and_then(|c| c.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => return Err("Invalid \\u escape in a string".to_string()),
+            }
+        }
+        Ok(code)
+    }
+
+    fn skip_white(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if c == ' ' || c == '\t' || c == '\n' || c == '\r' {
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+    }
+}
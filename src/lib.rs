@@ -1,3 +1,161 @@
 pub mod scanner;
 pub mod parser;
 pub mod codegen;
+pub mod cache;
+pub mod coverage;
+pub mod callgraph;
+pub mod cfg;
+pub mod checker;
+pub mod codes;
+pub mod diagnostic;
+#[cfg(feature = "os")]
+pub mod differential;
+pub mod doc;
+pub mod dot;
+pub mod emitter;
+pub mod flatten;
+pub mod grammar;
+pub mod inliner;
+pub mod interpreter;
+pub mod ir;
+pub mod json;
+pub mod lints;
+pub mod lsp;
+pub mod mi;
+pub mod optimizer;
+pub mod options;
+pub mod pretty;
+pub mod project;
+pub mod runtime;
+pub mod sarif;
+pub mod semantic;
+pub mod snapshot;
+pub mod sourcemap;
+pub mod synthetic;
+#[cfg(feature = "os")]
+pub mod testing;
+pub mod texts;
+#[cfg(feature = "os")]
+pub mod toolchain;
+
+use diagnostic::{Diagnostic, ErrorKind, Severity};
+use options::{CompilerOptions, OptLevel, OutputKind};
+use parser::SourceFile;
+use scanner::Scanner;
+
+/// Compiles the program made of files to C as options say, or returns its errors
+///
+/// This is what every front end does with a program once it has parsed it. The errors are the ones
+/// that check finds, and the program is then prepared and compiled with the runtime that
+/// options.runtime picks. With a library for options.output_kind, every function but main
+/// is compiled, as `haumea build --lib` does. The warnings are left to the front end, which
+/// gets them from check too.
+///
+/// # Examples
+/// ```
+/// # use haumea::options::CompilerOptions;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::scanner::Scanner;
+/// let options = CompilerOptions::new("main.hm");
+/// let parse = |source| vec![SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// }];
+/// let c = haumea::compile(&parse("to main do display(2 * 3) end"), &options).unwrap();
+/// assert!(c.contains("display(6l);"));
+/// let errors = haumea::compile(&parse("to main do display(x) end"), &options).unwrap_err();
+/// assert_eq!(errors[0].to_string(), "main.hm:1:12: error[E0201]: The variable x is not declared");
+/// ```
+pub fn compile(files: &[SourceFile], options: &CompilerOptions) -> Result<String, Vec<Diagnostic>> {
+    let errors = check(files, options).into_iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let files = prepare(files, options);
+    let full_runtime = runtime::needs_full_runtime(&files, options.runtime)
+        .map_err(|message| vec![Diagnostic::of_program(ErrorKind::Semantic, message)])?;
+    let mut c = String::new();
+    match options.output_kind {
+        OutputKind::StaticLibrary | OutputKind::SharedLibrary => {
+            codegen::compile_library(&mut c, &files, full_runtime, options)
+        },
+        OutputKind::Source | OutputKind::Executable => codegen::compile_files(&mut c, &files, full_runtime, options),
+    }
+    Ok(c)
+}
+
+/// Runs the semantic checks on the program made of files, returning its errors and warnings
+///
+/// Inline C is an error unless options.allow_inline_c. The lints are reported at the levels in
+/// options.lints, and with options.deny_warnings they are all errors.
+pub fn check(files: &[SourceFile], options: &CompilerOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = checker::check(files);
+    if !options.allow_inline_c {
+        diagnostics.extend(checker::inline_c_errors(files));
+    }
+    for diagnostic in checker::lints(files, &options.lints) {
+        diagnostics.push(if options.deny_warnings { diagnostic.denied() } else { diagnostic });
+    }
+    diagnostics
+}
+
+/// Returns files as they are compiled to C with options, after they have been checked
+///
+/// Their comparisons of texts are made into calls of same_text, then they are optimized at
+/// options.opt_level, and their expressions are flattened with options.temporaries.
+/// Coverage is of the program as it was written, so with options.instrument_coverage it isn't
+/// optimized or flattened, and code that can never run is reported as not being run.
+pub fn prepare(files: &[SourceFile], options: &CompilerOptions) -> Vec<SourceFile> {
+    let files = texts::compare_by_chars(files);
+    if options.instrument_coverage {
+        return files.into_owned();
+    }
+    let files = optimizer::optimize(&files, options.opt_level);
+    if options.temporaries {
+        return flatten::flatten_expressions(&files);
+    }
+    files
+}
+
+/// Runs source through every part of haumea that doesn't need a C compiler, returning its errors
+///
+/// It is lexed, classified for highlighting, parsed, checked, formatted, documented, drawn as graphs, optimized, lowered, and compiled to C,
+/// whether or not it has semantic errors. However malformed source is, this should give
+/// errors rather than panicking, which the fuzz target in fuzz/ makes sure of.
+///
+/// # Examples
+/// ```
+/// assert!(haumea::check_no_panic("to main do display(1 + 2) end").is_ok());
+/// assert!(haumea::check_no_panic("to main do display(x) end").is_err());
+/// assert!(haumea::check_no_panic("to main do c {").is_err());
+/// let deep = format!("to main do display({}1{}) end", "(".repeat(100000), ")".repeat(100000));
+/// assert!(haumea::check_no_panic(&deep).is_err());
+/// ```
+pub fn check_no_panic(source: &str) -> Result<(), Vec<Diagnostic>> {
+    semantic::classify(source);
+    let program = parser::parse(Scanner::new(source)).map_err(|e| vec![e])?;
+    let mut options = CompilerOptions::new("input.hm");
+    let files = vec![SourceFile {
+        name: options.source_name(),
+        program,
+    }];
+    options.lints.set("all", lints::Level::Warn).unwrap_or(());
+    options.opt_level = OptLevel::O2;
+    let errors = check(&files, &options).into_iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .collect::<Vec<_>>();
+    pretty::print_program(&mut String::new(), &files[0].program);
+    doc::markdown(&files);
+    dot::program_to_dot(&files);
+    callgraph::CallGraph::new(&files).to_json();
+    // The rest of compile is done whether or not there are errors, with and without temporaries
+    let optimized = prepare(&files, &options);
+    let full_runtime = runtime::needs_full_runtime(&optimized, options.runtime).unwrap_or(true);
+    cfg::to_dot(&ir::lower(&optimized));
+    codegen::compile_files(&mut String::new(), &optimized, full_runtime, &options);
+    options.temporaries = true;
+    codegen::compile_files(&mut String::new(), &prepare(&files, &options), full_runtime, &options);
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
@@ -0,0 +1,128 @@
+/// src/lints.rs
+/// The lints that the checker can warn about, and whether each one is allowed, warned about, or denied
+use diagnostic::Diagnostic;
+
+/// Whether a lint is reported, and how
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// The lint isn't reported at all
+    Allow,
+    /// The lint is reported as a warning, which doesn't stop the program from being compiled
+    Warn,
+    /// The lint is reported as an error
+    Deny,
+}
+
+impl Level {
+    /// Returns the Level written as name in a manifest, like warn
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::lints::Level;
+    /// assert_eq!(Level::from_name("deny"), Some(Level::Deny));
+    /// assert_eq!(Level::from_name("forbid"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// Something that the checker can warn about, which is allowed but easy to get wrong
+#[derive(Debug, PartialEq)]
+pub struct Lint {
+    /// The name of the lint on the command line and in manifests, like unused-variable
+    pub name: &'static str,
+    /// The code of its warnings, which `haumea explain` explains
+    pub code: &'static str,
+    /// The level of the lint when nothing sets it
+    pub default: Level,
+}
+
+/// Every lint, in the order of their codes
+///
+/// The ones about code that was always allowed are allowed unless they are turned on, so a
+/// program that compiles with one version of haumea doesn't start getting warnings with the
+/// next. deprecated is only about functions that a program marks itself, so it warns unless it
+/// is allowed. `all` stands for every one of them.
+pub const LINTS: &[Lint] = &[
+    Lint { name: "short-circuit-call", code: "W0001", default: Level::Allow },
+    Lint { name: "unused-variable", code: "W0002", default: Level::Allow },
+    Lint { name: "shadowing", code: "W0003", default: Level::Allow },
+    Lint { name: "deprecated", code: "W0004", default: Level::Warn },
+];
+
+/// Returns the lint named name, or None if there is no such lint
+pub fn find(name: &str) -> Option<&'static Lint> {
+    LINTS.iter().find(|lint| lint.name == name)
+}
+
+/// Returns the lint whose warnings have code, or None if code isn't the code of a lint
+pub fn with_code(code: &str) -> Option<&'static Lint> {
+    LINTS.iter().find(|lint| lint.code == code)
+}
+
+/// The levels that lints are set to, like with `-W unused-variable`
+///
+/// The levels are set in order, so a later one wins over an earlier one for the same lint,
+/// which lets `-W all -A shadowing` warn about everything but shadowing.
+///
+/// # Examples
+/// ```
+/// # use haumea::lints::{self, Level, LintLevels};
+/// let mut levels = LintLevels::default();
+/// let shadowing = lints::find("shadowing").unwrap();
+/// assert_eq!(levels.level(shadowing), Level::Allow);
+/// assert_eq!(levels.level(lints::find("deprecated").unwrap()), Level::Warn);
+/// levels.set("all", Level::Warn).unwrap();
+/// levels.set("shadowing", Level::Deny).unwrap();
+/// assert_eq!(levels.level(shadowing), Level::Deny);
+/// assert_eq!(levels.level(lints::find("unused-variable").unwrap()), Level::Warn);
+/// assert_eq!(levels.set("unused", Level::Warn).unwrap_err(), "Unknown lint 'unused'");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LintLevels {
+    levels: Vec<(&'static str, Level)>,
+}
+
+impl LintLevels {
+    /// Sets the lint named name, or every lint if name is all, to level
+    pub fn set(&mut self, name: &str, level: Level) -> Result<(), String> {
+        match name {
+            "all" => self.levels.push(("all", level)),
+            _ => match find(name) {
+                Some(lint) => self.levels.push((lint.name, level)),
+                None => return Err(format!("Unknown lint '{}'", name)),
+            },
+        }
+        Ok(())
+    }
+
+    /// Sets the levels in other after the ones in self, so that they win over them
+    pub fn extend(&mut self, other: LintLevels) {
+        self.levels.extend(other.levels);
+    }
+
+    /// Returns the level that lint is set to
+    pub fn level(&self, lint: &Lint) -> Level {
+        self.levels.iter().rev()
+            .find(|&&(name, _)| name == "all" || name == lint.name)
+            .map_or(lint.default, |&(_, level)| level)
+    }
+
+    /// Returns true if any lint isn't allowed, so the checker has something to look for
+    pub fn any_reported(&self) -> bool {
+        LINTS.iter().any(|lint| self.level(lint) != Level::Allow)
+    }
+}
+
+impl Diagnostic {
+    /// Returns the lint that this Diagnostic is a warning of, if it is one
+    pub fn lint(&self) -> Option<&'static Lint> {
+        self.code.and_then(with_code)
+    }
+}
@@ -0,0 +1,455 @@
+/// src/lsp.rs
+/// A Language Server Protocol server, so that editors can show haumea errors as they are typed
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::slice;
+use checker::{self, BUILTINS};
+use diagnostic::Diagnostic;
+use json::Json;
+use parser::{self, Function, SourceFile};
+use pretty::function_header;
+use scanner::{Scanner, Span, Token};
+use semantic::{self, SemanticToken, TOKEN_KINDS, token_length};
+
+/// The JSON-RPC error code for a method that the server doesn't know
+const METHOD_NOT_FOUND: i64 = -32601;
+/// The JSON-RPC error code for a request with the wrong parameters
+const INVALID_PARAMS: i64 = -32602;
+
+/// The LSP SymbolKind of a function
+const FUNCTION_SYMBOL: i64 = 12;
+/// The LSP DiagnosticSeverity of an error
+const ERROR_SEVERITY: i64 = 1;
+/// The LSP TextDocumentSyncKind for sending the whole document on every change
+const FULL_SYNC: i64 = 1;
+/// The semantic token modifiers that the server uses, each of which is a bit of the modifiers of a token
+const TOKEN_MODIFIERS: &[&str] = &["declaration", "defaultLibrary"];
+
+/// Runs the language server, reading messages from input and writing replies to output,
+/// until the client tells it to exit
+///
+/// Every open document is checked on its own, as if it were the whole program
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> Result<(), String> {
+    let mut server = Server {
+        documents: BTreeMap::new(),
+        shut_down: false,
+    };
+    loop {
+        let message = match read_message(&mut input)? {
+            Some(message) => message,
+            None => return Err("The client closed the connection without exiting".to_string()),
+        };
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        if method == "exit" {
+            return if server.shut_down {
+                Ok(())
+            } else {
+                Err("The client exited without shutting down the server".to_string())
+            };
+        }
+        let mut replies = vec![];
+        match message.get("id") {
+            Some(id) => {
+                let reply = match server.request(method, &params) {
+                    Ok(result) => Json::object(vec![("jsonrpc", Json::from("2.0")),
+                                                    ("id", id.clone()),
+                                                    ("result", result)]),
+                    Err((code, message)) => {
+                        let error = Json::object(vec![("code", Json::from(code)), ("message", Json::from(message))]);
+                        Json::object(vec![("jsonrpc", Json::from("2.0")), ("id", id.clone()), ("error", error)])
+                    },
+                };
+                replies.push(reply);
+            },
+            None => server.notification(method, &params, &mut replies),
+        }
+        for reply in replies {
+            write_message(&mut output, &reply)?;
+        }
+    }
+}
+
+/// An open document, along with what the server knows about it
+struct Document {
+    /// The text of the document
+    text: String,
+    /// Every token in the document, including any that aren't valid
+    tokens: Vec<(Token, Span)>,
+    /// The parsed program, if the document could be parsed
+    program: Option<SourceFile>,
+    /// The errors in the document
+    errors: Vec<Diagnostic>,
+}
+
+impl Document {
+    /// Scans, parses, and checks text, which is the document at uri
+    fn new(uri: &str, text: String) -> Document {
+        let tokens = semantic::spanned_tokens(&text);
+        let (program, errors) = match Scanner::new(&text).tokenize().and_then(parser::parse_tokens) {
+            Ok(program) => {
+                let file = SourceFile {
+                    name: uri.to_string(),
+                    program,
+                };
+                let errors = checker::check(slice::from_ref(&file));
+                (Some(file), errors)
+            },
+            Err(error) => (None, vec![error]),
+        };
+        Document {
+            text,
+            tokens,
+            program,
+            errors,
+        }
+    }
+
+    /// Returns the function named name, if the document defines it
+    fn function(&self, name: &str) -> Option<&Function> {
+        self.program.as_ref().and_then(|file| file.program.iter().find(|func| func.name == name))
+    }
+
+    /// Returns the identifier or keyword at position, along with its index in self.tokens
+    fn word_at(&self, position: &Json) -> Option<(usize, &str)> {
+        let line = position.get("line").and_then(Json::as_i64)? as usize + 1;
+        let column = position.get("character").and_then(Json::as_i64)? as usize + 1;
+        self.tokens.iter().enumerate().filter_map(|(index, &(ref token, span))| match *token {
+            Token::Ident(ref name) | Token::Keyword(ref name)
+                if span.line == line && span.column <= column && column <= span.column + name.len() => {
+                Some((index, &name[..]))
+            },
+            _ => None,
+        }).next()
+    }
+
+    /// Returns the range of the token that starts at span
+    fn token_range(&self, span: Span) -> Json {
+        let length = self.tokens.iter().find(|&&(_, token_span)| token_span == span)
+            .map(|(token, _)| token_length(token))
+            .unwrap_or(1);
+        range(span, Span { line: span.line, column: span.column + length })
+    }
+
+    /// Returns the range of the name of func
+    fn name_range(&self, func: &Function) -> Json {
+        // The name comes after `to` or `external function`, and external text functions have an identifier before it
+        let start = self.tokens.iter().position(|&(_, span)| span == func.span).unwrap_or(self.tokens.len());
+        match self.tokens[start..].iter().find(|(token, _)| matches!(*token, Token::Ident(ref name) if *name == func.name)) {
+            Some(&(_, span)) => self.token_range(span),
+            None => self.token_range(func.span),
+        }
+    }
+
+    /// Returns where the document ends
+    fn end(&self) -> Span {
+        let line = self.text.split('\n').count();
+        let column = self.text.split('\n').next_back().map(|line| line.chars().count()).unwrap_or(0) + 1;
+        Span { line, column }
+    }
+}
+
+/// The state of the language server
+struct Server {
+    /// The open documents, by URI
+    documents: BTreeMap<String, Document>,
+    /// Whether the client has asked the server to shut down
+    shut_down: bool,
+}
+
+impl Server {
+    /// Handles a request from the client, returning its result or an error code and message
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+        match method {
+            "initialize" => Ok(Json::object(vec![
+                ("capabilities", Json::object(vec![
+                    ("textDocumentSync", Json::from(FULL_SYNC)),
+                    ("definitionProvider", Json::from(true)),
+                    ("hoverProvider", Json::from(true)),
+                    ("documentSymbolProvider", Json::from(true)),
+                    ("semanticTokensProvider", Json::object(vec![
+                        ("legend", Json::object(vec![
+                            ("tokenTypes", Json::from(TOKEN_KINDS.iter().map(|kind| Json::from(kind.name())).collect::<Vec<_>>())),
+                            ("tokenModifiers", Json::from(TOKEN_MODIFIERS.iter().map(|&modifier| Json::from(modifier)).collect::<Vec<_>>())),
+                        ])),
+                        ("full", Json::from(true)),
+                    ])),
+                ])),
+                ("serverInfo", Json::object(vec![
+                    ("name", Json::from("haumea")),
+                    ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+                ])),
+            ])),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            },
+            "textDocument/definition" => {
+                let (uri, document) = self.document(params)?;
+                let position = params.get("position").cloned().unwrap_or(Json::Null);
+                Ok(match document.word_at(&position) {
+                    Some((index, name)) => self.definition(uri, document, index, name).unwrap_or(Json::Null),
+                    None => Json::Null,
+                })
+            },
+            "textDocument/hover" => {
+                let (_, document) = self.document(params)?;
+                let position = params.get("position").cloned().unwrap_or(Json::Null);
+                Ok(match document.word_at(&position) {
+                    Some((index, name)) => match self.hover(name) {
+                        Some(contents) => Json::object(vec![
+                            ("contents", Json::object(vec![("kind", Json::from("markdown")),
+                                                           ("value", Json::from(contents))])),
+                            ("range", document.token_range(document.tokens[index].1)),
+                        ]),
+                        None => Json::Null,
+                    },
+                    None => Json::Null,
+                })
+            },
+            "textDocument/documentSymbol" => {
+                let (_, document) = self.document(params)?;
+                let functions = match document.program {
+                    Some(ref file) => &file.program[..],
+                    None => &[],
+                };
+                let symbols = functions.iter().enumerate().map(|(index, func)| {
+                    let end = match functions.get(index + 1) {
+                        Some(next) => next.span,
+                        None => document.end(),
+                    };
+                    Json::object(vec![
+                        ("name", Json::from(&func.name[..])),
+                        ("detail", Json::from(signature(func).trim_start())),
+                        ("kind", Json::from(FUNCTION_SYMBOL)),
+                        ("range", range(func.span, end)),
+                        ("selectionRange", document.name_range(func)),
+                    ])
+                }).collect::<Vec<_>>();
+                Ok(Json::from(symbols))
+            },
+            "textDocument/semanticTokens/full" => {
+                let (_, document) = self.document(params)?;
+                let tokens = semantic::classify(&document.text);
+                Ok(Json::object(vec![("data", Json::from(encode_semantic_tokens(&tokens)))]))
+            },
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+        }
+    }
+
+    /// Handles a notification from the client, adding any messages to send back to replies
+    fn notification(&mut self, method: &str, params: &Json, replies: &mut Vec<Json>) {
+        let document = params.get("textDocument");
+        let uri = match document.and_then(|document| document.get("uri")).and_then(Json::as_str) {
+            Some(uri) => uri.to_string(),
+            None => return,
+        };
+        let text = match method {
+            "textDocument/didOpen" => document.and_then(|document| document.get("text")).and_then(Json::as_str),
+            // Only full syncs are supported, so the last change is the whole document
+            "textDocument/didChange" => params.get("contentChanges")
+                .and_then(Json::as_array)
+                .and_then(|changes| changes.last())
+                .and_then(|change| change.get("text"))
+                .and_then(Json::as_str),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                replies.push(publish_diagnostics(&uri, vec![]));
+                return;
+            },
+            _ => return,
+        };
+        if let Some(text) = text {
+            let document = Document::new(&uri, text.to_string());
+            let diagnostics = document.errors.iter().map(|error| {
+                let span = error.span.unwrap_or(Span { line: 1, column: 1 });
+                let message = match error.help {
+                    Some(ref help) => format!("{}\nhelp: {}", error.message, help),
+                    None => error.message.clone(),
+                };
+                Json::object(vec![
+                    ("range", document.token_range(span)),
+                    ("severity", Json::from(ERROR_SEVERITY)),
+                    ("source", Json::from("haumea")),
+                    ("message", Json::from(message)),
+                ])
+            }).collect();
+            replies.push(publish_diagnostics(&uri, diagnostics));
+            self.documents.insert(uri, document);
+        }
+    }
+
+    /// Returns the URI of the document named in params, and the document
+    fn document<'a>(&'a self, params: &'a Json) -> Result<(&'a str, &'a Document), (i64, String)> {
+        let uri = params.get("textDocument").and_then(|document| document.get("uri")).and_then(Json::as_str);
+        match uri {
+            Some(uri) => match self.documents.get(uri) {
+                Some(document) => Ok((uri, document)),
+                None => Err((INVALID_PARAMS, format!("{} is not open", uri))),
+            },
+            None => Err((INVALID_PARAMS, "No document given".to_string())),
+        }
+    }
+
+    /// Returns the location of the definition of name, whose token has the index in document
+    ///
+    /// Functions are looked up in every open document, starting with document,
+    /// and variables are looked up in the function around the token, up to and including it
+    fn definition(&self, uri: &str, document: &Document, index: usize, name: &str) -> Option<Json> {
+        let is_call = document.tokens.get(index + 1).map(|(token, _)| *token == Token::Lp).unwrap_or(false);
+        if is_call {
+            let documents = Some((uri, document)).into_iter()
+                .chain(self.documents.iter().map(|(uri, document)| (&uri[..], document)));
+            for (uri, document) in documents {
+                if let Some(func) = document.function(name) {
+                    return Some(location(uri, document.name_range(func)));
+                }
+            }
+            return None;
+        }
+        // The variable is a parameter or declared by a variable statement earlier in the function
+        let span = document.tokens[index].1;
+        let functions = &document.program.as_ref()?.program;
+        let func = functions.iter().rfind(|func| func.span <= span)?;
+        let start = document.tokens.iter().position(|&(_, token_span)| token_span == func.span)?;
+        let tokens = &document.tokens[start..=index];
+        // How deep in the brackets of the signature the tokens are, which is deeper for the
+        // parameters of a parameter that is a function
+        let mut signature_depth = 0;
+        let mut in_signature = false;
+        for (offset, &(ref token, token_span)) in tokens.iter().enumerate() {
+            match *token {
+                // The signature's with comes right after the name, which isn't always the second token of the header
+                Token::Keyword(ref keyword) if keyword == "with" && offset > 0 && signature_depth == 0 &&
+                    matches!(tokens[offset - 1].0, Token::Ident(ref ident) if *ident == func.name) => in_signature = true,
+                Token::Lp if in_signature => signature_depth += 1,
+                Token::Rp if in_signature => {
+                    signature_depth -= 1;
+                    in_signature = signature_depth > 0;
+                },
+                Token::Ident(ref ident) if ident == name => {
+                    let declared = in_signature ||
+                        offset > 0 && tokens[offset - 1].0 == Token::Keyword("variable".to_string());
+                    if declared {
+                        return Some(location(uri, document.token_range(token_span)));
+                    }
+                },
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// Returns the markdown shown when hovering over name
+    fn hover(&self, name: &str) -> Option<String> {
+        if let Some(&(_, arity)) = BUILTINS.iter().find(|&&(builtin, _)| builtin == name) {
+            let parameters = (0..arity).map(|n| format!("n{}", n + 1)).collect::<Vec<_>>();
+            return Some(format!("```haumea\nto {} with ({})\n```\nA builtin function", name, parameters.join(", ")));
+        }
+        let func = self.documents.values().filter_map(|document| document.function(name)).next()?;
+        let mut contents = format!("```haumea\n{}\n```", function_header(func));
+        // The documentation comments describe the function, or failing those, the comments before it
+        if !func.doc.is_empty() {
+            contents.push('\n');
+            for line in &func.doc {
+                contents.push_str(line);
+                contents.push('\n');
+            }
+        } else if !func.comments.leading.is_empty() {
+            contents.push('\n');
+            for comment in &func.comments.leading {
+                contents.push_str(comment.trim());
+                contents.push('\n');
+            }
+        }
+        Some(contents)
+    }
+}
+
+/// Returns the signature of func as written after its name, like ` with (a, b)`
+fn signature(func: &Function) -> String {
+    match func.signature {
+        Some(ref signature) => format!(" with ({})", signature.join(", ")),
+        None => String::new(),
+    }
+}
+
+/// Returns tokens in the encoding of LSP semantic tokens, where each token is five numbers: how
+/// many lines it is after the one before, how far along it is (from the one before, if it is on
+/// the same line), its length, the index of its kind in the legend, and the bits of its modifiers
+fn encode_semantic_tokens(tokens: &[SemanticToken]) -> Vec<Json> {
+    let mut data = vec![];
+    let mut last = Span { line: 1, column: 1 };
+    for token in tokens {
+        let kind = TOKEN_KINDS.iter().position(|&kind| kind == token.kind).unwrap_or(0);
+        let modifiers = token.declaration as usize | (token.builtin as usize) << 1;
+        let column = if token.span.line == last.line { token.span.column - last.column } else { token.span.column - 1 };
+        for number in &[token.span.line - last.line, column, token.length, kind, modifiers] {
+            data.push(Json::from(*number));
+        }
+        last = token.span;
+    }
+    data
+}
+
+/// Returns an LSP Range from start up to end
+///
+/// LSP positions count from 0, and count UTF-16 code units rather than characters,
+/// which only matters for lines with characters outside the Basic Multilingual Plane
+fn range(start: Span, end: Span) -> Json {
+    let position = |span: Span| Json::object(vec![
+        ("line", Json::from(span.line.saturating_sub(1))),
+        ("character", Json::from(span.column.saturating_sub(1))),
+    ]);
+    Json::object(vec![("start", position(start)), ("end", position(end))])
+}
+
+/// Returns an LSP Location
+fn location(uri: &str, range: Json) -> Json {
+    Json::object(vec![("uri", Json::from(uri)), ("range", range)])
+}
+
+/// Returns a publishDiagnostics notification
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from("textDocument/publishDiagnostics")),
+        ("params", Json::object(vec![("uri", Json::from(uri)), ("diagnostics", Json::from(diagnostics))])),
+    ])
+}
+
+/// Reads a message from the client, or returns None at the end of the input
+///
+/// The Debug Adapter Protocol frames its messages the same way, so the debug adapter reads them with this too
+pub fn read_message<R: BufRead>(input: &mut R) -> Result<Option<Json>, String> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|e| format!("Could not read a message: {}", e))? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = match length {
+        Some(length) => length,
+        None => return Err("A message had no Content-Length".to_string()),
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).map_err(|e| format!("Could not read a message: {}", e))?;
+    let body = String::from_utf8(body).map_err(|_| "A message was not UTF-8".to_string())?;
+    Json::parse(&body).map(Some).map_err(|e| format!("A message was not valid JSON: {}", e))
+}
+
+/// Sends a message to the client
+pub fn write_message<W: Write>(output: &mut W, message: &Json) -> Result<(), String> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| output.flush())
+        .map_err(|e| format!("Could not send a message: {}", e))
+}
@@ -1,15 +1,689 @@
 extern crate haumea;
+mod cli;
+mod debug;
+mod repl;
+mod timings;
+mod watch;
+
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
 use std::io;
 use std::io::prelude::*;
+use std::panic;
+use std::process;
+use std::thread;
+use std::path::{Path, PathBuf};
+use haumea::cache::FunctionCache;
+use haumea::callgraph::CallGraph;
+use haumea::diagnostic::{Diagnostic, Severity};
+use haumea::json::Json;
+use haumea::lints::LintLevels;
+use haumea::options::{CompilerOptions, Emit, MessageFormat, OutputKind, is_stdio, source_name};
+use haumea::parser::{self, SourceFile, NodeCounts};
+use haumea::scanner::{Scanner, Span, Token};
+use haumea::sourcemap::SourceMap;
+use timings::Timings;
+use cli::Command;
+
+/// The tokens of a source file, each with where it starts
+type Tokens = Vec<(Token, Span)>;
+
+/// The ways that a command can fail
+#[derive(Debug)]
+enum Error {
+    /// The haumea program being compiled has errors
+    Program(Vec<Diagnostic>),
+    /// Anything else went wrong, like a file not being readable
+    Other(String),
+}
+
+impl Error {
+    /// Returns the exit code of the haumea binary when it fails with this error
+    fn exit_code(&self) -> i32 {
+        match *self {
+            Error::Program(ref diagnostics) => match diagnostics.first() {
+                Some(diagnostic) => diagnostic.kind.exit_code(),
+                None => 1,
+            },
+            Error::Other(_) => 1,
+        }
+    }
+
+    /// Writes the error to stderr in format
+    ///
+    /// In JSON, an error that isn't in the program is written like a diagnostic that isn't
+    /// anywhere in particular, with null for its kind. In SARIF, the log has the warnings
+    /// found before the error too.
+    fn print(&self, format: MessageFormat) {
+        match (self, format) {
+            (_, MessageFormat::Human) => eprintln!("{}", self),
+            (Error::Program(diagnostics), MessageFormat::Json) => {
+                for diagnostic in diagnostics {
+                    eprintln!("{}", diagnostic.to_json());
+                }
+            },
+            (Error::Other(message), MessageFormat::Json) => {
+                eprintln!("{}", Json::object(vec![
+                    ("severity", Json::from("error")),
+                    ("kind", Json::Null),
+                    ("code", Json::Null),
+                    ("message", Json::from(&message[..])),
+                    ("file", Json::Null),
+                    ("spans", Json::Array(vec![])),
+                    ("suggestions", Json::Array(vec![])),
+                ]));
+            },
+            (Error::Program(errors), MessageFormat::Sarif) => {
+                let mut diagnostics = take_sarif_warnings();
+                diagnostics.extend(errors.iter().cloned());
+                eprintln!("{}", haumea::sarif::log(&diagnostics, None));
+            },
+            (Error::Other(message), MessageFormat::Sarif) => {
+                eprintln!("{}", haumea::sarif::log(&take_sarif_warnings(), Some(message)));
+            },
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Error {
+        Error::Other(message)
+    }
+}
+
+impl From<Diagnostic> for Error {
+    fn from(diagnostic: Diagnostic) -> Error {
+        Error::Program(vec![diagnostic])
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Program(ref diagnostics) => {
+                let lines = diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+                write!(f, "{}", lines.join("\n"))
+            },
+            Error::Other(ref message) => write!(f, "error: {}", message),
+        }
+    }
+}
+
+/// How much stack the compiler runs with for each level that statements and expressions
+/// can be nested in each other
+///
+/// This is enough for debug builds, which use a lot more stack than release ones
+const STACK_PER_NESTING: usize = 64 * 1024;
 
 fn main() {
-    let mut source = String::new();
-	let mut stdin = io::stdin();
-	stdin.read_to_string(&mut source).expect("Must provide input");
-	let scanner = haumea::scanner::Scanner::new(&source);
-	let ast = haumea::parser::parse(scanner);
-    //println!("{:?}", ast);
-	let mut out = String::new();
-	haumea::codegen::compile_ast(&mut out, ast);
-	println!("{}", out);
+    // A panic is a bug in the compiler rather than in the program, so say so.
+    // Rust exits with 101 after a panic, which is the exit code of an ErrorKind::Internal.
+    panic::set_hook(Box::new(|info| {
+        eprintln!("error: internal compiler error: {}", info);
+    }));
+    let command = match cli::parse_args(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, cli::USAGE);
+            process::exit(2);
+        },
+    };
+    // The parser and the passes after it recurse into what is nested, so the stack grows with the limit
+    let max_nesting = command.options().map_or(parser::MAX_NESTING, |options| options.max_nesting);
+    let thread = thread::Builder::new()
+        .stack_size(max_nesting.max(parser::MAX_NESTING) * STACK_PER_NESTING)
+        .spawn(move || run_command(command));
+    match thread {
+        // run_command exits, so the thread only finishes if it panicked, which has already been reported
+        Ok(thread) => {
+            let _ = thread.join();
+            process::exit(101);
+        },
+        Err(e) => {
+            eprintln!("error: Could not start the compiler: {}", e);
+            process::exit(101);
+        },
+    }
+}
+
+/// Runs command and exits with its exit code
+fn run_command(command: Command) -> ! {
+    let message_format = command.options().map_or(MessageFormat::Human, |options| options.message_format);
+    let result = match command {
+        Command::Build(options) => build(&options),
+        Command::Run { options, arguments } => run(&options, &arguments).map(|code| exit(code, message_format)),
+        Command::Test(options) => test(&options).map(|code| exit(code, message_format)),
+        Command::Bench(options) => bench(&options).map(|code| exit(code, message_format)),
+        Command::Watch { options, run } => watch::watch(&options, run).map_err(Error::Other),
+        Command::Fmt { inputs, check } => fmt(&inputs, check),
+        Command::Lsp => {
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+            haumea::lsp::serve(stdin.lock(), stdout.lock()).map_err(Error::Other)
+        },
+        Command::Debug => debug::serve().map_err(Error::Other),
+        Command::Repl(inputs) => repl::repl(&inputs).map_err(Error::Other),
+        Command::New(path) => haumea::project::create(&path).map_err(Error::Other).map(|_| {
+            println!("Created the project {}", path.display());
+        }),
+        Command::Doc { options, html } => doc(&options, html),
+        Command::Explain(code) => match haumea::codes::explain(&code) {
+            Some(explanation) => {
+                print!("{}", explanation);
+                Ok(())
+            },
+            None => Err(Error::Other(format!("There is no error code {}", code))),
+        },
+        Command::Grammar(format) => {
+            print!("{}", haumea::grammar::generate(format));
+            Ok(())
+        },
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            Ok(())
+        },
+        Command::Version => {
+            println!("haumea {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        },
+    };
+    match result {
+        Ok(()) => exit(0, message_format),
+        Err(error) => {
+            error.print(message_format);
+            process::exit(error.exit_code());
+        },
+    }
+}
+
+/// Exits with code, after writing the SARIF log of the warnings if the format is SARIF
+fn exit(code: i32, format: MessageFormat) -> ! {
+    if format == MessageFormat::Sarif {
+        eprintln!("{}", haumea::sarif::log(&take_sarif_warnings(), None));
+    }
+    process::exit(code)
+}
+
+thread_local! {
+    /// The warnings found so far with --message-format sarif, which all go in the one log
+    /// written when haumea exits
+    static SARIF_WARNINGS: RefCell<Vec<Diagnostic>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns the warnings found so far with --message-format sarif
+fn take_sarif_warnings() -> Vec<Diagnostic> {
+    SARIF_WARNINGS.with(|warnings| warnings.replace(vec![]))
+}
+
+/// Compiles the program described by options
+fn build(options: &CompilerOptions) -> Result<(), Error> {
+    if options.check {
+        return check_only(options);
+    }
+    if options.split {
+        return build_split(options);
+    }
+    if let OutputKind::StaticLibrary | OutputKind::SharedLibrary = options.output_kind {
+        return build_library(options);
+    }
+    let out = compile(options)?;
+    let output = options.output_path();
+    match (options.output_kind, output) {
+        (OutputKind::Executable, Some(output)) => {
+            Ok(haumea::toolchain::build_executable(&out, &output, options)?)
+        },
+        (_, Some(ref output)) if options.emit == Emit::Asm => {
+            Ok(haumea::toolchain::build_assembly(&out, output, options)?)
+        },
+        (_, Some(output)) => {
+            write_file(&output, &out)?;
+            if let (true, Some(map_path)) = (options.source_map, options.source_map_path()) {
+                let c_file = output.file_name().map_or("".into(), |name| name.to_string_lossy());
+                write_file(&map_path, &format!("{}\n", SourceMap::from_c(&out).to_json(&c_file)))?;
+            }
+            // The C has to be compiled with the runtime, so it goes next to it
+            if options.emit == Emit::C && haumea::runtime::is_used_by(&out) {
+                haumea::runtime::write_runtime(output.parent().unwrap_or_else(|| Path::new("")))?;
+            }
+            Ok(())
+        },
+        (OutputKind::Executable, None) => Err(Error::Other("An executable can't be written to stdout".to_string())),
+        (_, None) if options.emit == Emit::Asm => {
+            let assembly = haumea::toolchain::temp_assembly(options.input());
+            let result = haumea::toolchain::build_assembly(&out, &assembly, options)
+                .and_then(|_| fs::read_to_string(&assembly).map_err(|e| e.to_string()));
+            let _ = fs::remove_file(&assembly);
+            print!("{}", result?);
+            Ok(())
+        },
+        (_, None) if options.source_map => {
+            Err(Error::Other("A source map can't be written for C written to stdout".to_string()))
+        },
+        (_, None) => {
+            print!("{}", out);
+            Ok(())
+        },
+    }
+}
+
+/// Compiles the program described by options into a temporary executable and runs it with arguments
+///
+/// Returns the exit code of the program
+fn run(options: &CompilerOptions, arguments: &[String]) -> Result<i32, Error> {
+    let out = compile(options)?;
+    let executable = haumea::toolchain::temp_executable(options.input());
+    haumea::toolchain::build_executable(&out, &executable, options)?;
+    let code = haumea::toolchain::run_executable(&executable, arguments);
+    let _ = fs::remove_file(&executable);
+    Ok(code?)
+}
+
+/// Compiles the tests in the program described by options into a temporary executable and runs it
+///
+/// An input that is a directory stands for the source files of the project in it.
+/// Returns the exit code of the tests, which is 1 if any of them failed.
+/// With options.instrument_coverage, it reports which lines of the program the tests ran.
+fn test(options: &CompilerOptions) -> Result<i32, Error> {
+    if !options.instrument_coverage {
+        return run_harness(options, haumea::project::test_sources,
+                           &|out, files| haumea::codegen::compile_tests(out, files, false, options.safe), None);
+    }
+    let report = env::temp_dir().join(format!("haumea-{}-coverage.txt", process::id()));
+    let code = run_harness(options, haumea::project::test_sources,
+                           &|out, files| haumea::codegen::compile_tests(out, files, true, options.safe), Some(&report));
+    let coverage = fs::read_to_string(&report)
+        .map_err(|e| format!("Could not read the coverage report: {}", e))
+        .and_then(|report| haumea::coverage::parse_report(&report));
+    let _ = fs::remove_file(&report);
+    // The tests might not have run at all, and then that is the error to report
+    let code = code?;
+    print!("\n{}", haumea::coverage::summary(&coverage?));
+    Ok(code)
+}
+
+/// Compiles the benchmarks in the program described by options into a temporary executable and runs it
+///
+/// An input that is a directory stands for the source files of the project in it
+fn bench(options: &CompilerOptions) -> Result<i32, Error> {
+    run_harness(options, haumea::project::bench_sources,
+                &|out, files| haumea::codegen::compile_benches(out, files, options.safe), None)
+}
+
+/// Compiles the program described by options with compile, which generates a harness, and runs it
+///
+/// An input that is a directory stands for the files that project_sources returns for it,
+/// and the harness is linked with the libraries in its manifest and reports the lints it sets. A harness instrumented for coverage
+/// writes its report to coverage_report. Returns the exit code of the harness.
+fn run_harness(options: &CompilerOptions,
+               project_sources: fn(&Path) -> Result<Vec<PathBuf>, String>,
+               compile: &dyn Fn(&mut String, &[SourceFile]),
+               coverage_report: Option<&Path>) -> Result<i32, Error> {
+    let mut options = options.clone();
+    let mut inputs = vec![];
+    let mut libraries = vec![];
+    let mut library_paths = vec![];
+    let mut lints = LintLevels::default();
+    for input in &options.inputs {
+        if input.is_dir() {
+            inputs.extend(project_sources(input)?);
+            let manifest = haumea::project::read_manifest(input)?;
+            libraries.extend(manifest.libraries);
+            library_paths.extend(manifest.library_paths.into_iter().map(|path| input.join(path)));
+            lints.extend(manifest.lints);
+            options.deny_warnings |= manifest.deny_warnings;
+        } else {
+            inputs.push(input.clone());
+        }
+    }
+    if inputs.is_empty() {
+        return Err(Error::Other("There are no source files to compile".to_string()));
+    }
+    options.inputs = inputs;
+    options.libraries.extend(libraries);
+    options.library_paths.extend(library_paths);
+    // The flags come after the manifest, so they win over it
+    lints.extend(options.lints);
+    options.lints = lints;
+    let mut timings = Timings::new(options.timings && !options.quiet);
+    let files = parse(lex(read_sources(&options)?, &mut timings)?, &options, &mut timings)?;
+    check(&files, &options, &mut timings)?;
+    let files = prepare(files, &options, &mut timings);
+    let mut out = String::new();
+    timings.time("codegen", || compile(&mut out, &files));
+    timings.report();
+    let executable = haumea::toolchain::temp_executable(options.input());
+    haumea::toolchain::build_executable(&out, &executable, &options)?;
+    let code = match coverage_report {
+        Some(report) => haumea::toolchain::run_executable_with_coverage(&executable, &[], report),
+        None => haumea::toolchain::run_executable(&executable, &[]),
+    };
+    let _ = fs::remove_file(&executable);
+    Ok(code?)
+}
+
+/// Rewrites each of the inputs in the canonical format, or with check, lists the ones that aren't
+///
+/// stdin is formatted to stdout
+fn fmt(inputs: &[PathBuf], check: bool) -> Result<(), Error> {
+    let mut errors = vec![];
+    let mut unformatted = 0;
+    for input in inputs {
+        let name = source_name(input);
+        let mut source = String::new();
+        if is_stdio(input) {
+            io::stdin().read_to_string(&mut source)
+        } else {
+            File::open(input).and_then(|mut file| file.read_to_string(&mut source))
+        }.map_err(|e| format!("Could not read {}: {}", name, e))?;
+        let formatted = match haumea::pretty::format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(error) => {
+                errors.push(error.in_file(&name));
+                continue;
+            },
+        };
+        if formatted == source {
+            if is_stdio(input) && !check {
+                print!("{}", formatted);
+            }
+        } else if check {
+            println!("{} is not formatted", name);
+            unformatted += 1;
+        } else if is_stdio(input) {
+            print!("{}", formatted);
+        } else {
+            write_file(input, &formatted)?;
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error::Program(errors));
+    }
+    match unformatted {
+        0 => Ok(()),
+        1 => Err(Error::Other("1 file is not formatted".to_string())),
+        n => Err(Error::Other(format!("{} files are not formatted", n))),
+    }
+}
+
+/// Writes a reference of the functions in the program described by options
+///
+/// It goes to stdout unless an output is given, as Markdown or, if html is true, HTML
+fn doc(options: &CompilerOptions, html: bool) -> Result<(), Error> {
+    let mut timings = Timings::new(options.timings && !options.quiet);
+    let files = parse(lex(read_sources(options)?, &mut timings)?, options, &mut timings)?;
+    let out = if html {
+        haumea::doc::html(&files)
+    } else {
+        haumea::doc::markdown(&files)
+    };
+    timings.report();
+    match options.output {
+        Some(ref output) if !is_stdio(output) => Ok(write_file(output, &out)?),
+        _ => {
+            print!("{}", out);
+            Ok(())
+        },
+    }
+}
+
+/// Looks for errors in the program described by options without generating any code
+fn check_only(options: &CompilerOptions) -> Result<(), Error> {
+    let mut timings = Timings::new(options.timings && !options.quiet);
+    let files = parse(lex(read_sources(options)?, &mut timings)?, options, &mut timings)?;
+    check(&files, options, &mut timings)?;
+    timings.report();
+    Ok(())
+}
+
+/// Compiles each input into its own C file in the directory of the header, plus the header
+fn build_split(options: &CompilerOptions) -> Result<(), Error> {
+    let mut timings = Timings::new(options.timings && !options.quiet);
+    let files = parse(lex(read_sources(options)?, &mut timings)?, options, &mut timings)?;
+    check(&files, options, &mut timings)?;
+    let files = prepare(files, options, &mut timings);
+    let header = match options.output {
+        Some(ref output) => output.clone(),
+        None => options.input().with_extension("h"),
+    };
+    let header_name = match header.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return Err(Error::Other(format!("{} is not a valid header name", header.display()))),
+    };
+    let directory = header.parent().unwrap_or_else(|| Path::new(""));
+    let full_runtime = haumea::runtime::needs_full_runtime(&files, options.runtime)?;
+    if full_runtime {
+        haumea::runtime::write_runtime(directory)?;
+    }
+    let mut out = String::new();
+    let guard = haumea::codegen::include_guard(&header_name);
+    timings.time("codegen", || haumea::codegen::compile_header(&mut out, &files, &guard));
+    write_file(&header, &out)?;
+    let mut size = out.len();
+    for (input, file) in options.inputs.iter().zip(&files) {
+        let mut out = String::new();
+        timings.time("codegen", || haumea::codegen::compile_split_file(&mut out, file, &header_name, full_runtime, options));
+        let stem = input.file_stem().unwrap_or_else(|| input.as_os_str());
+        write_file(&directory.join(stem).with_extension("c"), &out)?;
+        size += out.len();
+    }
+    timings.note("codegen", || format!("{} bytes of C", size));
+    timings.report();
+    Ok(())
+}
+
+/// Compiles every function but main into a static or shared library, plus a header that declares them
+///
+/// The header is named after the library without its lib prefix, so libmath.a and libmath.so get math.h
+fn build_library(options: &CompilerOptions) -> Result<(), Error> {
+    let library = match options.output_path() {
+        Some(library) => library,
+        None => return Err(Error::Other("A library can't be written to stdout".to_string())),
+    };
+    let mut timings = Timings::new(options.timings && !options.quiet);
+    let files = parse(lex(read_sources(options)?, &mut timings)?, options, &mut timings)?;
+    check(&files, options, &mut timings)?;
+    let files = prepare(files, options, &mut timings);
+    let stem = library.file_stem().map_or("".into(), |stem| stem.to_string_lossy().into_owned());
+    let prefix = if options.output_kind == OutputKind::StaticLibrary { "lib" } else { env::consts::DLL_PREFIX };
+    let header = library.with_file_name(format!("{}.h", stem.strip_prefix(prefix).unwrap_or(&stem)));
+    let header_name = header.file_name().map_or("".into(), |name| name.to_string_lossy().into_owned());
+    let mut interface = String::new();
+    let guard = haumea::codegen::include_guard(&header_name);
+    timings.time("codegen", || haumea::codegen::compile_interface(&mut interface, &files, &guard));
+    let full_runtime = haumea::runtime::needs_full_runtime(&files, options.runtime)?;
+    let mut out = String::new();
+    timings.time("codegen", || haumea::codegen::compile_library(&mut out, &files, full_runtime, options));
+    timings.note("codegen", || format!("{} bytes of C", out.len()));
+    timings.report();
+    if options.output_kind == OutputKind::StaticLibrary {
+        haumea::toolchain::build_static_library(&out, &library, options)?;
+    } else {
+        haumea::toolchain::build_shared_library(&out, &library, options)?;
+    }
+    Ok(write_file(&header, &interface)?)
+}
+
+/// Reads the source files named in options and compiles them as far as options.emit asks
+///
+/// Assembly is produced from the C by the C compiler, so for Emit::Asm this returns the C
+fn compile(options: &CompilerOptions) -> Result<String, Error> {
+    let mut timings = Timings::new(options.timings && !options.quiet);
+    let tokens = lex(read_sources(options)?, &mut timings)?;
+    if options.emit == Emit::Tokens {
+        let mut out = String::new();
+        for (name, tokens) in tokens {
+            for (token, span) in tokens {
+                if token != Token::EOF {
+                    out.push_str(&format!("{}:{}:{} {:?}\n", name, span.line, span.column, token));
+                }
+            }
+        }
+        timings.report();
+        return Ok(out);
+    }
+    let files = parse(tokens, options, &mut timings)?;
+    if options.emit == Emit::Ast {
+        timings.report();
+        return Ok(format!("{:#?}\n", files));
+    }
+    if options.emit == Emit::Dot {
+        timings.report();
+        return Ok(haumea::dot::program_to_dot(&files));
+    }
+    if options.emit == Emit::CallGraph {
+        timings.report();
+        return Ok(CallGraph::new(&files).to_dot());
+    }
+    if options.emit == Emit::CallGraphJson {
+        timings.report();
+        return Ok(format!("{}\n", CallGraph::new(&files).to_json()));
+    }
+    check(&files, options, &mut timings)?;
+    let files = prepare(files, options, &mut timings);
+    if options.emit == Emit::Ir || options.emit == Emit::Cfg {
+        let functions = timings.time("lowering", || haumea::ir::lower(&files));
+        timings.report();
+        if options.emit == Emit::Cfg {
+            return Ok(haumea::cfg::to_dot(&functions));
+        }
+        return Ok(functions.iter().map(|func| func.to_string()).collect::<Vec<_>>().join("\n"));
+    }
+    let mut out = String::new();
+    if options.emit == Emit::Header {
+        // A header written to stdout is named after the input, or if that is stdin too, after stdin
+        let header = match options.output_path() {
+            Some(output) => output,
+            None if is_stdio(options.input()) => PathBuf::from("stdin.h"),
+            None => options.input().with_extension("h"),
+        };
+        let header_name = header.file_name().map_or("".into(), |name| name.to_string_lossy());
+        let guard = haumea::codegen::include_guard(&header_name);
+        timings.time("codegen", || haumea::codegen::compile_interface(&mut out, &files, &guard));
+        timings.report();
+        return Ok(out);
+    }
+    let full_runtime = haumea::runtime::needs_full_runtime(&files, options.runtime)?;
+    match options.cache_dir {
+        Some(ref dir) => {
+            let mut cache = FunctionCache::open(dir)?;
+            timings.time("codegen", || {
+                haumea::codegen::compile_files_cached(&mut out, &files, full_runtime, options, &mut cache)
+            });
+            timings.note("codegen", || {
+                format!("{} of {} functions from the cache", cache.hits, cache.hits + cache.misses)
+            });
+        },
+        None => timings.time("codegen", || haumea::codegen::compile_files(&mut out, &files, full_runtime, options)),
+    }
+    timings.note("codegen", || format!("{} bytes of C", out.len()));
+    timings.report();
+    Ok(out)
+}
+
+/// Reads the source of every input in options, returning each one with its name
+fn read_sources(options: &CompilerOptions) -> Result<Vec<(String, String)>, String> {
+    let mut sources = vec![];
+    for input in &options.inputs {
+        let name = source_name(input);
+        let mut source = String::new();
+        if is_stdio(input) {
+            io::stdin().read_to_string(&mut source)
+        } else {
+            File::open(input).and_then(|mut file| file.read_to_string(&mut source))
+        }.map_err(|e| format!("Could not read {}: {}", name, e))?;
+        sources.push((name, source));
+    }
+    Ok(sources)
+}
+
+/// Scans each of the named sources into tokens
+///
+/// If any of the sources can't be scanned, returns the first error in each of them
+fn lex(sources: Vec<(String, String)>, timings: &mut Timings)
+       -> Result<Vec<(String, Tokens)>, Error> {
+    let mut tokens = vec![];
+    let mut errors = vec![];
+    for (name, source) in sources {
+        match timings.time("lexing", || Scanner::new(&source).tokenize()) {
+            Ok(file_tokens) => tokens.push((name, file_tokens)),
+            Err(error) => errors.push(error.in_file(&name)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error::Program(errors));
+    }
+    // Every stream of tokens ends with a Token::EOF, which isn't counted
+    timings.note("lexing", || {
+        format!("{} tokens", tokens.iter().map(|(_, tokens)| tokens.len() - 1).sum::<usize>())
+    });
+    Ok(tokens)
+}
+
+/// Parses each of the named streams of tokens
+///
+/// If any of them can't be parsed, returns the first error in each of them
+fn parse(tokens: Vec<(String, Tokens)>, options: &CompilerOptions, timings: &mut Timings)
+         -> Result<Vec<SourceFile>, Error> {
+    let mut files = vec![];
+    let mut errors = vec![];
+    for (name, tokens) in tokens {
+        match timings.time("parsing", || haumea::parser::parse_tokens_nested(tokens, options.max_nesting)) {
+            Ok(program) => files.push(SourceFile { name, program }),
+            Err(error) => errors.push(error.in_file(&name)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error::Program(errors));
+    }
+    timings.note("parsing", || {
+        let mut counts = NodeCounts::default();
+        for file in &files {
+            let file_counts = NodeCounts::of(&file.program);
+            counts.functions += file_counts.functions;
+            counts.statements += file_counts.statements;
+            counts.expressions += file_counts.expressions;
+        }
+        format!("{} functions, {} statements, {} expressions",
+                counts.functions, counts.statements, counts.expressions)
+    });
+    Ok(files)
+}
+
+/// Runs the semantic checks on the program made of files
+///
+/// Inline C is an error unless options allow it. The lints that options.lints warns about are
+/// printed too, unless options.quiet, and they don't stop the program from being compiled,
+/// but the ones that it denies are errors, as are all of them with options.deny_warnings.
+fn check(files: &[SourceFile], options: &CompilerOptions, timings: &mut Timings) -> Result<(), Error> {
+    let (errors, warnings): (Vec<_>, Vec<_>) = timings.time("checking", || {
+        haumea::check(files, options).into_iter().partition(|diagnostic| diagnostic.severity == Severity::Error)
+    });
+    if !options.quiet {
+        for warning in warnings {
+            match options.message_format {
+                MessageFormat::Human => eprintln!("{}", warning),
+                MessageFormat::Json => eprintln!("{}", warning.to_json()),
+                MessageFormat::Sarif => SARIF_WARNINGS.with(|warnings| warnings.borrow_mut().push(warning)),
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Program(errors))
+    }
+}
+
+/// Prepares files to be compiled with options, as haumea::prepare does
+fn prepare(files: Vec<SourceFile>, options: &CompilerOptions, timings: &mut Timings) -> Vec<SourceFile> {
+    timings.time("optimizing", || haumea::prepare(&files, options))
+}
+
+/// Writes contents to the file at path
+fn write_file(path: &Path, contents: &str) -> Result<(), String> {
+    File::create(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| format!("Could not write {}: {}", path.display(), e))
 }
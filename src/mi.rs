@@ -0,0 +1,229 @@
+/// src/mi.rs
+/// Reads the records that debuggers like gdb write through their machine interface (MI)
+use json::Json;
+
+/// A line of output from the debugger
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    /// The result of a command, with the token it was sent with, its class (like done or error)
+    /// and its results
+    Result { token: Option<u64>, class: String, results: Json },
+    /// A change in the state of the program, like *stopped, with its class and results
+    Exec { class: String, results: Json },
+    /// A status or notification record, like =breakpoint-modified, which the debug adapter doesn't need
+    Notify,
+    /// Text for the console (~), from the program (@), or from the debugger's log (&)
+    Stream(char, String),
+    /// The prompt that the debugger writes after each batch of records
+    Prompt,
+    /// A line that isn't a record, which the program wrote
+    Program(String),
+}
+
+impl Record {
+    /// Parses a line that the debugger wrote, like `3^done,bkpt={number="1",line="2"}`
+    ///
+    /// A line that isn't a record is something that the program wrote, so nothing fails to parse.
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::json::Json;
+    /// # use haumea::mi::Record;
+    /// let record = Record::parse("3^done,bkpt={number=\"1\",line=\"2\"}");
+    /// let bkpt = Json::object(vec![("number", Json::from("1")), ("line", Json::from("2"))]);
+    /// assert_eq!(record, Record::Result {
+    ///     token: Some(3),
+    ///     class: "done".to_string(),
+    ///     results: Json::object(vec![("bkpt", bkpt)]),
+    /// });
+    /// assert_eq!(Record::parse("~\"hi\\n\""), Record::Stream('~', "hi\n".to_string()));
+    /// assert_eq!(Record::parse("(gdb) "), Record::Prompt);
+    /// assert_eq!(Record::parse("16"), Record::Program("16".to_string()));
+    /// ```
+    pub fn parse(line: &str) -> Record {
+        if line.trim_end() == "(gdb)" {
+            return Record::Prompt;
+        }
+        let digits = line.find(|c: char| !c.is_ascii_digit()).unwrap_or(line.len());
+        let (token, rest) = line.split_at(digits);
+        let (kind, body) = match rest.chars().next() {
+            Some(kind) => (kind, &rest[kind.len_utf8()..]),
+            None => return Record::Program(line.to_string()),
+        };
+        match kind {
+            '~' | '@' | '&' if token.is_empty() && body.starts_with('"') => {
+                Record::Stream(kind, MiReader { text: body.as_bytes(), index: 0 }.string())
+            },
+            '^' | '*' | '+' | '=' if body.starts_with(|c: char| c.is_ascii_lowercase()) => {
+                let (class, results) = match body.find(',') {
+                    Some(comma) => (&body[..comma], MiReader { text: &body.as_bytes()[comma + 1..], index: 0 }.results(None)),
+                    None => (body, Json::Object(vec![])),
+                };
+                match kind {
+                    '^' => Record::Result { token: token.parse().ok(), class: class.to_string(), results },
+                    '*' => Record::Exec { class: class.to_string(), results },
+                    _ => Record::Notify,
+                }
+            },
+            _ => Record::Program(line.to_string()),
+        }
+    }
+}
+
+/// Reads the values in MI records into JSON
+///
+/// A tuple, like `{a="1",b="2"}`, is an object, and a list, like `[frame={...},frame={...}]`,
+/// is an array of its values, without the names that they might have.
+struct MiReader<'a> {
+    text: &'a [u8],
+    /// How far into text has been read
+    index: usize,
+}
+
+impl<'a> MiReader<'a> {
+    /// Returns the next byte, without reading it
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.index).cloned()
+    }
+
+    /// Reads c if it is the next byte
+    fn skip(&mut self, c: u8) {
+        if self.peek() == Some(c) {
+            self.index += 1;
+        }
+    }
+
+    /// Reads results, like `a="1",b={c="2"}`, up to the end or to end, into an object
+    fn results(&mut self, end: Option<u8>) -> Json {
+        let mut members = vec![];
+        while self.peek().is_some() && self.peek() != end {
+            let start = self.index;
+            members.push(self.result());
+            self.skip(b',');
+            // Anything that can't be read is skipped, so a bad record can't stop the adapter
+            if self.index == start {
+                self.index += 1;
+            }
+        }
+        Json::Object(members)
+    }
+
+    /// Reads a result, which is a name, =, and a value
+    fn result(&mut self) -> (String, Json) {
+        let start = self.index;
+        while let Some(c) = self.peek() {
+            if b"=,{}[]\"".contains(&c) {
+                break;
+            }
+            self.index += 1;
+        }
+        let name = String::from_utf8_lossy(&self.text[start..self.index]).into_owned();
+        self.skip(b'=');
+        (name, self.value())
+    }
+
+    /// Reads a value, which is a string, a tuple or a list
+    fn value(&mut self) -> Json {
+        match self.peek() {
+            Some(b'"') => Json::String(self.string()),
+            Some(b'{') => {
+                self.index += 1;
+                let tuple = self.results(Some(b'}'));
+                self.skip(b'}');
+                tuple
+            },
+            Some(b'[') => {
+                self.index += 1;
+                let mut values = vec![];
+                while self.peek().is_some() && self.peek() != Some(b']') {
+                    let start = self.index;
+                    values.push(match self.peek() {
+                        Some(b'"') | Some(b'{') | Some(b'[') => self.value(),
+                        _ => self.result().1,
+                    });
+                    self.skip(b',');
+                    if self.index == start {
+                        self.index += 1;
+                    }
+                }
+                self.skip(b']');
+                Json::Array(values)
+            },
+            _ => Json::Null,
+        }
+    }
+
+    /// Reads a C string, like `"x = \"1\"\n"`, whose bytes that aren't ASCII are in octal escapes
+    fn string(&mut self) -> String {
+        let mut bytes = vec![];
+        self.skip(b'"');
+        while let Some(c) = self.peek() {
+            self.index += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escape = match self.peek() {
+                        Some(escape) => escape,
+                        None => break,
+                    };
+                    self.index += 1;
+                    match escape {
+                        b'n' => bytes.push(b'\n'),
+                        b't' => bytes.push(b'\t'),
+                        b'r' => bytes.push(b'\r'),
+                        b'0'..=b'7' => {
+                            let mut byte = u32::from(escape - b'0');
+                            for _ in 0..2 {
+                                match self.peek() {
+                                    Some(digit @ b'0'..=b'7') => {
+                                        byte = byte * 8 + u32::from(digit - b'0');
+                                        self.index += 1;
+                                    },
+                                    _ => break,
+                                }
+                            }
+                            bytes.push(byte as u8);
+                        },
+                        _ => bytes.push(escape),
+                    }
+                },
+                _ => bytes.push(c),
+            }
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+/// Returns the string at path in the results of a record, like bkpt.number
+///
+/// # Examples
+/// ```
+/// # use haumea::mi::{Record, field, number_field};
+/// let results = match Record::parse("1^done,bkpt={number=\"2\",func=\"main\"}") {
+///     Record::Result { results, .. } => results,
+///     record => panic!("{:?} isn't a result", record),
+/// };
+/// assert_eq!(field(&results, &["bkpt", "func"]), Some("main"));
+/// assert_eq!(number_field(&results, &["bkpt", "number"]), Some(2));
+/// assert_eq!(field(&results, &["bkpt", "line"]), None);
+/// ```
+pub fn field<'a>(results: &'a Json, path: &[&str]) -> Option<&'a str> {
+    path.iter().try_fold(results, |json, key| json.get(key)).and_then(Json::as_str)
+}
+
+/// Returns the number at path in the results of a record, which the debugger writes as a string
+pub fn number_field(results: &Json, path: &[&str]) -> Option<i64> {
+    field(results, path).and_then(|number| number.parse().ok())
+}
+
+/// Returns text as a C string, for an argument of an MI command that can have spaces in it
+///
+/// # Examples
+/// ```
+/// # use haumea::mi::quote;
+/// assert_eq!(quote("/tmp/my program.hm:3"), "\"/tmp/my program.hm:3\"");
+/// assert_eq!(quote("say \"hi\" \\ bye"), "\"say \\\"hi\\\" \\\\ bye\"");
+/// ```
+pub fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
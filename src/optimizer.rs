@@ -0,0 +1,746 @@
+/// src/optimizer.rs
+/// Makes programs simpler before they are compiled to C, with the passes that the optimization level picks
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use inliner;
+use options::OptLevel;
+use parser::{self, Callbacks, Comments, Expression, Operator, SourceFile, Statement, StatementKind};
+
+/// The name that the variables made by common subexpression elimination start with, before their number
+const COMMON_PREFIX: &str = "haumea_common";
+
+/// Returns files optimized at level
+///
+/// At O0 they are returned as they are. O1 inlines the calls of the functions marked @inline,
+/// then folds constants, propagates them, and removes dead code, and O2 eliminates common
+/// subexpressions too.
+/// None of the passes change what a program does when it is run by the interpreter, so an
+/// expression that would fail, like a division by zero, is left to fail.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::options::OptLevel;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::{optimizer, pretty};
+/// let source = "to main do\n    display(2 * 3 + 1)\n    return 0\n    display(7)\nend";
+/// let file = SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// let optimized = optimizer::optimize(&[file.clone()], OptLevel::O1);
+/// let mut out = String::new();
+/// pretty::print_function(&mut out, &optimized[0].program[0]);
+/// assert_eq!(out, "to main do\n    display(7)\n    return 0\nend\n");
+/// assert_eq!(optimizer::optimize(&[file.clone()], OptLevel::O0), vec![file]);
+/// ```
+pub fn optimize(files: &[SourceFile], level: OptLevel) -> Vec<SourceFile> {
+    if level == OptLevel::O0 {
+        return files.to_vec();
+    }
+    let callbacks = parser::callbacks(files);
+    let mut files = inliner::inline_functions(files);
+    for func in files.iter_mut().flat_map(|file| file.program.iter_mut()) {
+        if let Some(ref mut code) = func.code {
+            fold_constants(code);
+            propagate_constants(code, &callbacks);
+            eliminate_dead_code(code);
+            if level == OptLevel::O2 {
+                eliminate_common_subexpressions(code);
+            }
+        }
+    }
+    files
+}
+
+/// Works out the parts of the expressions in statement that only use numbers
+///
+/// The arithmetic wraps like the interpreter's, and a value is only kept if it fits in a
+/// number literal. A division by zero is left as it is, and so are the sides of and and or
+/// that the interpreter wouldn't work out, which is how `0 and f()` becomes 0.
+///
+/// # Examples
+/// ```
+/// # use haumea::parser::{self, StatementKind};
+/// # use haumea::scanner::Scanner;
+/// # use haumea::optimizer;
+/// # use haumea::pretty;
+/// let mut program = parser::parse(Scanner::new("to main do display(x * (4 - 1), 1 / 0, 0 - 3 < 2) end")).unwrap();
+/// optimizer::fold_constants(program[0].code.as_mut().unwrap());
+/// let mut out = String::new();
+/// pretty::print_function(&mut out, &program[0]);
+/// assert_eq!(out, "to main do\n    display(x * 3, 1 / 0, 1)\nend\n");
+/// ```
+pub fn fold_constants(statement: &mut Statement) {
+    for_each_expression(statement, &mut fold_expression);
+}
+
+/// Returns the number that expression always is, worked out by folding it as fold_constants
+/// would, or None if it depends on something that isn't known until the program runs
+///
+/// # Examples
+/// ```
+/// # use haumea::parser::{self, Entry};
+/// # use haumea::scanner::Scanner;
+/// # use haumea::optimizer;
+/// let expression = |source| match parser::parse_entry(Scanner::new(source).tokenize().unwrap()).unwrap() {
+///     Entry::Expression(expression, _) => expression,
+///     _ => unreachable!(),
+/// };
+/// assert_eq!(optimizer::constant_value(&expression("2 * (3 + 4)")), Some(14));
+/// assert_eq!(optimizer::constant_value(&expression("0 and f()")), Some(0));
+/// assert_eq!(optimizer::constant_value(&expression("x + 1")), None);
+/// assert_eq!(optimizer::constant_value(&expression("1 / 0")), None);
+/// ```
+pub fn constant_value(expression: &Expression) -> Option<i32> {
+    let mut folded = expression.clone();
+    fold_expression(&mut folded);
+    match folded {
+        Expression::Integer(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// Folds the constants in expression
+fn fold_expression(expression: &mut Expression) {
+    let folded = match *expression {
+        Expression::BinaryOp { ref operator, ref mut left, ref mut right } => {
+            fold_expression(left);
+            fold_expression(right);
+            match (&**left, &**right) {
+                (&Expression::Integer(left), &Expression::Integer(right)) => fold_binary(operator, left.into(), right.into()),
+                // The right side isn't worked out, so it doesn't matter what it is
+                (&Expression::Integer(0), _) if *operator == Operator::LogicalAnd => Some(0),
+                (&Expression::Integer(left), _) if left != 0 && *operator == Operator::LogicalOr => Some(1),
+                _ => None,
+            }
+        },
+        Expression::UnaryOp { ref operator, ref mut expression } => {
+            fold_expression(expression);
+            match **expression {
+                Expression::Integer(value) => fold_unary(operator, value.into()),
+                _ => None,
+            }
+        },
+        Expression::Call { ref mut arguments, .. } => {
+            for argument in arguments {
+                fold_expression(argument);
+            }
+            None
+        },
+        Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) => None,
+    };
+    if let Some(value) = folded.and_then(fit) {
+        *expression = Expression::Integer(value);
+    }
+}
+
+/// Returns value as a number literal, if it fits in one
+///
+/// The lowest number can't be negated in C without being larger than a literal can be, so it doesn't
+fn fit(value: i64) -> Option<i32> {
+    if value > i32::MIN.into() && value <= i32::MAX.into() {
+        Some(value as i32)
+    } else {
+        None
+    }
+}
+
+/// Returns what operator gives for left and right, as the interpreter works it out,
+/// or None if it would fail or operator isn't binary
+fn fold_binary(operator: &Operator, left: i64, right: i64) -> Option<i64> {
+    let truth = |condition: bool| if condition { 1 } else { 0 };
+    Some(match *operator {
+        Operator::Add => left.wrapping_add(right),
+        Operator::Sub => left.wrapping_sub(right),
+        Operator::Mul => left.wrapping_mul(right),
+        Operator::Div if right != 0 => left.wrapping_div(right),
+        Operator::Equals => truth(left == right),
+        Operator::NotEquals => truth(left != right),
+        Operator::Gt => truth(left > right),
+        Operator::Lt => truth(left < right),
+        Operator::Gte => truth(left >= right),
+        Operator::Lte => truth(left <= right),
+        Operator::LogicalAnd => truth(left != 0 && right != 0),
+        Operator::LogicalOr => truth(left != 0 || right != 0),
+        Operator::BinaryAnd => left & right,
+        Operator::BinaryOr => left | right,
+        _ => return None,
+    })
+}
+
+/// Returns what operator gives for value, as the interpreter works it out, or None if it isn't unary
+fn fold_unary(operator: &Operator, value: i64) -> Option<i64> {
+    match *operator {
+        Operator::Sub | Operator::Negate => Some(value.wrapping_neg()),
+        Operator::LogicalNot => Some(if value == 0 { 1 } else { 0 }),
+        Operator::BinaryNot => Some(!value),
+        _ => None,
+    }
+}
+
+/// Replaces the variables in statement whose values are known numbers with the numbers,
+/// folding the expressions that they are in
+///
+/// A value is known from a declaration or a set that gives a variable a number, and stays
+/// known until the variable is given something else. After an if, it is only known if it is
+/// the same whichever way the if went, and in and after a loop, the variables that the loop
+/// changes aren't known. Variables declared without a value and persistent variables are
+/// never known, and inline C could change any variable, so nothing is known after it. The
+/// arguments that name functions for C to call, in callbacks, are left alone.
+///
+/// # Examples
+/// ```
+/// # use haumea::parser;
+/// # use haumea::scanner::Scanner;
+/// # use haumea::optimizer;
+/// # use haumea::pretty;
+/// let source = "to main do\n    variable x is 4\n    if x > 3 then display(x * 2)\n    repeat 3 times increase x\n    display(x)\nend";
+/// let mut program = parser::parse(Scanner::new(source)).unwrap();
+/// optimizer::propagate_constants(program[0].code.as_mut().unwrap(), &Default::default());
+/// let mut out = String::new();
+/// pretty::print_function(&mut out, &program[0]);
+/// assert_eq!(out, "to main do\n    variable x is 4\n    if 1 then display(8)\n    repeat 3 times increase x\n    display(x)\nend\n");
+/// ```
+pub fn propagate_constants(statement: &mut Statement, callbacks: &Callbacks) {
+    let mut propagator = Propagator { callbacks, scopes: vec![vec![]] };
+    propagator.statement(statement);
+}
+
+/// The variables declared in the blocks around the statement being propagated into, innermost
+/// last, with their values there if they are known and whether they are persistent
+type Scopes = Vec<Vec<(String, Option<i32>, bool)>>;
+
+/// Propagates constants through the statements of a function, in the order they run
+struct Propagator<'a> {
+    callbacks: &'a Callbacks<'a>,
+    scopes: Scopes,
+}
+
+impl<'a> Propagator<'a> {
+    /// Returns the value of the variable that name means, or None if it isn't declared in the
+    /// function or is persistent, since a call could give a persistent variable anything
+    fn value(&mut self, name: &str) -> Option<&mut Option<i32>> {
+        self.scopes.iter_mut().rev()
+            .flat_map(|scope| scope.iter_mut().rev())
+            .find(|&&mut (ref known, _, _)| known == name)
+            .and_then(|&mut (_, ref mut value, persistent)| if persistent { None } else { Some(value) })
+    }
+
+    fn declare(&mut self, name: &str, value: Option<i32>, persistent: bool) {
+        self.scopes.last_mut().expect("there is always a scope").push((name.to_string(), value, persistent));
+    }
+
+    /// Propagates into statement in a scope of its own, as a clause of an if or the body of a loop is
+    fn scoped(&mut self, statement: &mut Statement) {
+        self.scopes.push(vec![]);
+        self.statement(statement);
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, statement: &mut Statement) {
+        match statement.kind {
+            StatementKind::Return(ref mut expression) => self.expression(expression),
+            StatementKind::Var(ref names) => {
+                for name in names {
+                    self.declare(name, None, false);
+                }
+            },
+            StatementKind::Persistent(ref name) => self.declare(name, None, true),
+            StatementKind::Define(ref name, ref mut expression) => {
+                self.expression(expression);
+                let value = integer(expression);
+                self.declare(name, value, false);
+            },
+            StatementKind::Set(ref name, ref mut expression) => {
+                self.expression(expression);
+                let value = integer(expression);
+                if let Some(known) = self.value(name) {
+                    *known = value;
+                }
+            },
+            StatementKind::Change(ref name, ref mut expression) => {
+                self.expression(expression);
+                let by = integer(expression);
+                if let Some(known) = self.value(name) {
+                    *known = match (*known, by) {
+                        (Some(value), Some(by)) => fold_binary(&Operator::Add, value.into(), by.into()).and_then(fit),
+                        _ => None,
+                    };
+                }
+            },
+            StatementKind::Swap(ref first, ref second) => {
+                let first_value = self.value(first).and_then(|value| *value);
+                let second_value = self.value(second).and_then(|value| *value);
+                if let Some(known) = self.value(first) {
+                    *known = second_value;
+                }
+                if let Some(known) = self.value(second) {
+                    *known = first_value;
+                }
+            },
+            StatementKind::If { ref mut cond, ref mut if_clause, ref mut else_clause, .. } => {
+                self.expression(cond);
+                match *cond {
+                    // The other clause never runs, so it doesn't matter what it would do
+                    Expression::Integer(0) => {
+                        if let Some(ref mut else_clause) = *else_clause {
+                            self.scoped(else_clause);
+                        }
+                    },
+                    Expression::Integer(_) => self.scoped(if_clause),
+                    _ => {
+                        let before = self.scopes.clone();
+                        self.scoped(if_clause);
+                        let after_if = mem::replace(&mut self.scopes, before);
+                        if let Some(ref mut else_clause) = *else_clause {
+                            self.scoped(else_clause);
+                        }
+                        self.merge(&after_if);
+                    },
+                }
+            },
+            StatementKind::Repeat { ref mut count, ref mut body, .. } => {
+                self.expression(count);
+                let mut changed = HashSet::new();
+                if changes(body, &mut changed) {
+                    self.forget(None);
+                } else {
+                    self.forget(Some(&changed));
+                }
+                self.scoped(body);
+                self.forget(Some(&changed));
+            },
+            StatementKind::Do(ref mut block) => {
+                self.scopes.push(vec![]);
+                for statement in block {
+                    self.statement(statement);
+                }
+                self.scopes.pop();
+            },
+            StatementKind::Call { ref function, ref mut arguments } => self.arguments(function, arguments),
+            StatementKind::InlineC(_) => self.forget(None),
+            StatementKind::Break(_) => (),
+        }
+    }
+
+    /// Makes the variables named in names unknown, or every variable if names is None
+    fn forget(&mut self, names: Option<&HashSet<String>>) {
+        for &mut (ref name, ref mut value, _) in self.scopes.iter_mut().flat_map(|scope| scope.iter_mut()) {
+            if names.is_none_or(|names| names.contains(name)) {
+                *value = None;
+            }
+        }
+    }
+
+    /// Keeps each value that is known only if it is the same in other, which has the same variables
+    fn merge(&mut self, other: &Scopes) {
+        for (scope, other) in self.scopes.iter_mut().zip(other) {
+            for (&mut (_, ref mut value, _), (_, other, _)) in scope.iter_mut().zip(other) {
+                if *value != *other {
+                    *value = None;
+                }
+            }
+        }
+    }
+
+    /// Replaces the known variables in expression with their values, and folds it
+    fn expression(&mut self, expression: &mut Expression) {
+        self.substitute(expression);
+        fold_expression(expression);
+    }
+
+    fn substitute(&mut self, expression: &mut Expression) {
+        let value = match *expression {
+            Expression::Ident(ref name) => self.value(name).and_then(|value| *value),
+            Expression::BinaryOp { ref mut left, ref mut right, .. } => {
+                self.substitute(left);
+                self.substitute(right);
+                None
+            },
+            Expression::UnaryOp { ref mut expression, .. } => {
+                self.substitute(expression);
+                None
+            },
+            Expression::Call { ref function, ref mut arguments } => {
+                let callbacks = self.callbacks.get(&function[..]).map_or(&[][..], |positions| &positions[..]);
+                for (index, argument) in arguments.iter_mut().enumerate() {
+                    if !callbacks.contains(&index) {
+                        self.substitute(argument);
+                    }
+                }
+                None
+            },
+            Expression::Integer(_) | Expression::Text(_) => None,
+        };
+        if let Some(value) = value {
+            *expression = Expression::Integer(value);
+        }
+    }
+
+    /// Propagates into the arguments of a call statement of function
+    fn arguments(&mut self, function: &str, arguments: &mut [Expression]) {
+        let callbacks = self.callbacks.get(function).map_or(&[][..], |positions| &positions[..]);
+        for (index, argument) in arguments.iter_mut().enumerate() {
+            if !callbacks.contains(&index) {
+                self.expression(argument);
+            }
+        }
+    }
+}
+
+/// Returns the value of expression if it is a number
+fn integer(expression: &Expression) -> Option<i32> {
+    match *expression {
+        Expression::Integer(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// Adds the names of the variables that statement sets, changes or swaps to names, returning
+/// true if it has inline C, which could change any of them
+fn changes(statement: &Statement, names: &mut HashSet<String>) -> bool {
+    match statement.kind {
+        StatementKind::Set(ref name, _) | StatementKind::Change(ref name, _) => {
+            names.insert(name.clone());
+            false
+        },
+        StatementKind::Swap(ref first, ref second) => {
+            names.insert(first.clone());
+            names.insert(second.clone());
+            false
+        },
+        StatementKind::If { ref if_clause, ref else_clause, .. } => {
+            let if_changes = changes(if_clause, names);
+            else_clause.as_ref().is_some_and(|else_clause| changes(else_clause, names)) || if_changes
+        },
+        StatementKind::Repeat { ref body, .. } => changes(body, names),
+        StatementKind::Do(ref block) => {
+            // Every statement is looked at, even after inline C, so that names has all of them
+            let mut inline_c = false;
+            for statement in block {
+                inline_c |= changes(statement, names);
+            }
+            inline_c
+        },
+        StatementKind::InlineC(_) => true,
+        StatementKind::Return(_) | StatementKind::Var(_) | StatementKind::Persistent(_) | StatementKind::Define(..) |
+        StatementKind::Break(_) | StatementKind::Call { .. } => false,
+    }
+}
+
+/// Calls f with each of the expressions in statement, and in the statements in it
+fn for_each_expression<F: FnMut(&mut Expression)>(statement: &mut Statement, f: &mut F) {
+    match statement.kind {
+        StatementKind::Return(ref mut expression) |
+        StatementKind::Set(_, ref mut expression) |
+        StatementKind::Define(_, ref mut expression) |
+        StatementKind::Change(_, ref mut expression) => f(expression),
+        StatementKind::If { ref mut cond, ref mut if_clause, ref mut else_clause, .. } => {
+            f(cond);
+            for_each_expression(if_clause, f);
+            if let Some(ref mut else_clause) = *else_clause {
+                for_each_expression(else_clause, f);
+            }
+        },
+        StatementKind::Repeat { ref mut count, ref mut body, .. } => {
+            f(count);
+            for_each_expression(body, f);
+        },
+        StatementKind::Do(ref mut block) => {
+            for statement in block {
+                for_each_expression(statement, f);
+            }
+        },
+        StatementKind::Call { ref mut arguments, .. } => {
+            for argument in arguments {
+                f(argument);
+            }
+        },
+        StatementKind::Var(_) | StatementKind::Persistent(_) | StatementKind::Swap(..) |
+        StatementKind::InlineC(_) | StatementKind::Break(_) => (),
+    }
+}
+
+/// Removes the code in statement that can never run
+///
+/// That is the statements after a return or a break in the same block, the side of an if
+/// whose condition is a number that isn't taken, and loops that repeat a number of times that
+/// isn't positive. Inline C is never removed, as other inline C could jump to it. It is best
+/// to fold the constants first, so that more conditions are numbers.
+///
+/// # Examples
+/// ```
+/// # use haumea::parser;
+/// # use haumea::scanner::Scanner;
+/// # use haumea::optimizer;
+/// # use haumea::pretty;
+/// let source = "to main do\n    if 0 then display(1) else display(2)\n    repeat 0 times display(3)\n    return 0\n    display(4)\nend";
+/// let mut program = parser::parse(Scanner::new(source)).unwrap();
+/// optimizer::eliminate_dead_code(program[0].code.as_mut().unwrap());
+/// let mut out = String::new();
+/// pretty::print_function(&mut out, &program[0]);
+/// assert_eq!(out, "to main do\n    do\n        display(2)\n    end\n    return 0\nend\n");
+/// ```
+pub fn eliminate_dead_code(statement: &mut Statement) {
+    let replacement = match statement.kind {
+        StatementKind::If { ref cond, ref mut if_clause, ref mut else_clause, .. } => {
+            eliminate_dead_code(if_clause);
+            if let Some(ref mut else_clause) = *else_clause {
+                eliminate_dead_code(else_clause);
+            }
+            match *cond {
+                Expression::Integer(0) => Some(else_clause.take().map(|clause| *clause)),
+                Expression::Integer(_) => Some(Some(placeholder(if_clause))),
+                _ => None,
+            }
+        },
+        StatementKind::Repeat { ref count, ref mut body, .. } => {
+            eliminate_dead_code(body);
+            match *count {
+                Expression::Integer(count) if count <= 0 => Some(None),
+                _ => None,
+            }
+        },
+        StatementKind::Do(ref mut block) => {
+            eliminate_dead_code_in(block);
+            None
+        },
+        _ => None,
+    };
+    match replacement {
+        // A clause on its own has a scope of its own in C, the same as a block does
+        Some(Some(clause)) => statement.kind = match clause.kind {
+            StatementKind::Do(_) => clause.kind,
+            _ => StatementKind::Do(vec![clause]),
+        },
+        Some(None) => statement.kind = StatementKind::Do(vec![]),
+        None => (),
+    }
+}
+
+/// Removes the code that can never run from the statements in block, and the ones that are
+/// left empty by it
+fn eliminate_dead_code_in(block: &mut Vec<Statement>) {
+    for statement in block.iter_mut() {
+        eliminate_dead_code(statement);
+    }
+    if let Some(end) = block.iter().position(|statement| matches!(statement.kind, StatementKind::Return(_) |
+                                                                                      StatementKind::Break(_))) {
+        let mut index = 0;
+        block.retain(|statement| {
+            index += 1;
+            index <= end + 1 || matches!(statement.kind, StatementKind::InlineC(_))
+        });
+    }
+    // A block that was in the source is kept, even if it is empty, for the comments in it
+    block.retain(|statement| !matches!(statement.kind, StatementKind::Do(ref block) if block.is_empty()) ||
+                             statement.comments != Comments::default());
+}
+
+/// Takes statement out of its box, leaving an empty block in its place
+fn placeholder(statement: &mut Box<Statement>) -> Statement {
+    let empty = Statement { kind: StatementKind::Do(vec![]), span: statement.span, comments: Comments::default() };
+    *std::mem::replace(statement, Box::new(empty))
+}
+
+/// Works out each expression that is in a statement more than once just once, in a variable
+/// declared before the statement
+///
+/// Only expressions that are made of operators are worked out in variables, and only in
+/// statements whose expressions have no calls in them, as a call could change what variables
+/// are. The sides of and and or that aren't always worked out don't count, so that nothing
+/// that could fail, like a division by zero, is worked out when it wasn't before.
+///
+/// # Examples
+/// ```
+/// # use haumea::parser;
+/// # use haumea::scanner::Scanner;
+/// # use haumea::optimizer;
+/// # use haumea::pretty;
+/// let mut program = parser::parse(Scanner::new("to f with (a, b) is (a + b) * (a + b) - a")).unwrap();
+/// optimizer::eliminate_common_subexpressions(program[0].code.as_mut().unwrap());
+/// let mut out = String::new();
+/// pretty::print_function(&mut out, &program[0]);
+/// assert_eq!(out, "to f with (a, b) do\n    variable haumea_common1 is a + b\n    return haumea_common1 * haumea_common1 - a\nend\n");
+/// ```
+pub fn eliminate_common_subexpressions(statement: &mut Statement) {
+    let mut eliminator = Eliminator { count: 0 };
+    eliminator.clause(statement);
+}
+
+/// Eliminates the common subexpressions in the statements of a function, counting the
+/// variables it makes so that each has a name of its own
+struct Eliminator {
+    count: usize,
+}
+
+impl Eliminator {
+    /// Eliminates the common subexpressions in statement, which isn't in a block, so the
+    /// variables go in a new block along with it
+    fn clause(&mut self, statement: &mut Statement) {
+        if let StatementKind::Do(ref mut block) = statement.kind {
+            return self.block(block);
+        }
+        self.nested(statement);
+        // A variable declared as a clause would end up in the new block instead of where it was
+        if matches!(statement.kind, StatementKind::Define(..)) {
+            return;
+        }
+        let mut variables = self.variables(statement);
+        if !variables.is_empty() {
+            let span = statement.span;
+            let clause = std::mem::replace(statement, Statement {
+                kind: StatementKind::Do(vec![]),
+                span,
+                comments: Comments::default(),
+            });
+            variables.push(clause);
+            statement.kind = StatementKind::Do(variables);
+        }
+    }
+
+    /// Eliminates the common subexpressions in the statements of block, declaring the
+    /// variables just before the statements that use them
+    fn block(&mut self, block: &mut Vec<Statement>) {
+        let mut index = 0;
+        while index < block.len() {
+            self.nested(&mut block[index]);
+            let variables = self.variables(&mut block[index]);
+            let count = variables.len();
+            block.splice(index..index, variables);
+            index += count + 1;
+        }
+    }
+
+    /// Eliminates the common subexpressions in the statements in statement
+    fn nested(&mut self, statement: &mut Statement) {
+        match statement.kind {
+            StatementKind::If { ref mut if_clause, ref mut else_clause, .. } => {
+                self.clause(if_clause);
+                if let Some(ref mut else_clause) = *else_clause {
+                    self.clause(else_clause);
+                }
+            },
+            StatementKind::Repeat { ref mut body, .. } => self.clause(body),
+            StatementKind::Do(ref mut block) => self.block(block),
+            _ => (),
+        }
+    }
+
+    /// Replaces the common subexpressions in the expressions of statement itself with variables,
+    /// returning the declarations of the variables in the order they have to be in
+    ///
+    /// The smallest common subexpression is replaced first, so that a larger one that has it
+    /// in it uses its variable, whose declaration comes before.
+    fn variables(&mut self, statement: &mut Statement) -> Vec<Statement> {
+        let span = statement.span;
+        let mut expressions = own_expressions(statement);
+        if expressions.iter().any(|expression| has_call(expression)) {
+            return vec![];
+        }
+        let mut variables = vec![];
+        while let Some(common) = common_subexpression(&expressions) {
+            self.count += 1;
+            let name = format!("{}{}", COMMON_PREFIX, self.count);
+            for expression in expressions.iter_mut() {
+                replace(expression, &common, &name);
+            }
+            variables.push(Statement {
+                kind: StatementKind::Define(name, common),
+                span,
+                comments: Comments::default(),
+            });
+        }
+        variables
+    }
+}
+
+/// Returns the expressions that statement works out itself, without the ones in the statements in it
+fn own_expressions(statement: &mut Statement) -> Vec<&mut Expression> {
+    match statement.kind {
+        StatementKind::Return(ref mut expression) |
+        StatementKind::Set(_, ref mut expression) |
+        StatementKind::Define(_, ref mut expression) |
+        StatementKind::Change(_, ref mut expression) |
+        StatementKind::If { cond: ref mut expression, .. } |
+        StatementKind::Repeat { count: ref mut expression, .. } => vec![expression],
+        StatementKind::Call { ref mut arguments, .. } => arguments.iter_mut().collect(),
+        StatementKind::Var(_) | StatementKind::Persistent(_) | StatementKind::Swap(..) |
+        StatementKind::InlineC(_) | StatementKind::Break(_) | StatementKind::Do(_) => vec![],
+    }
+}
+
+/// Returns true if there is a call anywhere in expression
+fn has_call(expression: &Expression) -> bool {
+    match *expression {
+        Expression::BinaryOp { ref left, ref right, .. } => has_call(left) || has_call(right),
+        Expression::UnaryOp { ref expression, .. } => has_call(expression),
+        Expression::Call { .. } => true,
+        Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) => false,
+    }
+}
+
+/// Returns the smallest operation that is always worked out more than once in expressions, if there is one
+fn common_subexpression(expressions: &[&mut Expression]) -> Option<Expression> {
+    let mut counts = HashMap::new();
+    let mut order = vec![];
+    for expression in expressions {
+        count_operations(expression, &mut counts, &mut order);
+    }
+    order.into_iter()
+        .filter(|operation| counts[operation] > 1)
+        .min_by_key(|operation| size(operation))
+        .cloned()
+}
+
+/// Counts each operation in expression that is always worked out when it is, in the order they are first found
+fn count_operations<'a>(expression: &'a Expression, counts: &mut HashMap<&'a Expression, usize>,
+                        order: &mut Vec<&'a Expression>) {
+    match *expression {
+        Expression::BinaryOp { ref operator, ref left, ref right } => {
+            count_operations(left, counts, order);
+            if !matches!(*operator, Operator::LogicalAnd | Operator::LogicalOr) {
+                count_operations(right, counts, order);
+            }
+        },
+        Expression::UnaryOp { ref expression, .. } => count_operations(expression, counts, order),
+        Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) | Expression::Call { .. } => return,
+    }
+    let count = counts.entry(expression).or_insert(0);
+    if *count == 0 {
+        order.push(expression);
+    }
+    *count += 1;
+}
+
+/// Returns how many operations and values there are in expression
+fn size(expression: &Expression) -> usize {
+    match *expression {
+        Expression::BinaryOp { ref left, ref right, .. } => 1 + size(left) + size(right),
+        Expression::UnaryOp { ref expression, .. } => 1 + size(expression),
+        Expression::Call { ref arguments, .. } => 1 + arguments.iter().map(size).sum::<usize>(),
+        Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) => 1,
+    }
+}
+
+/// Replaces each common in expression with the variable named name
+fn replace(expression: &mut Expression, common: &Expression, name: &str) {
+    if *expression == *common {
+        *expression = Expression::Ident(name.to_string());
+        return;
+    }
+    match *expression {
+        Expression::BinaryOp { ref mut left, ref mut right, .. } => {
+            replace(left, common, name);
+            replace(right, common, name);
+        },
+        Expression::UnaryOp { ref mut expression, .. } => replace(expression, common, name),
+        Expression::Call { ref mut arguments, .. } => {
+            for argument in arguments {
+                replace(argument, common, name);
+            }
+        },
+        Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) => (),
+    }
+}
@@ -0,0 +1,452 @@
+/// src/options.rs
+/// The options that control a run of the haumea compiler
+use std::env;
+use std::path::{Path, PathBuf};
+use lints::LintLevels;
+use parser;
+
+/// The file extensions used for haumea source files
+pub const SOURCE_EXTENSIONS: &[&str] = &["hm", "hau"];
+
+/// The path that stands for stdin as an input, or stdout as an output
+pub const STDIO_PATH: &str = "-";
+
+/// The kind of output the compiler should produce
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    /// The stream of tokens read by the scanner
+    Tokens,
+    /// The abstract syntax tree built by the parser
+    Ast,
+    /// The intermediate representation that the functions are lowered to, after they are optimized
+    Ir,
+    /// The generated C source (the default)
+    C,
+    /// The assembly that the system C compiler generates for the C source
+    Asm,
+    /// A Graphviz graph of the abstract syntax tree
+    Dot,
+    /// A Graphviz graph of which functions call which others
+    CallGraph,
+    /// The call graph as JSON
+    CallGraphJson,
+    /// A Graphviz graph of the basic blocks of each function and which ones can run after which
+    Cfg,
+    /// A C header with the prototypes of the functions, for C code that calls them
+    Header,
+}
+
+impl Emit {
+    /// Looks up an Emit by the name used on the command line
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::options::Emit;
+    /// assert_eq!(Emit::from_name("c"), Some(Emit::C));
+    /// assert_eq!(Emit::from_name("pdf"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<Emit> {
+        match name {
+            "tokens" => Some(Emit::Tokens),
+            "ast" => Some(Emit::Ast),
+            "ir" => Some(Emit::Ir),
+            "c" => Some(Emit::C),
+            "asm" => Some(Emit::Asm),
+            "dot" => Some(Emit::Dot),
+            "callgraph" => Some(Emit::CallGraph),
+            "callgraph-json" => Some(Emit::CallGraphJson),
+            "cfg" => Some(Emit::Cfg),
+            "header" => Some(Emit::Header),
+            _ => None,
+        }
+    }
+
+    /// Returns the file extension used for this kind of output
+    ///
+    /// None means that the output is meant for reading, so it goes to stdout by default
+    pub fn extension(&self) -> Option<&'static str> {
+        match *self {
+            Emit::Tokens | Emit::Ast | Emit::Ir => None,
+            Emit::C => Some("c"),
+            Emit::Asm => Some("s"),
+            Emit::Dot => Some("dot"),
+            Emit::CallGraph => Some("calls.dot"),
+            Emit::CallGraphJson => Some("calls.json"),
+            Emit::Cfg => Some("cfg.dot"),
+            Emit::Header => Some("h"),
+        }
+    }
+}
+
+/// The backend that the compiler generates code for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// Portable C source (the default)
+    C,
+}
+
+impl Target {
+    /// Looks up a Target by the name used on the command line
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::options::Target;
+    /// assert_eq!(Target::from_name("c"), Some(Target::C));
+    /// assert_eq!(Target::from_name("jvm"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "c" => Some(Target::C),
+            _ => None,
+        }
+    }
+}
+
+/// Which runtime library the generated C uses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Runtime {
+    /// The minimal runtime if the program only calls the builtins it has, and the full one otherwise
+    /// (the default)
+    Auto,
+    /// A tiny prolog in the generated C itself, which only has display
+    Minimal,
+    /// The full runtime library, which is compiled separately and linked with the program
+    Full,
+}
+
+impl Runtime {
+    /// Looks up a Runtime by the name used on the command line
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::options::Runtime;
+    /// assert_eq!(Runtime::from_name("minimal"), Some(Runtime::Minimal));
+    /// assert_eq!(Runtime::from_name("none"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<Runtime> {
+        match name {
+            "auto" => Some(Runtime::Auto),
+            "minimal" => Some(Runtime::Minimal),
+            "full" => Some(Runtime::Full),
+            _ => None,
+        }
+    }
+}
+
+/// How errors and warnings are written to stderr
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageFormat {
+    /// A line like `main.hm:2:5: error[E0201]: message` for each one, as C compilers write them (the default)
+    Human,
+    /// A JSON object on a line of its own for each one, for editors and other programs to read
+    Json,
+    /// A SARIF log of all of them, written when haumea finishes, for code scanning services
+    Sarif,
+}
+
+impl MessageFormat {
+    /// Looks up a MessageFormat by the name used on the command line
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::options::MessageFormat;
+    /// assert_eq!(MessageFormat::from_name("json"), Some(MessageFormat::Json));
+    /// assert_eq!(MessageFormat::from_name("xml"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<MessageFormat> {
+        match name {
+            "human" => Some(MessageFormat::Human),
+            "json" => Some(MessageFormat::Json),
+            "sarif" => Some(MessageFormat::Sarif),
+            _ => None,
+        }
+    }
+}
+
+/// How much the compiler optimizes a program, which picks the passes that are run on it
+/// before it is compiled to C and is passed on to the C compiler
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptLevel {
+    /// No passes, so that the C is as close to the source as it can be
+    O0,
+    /// Inlining the functions marked @inline, folding constants, and removing dead code (the default)
+    O1,
+    /// Everything that O1 does, and eliminating common subexpressions
+    O2,
+}
+
+impl OptLevel {
+    /// Looks up an OptLevel by the name used on the command line, which is what comes after -O
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::options::OptLevel;
+    /// assert_eq!(OptLevel::from_name("2"), Some(OptLevel::O2));
+    /// assert_eq!(OptLevel::from_name("3"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<OptLevel> {
+        match name {
+            "0" => Some(OptLevel::O0),
+            "1" => Some(OptLevel::O1),
+            "2" => Some(OptLevel::O2),
+            _ => None,
+        }
+    }
+
+    /// Returns the flag that asks the C compiler for the same level
+    pub fn c_flag(&self) -> &'static str {
+        match *self {
+            OptLevel::O0 => "-O0",
+            OptLevel::O1 => "-O1",
+            OptLevel::O2 => "-O2",
+        }
+    }
+}
+
+/// What the generated C is turned into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputKind {
+    /// The output is written out as it is (the default)
+    Source,
+    /// The generated C is compiled into a native executable by the system C compiler
+    Executable,
+    /// The functions other than main are compiled into a static library, which is archived by `ar`
+    StaticLibrary,
+    /// The functions other than main are compiled into a shared library, like a .so or a .dylib
+    SharedLibrary,
+}
+
+/// The options for a single compilation
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompilerOptions {
+    /// The paths of the source files to compile, or `-` to read the source from stdin
+    ///
+    /// The functions of all of the files are compiled into one program.
+    /// There is always at least one input, and the first one is used to name the output
+    pub inputs: Vec<PathBuf>,
+    /// Where to write the output
+    ///
+    /// None means that the output path is derived from the input path
+    /// by replacing its extension with the one for `emit`
+    /// (or the platform's executable extension when building an executable),
+    /// or that the output goes to stdout if `emit` has no extension or the input is stdin.
+    /// `-` also means stdout
+    pub output: Option<PathBuf>,
+    /// What kind of output to produce
+    pub emit: Emit,
+    /// The backend to generate code for
+    pub target: Target,
+    /// Which runtime library the generated C uses
+    pub runtime: Runtime,
+    /// What the generated C is turned into
+    pub output_kind: OutputKind,
+    /// Whether to generate a separate C file for each input, along with a shared header
+    ///
+    /// When this is true `output` names the header
+    pub split: bool,
+    /// The C compiler used to build executables and assembly
+    ///
+    /// None means that $CC is used, or `cc` if that isn't set
+    pub cc: Option<String>,
+    /// How much the program is optimized, by haumea and by the C compiler
+    pub opt_level: OptLevel,
+    /// Extra flags passed to the C compiler, in order, after the one for opt_level
+    pub cflags: Vec<String>,
+    /// The libraries that executables are linked with, like `m` for `-lm`
+    pub libraries: Vec<String>,
+    /// The directories searched for the libraries, passed to the C compiler with `-L`
+    pub library_paths: Vec<PathBuf>,
+    /// Whether the generated C checks for errors like division by zero, stopping the program
+    /// with the line of the source where they happened
+    pub safe: bool,
+    /// Whether each expression is broken up into assignments to temporaries with one operation
+    /// each before it is compiled, so that the C is like the IR and easier to step through
+    pub temporaries: bool,
+    /// Whether programs can use inline C, which is an error otherwise
+    pub allow_inline_c: bool,
+    /// Which lints the checker reports, about code that is allowed but easy to get wrong
+    pub lints: LintLevels,
+    /// Whether every warning is an error, so that a program with any warnings fails to compile
+    pub deny_warnings: bool,
+    /// Whether each statement counts how many times it runs, to write a coverage report
+    /// when the program exits
+    pub instrument_coverage: bool,
+    /// How many threads codegen can compile the functions of a large program on
+    ///
+    /// None means as many as the machine can run at once. The C is the same however many are used
+    pub codegen_threads: Option<usize>,
+    /// The directory that the C of each function is kept in, so that rebuilding the program
+    /// only compiles the functions that changed, or None to compile every function every time
+    pub cache_dir: Option<PathBuf>,
+    /// C that is put in the prolog of the generated C, after the runtime, like helper functions
+    pub extra_prolog: String,
+    /// C that is put at the end of the generated C
+    pub extra_epilog: String,
+    /// How deeply statements and expressions can be nested in each other before the program
+    /// is rejected, instead of overflowing the stack
+    pub max_nesting: usize,
+    /// Whether to stop after the semantic checks, only reporting errors and generating nothing
+    pub check: bool,
+    /// Whether to report how long each phase of the compilation took to stderr
+    pub timings: bool,
+    /// Whether the generated C is minified, without comments, #line directives or layout,
+    /// for embedding it where space matters
+    pub minify: bool,
+    /// Whether to write a source map of which lines of C each line of haumea produced,
+    /// to source_map_path, along with the C
+    pub source_map: bool,
+    /// How errors and warnings are written
+    pub message_format: MessageFormat,
+    /// Whether to print nothing but errors, for scripts that only care about the exit code
+    ///
+    /// This overrides `timings`
+    pub quiet: bool,
+}
+
+impl CompilerOptions {
+    /// Constructs the default CompilerOptions for compiling the file at `input`
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::options::{CompilerOptions, Emit, Target, OutputKind};
+    /// let options = CompilerOptions::new("factorial.hm");
+    /// assert_eq!(options.output, None);
+    /// assert_eq!(options.emit, Emit::C);
+    /// assert_eq!(options.target, Target::C);
+    /// assert_eq!(options.output_kind, OutputKind::Source);
+    /// assert!(!options.split);
+    /// ```
+    pub fn new<P: Into<PathBuf>>(input: P) -> CompilerOptions {
+        CompilerOptions {
+            inputs: vec![input.into()],
+            output: None,
+            emit: Emit::C,
+            target: Target::C,
+            runtime: Runtime::Auto,
+            output_kind: OutputKind::Source,
+            split: false,
+            cc: None,
+            opt_level: OptLevel::O1,
+            cflags: vec![],
+            libraries: vec![],
+            library_paths: vec![],
+            safe: false,
+            temporaries: false,
+            allow_inline_c: false,
+            lints: LintLevels::default(),
+            deny_warnings: false,
+            instrument_coverage: false,
+            codegen_threads: None,
+            cache_dir: None,
+            extra_prolog: String::new(),
+            extra_epilog: String::new(),
+            max_nesting: parser::MAX_NESTING,
+            check: false,
+            timings: false,
+            minify: false,
+            source_map: false,
+            message_format: MessageFormat::Human,
+            quiet: false,
+        }
+    }
+
+    /// Returns the path that the output should be written to, or None for stdout
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::options::{CompilerOptions, Emit, OutputKind};
+    /// # use std::path::{Path, PathBuf};
+    /// let mut options = CompilerOptions::new("examples/factorial.hm");
+    /// assert_eq!(options.output_path(), Some(PathBuf::from("examples/factorial.c")));
+    /// options.emit = Emit::Ast;
+    /// assert_eq!(options.output_path(), None);
+    /// options.output = Some(PathBuf::from("out.txt"));
+    /// assert_eq!(options.output_path(), Some(PathBuf::from("out.txt")));
+    /// options.output = Some(PathBuf::from("-"));
+    /// assert_eq!(options.output_path(), None);
+    ///
+    /// options.output = None;
+    /// options.output_kind = OutputKind::StaticLibrary;
+    /// assert_eq!(options.output_path(), Some(PathBuf::from("examples/libfactorial.a")));
+    ///
+    /// let options = CompilerOptions::new("-");
+    /// assert_eq!(options.output_path(), None);
+    /// ```
+    pub fn output_path(&self) -> Option<PathBuf> {
+        match self.output {
+            Some(ref path) if is_stdio(path) => None,
+            Some(ref path) => Some(path.clone()),
+            None if self.reads_stdin() => None,
+            None => match self.output_kind {
+                OutputKind::Source => self.emit.extension().map(|ext| self.input().with_extension(ext)),
+                OutputKind::Executable => Some(self.input().with_extension(env::consts::EXE_EXTENSION)),
+                OutputKind::StaticLibrary => {
+                    let stem = self.input().file_stem().map_or("".into(), |stem| stem.to_string_lossy());
+                    Some(self.input().with_file_name(format!("lib{}.a", stem)))
+                },
+                OutputKind::SharedLibrary => {
+                    let stem = self.input().file_stem().map_or("".into(), |stem| stem.to_string_lossy());
+                    Some(self.input().with_file_name(format!("{}{}{}", env::consts::DLL_PREFIX, stem,
+                                                             env::consts::DLL_SUFFIX)))
+                },
+            },
+        }
+    }
+
+    /// Returns the path that the source map is written to, which is the output with .map after it,
+    /// or None if the output goes to stdout
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::options::CompilerOptions;
+    /// # use std::path::PathBuf;
+    /// let mut options = CompilerOptions::new("examples/factorial.hm");
+    /// assert_eq!(options.source_map_path(), Some(PathBuf::from("examples/factorial.c.map")));
+    /// options.output = Some(PathBuf::from("-"));
+    /// assert_eq!(options.source_map_path(), None);
+    /// ```
+    pub fn source_map_path(&self) -> Option<PathBuf> {
+        self.output_path().map(|path| {
+            let mut path = path.into_os_string();
+            path.push(".map");
+            PathBuf::from(path)
+        })
+    }
+
+    /// Returns the first input, which is used to name the output
+    pub fn input(&self) -> &Path {
+        &self.inputs[0]
+    }
+
+    /// Returns true if one of the sources is read from stdin instead of a file
+    pub fn reads_stdin(&self) -> bool {
+        self.inputs.iter().any(|input| is_stdio(input))
+    }
+
+    /// Returns the name of the first input used in messages and #line directives
+    pub fn source_name(&self) -> String {
+        source_name(self.input())
+    }
+}
+
+/// Returns true if path stands for stdin or stdout
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == STDIO_PATH
+}
+
+/// Returns the name of the input at path used in messages and #line directives
+///
+/// # Examples
+/// ```
+/// # use haumea::options::source_name;
+/// # use std::path::Path;
+/// assert_eq!(source_name(Path::new("factorial.hm")), "factorial.hm");
+/// assert_eq!(source_name(Path::new("-")), "<stdin>");
+/// ```
+pub fn source_name(path: &Path) -> String {
+    if is_stdio(path) {
+        "<stdin>".to_string()
+    } else {
+        path.display().to_string()
+    }
+}
@@ -1,13 +1,47 @@
 /// src/parser.rs
 /// The parser for the haumea language.
-use std::rc::Rc;
-use scanner::{Scanner, Token};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use scanner::{Scanner, Token, Span};
+use diagnostic::{Diagnostic, ErrorKind};
+
+/// How deeply statements and expressions can be nested in each other, unless
+/// parse_tokens_nested is given another limit
+///
+/// The parser is recursive, so without a limit a program that nests too deeply
+/// would overflow the stack rather than getting an error
+pub const MAX_NESTING: usize = 200;
 
 /// A Program is a Vec of Functions
 pub type Program = Vec<Function>;
 
-/// A Block is a Vec of Rc<Statement>s
-pub type Block = Vec<Rc<Statement>>;
+/// A Program along with the name of the source it was parsed from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceFile {
+    /// The name of the source, used for diagnostics and #line directives
+    pub name: String,
+    /// The parsed program
+    pub program: Program,
+}
+
+/// The positions of the parameters of each external function that are given haumea functions
+/// for C to call, by the name of the function
+pub type Callbacks<'a> = HashMap<&'a str, Vec<usize>>;
+
+/// Returns the Callbacks of the external functions in files
+///
+/// An argument in one of those positions is the name of a function, even if a variable has the same name.
+pub fn callbacks(files: &[SourceFile]) -> Callbacks<'_> {
+    let mut callbacks = Callbacks::new();
+    for func in files.iter().flat_map(|file| file.program.iter()).filter(|func| func.code.is_none()) {
+        callbacks.entry(&func.name[..]).or_insert_with(|| func.callback_positions());
+    }
+    callbacks
+}
+
+/// A Block is a Vec of Statements
+pub type Block = Vec<Statement>;
 
 /// A Type is a String (for now)
 pub type Type = String;
@@ -19,7 +53,20 @@ pub type Ident = String;
 pub type Signature = Vec<String>;
 
 /// A function is a callable unit of code that returns a value
-#[derive(Debug)]
+///
+/// Two functions are equal if everything but where they are in the source is the same,
+/// so that the same function parsed from differently laid out source is equal
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser;
+/// let program = parser::parse(Scanner::new("to main do display(1 + 2) end")).unwrap();
+/// let laid_out = parser::parse(Scanner::new("to main\ndo\n    display((1) + 2)\nend\n")).unwrap();
+/// assert_eq!(program, laid_out);
+/// assert_ne!(program, parser::parse(Scanner::new("to main do display(2 + 1) end")).unwrap());
+/// ```
+#[derive(Debug, Clone)]
 pub struct Function {
     /// The name of the function
     pub name: String,
@@ -30,12 +77,187 @@ pub struct Function {
     /// the function takes no arguments and return the Integer 0
     pub signature: Option<Signature>,
     /// The code of the function
-    pub code: Statement,
+    ///
+    /// This is None for an external function, which is declared with
+    /// `external function name with (parameters)` and defined in C
+    pub code: Option<Statement>,
+    /// Where the function starts in the source
+    pub span: Span,
+    /// The comments before the function, and after it if it is the last one in the source
+    ///
+    /// Documentation comments aren't included, as they are kept in doc
+    pub comments: Comments,
+    /// The lines of the documentation comments (the ones starting with ##) before the function
+    ///
+    /// Each line is stored without the ## and the space after it
+    pub doc: Vec<String>,
+    /// The C headers included before the function with `include "header.h"`
+    pub includes: Vec<String>,
+    /// Whether the function is declared with `exported to`, which makes it part of the
+    /// interface of a library, for C code to call
+    pub exported: bool,
+    /// The parameters of an external function that are texts, like name in
+    /// `external function getenv with (text name)`, which C is given as `const char *`
+    pub text_parameters: Vec<String>,
+    /// Whether an external function returns a text, which it does when it is declared
+    /// with `external text function`
+    pub returns_text: bool,
+    /// The parameters of an external function that are given haumea functions for C to call,
+    /// with the parameters of those functions, like compare in
+    /// `external function qsort with (base, count, size, function compare with (a, b))`
+    pub callback_parameters: Vec<(Ident, Signature)>,
+    /// The attributes written before the function, like `@test`, in order
+    pub attributes: Vec<Attribute>,
+}
+
+impl Function {
+    /// Returns the first attribute of the function named name, if it has one
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::scanner::Scanner;
+    /// # use haumea::parser;
+    /// let program = parser::parse(Scanner::new("@deprecated(\"use add\")\nto plus with (a, b) is a + b")).unwrap();
+    /// assert_eq!(program[0].attribute("deprecated").and_then(|attribute| attribute.argument.as_ref()).unwrap(), "use add");
+    /// assert!(program[0].attribute("test").is_none());
+    /// ```
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+
+    /// Returns true if the function is part of the interface of a library, which it is when
+    /// it is declared with `exported to` or marked `@export`
+    pub fn is_exported(&self) -> bool {
+        self.exported || self.attribute("export").is_some()
+    }
+
+    /// Returns the positions of the parameters of an external function that are given haumea
+    /// functions for C to call, as in a call of it those arguments are names of functions
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::scanner::Scanner;
+    /// # use haumea::parser;
+    /// let program = parser::parse(Scanner::new("external function qsort with (base, count, size, function compare with (a, b))")).unwrap();
+    /// assert_eq!(program[0].callback_positions(), vec![3]);
+    /// ```
+    pub fn callback_positions(&self) -> Vec<usize> {
+        self.signature.iter()
+            .flat_map(|signature| signature.iter())
+            .enumerate()
+            .filter(|&(_, parameter)| self.callback_parameters.iter().any(|(callback, _)| callback == parameter))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// Something written before a function with an @, like `@inline` or `@deprecated("use add")`,
+/// that tells the rest of haumea how to treat it
+///
+/// The parser takes any name, so that the checker can say which ones it doesn't know.
+/// As for Statements, where an attribute is is left out when they are compared or hashed.
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    /// The name of the attribute, without the @
+    pub name: Ident,
+    /// The text given to it in brackets, if it was given one
+    pub argument: Option<String>,
+    /// Where the @ is in the source
+    pub span: Span,
+}
+
+impl PartialEq for Attribute {
+    fn eq(&self, other: &Attribute) -> bool {
+        self.name == other.name && self.argument == other.argument
+    }
 }
 
-/// A Haumea statement
-#[derive(Debug)]
-pub enum Statement {
+impl Eq for Attribute {}
+
+impl Hash for Attribute {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.argument.hash(state);
+    }
+}
+
+/// A Haumea statement, along with where it starts in the source
+///
+/// As for Functions, where a statement is is left out when statements are compared or hashed
+#[derive(Debug, Clone)]
+pub struct Statement {
+    /// What kind of statement it is
+    pub kind: StatementKind,
+    /// Where the statement starts in the source
+    pub span: Span,
+    /// The comments around the statement
+    pub comments: Comments,
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Function) -> bool {
+        self.name == other.name &&
+        self.signature == other.signature &&
+        self.code == other.code &&
+        self.comments == other.comments &&
+        self.doc == other.doc &&
+        self.includes == other.includes &&
+        self.exported == other.exported &&
+        self.text_parameters == other.text_parameters &&
+        self.returns_text == other.returns_text &&
+        self.callback_parameters == other.callback_parameters &&
+        self.attributes == other.attributes
+    }
+}
+
+impl Eq for Function {}
+
+impl Hash for Function {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.signature.hash(state);
+        self.code.hash(state);
+        self.comments.hash(state);
+        self.doc.hash(state);
+        self.includes.hash(state);
+        self.exported.hash(state);
+        self.text_parameters.hash(state);
+        self.returns_text.hash(state);
+        self.callback_parameters.hash(state);
+        self.attributes.hash(state);
+    }
+}
+
+impl PartialEq for Statement {
+    fn eq(&self, other: &Statement) -> bool {
+        self.kind == other.kind && self.comments == other.comments
+    }
+}
+
+impl Eq for Statement {}
+
+impl Hash for Statement {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+        self.comments.hash(state);
+    }
+}
+
+/// The comments attached to a function or statement, without their #s
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Comments {
+    /// The comments on the lines before it
+    pub leading: Vec<String>,
+    /// The comment at the end of its last line
+    pub trailing: Option<String>,
+    /// The comments after everything in it: before the `end` of a do block,
+    /// or after the last function in the source
+    pub closing: Vec<String>,
+}
+
+/// The different kinds of Haumea statements
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StatementKind {
     /// A return statement
     ///
     /// return 1
@@ -47,7 +269,18 @@ pub enum Statement {
 	/// A variable statement
 	///
 	/// variable x
-	Var(Ident),
+	///
+	/// or, for more than one at a time, variables x, y, z
+	Var(Vec<Ident>),
+    /// A variable statement whose variable keeps its value from one call of the function
+    /// to the next, starting at 0
+    ///
+    /// persistent variable x
+    Persistent(Ident),
+    /// A variable statement that gives the variable its starting value
+    ///
+    /// variable x is 5
+    Define(Ident, Expression),
     /// An assignment statement
     ///
     /// set x to 5
@@ -55,17 +288,42 @@ pub enum Statement {
     /// A change statement
     ///
     /// change x by -2
+    ///
+    /// increase x and decrease x are parsed as changes by 1 and -1
     Change(Ident, Expression),
+    /// A swap statement, which gives each variable the value of the other
+    ///
+    /// swap x and y
+    Swap(Ident, Ident),
     /// An if statement
     ///
     /// if True then return 1
     /// else return -3
-    /// (else is optional)
+    /// @unlikely if n = 0 then return 1
+    /// (else is optional, and so are the attributes, which say whether the then clause is likely to run)
     If {
         cond: Expression,
-        if_clause: Rc<Statement>,
-        else_clause: Rc<Option<Statement>>,
+        if_clause: Box<Statement>,
+        else_clause: Option<Box<Statement>>,
+        attributes: Vec<Attribute>,
     },
+    /// A repeat statement, which runs body count times, or not at all if count isn't positive
+    ///
+    /// repeat 3 times display(1)
+    /// repeat 3 times as outer do ... end
+    /// (the label, which break can leave the loop by, is optional)
+    ///
+    /// repeat n times display(1) otherwise display(0) is parsed as a do block that keeps n in a
+    /// variable and repeats in an if, whose else is the otherwise (see repeat_otherwise)
+    Repeat {
+        count: Expression,
+        label: Option<Ident>,
+        body: Box<Statement>,
+    },
+    /// A break statement, which leaves the enclosing loop with the label
+    ///
+    /// break out of outer
+    Break(Ident),
     /// A Do statement
     ///
     /// do
@@ -80,10 +338,14 @@ pub enum Statement {
         function: Ident,
         arguments: Vec<Expression>,
     },
+    /// A block of inline C, which is put into the generated C as it is
+    ///
+    /// c { putchar('x'); }
+    InlineC(String),
 }
 
 /// The operators in Haumea
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Operator {
     /// Addition (+)
     Add,
@@ -108,8 +370,12 @@ pub enum Operator {
     /// Lesser than or equal to (<=)
     Lte,
     /// Logical And (and)
+    ///
+    /// The right side is only evaluated if the left side is true, by every backend
     LogicalAnd,
     /// Logical OR (or)
+    ///
+    /// The right side is only evaluated if the left side is false, by every backend
     LogicalOr,
     /// Logical Not (not)
     LogicalNot,
@@ -121,366 +387,962 @@ pub enum Operator {
     BinaryNot,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Expression {
     /// A binary operation (eg, "1 +2" or "True or False")
     BinaryOp {
         operator: Operator,
-        left: Rc<Expression>,
-        right: Rc<Expression>,
+        left: Box<Expression>,
+        right: Box<Expression>,
     },
     /// A unary operation (eg, "not False" or "-(1 + 2)")
     UnaryOp {
         operator: Operator,
-        expression: Rc<Expression>,
+        expression: Box<Expression>,
     },
     /// An integer literal
     Integer(i32),
+    /// A text literal
+    Text(String),
     /// An identifier
     Ident(Ident),
     /// A function call
     Call {
         function: Ident,
-        arguments: Vec<Rc<Expression>>,
+        arguments: Vec<Expression>,
     },
 }
 
-pub fn parse(scanner: Scanner) -> Program {
-    let mut tokens = scanner.collect::<Vec<_>>();
+/// The number of nodes of each kind in a Program
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NodeCounts {
+    /// The number of functions
+    pub functions: usize,
+    /// The number of statements
+    pub statements: usize,
+    /// The number of expressions
+    pub expressions: usize,
+}
+
+impl NodeCounts {
+    /// Counts the nodes in program
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::scanner::Scanner;
+    /// # use haumea::parser::{self, NodeCounts};
+    /// let program = parser::parse(Scanner::new("to main do display(1 + 2) end")).unwrap();
+    /// assert_eq!(NodeCounts::of(&program), NodeCounts {
+    ///     functions: 1,
+    ///     statements: 2,
+    ///     expressions: 3,
+    /// });
+    /// ```
+    pub fn of(program: &Program) -> NodeCounts {
+        let mut counts = NodeCounts::default();
+        for func in program {
+            counts.functions += 1;
+            if let Some(ref code) = func.code {
+                counts.add_statement(code);
+            }
+        }
+        counts
+    }
+
+    /// Adds statement and everything in it to the counts
+    fn add_statement(&mut self, statement: &Statement) {
+        self.statements += 1;
+        match statement.kind {
+            StatementKind::Return(ref expr) |
+            StatementKind::Set(_, ref expr) |
+            StatementKind::Define(_, ref expr) |
+            StatementKind::Change(_, ref expr) => self.add_expression(expr),
+            StatementKind::Var(_) | StatementKind::Persistent(_) | StatementKind::Swap(..) | StatementKind::InlineC(_) | StatementKind::Break(_) => (),
+            StatementKind::If { ref cond, ref if_clause, ref else_clause, .. } => {
+                self.add_expression(cond);
+                self.add_statement(if_clause);
+                if let Some(ref else_clause) = *else_clause {
+                    self.add_statement(else_clause);
+                }
+            },
+            StatementKind::Repeat { ref count, ref body, .. } => {
+                self.add_expression(count);
+                self.add_statement(body);
+            },
+            StatementKind::Do(ref block) => {
+                for statement in block {
+                    self.add_statement(statement);
+                }
+            },
+            StatementKind::Call { ref arguments, .. } => {
+                for argument in arguments {
+                    self.add_expression(argument);
+                }
+            },
+        }
+    }
+
+    /// Adds expression and everything in it to the counts
+    fn add_expression(&mut self, expression: &Expression) {
+        self.expressions += 1;
+        match *expression {
+            Expression::BinaryOp { ref left, ref right, .. } => {
+                self.add_expression(left);
+                self.add_expression(right);
+            },
+            Expression::UnaryOp { ref expression, .. } => self.add_expression(expression),
+            Expression::Call { ref arguments, .. } => {
+                for argument in arguments {
+                    self.add_expression(argument);
+                }
+            },
+            Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) => (),
+        }
+    }
+}
+
+/// Parses the tokens produced by scanner into a Program
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser;
+/// let program = parser::parse(Scanner::new("to main do display(1) end")).unwrap();
+/// assert_eq!(program[0].name, "main");
+///
+/// let error = parser::parse(Scanner::new("to main do display(1 end")).unwrap_err();
+/// assert_eq!(error.to_string(), "1:22: error[E0101]: Expected Comma, but found Keyword(\"end\")!");
+/// ```
+pub fn parse(mut scanner: Scanner) -> Result<Program, Diagnostic> {
+    parse_tokens(scanner.tokenize()?)
+}
+
+/// Parses tokens (as produced by Scanner::tokenize) into a Program
+pub fn parse_tokens(tokens: Vec<(Token, Span)>) -> Result<Program, Diagnostic> {
+    parse_tokens_nested(tokens, MAX_NESTING)
+}
+
+/// Parses tokens into a Program like parse_tokens, with statements and expressions nested
+/// at most max_depth deep instead of MAX_NESTING
+///
+/// Code that parses programs it can't trust, like a compiler run as a service, can lower
+/// the limit to bound how much stack and time a program can take. The passes after parsing
+/// recurse into what is nested too, so a limit far above MAX_NESTING can overflow the stack.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser;
+/// let tokens = || Scanner::new("to main do display((1 + 2) * 3) end").tokenize().unwrap();
+/// assert!(parser::parse_tokens_nested(tokens(), 3).is_ok());
+///
+/// let error = parser::parse_tokens_nested(tokens(), 2).unwrap_err();
+/// assert_eq!(error.to_string(), "1:23: error[E0102]: The program is nested more than 2 deep");
+/// ```
+pub fn parse_tokens_nested(tokens: Vec<(Token, Span)>, max_depth: usize) -> Result<Program, Diagnostic> {
+    let mut tokens = TokenStream::new(tokens);
+    tokens.max_depth = max_depth;
     let mut program = vec![];
     while !tokens.is_empty() {
-        program.push(parse_function(&mut tokens));
+        program.push(parse_function(&mut tokens)?);
+    }
+    if let Some(func) = program.last_mut() {
+        func.comments.closing = tokens.leading_comments();
+    }
+    Ok(program)
+}
+
+/// A single function definition, statement, or expression, like a line typed into the REPL
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub enum Entry {
+    /// A function definition
+    Function(Box<Function>),
+    /// A statement
+    Statement(Statement),
+    /// An expression, along with where it starts
+    Expression(Expression, Span),
+}
+
+/// Parses tokens (as produced by Scanner::tokenize) into a single Entry
+///
+/// Input that starts with `to`, `external`, `include`, or an attribute is a function, input that starts
+/// with any other keyword is a statement, and anything else is an expression
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, Entry};
+/// let entry = parser::parse_entry(Scanner::new("1 + 2").tokenize().unwrap()).unwrap();
+/// assert!(match entry { Entry::Expression(..) => true, _ => false });
+/// let entry = parser::parse_entry(Scanner::new("set x to 2").tokenize().unwrap()).unwrap();
+/// assert!(match entry { Entry::Statement(..) => true, _ => false });
+/// ```
+pub fn parse_entry(tokens: Vec<(Token, Span)>) -> Result<Entry, Diagnostic> {
+    let mut tokens = TokenStream::new(tokens);
+    let entry = match *tokens.peek() {
+        Token::Keyword(ref keyword) if ["to", "external", "include", "exported"].contains(&&keyword[..]) => {
+            Entry::Function(Box::new(parse_function(&mut tokens)?))
+        },
+        Token::At => Entry::Function(Box::new(parse_function(&mut tokens)?)),
+        Token::Keyword(_) => Entry::Statement(parse_statement(&mut tokens)?),
+        _ => {
+            let span = tokens.span();
+            Entry::Expression(parse_expression(&mut tokens)?, span)
+        },
+    };
+    if !tokens.is_empty() {
+        let t = tokens.next();
+        return Err(tokens.error("E0101", format!("Expected the end of the input, but found {:?}!", t)));
+    }
+    Ok(entry)
+}
+
+/// The tokens of a program, along with where each of them starts
+///
+/// Comments are kept apart from the other tokens, so that the parser only sees them
+/// when it asks for them
+struct TokenStream {
+    /// The tokens and their spans, always ending with Token::EOF
+    tokens: Vec<(Token, Span)>,
+    /// The index of the next token
+    position: usize,
+    /// Where the last token consumed starts
+    last_span: Span,
+    /// The text of each comment that hasn't been attached to anything yet, with its span
+    comments: Vec<(String, Span)>,
+    /// The index of the next comment
+    comment_position: usize,
+    /// How many nested statements and expressions are being parsed
+    depth: usize,
+    /// How many can be nested before parsing fails
+    max_depth: usize,
+}
+
+impl TokenStream {
+    /// Constructs a new TokenStream, adding a Token::EOF to the end of tokens if it is missing
+    fn new(tokens: Vec<(Token, Span)>) -> TokenStream {
+        let mut comments = vec![];
+        let mut tokens = tokens.into_iter().filter_map(|(token, span)| match token {
+            Token::Comment(text) => {
+                comments.push((text, span));
+                None
+            },
+            token => Some((token, span)),
+        }).collect::<Vec<_>>();
+        let end = match tokens.last() {
+            Some(&(Token::EOF, _)) => None,
+            Some(&(_, span)) => Some(span),
+            None => Some(Span { line: 1, column: 1 }),
+        };
+        if let Some(span) = end {
+            tokens.push((Token::EOF, span));
+        }
+        let last_span = tokens[0].1;
+        TokenStream {
+            tokens,
+            position: 0,
+            last_span,
+            comments,
+            comment_position: 0,
+            depth: 0,
+            max_depth: MAX_NESTING,
+        }
+    }
+
+    /// Returns the next token without consuming it
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    /// Returns the token after the next one without consuming either
+    fn peek_after(&self) -> &Token {
+        &self.tokens[(self.position + 1).min(self.tokens.len() - 1)].0
+    }
+
+    /// Returns where the next token starts
+    fn span(&self) -> Span {
+        self.tokens[self.position].1
+    }
+
+    /// Consumes the next token and returns it
+    ///
+    /// Once the end of the tokens is reached, Token::EOF is returned forever
+    fn next(&mut self) -> Token {
+        self.last_span = self.span();
+        if self.position + 1 == self.tokens.len() {
+            Token::EOF
+        } else {
+            self.position += 1;
+            mem::replace(&mut self.tokens[self.position - 1].0, Token::EOF)
+        }
+    }
+
+    /// Returns true if all of the tokens have been consumed
+    fn is_empty(&self) -> bool {
+        *self.peek() == Token::EOF
+    }
+
+    /// Takes the comments before the next token
+    fn leading_comments(&mut self) -> Vec<String> {
+        let mut comments = vec![];
+        while self.comment_position < self.comments.len() &&
+              self.comments[self.comment_position].1 < self.span() {
+            comments.push(mem::take(&mut self.comments[self.comment_position].0));
+            self.comment_position += 1;
+        }
+        comments
+    }
+
+    /// Takes the comment after the last token consumed, if it is on the same line
+    fn trailing_comment(&mut self) -> Option<String> {
+        match self.comments.get_mut(self.comment_position) {
+            Some(&mut (ref mut text, span)) if span.line == self.last_span.line && span > self.last_span => {
+                self.comment_position += 1;
+                Some(mem::take(text))
+            },
+            _ => None,
+        }
+    }
+
+    /// Parses something nested in what is being parsed with parse
+    ///
+    /// This fails if that goes deeper than max_depth
+    fn nested<T>(&mut self, parse: fn(&mut TokenStream) -> Result<T, Diagnostic>) -> Result<T, Diagnostic> {
+        if self.depth >= self.max_depth {
+            return Err(self.error("E0102", format!("The program is nested more than {} deep", self.max_depth)));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Returns a parse error with code at the last token consumed
+    fn error(&self, code: &'static str, message: String) -> Diagnostic {
+        Diagnostic::new(ErrorKind::Parse, message, self.last_span).with_code(code)
+    }
+}
+
+/// Consumes the next token, returning a parse error if it isn't expected
+fn match_token(token_stream: &mut TokenStream, expected: Token) -> Result<(), Diagnostic> {
+    let t = token_stream.next();
+    if t == expected {
+        Ok(())
+    } else {
+        Err(token_stream.error("E0101", format!("Expected {:?}, but found {:?}!", expected, t)))
     }
-    program
 }
 
-fn match_token(mut token_stream: &mut Vec<Token>, expected: &Token) -> Result<Token, Token> {
-    let t = token_stream.remove(0);
-    if t == *expected {
-        Ok(t)
+/// Consumes the next token, which should be an identifier, and returns its name
+fn match_ident(token_stream: &mut TokenStream) -> Result<Ident, Diagnostic> {
+    match token_stream.next() {
+        Token::Ident(s) => Ok(s),
+        t => Err(token_stream.error("E0103", format!("Expected an identifier, but found {:?}!", t))),
+    }
+}
+
+fn parse_function(token_stream: &mut TokenStream) -> Result<Function, Diagnostic> {
+    let mut includes = vec![];
+    while *token_stream.peek() == Token::Keyword("include".to_string()) {
+        match_token(token_stream, Token::Keyword("include".to_string()))?;
+        match token_stream.next() {
+            Token::Text(header) => includes.push(header),
+            t => return Err(token_stream.error("E0104", format!("Expected the name of a header in quotes, but found {:?}!", t))),
+        }
+    }
+    // A comment is stored without its first #, so a documentation comment still starts with one
+    let (doc, leading): (Vec<_>, Vec<_>) = token_stream.leading_comments()
+        .into_iter()
+        .partition(|comment| comment.starts_with('#'));
+    let doc = doc.iter().map(|comment| {
+        let line = &comment[1..];
+        line.strip_prefix(' ').unwrap_or(line).to_string()
+    }).collect();
+    let comments = Comments {
+        leading,
+        ..Comments::default()
+    };
+    let attributes = parse_attributes(token_stream)?;
+    let span = token_stream.span();
+    let exported = *token_stream.peek() == Token::Keyword("exported".to_string());
+    if exported {
+        match_token(token_stream, Token::Keyword("exported".to_string()))?;
+    }
+    // An external function is already C, so it can't be exported
+    let external = !exported && *token_stream.peek() == Token::Keyword("external".to_string());
+    let mut returns_text = false;
+    if external {
+        match_token(token_stream, Token::Keyword("external".to_string()))?;
+        // text isn't a keyword, so that it can still be the name of a variable
+        returns_text = *token_stream.peek() == Token::Ident("text".to_string());
+        if returns_text {
+            token_stream.next();
+        }
+        match_token(token_stream, Token::Keyword("function".to_string()))?;
     } else {
-        Err(t)
+        match_token(token_stream, Token::Keyword("to".to_string()))?;
     }
+    let name = match_ident(token_stream)?;
+    let mut types = ParameterTypes::default();
+    let signature = parse_signature(token_stream, if external { Some(&mut types) } else { None })?;
+    let code = if external {
+        None
+    } else if *token_stream.peek() == Token::Keyword("is".to_string()) {
+        Some(parse_expression_body(token_stream)?)
+    } else {
+        Some(parse_statement(token_stream)?)
+    };
+    Ok(Function {
+               name,
+               signature,
+               code,
+               span,
+               comments,
+               doc,
+               includes,
+               exported,
+               text_parameters: types.texts,
+               returns_text,
+               callback_parameters: types.callbacks,
+               attributes,
+             })
 }
 
-fn match_panic(mut token_stream: &mut Vec<Token>, expected: Token) {
-    match match_token(&mut token_stream, &expected) {
-        Ok(_) => (),
-        Err(t) => panic!(format!("Expected {:?}, but found {:?}!", expected, t)),
+/// Parses the attributes before a function or an if, each of which is an @ and a name, with a text
+/// in brackets after it if it is given one
+fn parse_attributes(token_stream: &mut TokenStream) -> Result<Vec<Attribute>, Diagnostic> {
+    let mut attributes = vec![];
+    while *token_stream.peek() == Token::At {
+        let span = token_stream.span();
+        token_stream.next();
+        let name = match_ident(token_stream)?;
+        let mut argument = None;
+        if *token_stream.peek() == Token::Lp {
+            token_stream.next();
+            match token_stream.next() {
+                Token::Text(text) => argument = Some(text),
+                t => return Err(token_stream.error("E0101", format!("Expected a text for @{}, but found {:?}!", name, t))),
+            }
+            match_token(token_stream, Token::Rp)?;
+        }
+        attributes.push(Attribute { name, argument, span });
     }
+    Ok(attributes)
 }
 
-fn parse_function(mut token_stream: &mut Vec<Token>) -> Function {
-    match_panic(&mut token_stream, Token::Keyword("to".to_string()));
-    let name = match token_stream.remove(0) {
-        Token::Ident(s) => s,
-        t @ _ => panic!(format!("Expected an identifier, but found {:?}!", t)),
+/// Parses the body of a function written as `is <expression>`, which returns the expression
+///
+/// It is the same as `return <expression>` would be, so nothing after the parser has to know about it
+fn parse_expression_body(token_stream: &mut TokenStream) -> Result<Statement, Diagnostic> {
+    let mut comments = Comments {
+        leading: token_stream.leading_comments(),
+        ..Comments::default()
     };
-    let signature = parse_signature(&mut token_stream);
-    let code = parse_statement(&mut token_stream);
-    Function {
-               name: name,
-               signature: signature,
-               code: code,
-             }
+    let span = token_stream.span();
+    match_token(token_stream, Token::Keyword("is".to_string()))?;
+    let kind = StatementKind::Return(parse_expression(token_stream)?);
+    comments.trailing = token_stream.trailing_comment();
+    Ok(Statement {
+        kind,
+        span,
+        comments,
+    })
+}
+
+/// The parameters of an external function that aren't numbers
+#[derive(Default)]
+struct ParameterTypes {
+    /// The parameters that are texts
+    texts: Vec<Ident>,
+    /// The parameters that are functions, with their parameters
+    callbacks: Vec<(Ident, Signature)>,
 }
 
-fn parse_signature(mut token_stream: &mut Vec<Token>) -> Option<Signature> {
-    if token_stream[0] == Token::Keyword("with".to_string()) {
+/// Parses the signature of a function, if it has one
+///
+/// With types, which external functions have, parameters can be declared as texts with
+/// `text name` or as functions with `function name with (parameters)`, and they are added to it
+fn parse_signature(token_stream: &mut TokenStream, mut types: Option<&mut ParameterTypes>)
+                   -> Result<Option<Signature>, Diagnostic> {
+    if *token_stream.peek() == Token::Keyword("with".to_string()) {
         let mut args = vec![];
-        match_panic(&mut token_stream, Token::Keyword("with".to_string()));
-        match_panic(&mut token_stream, Token::Lp);
+        match_token(token_stream, Token::Keyword("with".to_string()))?;
+        match_token(token_stream, Token::Lp)?;
         loop {
-            args.push(match token_stream.remove(0) {
+            args.push(match token_stream.next() {
+                Token::Ident(ref text) if text == "text" && types.is_some() &&
+                                          matches!(*token_stream.peek(), Token::Ident(_)) => {
+                    let name = match_ident(token_stream)?;
+                    if let Some(ref mut types) = types {
+                        types.texts.push(name.clone());
+                    }
+                    name
+                },
+                Token::Keyword(ref keyword) if keyword == "function" && types.is_some() => {
+                    let name = match_ident(token_stream)?;
+                    let signature = parse_signature(token_stream, None)?.unwrap_or_default();
+                    if let Some(ref mut types) = types {
+                        types.callbacks.push((name.clone(), signature));
+                    }
+                    name
+                },
                 Token::Ident(name) => name,
                 Token::Rp => break,
-                t @ _ => panic!(format!("Expected an identifier, but found {:?}!", t)),
+                t => return Err(token_stream.error("E0103", format!("Expected an identifier, but found {:?}!", t))),
             });
-            if token_stream[0] == Token::Rp {
-                token_stream.remove(0);
+            if *token_stream.peek() == Token::Rp {
+                token_stream.next();
                 break;
             }
-            match_panic(&mut token_stream, Token::Comma);
+            match_token(token_stream, Token::Comma)?;
         }
-        Some(args)
+        Ok(Some(args))
     } else {
-        None
+        Ok(None)
     }
 }
 
-fn parse_statement(mut token_stream: &mut Vec<Token>) -> Statement {
-    match token_stream.remove(0) {
+fn parse_statement(token_stream: &mut TokenStream) -> Result<Statement, Diagnostic> {
+    let mut comments = Comments {
+        leading: token_stream.leading_comments(),
+        ..Comments::default()
+    };
+    // The checker says which attributes an if can have, as it does for functions
+    let attributes = parse_attributes(token_stream)?;
+    if !attributes.is_empty() && *token_stream.peek() != Token::Keyword("if".to_string()) {
+        return Err(token_stream.error("E0105", format!("Only an if can have attributes, but found {:?}!", token_stream.peek())));
+    }
+    let span = token_stream.span();
+    let kind = match token_stream.next() {
         Token::Keyword(t) => {
-            if t == "return".to_string() {
-                parse_return(&mut token_stream)
-            } else if t == "do".to_string() {
-                parse_do(&mut token_stream)
-            } else if t == "if".to_string() {
-                parse_if(&mut token_stream)
-            } else if t == "set".to_string() {
-                parse_set(&mut token_stream)
-            } else if t == "change".to_string() {
-                parse_change(&mut token_stream)
-			} else if t == "variable".to_string() {
-				parse_declare(&mut token_stream)
+            if t == "return" {
+                parse_return(token_stream)?
+            } else if t == "do" {
+                parse_do(token_stream, &mut comments)?
+            } else if t == "if" {
+                parse_if(token_stream, attributes)?
+            } else if t == "repeat" {
+                parse_repeat(token_stream, span)?
+            } else if t == "set" {
+                parse_set(token_stream)?
+            } else if t == "change" {
+                parse_change(token_stream)?
+            } else if t == "swap" {
+                parse_swap(token_stream)?
+            } else if t == "increase" {
+                StatementKind::Change(match_ident(token_stream)?, Expression::Integer(1))
+            } else if t == "decrease" {
+                StatementKind::Change(match_ident(token_stream)?, Expression::UnaryOp {
+                    operator: Operator::Sub,
+                    expression: Box::new(Expression::Integer(1)),
+                })
+			} else if t == "variable" {
+				parse_declare(token_stream)?
+			} else if t == "persistent" {
+				match_token(token_stream, Token::Keyword("variable".to_string()))?;
+				StatementKind::Persistent(match_ident(token_stream)?)
+			} else if t == "variables" {
+				parse_declarations(token_stream)?
             } else {
-                panic!("Invalid statement!")
+                return Err(token_stream.error("E0105", format!("{} can't start a statement!", t)))
             }
         }
-        t @ Token::Ident(_) => {
-            token_stream.insert(0, t);
-            parse_call(&mut token_stream)
+        // A call has a bracket after its name, so break out of can't be one
+        Token::Ident(ident) => if ident == "break" && is_word(token_stream.peek(), "out") {
+            parse_break(token_stream)?
+        } else {
+            parse_call(token_stream, ident)?
         },
-        t @ _ => panic!("Syntax error! {:?}", t),
-    }
+        Token::InlineC(c_source) => StatementKind::InlineC(c_source),
+        t => return Err(token_stream.error("E0105", format!("Syntax error! {:?}", t))),
+    };
+    comments.trailing = token_stream.trailing_comment();
+    Ok(Statement {
+        kind,
+        span,
+        comments,
+    })
     /*
-    match_panic(&mut token_stream, Token::Ident("foo".to_string()));
+    match_panic(token_stream, Token::Ident("foo".to_string()));
     Statement::Return(Expression::Integer(1))*/
 }
 
-fn parse_return(mut token_stream: &mut Vec<Token>) -> Statement {
-	Statement::Return(parse_expression(&mut token_stream))
+fn parse_return(token_stream: &mut TokenStream) -> Result<StatementKind, Diagnostic> {
+	Ok(StatementKind::Return(parse_expression(token_stream)?))
 }
 
-fn parse_declare(mut token_stream: &mut Vec<Token>) -> Statement {
-	let ident = match token_stream.remove(0) {
-		Token::Ident(ident) => ident,
-		t @ _ => panic!("Expected an identifier, not {:?}!", t),
-	};
-    Statement::Var(ident)
+fn parse_declare(token_stream: &mut TokenStream) -> Result<StatementKind, Diagnostic> {
+	let ident = match_ident(token_stream)?;
+    if *token_stream.peek() == Token::Keyword("is".to_string()) {
+        token_stream.next();
+        return Ok(StatementKind::Define(ident, parse_expression(token_stream)?));
+    }
+    Ok(StatementKind::Var(vec![ident]))
 }
-fn parse_do(mut token_stream: &mut Vec<Token>) -> Statement {
+
+/// Parses the names after variables, separated by commas
+fn parse_declarations(token_stream: &mut TokenStream) -> Result<StatementKind, Diagnostic> {
+    let mut idents = vec![match_ident(token_stream)?];
+    while *token_stream.peek() == Token::Comma {
+        token_stream.next();
+        idents.push(match_ident(token_stream)?);
+    }
+    Ok(StatementKind::Var(idents))
+}
+
+/// Parses the rest of a do block, adding the comments before its end to comments
+fn parse_do(token_stream: &mut TokenStream, comments: &mut Comments) -> Result<StatementKind, Diagnostic> {
     let mut block = vec![];
-    while token_stream[0] != Token::Keyword("end".to_string()) {
-        block.push(Rc::new(parse_statement(&mut token_stream)));
+    while *token_stream.peek() != Token::Keyword("end".to_string()) {
+        block.push(token_stream.nested(parse_statement)?);
     }
-    token_stream.remove(0);
-    Statement::Do(block)
+    comments.closing = token_stream.leading_comments();
+    token_stream.next();
+    Ok(StatementKind::Do(block))
 }
 
-fn parse_if(mut token_stream: &mut Vec<Token>) -> Statement {
-    let cond = parse_expression(&mut token_stream);
-    match_panic(&mut token_stream, Token::Keyword("then".to_string()));
-    let if_clause = Rc::new(parse_statement(&mut token_stream));
-    let else_clause = Rc::new(if !token_stream.is_empty() &&
-                                 token_stream[0] == Token::Keyword("else".to_string()) {
-        match_panic(&mut token_stream, Token::Keyword("else".to_string()));
-        Some(parse_statement(&mut token_stream))
+fn parse_if(token_stream: &mut TokenStream, attributes: Vec<Attribute>) -> Result<StatementKind, Diagnostic> {
+    let cond = parse_expression(token_stream)?;
+    match_token(token_stream, Token::Keyword("then".to_string()))?;
+    let if_clause = Box::new(token_stream.nested(parse_statement)?);
+    let else_clause = if *token_stream.peek() == Token::Keyword("else".to_string()) {
+        match_token(token_stream, Token::Keyword("else".to_string()))?;
+        Some(Box::new(token_stream.nested(parse_statement)?))
     } else {
         None
-    });
-    Statement::If {
-        cond: cond,
-        if_clause: if_clause,
-        else_clause: else_clause,
-    }
+    };
+    Ok(StatementKind::If {
+        cond,
+        if_clause,
+        else_clause,
+        attributes,
+    })
 }
 
-fn parse_set(mut token_stream: &mut Vec<Token>) -> Statement {
-    let ident = match token_stream.remove(0) {
-        Token::Ident(ident) => ident,
-        t @ _ => panic!(format!("Expected an identifier, but found {:?}!", t)),
+/// Parses the rest of a repeat statement
+///
+/// times, as and otherwise aren't keywords, so that they can still be names. A statement can't be
+/// a name followed by another name, so an as followed by a name is a label rather than the statement,
+/// and an otherwise not followed by a bracket is the otherwise rather than the call after the repeat.
+fn parse_repeat(token_stream: &mut TokenStream, span: Span) -> Result<StatementKind, Diagnostic> {
+    let count = parse_expression(token_stream)?;
+    match_word(token_stream, "times")?;
+    let label = if is_word(token_stream.peek(), "as") && matches!(*token_stream.peek_after(), Token::Ident(_)) {
+        token_stream.next();
+        Some(match_ident(token_stream)?)
+    } else {
+        None
+    };
+    let body = Box::new(token_stream.nested(parse_statement)?);
+    if !is_word(token_stream.peek(), "otherwise") || *token_stream.peek_after() == Token::Lp {
+        return Ok(StatementKind::Repeat {
+            count,
+            label,
+            body,
+        });
+    }
+    token_stream.next();
+    let otherwise = token_stream.nested(parse_statement)?;
+    // The count is kept in a variable, as it is only worked out once
+    let hidden = format!("{}{}_{}", OTHERWISE_COUNT_PREFIX, span.line, span.column);
+    let statement = |kind| Statement { kind, span, comments: Comments::default() };
+    let repeat = statement(StatementKind::Repeat {
+        count: Expression::Ident(hidden.clone()),
+        label,
+        body,
+    });
+    let positive = Expression::BinaryOp {
+        operator: Operator::Gt,
+        left: Box::new(Expression::Ident(hidden.clone())),
+        right: Box::new(Expression::Integer(0)),
     };
-    match_panic(&mut token_stream, Token::Keyword("to".to_string()));
-    let expr = parse_expression(&mut token_stream);
-    Statement::Set(ident, expr)
+    Ok(StatementKind::Do(vec![
+        statement(StatementKind::Define(hidden, count)),
+        statement(StatementKind::If {
+            cond: positive,
+            if_clause: Box::new(repeat),
+            else_clause: Some(Box::new(otherwise)),
+            attributes: vec![],
+        }),
+    ]))
 }
 
-fn parse_change(mut token_stream: &mut Vec<Token>) -> Statement {
-    let ident = match token_stream.remove(0) {
-        Token::Ident(ident) => ident,
-        t @ _ => panic!(format!("Expected an identifier, but found {:?}!", t)),
+/// Returns the count, label, body and otherwise of statement if it is what a repeat with
+/// an otherwise is parsed as, so that it can be written the way it was
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, Expression, StatementKind};
+/// let program = parser::parse(Scanner::new("to main do repeat 2 times as twice display(1) otherwise return 0 end")).unwrap();
+/// let block = match program[0].code.as_ref().unwrap().kind {
+///     StatementKind::Do(ref block) => block,
+///     _ => unreachable!(),
+/// };
+/// let (count, label, body, otherwise) = parser::repeat_otherwise(&block[0]).unwrap();
+/// assert_eq!((count, label), (&Expression::Integer(2), Some(&"twice".to_string())));
+/// assert!(matches!(body.kind, StatementKind::Call { .. }));
+/// assert!(matches!(otherwise.kind, StatementKind::Return(_)));
+/// ```
+pub fn repeat_otherwise(statement: &Statement) -> Option<(&Expression, Option<&Ident>, &Statement, &Statement)> {
+    let block = match statement.kind {
+        StatementKind::Do(ref block) if block.len() == 2 => block,
+        _ => return None,
+    };
+    let (hidden, count) = match block[0].kind {
+        StatementKind::Define(ref hidden, ref count) if hidden.starts_with(OTHERWISE_COUNT_PREFIX) => (hidden, count),
+        _ => return None,
+    };
+    let (cond, if_clause, otherwise) = match block[1].kind {
+        StatementKind::If { ref cond, ref if_clause, else_clause: Some(ref otherwise), .. } => (cond, if_clause, otherwise),
+        _ => return None,
     };
-    match_panic(&mut token_stream, Token::Keyword("by".to_string()));
-    let expr = parse_expression(&mut token_stream);
-    Statement::Change(ident, expr)
+    let uses_hidden = |expression: &Expression| *expression == Expression::Ident(hidden.clone());
+    match (cond, &if_clause.kind) {
+        (&Expression::BinaryOp { operator: Operator::Gt, ref left, ref right },
+         StatementKind::Repeat { count: repeated, label, body })
+            if uses_hidden(left) && **right == Expression::Integer(0) && uses_hidden(repeated) => {
+            Some((count, label.as_ref(), body, otherwise))
+        },
+        _ => None,
+    }
 }
 
-fn parse_call(mut token_stream: &mut Vec<Token>) -> Statement {
-    let ident = match token_stream.remove(0) {
-        Token::Ident(ident) => ident,
-        t @ _ => panic!(format!("Expected an identifier, but found {:?}!", t)),
-    };
-    match_panic(&mut token_stream, Token::Lp);
+/// The name of the variable that the count of a repeat with an otherwise is kept in, before
+/// the line and column of the repeat
+///
+/// Names in programs can't have digits, so it can't be the same as one
+const OTHERWISE_COUNT_PREFIX: &str = "haumea_count_";
+
+/// Parses the rest of a break statement, after the break
+///
+/// break, out and of aren't keywords, so that they can still be the names of variables
+fn parse_break(token_stream: &mut TokenStream) -> Result<StatementKind, Diagnostic> {
+    match_word(token_stream, "out")?;
+    match_word(token_stream, "of")?;
+    Ok(StatementKind::Break(match_ident(token_stream)?))
+}
+
+/// Returns true if token is the name word, which is a keyword where it is
+fn is_word(token: &Token, word: &str) -> bool {
+    matches!(*token, Token::Ident(ref ident) if ident == word)
+}
+
+/// Consumes the next token, returning a parse error if it isn't the name word
+fn match_word(token_stream: &mut TokenStream, word: &str) -> Result<(), Diagnostic> {
+    match token_stream.next() {
+        Token::Ident(ref ident) if ident == word => Ok(()),
+        t => Err(token_stream.error("E0101", format!("Expected Ident({:?}), but found {:?}!", word, t))),
+    }
+}
+
+fn parse_swap(token_stream: &mut TokenStream) -> Result<StatementKind, Diagnostic> {
+    let first = match_ident(token_stream)?;
+    match_token(token_stream, Token::Operator("and".to_string()))?;
+    let second = match_ident(token_stream)?;
+    Ok(StatementKind::Swap(first, second))
+}
+
+fn parse_set(token_stream: &mut TokenStream) -> Result<StatementKind, Diagnostic> {
+    let ident = match_ident(token_stream)?;
+    match_token(token_stream, Token::Keyword("to".to_string()))?;
+    let expr = parse_expression(token_stream)?;
+    Ok(StatementKind::Set(ident, expr))
+}
+
+fn parse_change(token_stream: &mut TokenStream) -> Result<StatementKind, Diagnostic> {
+    let ident = match_ident(token_stream)?;
+    match_token(token_stream, Token::Keyword("by".to_string()))?;
+    let expr = parse_expression(token_stream)?;
+    Ok(StatementKind::Change(ident, expr))
+}
+
+fn parse_call(token_stream: &mut TokenStream, ident: Ident) -> Result<StatementKind, Diagnostic> {
+    match_token(token_stream, Token::Lp)?;
     let mut args = vec![];
-    if token_stream[0] != Token::Rp {
+    if *token_stream.peek() != Token::Rp {
         loop {
-            args.push(parse_expression(&mut token_stream));
-            if token_stream[0] == Token::Rp {
-                token_stream.remove(0);
+            args.push(parse_expression(token_stream)?);
+            if *token_stream.peek() == Token::Rp {
+                token_stream.next();
                 break;
             }
-            match_panic(&mut token_stream, Token::Comma);
+            match_token(token_stream, Token::Comma)?;
         }
+    } else {
+        token_stream.next();
     }
-    Statement::Call{
+    Ok(StatementKind::Call{
         function: ident,
         arguments: args,
-    }
+    })
 }
 
-fn parse_expression(mut token_stream: &mut Vec<Token>) -> Expression {
-    prec_4(&mut token_stream)
+fn parse_expression(token_stream: &mut TokenStream) -> Result<Expression, Diagnostic> {
+    prec_4(token_stream)
 }
 
-fn prec_0(mut token_stream: &mut Vec<Token>) -> Expression {
-    if token_stream[0] == Token::Lp {
-        token_stream.remove(0);
-        let exp = parse_expression(&mut token_stream);
-        match_panic(&mut token_stream, Token::Rp);
-        exp
+fn prec_0(token_stream: &mut TokenStream) -> Result<Expression, Diagnostic> {
+    if *token_stream.peek() == Token::Lp {
+        token_stream.next();
+        let exp = token_stream.nested(parse_expression)?;
+        match_token(token_stream, Token::Rp)?;
+        Ok(exp)
     } else {
-        match token_stream.remove(0) {
-            Token::Number(n) => Expression::Integer(n),
+        match token_stream.next() {
+            Token::Number(n) => Ok(Expression::Integer(n)),
+            Token::Text(s) => Ok(Expression::Text(s)),
 			Token::Operator(op) => {
-				if op == "-".to_string() {
-					Expression::UnaryOp {
+				if op == "-" {
+					Ok(Expression::UnaryOp {
 						operator: Operator::Sub,
-						expression: Rc::new(parse_expression(&mut token_stream))
-					}
+						expression: Box::new(token_stream.nested(parse_expression)?)
+					})
 				} else {
-					panic!("Expected an expression, not {:?}", op)
+					Err(token_stream.error("E0106", format!("Expected an expression, not {:?}", op)))
 				}
 			}
             Token::Ident(id) => {
-                if token_stream[0] == Token::Lp {
-                    match_panic(&mut token_stream, Token::Lp);
+                if *token_stream.peek() == Token::Lp {
+                    match_token(token_stream, Token::Lp)?;
                     let mut args = vec![];
-                    if token_stream[0] != Token::Rp {
+                    if *token_stream.peek() != Token::Rp {
                         loop {
-                            args.push(Rc::new(parse_expression(&mut token_stream)));
-                            if token_stream[0] == Token::Rp {
-                                token_stream.remove(0);
+                            args.push(token_stream.nested(parse_expression)?);
+                            if *token_stream.peek() == Token::Rp {
+                                token_stream.next();
                                 break;
                             }
-                            match_panic(&mut token_stream, Token::Comma);
+                            match_token(token_stream, Token::Comma)?;
                         }
+                    } else {
+                        token_stream.next();
                     }
-                    Expression::Call{
+                    Ok(Expression::Call{
                         function: id,
                         arguments: args,
-                    }
+                    })
                 } else {
-                    Expression::Ident(id)
+                    Ok(Expression::Ident(id))
                 }
             },
-            t @ _ => panic!(format!("Expected an expression, not {:?}", t)),
+            t => Err(token_stream.error("E0106", format!("Expected an expression, not {:?}", t))),
         }
     }
 }
 
-fn prec_1(mut token_stream: &mut Vec<Token>) -> Expression {
-    let lh = prec_0(&mut token_stream);
+fn prec_1(token_stream: &mut TokenStream) -> Result<Expression, Diagnostic> {
+    let lh = prec_0(token_stream)?;
     if !token_stream.is_empty() {
-        let op = match token_stream.get(0) {
-            Some(&Token::Operator(ref name)) => {
-                if *name == "*".to_string() {
+        let op = match *token_stream.peek() {
+            Token::Operator(ref name) => {
+                if *name == "*" {
                     Operator::Mul
-                } else if *name == "/".to_string() {
+                } else if *name == "/" {
                     Operator::Div
                 } else {
-                    return lh
+                    return Ok(lh)
                 }
             },
-            _ => return lh,
+            _ => return Ok(lh),
         };
-        token_stream.remove(0);
-        let rh = prec_1(&mut token_stream);
-        Expression::BinaryOp {
+        token_stream.next();
+        let rh = token_stream.nested(prec_1)?;
+        Ok(Expression::BinaryOp {
             operator: op,
-            left: Rc::new(lh),
-            right: Rc::new(rh),
-        }
+            left: Box::new(lh),
+            right: Box::new(rh),
+        })
     } else {
-        lh
+        Ok(lh)
     }
 }
 
-fn prec_2(mut token_stream: &mut Vec<Token>) -> Expression {
-    let lh = prec_1(&mut token_stream);
+fn prec_2(token_stream: &mut TokenStream) -> Result<Expression, Diagnostic> {
+    let lh = prec_1(token_stream)?;
     if !token_stream.is_empty() {
-        let op = match token_stream.get(0) {
-            Some(&Token::Operator(ref name)) => {
-                if *name == "+".to_string() {
+        let op = match *token_stream.peek() {
+            Token::Operator(ref name) => {
+                if *name == "+" {
                     Operator::Add
-                } else if *name == "-".to_string() {
+                } else if *name == "-" {
                     Operator::Sub
                 } else {
-                    return lh
+                    return Ok(lh)
                 }
             },
-            _ => return lh,
+            _ => return Ok(lh),
         };
-        token_stream.remove(0);
-        let rh = prec_2(&mut token_stream);
-        Expression::BinaryOp {
+        token_stream.next();
+        let rh = token_stream.nested(prec_2)?;
+        Ok(Expression::BinaryOp {
             operator: op,
-            left: Rc::new(lh),
-            right: Rc::new(rh),
-        }
+            left: Box::new(lh),
+            right: Box::new(rh),
+        })
     } else {
-        lh
+        Ok(lh)
     }
 }
 
-fn prec_3(mut token_stream: &mut Vec<Token>) -> Expression {
-    let lh = prec_2(&mut token_stream);
+fn prec_3(token_stream: &mut TokenStream) -> Result<Expression, Diagnostic> {
+    let lh = prec_2(token_stream)?;
     if !token_stream.is_empty() {
-        let op = match token_stream.get(0) {
-            Some(&Token::Operator(ref name)) => {
-                if *name == ">".to_string() {
+        let op = match *token_stream.peek() {
+            Token::Operator(ref name) => {
+                if *name == ">" {
                     Operator::Gt
-                } else if *name == ">=".to_string() {
+                } else if *name == ">=" {
                     Operator::Gte
-                } else if *name == "<".to_string() {
+                } else if *name == "<" {
                     Operator::Lt
-                } else if *name == "<=".to_string() {
+                } else if *name == "<=" {
                     Operator::Lte
-                } else if *name == "=".to_string() {
+                } else if *name == "=" {
                     Operator::Equals
-                } else if *name == "!=".to_string() {
+                } else if *name == "!=" {
                     Operator::NotEquals
                 } else {
-                    return lh
+                    return Ok(lh)
                 }
             },
-            _ => return lh
+            _ => return Ok(lh)
         };
-        token_stream.remove(0);
-        let rh = prec_3(&mut token_stream);
-        Expression::BinaryOp {
+        token_stream.next();
+        let rh = token_stream.nested(prec_3)?;
+        Ok(Expression::BinaryOp {
             operator: op,
-            left: Rc::new(lh),
-            right: Rc::new(rh),
-        }
+            left: Box::new(lh),
+            right: Box::new(rh),
+        })
     } else {
-        lh
+        Ok(lh)
     }
 }
 
-fn prec_4(mut token_stream: &mut Vec<Token>) -> Expression {
-    let lh = prec_3(&mut token_stream);
+fn prec_4(token_stream: &mut TokenStream) -> Result<Expression, Diagnostic> {
+    let lh = prec_3(token_stream)?;
     if !token_stream.is_empty() {
-        let op = match token_stream.get(0) {
-            Some(&Token::Operator(ref name)) => {
-                if *name == "and".to_string() {
+        let op = match *token_stream.peek() {
+            Token::Operator(ref name) => {
+                if *name == "and" {
                     Operator::LogicalAnd
-                } else if *name == "or".to_string() {
+                } else if *name == "or" {
                     Operator::LogicalOr
                 } else {
-                    return lh
+                    return Ok(lh)
                 }
             },
-            _ => return lh
+            _ => return Ok(lh)
         };
-        token_stream.remove(0);
-        let rh = prec_4(&mut token_stream);
-        Expression::BinaryOp {
+        token_stream.next();
+        let rh = token_stream.nested(prec_4)?;
+        Ok(Expression::BinaryOp {
             operator: op,
-            left: Rc::new(lh),
-            right: Rc::new(rh),
-        }
+            left: Box::new(lh),
+            right: Box::new(rh),
+        })
     } else {
-        lh
+        Ok(lh)
     }
 }
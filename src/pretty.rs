@@ -0,0 +1,429 @@
+/// src/pretty.rs
+/// The pretty-printer, which turns parsed haumea programs back into canonical source code
+use parser::{Attribute, Comments, Expression, Function, Ident, Operator, Program, Statement, StatementKind};
+use scanner::Scanner;
+use diagnostic::Diagnostic;
+use parser;
+
+/// The indentation used for each level of nesting
+const INDENT: &str = "    ";
+
+/// Parses source and prints it back in the canonical format
+///
+/// All of the comments in source are kept, although they may move to a line of their own
+///
+/// # Examples
+/// ```
+/// # use haumea::pretty::format_source;
+/// let source = "to main do   # the start
+///   display( 1+2 )
+///   ## the end
+///     end";
+/// assert_eq!(format_source(source).unwrap(), "\
+/// to main do
+///     ## the start
+///     display(1 + 2)
+///     ## the end
+/// end
+/// ");
+///
+/// // A repeat with an otherwise is written as it was, although it isn't parsed as a repeat
+/// assert_eq!(format_source("to main do repeat 0 times display(1)\notherwise display(2) end").unwrap(), "\
+/// to main do
+///     repeat 0 times display(1)
+///     otherwise display(2)
+/// end
+/// ");
+/// ```
+pub fn format_source(source: &str) -> Result<String, Diagnostic> {
+    let program = parser::parse(Scanner::new(source))?;
+    let mut out = String::new();
+    print_program(&mut out, &program);
+    Ok(out)
+}
+
+/// Prints program, with a blank line between each function
+///
+/// Parsing what is printed gives back program, apart from where things are in the source,
+/// for any program that the parser could have made.
+pub fn print_program(out: &mut String, program: &Program) {
+    for (index, func) in program.iter().enumerate() {
+        if index > 0 {
+            out.push('\n');
+        }
+        print_function(out, func);
+    }
+}
+
+/// Prints func, followed by a newline
+///
+/// Its documentation comments go right before it, after any other comments, and then its
+/// attributes, each on a line of its own
+pub fn print_function(out: &mut String, func: &Function) {
+    for header in &func.includes {
+        out.push_str(&format!("include {}\n", text_literal(header)));
+    }
+    if !func.includes.is_empty() {
+        out.push('\n');
+    }
+    print_comment_lines(out, &func.comments.leading, 0);
+    for line in &func.doc {
+        out.push_str("##");
+        if !line.is_empty() {
+            out.push(' ');
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    for attribute in &func.attributes {
+        print_attribute(out, attribute);
+        out.push('\n');
+    }
+    out.push_str(&function_header(func));
+    match func.code {
+        // A function that only returns something is written as `is` the value
+        Some(Statement { kind: StatementKind::Return(ref expr), ref comments, .. }) if comments.leading.is_empty() => {
+            out.push_str(" is ");
+            print_expression(out, expr);
+            print_trailing_comment(out, comments);
+        },
+        Some(ref code) => print_nested_statement(out, code, 0),
+        None => (),
+    }
+    out.push('\n');
+    if !func.comments.closing.is_empty() {
+        out.push('\n');
+        print_comment_lines(out, &func.comments.closing, 0);
+    }
+}
+
+/// Returns the first line of func, like `to add with (a, b)` or `external function putchar with (c)`
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser;
+/// # use haumea::pretty::function_header;
+/// let source = "external function putchar with (c)\nexported to shout do putchar(65) end\nto main do shout() end";
+/// let program = parser::parse(Scanner::new(source)).unwrap();
+/// assert_eq!(function_header(&program[0]), "external function putchar with (c)");
+/// assert_eq!(function_header(&program[1]), "exported to shout");
+/// assert_eq!(function_header(&program[2]), "to main");
+///
+/// let program = parser::parse(Scanner::new("external text function strchr with (text s, c)")).unwrap();
+/// assert_eq!(function_header(&program[0]), "external text function strchr with (text s, c)");
+/// let program = parser::parse(Scanner::new("external function qsort with (base, n, size, function compare with (a, b))")).unwrap();
+/// assert_eq!(function_header(&program[0]), "external function qsort with (base, n, size, function compare with (a, b))");
+/// ```
+pub fn function_header(func: &Function) -> String {
+    let keyword = match func.code {
+        Some(_) if func.exported => "exported to",
+        Some(_) => "to",
+        None if func.returns_text => "external text function",
+        None => "external function",
+    };
+    match func.signature {
+        Some(ref signature) => {
+            let parameters = signature.iter().map(|parameter| {
+                match func.callback_parameters.iter().find(|(name, _)| name == parameter) {
+                    Some((_, parameters)) if parameters.is_empty() => format!("function {}", parameter),
+                    Some((_, parameters)) => format!("function {} with ({})", parameter, parameters.join(", ")),
+                    None if func.text_parameters.contains(parameter) => format!("text {}", parameter),
+                    None => parameter.clone(),
+                }
+            }).collect::<Vec<_>>();
+            format!("{} {} with ({})", keyword, func.name, parameters.join(", "))
+        },
+        None => format!("{} {}", keyword, func.name),
+    }
+}
+
+/// Prints statement at indent levels of indentation, followed by a newline
+pub fn print_statement(out: &mut String, statement: &Statement, indent: usize) {
+    print_comment_lines(out, &statement.comments.leading, indent);
+    out.push_str(&INDENT.repeat(indent));
+    print_statement_body(out, statement, indent);
+    out.push('\n');
+}
+
+/// Prints expression, with only the parentheses that it needs
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, Entry};
+/// # use haumea::pretty::print_expression;
+/// let tokens = Scanner::new("((1 + 2) * 3) + (f(4))").tokenize().unwrap();
+/// if let Entry::Expression(expression, _) = parser::parse_entry(tokens).unwrap() {
+///     let mut out = String::new();
+///     print_expression(&mut out, &expression);
+///     assert_eq!(out, "(1 + 2) * 3 + f(4)");
+/// }
+/// ```
+pub fn print_expression(out: &mut String, expression: &Expression) {
+    match *expression {
+        Expression::Integer(i) => out.push_str(&i.to_string()),
+        Expression::Text(ref s) => out.push_str(&text_literal(s)),
+        Expression::Ident(ref name) => out.push_str(name),
+        Expression::BinaryOp { ref operator, ref left, ref right } => {
+            let level = level(operator);
+            // The parser lets operators of the same level nest on the right but not the left
+            print_operand(out, left, binary_level(left) >= level);
+            out.push_str(&format!(" {} ", operator_name(operator)));
+            print_operand(out, right, binary_level(right) > level);
+        },
+        Expression::UnaryOp { ref operator, ref expression } => {
+            out.push_str(operator_name(operator));
+            if let Operator::LogicalNot = *operator {
+                out.push(' ');
+            }
+            let parenthesize = match **expression {
+                Expression::BinaryOp { .. } => true,
+                ref expression => is_unary(expression),
+            };
+            print_operand(out, expression, parenthesize);
+        },
+        Expression::Call { ref function, ref arguments } => {
+            out.push_str(function);
+            out.push('(');
+            for (index, argument) in arguments.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(", ");
+                }
+                print_expression(out, argument);
+            }
+            out.push(')');
+        },
+    }
+}
+
+/// Prints statement without its leading comments or indentation
+fn print_statement_body(out: &mut String, statement: &Statement, indent: usize) {
+    // A repeat with an otherwise is written the way it was, rather than as what it was parsed as
+    if let Some((count, label, body, otherwise)) = parser::repeat_otherwise(statement) {
+        print_repeat(out, count, label, body, indent);
+        out.push('\n');
+        out.push_str(&INDENT.repeat(indent));
+        out.push_str("otherwise");
+        print_nested_statement(out, otherwise, indent);
+        print_trailing_comment(out, &statement.comments);
+        return;
+    }
+    match statement.kind {
+        StatementKind::Return(ref expr) => {
+            out.push_str("return ");
+            print_expression(out, expr);
+        },
+        StatementKind::Var(ref names) => {
+            out.push_str(if names.len() == 1 { "variable " } else { "variables " });
+            out.push_str(&names.join(", "));
+        },
+        // The C is kept exactly as it was written, as haumea's formatting rules don't apply to it
+        StatementKind::InlineC(ref c_source) => {
+            out.push_str("c {");
+            out.push_str(c_source);
+            out.push('}');
+        },
+        StatementKind::Persistent(ref name) => out.push_str(&format!("persistent variable {}", name)),
+        StatementKind::Define(ref name, ref expr) => {
+            out.push_str(&format!("variable {} is ", name));
+            print_expression(out, expr);
+        },
+        StatementKind::Swap(ref first, ref second) => out.push_str(&format!("swap {} and {}", first, second)),
+        StatementKind::Set(ref name, ref expr) => {
+            out.push_str(&format!("set {} to ", name));
+            print_expression(out, expr);
+        },
+        // Changes by 1 and -1 are written the short way, as that's how they're usually meant
+        StatementKind::Change(ref name, Expression::Integer(1)) => out.push_str(&format!("increase {}", name)),
+        StatementKind::Change(ref name, Expression::UnaryOp { operator: Operator::Sub, ref expression })
+            if **expression == Expression::Integer(1) => out.push_str(&format!("decrease {}", name)),
+        StatementKind::Change(ref name, ref expr) => {
+            out.push_str(&format!("change {} by ", name));
+            print_expression(out, expr);
+        },
+        // The attributes of an if go on its line, so that one after an else stays there
+        StatementKind::If { ref cond, ref if_clause, ref else_clause, ref attributes } => {
+            for attribute in attributes {
+                print_attribute(out, attribute);
+                out.push(' ');
+            }
+            out.push_str("if ");
+            print_expression(out, cond);
+            out.push_str(" then");
+            print_nested_statement(out, if_clause, indent);
+            if let Some(ref else_clause) = *else_clause {
+                out.push('\n');
+                out.push_str(&INDENT.repeat(indent));
+                out.push_str("else");
+                print_nested_statement(out, else_clause, indent);
+            }
+        },
+        StatementKind::Repeat { ref count, ref label, ref body } => print_repeat(out, count, label.as_ref(), body, indent),
+        StatementKind::Break(ref label) => {
+            out.push_str("break out of ");
+            out.push_str(label);
+        },
+        StatementKind::Do(ref block) => {
+            out.push_str("do\n");
+            for statement in block {
+                print_statement(out, statement, indent + 1);
+            }
+            print_comment_lines(out, &statement.comments.closing, indent + 1);
+            out.push_str(&INDENT.repeat(indent));
+            out.push_str("end");
+        },
+        StatementKind::Call { ref function, ref arguments } => {
+            out.push_str(function);
+            out.push('(');
+            for (index, argument) in arguments.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(", ");
+                }
+                print_expression(out, argument);
+            }
+            out.push(')');
+        },
+    }
+    print_trailing_comment(out, &statement.comments);
+}
+
+/// Prints a repeat statement without its leading comments or indentation
+fn print_repeat(out: &mut String, count: &Expression, label: Option<&Ident>, body: &Statement, indent: usize) {
+    out.push_str("repeat ");
+    print_expression(out, count);
+    out.push_str(" times");
+    if let Some(label) = label {
+        out.push_str(" as ");
+        out.push_str(label);
+    }
+    print_nested_statement(out, body, indent);
+}
+
+/// Prints a statement that belongs to the line before it, like the body of a function
+///
+/// It goes on the same line unless it has comments before it,
+/// in which case it goes on the next line with one more level of indentation
+fn print_nested_statement(out: &mut String, statement: &Statement, indent: usize) {
+    if statement.comments.leading.is_empty() {
+        out.push(' ');
+        print_statement_body(out, statement, indent);
+    } else {
+        out.push('\n');
+        print_comment_lines(out, &statement.comments.leading, indent + 1);
+        out.push_str(&INDENT.repeat(indent + 1));
+        print_statement_body(out, statement, indent + 1);
+    }
+}
+
+/// Prints attribute as it is written, like `@deprecated("use add")`
+fn print_attribute(out: &mut String, attribute: &Attribute) {
+    out.push('@');
+    out.push_str(&attribute.name);
+    if let Some(ref argument) = attribute.argument {
+        out.push_str(&format!("({})", text_literal(argument)));
+    }
+}
+
+/// Prints each of the comments on a line of its own
+fn print_comment_lines(out: &mut String, comments: &[String], indent: usize) {
+    for comment in comments {
+        out.push_str(&INDENT.repeat(indent));
+        out.push('#');
+        out.push_str(comment);
+        out.push('\n');
+    }
+}
+
+/// Prints the trailing comment in comments, if there is one
+fn print_trailing_comment(out: &mut String, comments: &Comments) {
+    if let Some(ref comment) = comments.trailing {
+        out.push_str(" #");
+        out.push_str(comment);
+    }
+}
+
+/// Prints an operand of an operator, in parentheses if parenthesize is true
+fn print_operand(out: &mut String, expression: &Expression, parenthesize: bool) {
+    if parenthesize {
+        out.push('(');
+        print_expression(out, expression);
+        out.push(')');
+    } else {
+        print_expression(out, expression);
+    }
+}
+
+/// Returns how loosely a binary operator binds, from 1 for * and / to 4 for and and or
+fn level(operator: &Operator) -> u8 {
+    match *operator {
+        Operator::Mul | Operator::Div => 1,
+        Operator::Add | Operator::Sub => 2,
+        Operator::LogicalAnd | Operator::LogicalOr => 4,
+        _ => 3,
+    }
+}
+
+/// Returns how loosely expression binds when it is the operand of a binary operator
+///
+/// A unary operator takes everything after it as its operand, so it always needs parentheses
+fn binary_level(expression: &Expression) -> u8 {
+    match *expression {
+        Expression::BinaryOp { ref operator, .. } => level(operator),
+        _ if is_unary(expression) => u8::MAX,
+        _ => 0,
+    }
+}
+
+/// Returns true if expression is printed starting with a unary operator
+fn is_unary(expression: &Expression) -> bool {
+    match *expression {
+        Expression::UnaryOp { .. } => true,
+        Expression::Integer(i) => i < 0,
+        _ => false,
+    }
+}
+
+/// Returns s as a haumea text literal, with the escapes that it needs
+///
+/// # Examples
+/// ```
+/// # use haumea::pretty::text_literal;
+/// assert_eq!(text_literal("say \"hi\"\n"), r#""say \"hi\"\n""#);
+/// ```
+pub fn text_literal(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\t' => literal.push_str("\\t"),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Returns the source name of an operator
+pub fn operator_name(operator: &Operator) -> &'static str {
+    match *operator {
+        Operator::Add => "+",
+        Operator::Sub | Operator::Negate => "-",
+        Operator::Mul => "*",
+        Operator::Div => "/",
+        Operator::Equals => "=",
+        Operator::NotEquals => "!=",
+        Operator::Gt => ">",
+        Operator::Lt => "<",
+        Operator::Gte => ">=",
+        Operator::Lte => "<=",
+        Operator::LogicalAnd => "and",
+        Operator::LogicalOr => "or",
+        Operator::LogicalNot => "not",
+        Operator::BinaryAnd => "&",
+        Operator::BinaryOr => "|",
+        Operator::BinaryNot => "~",
+    }
+}
@@ -0,0 +1,253 @@
+/// src/project.rs
+/// The layout of a haumea project, and creating new ones
+use std::fs;
+use std::path::{Path, PathBuf};
+use lints::{Level, LintLevels};
+use options::SOURCE_EXTENSIONS;
+
+/// The name of the manifest file at the root of a project
+pub const MANIFEST_NAME: &str = "haumea.toml";
+
+/// The directory of a project that holds its program
+pub const SOURCE_DIR: &str = "src";
+
+/// The directory of a project that holds its tests
+pub const TESTS_DIR: &str = "tests";
+
+/// The directory of a project that holds its benchmarks
+pub const BENCHES_DIR: &str = "benches";
+
+/// The program written into a new project
+const HELLO_WORLD: &str = "\
+to main do
+    display(42)
+end
+";
+
+/// The settings in the manifest of a project
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    /// The name of the project, from `name` in `[package]`
+    pub name: String,
+    /// The libraries that the project's executables are linked with, from `link` in `[build]`
+    pub libraries: Vec<String>,
+    /// The directories searched for the libraries, from `library-paths` in `[build]`
+    pub library_paths: Vec<PathBuf>,
+    /// The levels of the lints, from `[lints]`, which has keys like `unused-variable = "deny"`
+    pub lints: LintLevels,
+    /// Whether every warning is an error, from `deny-warnings = true` in `[lints]`
+    pub deny_warnings: bool,
+}
+
+/// Parses the manifest in source
+///
+/// Manifests are TOML, but only a simple part of it is understood: sections, and keys
+/// whose values are strings, arrays of strings, or booleans on one line. Keys that haumea doesn't use are ignored.
+///
+/// # Examples
+/// ```
+/// # use haumea::project::parse_manifest;
+/// let manifest = parse_manifest("[package]\nname = \"game\"\n\n[build]\nlink = [\"m\", \"curses\"]\n").unwrap();
+/// assert_eq!(manifest.name, "game");
+/// assert_eq!(manifest.libraries, vec!["m", "curses"]);
+/// assert!(parse_manifest("[build]\nlink = m\n").is_err());
+///
+/// let manifest = parse_manifest("[lints]\nall = \"warn\"\nshadowing = \"allow\"\ndeny-warnings = true\n").unwrap();
+/// assert_eq!(manifest.lints.level(haumea::lints::find("shadowing").unwrap()), haumea::lints::Level::Allow);
+/// assert!(manifest.deny_warnings);
+/// assert_eq!(parse_manifest("[lints]\nshadowing = \"never\"\n").unwrap_err(),
+///            "haumea.toml:2: shadowing must be \"allow\", \"warn\" or \"deny\"");
+/// ```
+pub fn parse_manifest(source: &str) -> Result<Manifest, String> {
+    let mut manifest = Manifest::default();
+    let mut section = String::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| format!("{}:{}: {}", MANIFEST_NAME, index + 1, message);
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_string();
+            continue;
+        }
+        let (key, value) = match line.find('=') {
+            Some(equals) => (line[..equals].trim(), line[equals + 1..].trim()),
+            None => return Err(error("Expected a key = value line")),
+        };
+        match (&section[..], key) {
+            ("package", "name") => manifest.name = parse_string(value).ok_or_else(|| error("name must be a string"))?,
+            ("build", "link") => {
+                manifest.libraries = parse_strings(value).ok_or_else(|| error("link must be an array of strings"))?;
+            },
+            ("build", "library-paths") => {
+                manifest.library_paths = parse_strings(value)
+                    .ok_or_else(|| error("library-paths must be an array of strings"))?
+                    .into_iter()
+                    .map(PathBuf::from)
+                    .collect();
+            },
+            ("lints", "deny-warnings") => match value {
+                "true" => manifest.deny_warnings = true,
+                "false" => manifest.deny_warnings = false,
+                _ => return Err(error("deny-warnings must be true or false")),
+            },
+            ("lints", lint) => {
+                let level = parse_string(value).and_then(|level| Level::from_name(&level))
+                    .ok_or_else(|| error(&format!("{} must be \"allow\", \"warn\" or \"deny\"", lint)))?;
+                manifest.lints.set(lint, level).map_err(|e| error(&e))?;
+            },
+            _ => (),
+        }
+    }
+    Ok(manifest)
+}
+
+/// Reads the manifest of the project in the directory root
+pub fn read_manifest(root: &Path) -> Result<Manifest, String> {
+    let path = root.join(MANIFEST_NAME);
+    let source = fs::read_to_string(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    // The errors start with the name of the manifest, so this makes them start with its path
+    parse_manifest(&source).map_err(|e| format!("{}/{}", root.display(), e))
+}
+
+/// Parses a TOML string like `"name"`, or returns None if value isn't one
+fn parse_string(value: &str) -> Option<String> {
+    let mut chars = value.chars();
+    let string = take_string(&mut chars)?;
+    if chars.as_str().trim().is_empty() { Some(string) } else { None }
+}
+
+/// Parses a TOML array of strings like `["a", "b"]`, or returns None if value isn't one
+fn parse_strings(value: &str) -> Option<Vec<String>> {
+    let inside = value.strip_prefix('[')?.strip_suffix(']')?;
+    let mut strings = vec![];
+    let mut chars = inside.chars();
+    loop {
+        let rest = chars.as_str().trim_start();
+        if rest.is_empty() {
+            return Some(strings);
+        }
+        chars = rest.chars();
+        strings.push(take_string(&mut chars)?);
+        // Each string is followed by a comma, apart from the last one
+        let rest = chars.as_str().trim_start();
+        chars = match rest.strip_prefix(',') {
+            Some(rest) => rest.chars(),
+            None if rest.is_empty() => rest.chars(),
+            None => return None,
+        };
+    }
+}
+
+/// Takes a quoted string from the start of chars, with its escapes, leaving chars after it
+fn take_string(chars: &mut ::std::str::Chars) -> Option<String> {
+    if chars.next() != Some('"') {
+        return None;
+    }
+    let mut string = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(string),
+            '\\' => string.push(chars.next()?),
+            c => string.push(c),
+        }
+    }
+}
+
+/// Returns the manifest of a new project named name
+///
+/// # Examples
+/// ```
+/// # use haumea::project::new_manifest;
+/// assert_eq!(new_manifest("hello"), "[package]\nname = \"hello\"\nversion = \"0.1.0\"\n");
+/// ```
+pub fn new_manifest(name: &str) -> String {
+    format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Creates a new project in the directory path, which must not exist yet
+///
+/// The project is named after the directory, and has a manifest, a hello world program
+/// in src/main.hm, and an empty tests directory
+pub fn create(path: &Path) -> Result<(), String> {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return Err(format!("{} is not a valid project name", path.display())),
+    };
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()));
+    }
+    let create_dir = |dir: &Path| {
+        fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))
+    };
+    let write = |file: &Path, contents: &str| {
+        fs::write(file, contents).map_err(|e| format!("Could not write {}: {}", file.display(), e))
+    };
+    create_dir(&path.join(SOURCE_DIR))?;
+    create_dir(&path.join(TESTS_DIR))?;
+    write(&path.join(MANIFEST_NAME), &new_manifest(&name))?;
+    write(&path.join(SOURCE_DIR).join("main.hm"), HELLO_WORLD)
+}
+
+/// Returns the haumea source files at path, sorted by name
+///
+/// If path is a directory it is searched recursively
+pub fn source_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    if !metadata.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = vec![];
+    let entries = fs::read_dir(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let entry_path = entry.path();
+        if entry_path.is_dir() {
+            files.extend(source_files(&entry_path)?);
+        } else if is_source_file(&entry_path) {
+            files.push(entry_path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Returns the source files of the program and the tests of the project in the directory root
+///
+/// These are the files in src, followed by the files in tests
+pub fn test_sources(root: &Path) -> Result<Vec<PathBuf>, String> {
+    project_sources(root, &[SOURCE_DIR, TESTS_DIR])
+}
+
+/// Returns the source files of the program and the benchmarks of the project in the directory root
+///
+/// These are the files in src, followed by the files in benches
+pub fn bench_sources(root: &Path) -> Result<Vec<PathBuf>, String> {
+    project_sources(root, &[SOURCE_DIR, BENCHES_DIR])
+}
+
+/// Returns the source files in each of the dirs of the project in the directory root
+///
+/// A project doesn't have to have all of the dirs
+fn project_sources(root: &Path, dirs: &[&str]) -> Result<Vec<PathBuf>, String> {
+    if !root.join(MANIFEST_NAME).is_file() {
+        return Err(format!("{} is not a haumea project, as it has no {}", root.display(), MANIFEST_NAME));
+    }
+    let mut files = vec![];
+    for dir in dirs {
+        let dir = root.join(dir);
+        if dir.is_dir() {
+            files.extend(source_files(&dir)?);
+        }
+    }
+    Ok(files)
+}
+
+/// Returns true if path has the extension of a haumea source file
+fn is_source_file(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => SOURCE_EXTENSIONS.iter().any(|source_ext| ext == *source_ext),
+        None => false,
+    }
+}
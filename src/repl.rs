@@ -0,0 +1,132 @@
+/// src/repl.rs
+/// The interactive haumea REPL, which runs code with the interpreter as it is typed
+use std::fs;
+use std::io::{self, Stdout};
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use haumea::diagnostic::{Diagnostic, ErrorKind};
+use haumea::interpreter::Interpreter;
+use haumea::parser::{self, Entry, Expression};
+use haumea::scanner::Scanner;
+
+/// The message printed by :help
+const HELP: &str = "\
+Type a function definition, a statement, or an expression to run it.
+The value of an expression is printed, and variables are kept between lines.
+Input that isn't finished yet (like a do without an end) continues on the next line.
+
+Commands:
+    :functions    List the functions that have been defined
+    :help         Print this message
+    :quit         Leave the REPL (so does end of input)
+";
+
+/// The size of the stack of the thread that the REPL runs on
+///
+/// The interpreter recurses for every call in the haumea code, so it needs more than the default
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Runs the REPL on stdin, after defining the functions in inputs
+pub fn repl(inputs: &[PathBuf]) -> Result<(), String> {
+    let inputs = inputs.to_vec();
+    let thread = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(&inputs))
+        .map_err(|e| format!("Could not start the REPL: {}", e))?;
+    match thread.join() {
+        Ok(result) => result,
+        // The panic has already been reported
+        Err(_) => process::exit(101),
+    }
+}
+
+/// Runs the REPL on the current thread
+fn run(inputs: &[PathBuf]) -> Result<(), String> {
+    let mut interpreter = Interpreter::new(io::stdout());
+    for input in inputs {
+        let source = fs::read_to_string(input)
+            .map_err(|e| format!("Could not read {}: {}", input.display(), e))?;
+        let name = input.display().to_string();
+        let program = parser::parse(Scanner::new(&source))
+            .map_err(|error| error.in_file(&name).to_string())?;
+        interpreter.define_all(program);
+    }
+    println!("haumea {} (type :help for help)", env!("CARGO_PKG_VERSION"));
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut entry = String::new();
+    loop {
+        prompt(if entry.is_empty() { "> " } else { "... " });
+        let line = match lines.next() {
+            Some(line) => line.map_err(|e| format!("Could not read stdin: {}", e))?,
+            None => break,
+        };
+        if entry.is_empty() {
+            match line.trim() {
+                "" => continue,
+                ":quit" => break,
+                ":help" => {
+                    print!("{}", HELP);
+                    continue;
+                },
+                ":functions" => {
+                    for name in interpreter.function_names() {
+                        println!("{}", name);
+                    }
+                    continue;
+                },
+                command if command.starts_with(':') => {
+                    println!("Unknown command {} (type :help for help)", command);
+                    continue;
+                },
+                _ => (),
+            }
+        }
+        entry.push_str(&line);
+        entry.push('\n');
+        match run_entry(&mut interpreter, &entry) {
+            Ok(true) => (),
+            // Wait for the rest of the entry
+            Ok(false) => continue,
+            Err(error) => println!("{}", error),
+        }
+        entry.clear();
+    }
+    println!();
+    Ok(())
+}
+
+/// Parses and runs source, printing the value if it is an expression
+///
+/// Returns false (without running anything) if source isn't finished yet
+fn run_entry(interpreter: &mut Interpreter<Stdout>, source: &str) -> Result<bool, Diagnostic> {
+    let tokens = Scanner::new(source).tokenize()?;
+    let end = tokens[tokens.len() - 1].1;
+    let entry = match parser::parse_entry(tokens) {
+        Ok(entry) => entry,
+        // The parser ran out of tokens, so there is more to come
+        Err(ref error) if error.kind == ErrorKind::Parse && error.span == Some(end) => return Ok(false),
+        Err(error) => return Err(error),
+    };
+    match entry {
+        Entry::Function(func) => interpreter.define(*func),
+        Entry::Statement(statement) => interpreter.execute(&statement)?,
+        Entry::Expression(expression, span) => {
+            let value = interpreter.evaluate(&expression, span)?;
+            // display has already printed its argument, so its result isn't interesting
+            match expression {
+                Expression::Call { ref function, .. } if function == "display" => (),
+                _ => println!("{}", value),
+            }
+        },
+    }
+    Ok(true)
+}
+
+/// Prints prompt to stdout
+fn prompt(prompt: &str) {
+    print!("{}", prompt);
+    let _ = io::stdout().flush();
+}
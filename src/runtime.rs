@@ -0,0 +1,479 @@
+/// src/runtime.rs
+/// The runtime library that compiled haumea programs are linked with
+use std::fs;
+use std::path::Path;
+use callgraph::CallGraph;
+use checker::BUILTINS;
+use options::Runtime;
+use parser::SourceFile;
+
+/// The name of the header of the runtime, which the generated C includes
+pub const HEADER_NAME: &str = "haumea_rt.h";
+
+/// The name of the C source of the runtime
+pub const SOURCE_NAME: &str = "haumea_rt.c";
+
+/// The line of the generated C that includes the header of the full runtime
+pub const INCLUDE: &str = "#include \"haumea_rt.h\"\n";
+
+/// The builtins in the minimal runtime
+pub const MINIMAL_BUILTINS: &[&str] = &["display"];
+
+/// The builtins that have a different name in the runtime, with the name of their C function
+///
+/// Their haumea names are common words, so the C names have a prefix to keep out of the way of the C library
+pub const INTRINSICS: &[(&str, &str)] = &[
+    ("display_text", "haumea_display_text"),
+    ("substring", "haumea_substring"),
+    ("index_of", "haumea_index_of"),
+    ("same_text", "haumea_same_text"),
+    ("to_text", "haumea_to_text"),
+    ("to_number", "haumea_to_number"),
+    ("uppercase", "haumea_uppercase"),
+    ("lowercase", "haumea_lowercase"),
+    ("open", "haumea_open"),
+    ("read_line", "haumea_read_line"),
+    ("write_line", "haumea_write_line"),
+    ("close", "haumea_close"),
+    ("argument_count", "haumea_argument_count"),
+    ("argument_at", "haumea_argument_at"),
+    ("current_time", "haumea_current_time"),
+    ("current_milliseconds", "haumea_current_milliseconds"),
+    ("elapsed_milliseconds", "haumea_elapsed_milliseconds"),
+    ("environment_value", "haumea_environment_value"),
+];
+
+/// The minimal runtime, which is put in the generated C itself so that it can be compiled on its own
+///
+/// Like the header of the full runtime, it doesn't include stdio.h,
+/// so that external functions don't clash with the declarations in it
+pub const MINIMAL: &str = "\
+int printf(const char *format, ...);
+
+static long display(long n) {
+    printf(\"%ld\\n\", n);
+    return 0;
+}
+";
+
+/// The checks that the generated C does with --safe, which are put in the C itself
+///
+/// The #line directives make __FILE__ and __LINE__ point at the haumea source. Each function
+/// puts a frame on a stack as it starts and takes it off as it returns, with the line that
+/// it is running, so that an error can say which calls it happened in. A failed assertion
+/// outside of a test says too, and in a test the runtime's own assert_equal fails the test.
+///
+/// It declares abort itself rather than including stdlib.h, which would take names like div,
+/// abs and exit away from the program's functions only when it is compiled with --safe.
+pub const CHECKS: &str = "\
+#include <stdio.h>
+
+void abort(void);
+
+/* A function that is running, for the backtrace of a runtime error */
+struct haumea_frame {
+    const char *function;
+    const char *file;
+    int line;
+    struct haumea_frame *caller;
+};
+
+/* The innermost function that is running */
+static struct haumea_frame *haumea_stack = NULL;
+
+/* For the test harness, which has to empty the stack when a test fails */
+#define HAUMEA_BACKTRACES
+
+/* Writes the functions that are running to stderr, innermost first */
+static void haumea_backtrace(void) {
+    struct haumea_frame *frame;
+    fprintf(stderr, \"backtrace:\\n\");
+    for (frame = haumea_stack; frame; frame = frame->caller) {
+        fprintf(stderr, \"    %s at %s:%d\\n\", frame->function, frame->file, frame->line);
+    }
+}
+
+static long haumea_divide(long left, long right, const char *file, int line) {
+    if (right == 0) {
+        fflush(stdout);
+        fprintf(stderr, \"%s:%d: division by zero\\n\", file, line);
+        haumea_backtrace();
+        abort();
+    }
+    return left / right;
+}
+
+#ifdef assert_equal
+#undef assert_equal
+static long haumea_checked_assert_equal(long left, long right, const char *file, int line) {
+    if (left != right && !haumea_test_failed) {
+        fflush(stdout);
+        fprintf(stderr, \"%s:%d: assertion failed: %ld is not equal to %ld\\n\", file, line, left, right);
+        haumea_backtrace();
+        haumea_exit_with_error();
+    }
+    return haumea_assert_equal(left, right, file, line);
+}
+#define assert_equal(left, right) haumea_checked_assert_equal((left), (right), __FILE__, __LINE__)
+#endif
+";
+
+/// The header of the runtime, with the declarations of everything in it
+pub const HEADER: &str = "\
+/* haumea_rt.h
+ * The runtime library of compiled haumea programs */
+#ifndef HAUMEA_RT_H
+#define HAUMEA_RT_H
+
+#include <setjmp.h>
+
+long display(long n);
+
+/* Where a failed assertion jumps to while a test runs, or NULL to exit instead */
+extern jmp_buf *haumea_test_failed;
+
+long haumea_assert_equal(long left, long right, const char *file, int line);
+
+/* Exits with 1, for an error that has already been written to stderr */
+void haumea_exit_with_error(void);
+
+/* The #line directives make __FILE__ and __LINE__ point at the haumea source */
+#define assert_equal(left, right) haumea_assert_equal((left), (right), __FILE__, __LINE__)
+
+/* A text is the address of its chars, which are never freed, and 0 is the empty text */
+#define HAUMEA_CHARS(text) ((text) ? (const char *)(text) : \"\")
+
+long haumea_display_text(long text);
+long haumea_substring(long text, long start, long length);
+long haumea_index_of(long text, long part);
+long haumea_same_text(long text, long other);
+long haumea_to_text(long n);
+long haumea_to_number(long text);
+long haumea_uppercase(long text);
+long haumea_lowercase(long text);
+
+long haumea_open(long path, long mode);
+long haumea_read_line(long file);
+long haumea_write_line(long file, long text);
+long haumea_close(long file);
+
+/* main calls this first, so that the program can get at its arguments */
+void haumea_set_arguments(int argc, char **argv);
+
+long haumea_argument_count(void);
+long haumea_argument_at(long index);
+
+long haumea_current_time(void);
+long haumea_current_milliseconds(void);
+long haumea_elapsed_milliseconds(long since);
+
+long haumea_environment_value(long name);
+
+#endif /* HAUMEA_RT_H */
+";
+
+/// The C source of the runtime
+pub const SOURCE: &str = "\
+/* haumea_rt.c
+ * The runtime library of compiled haumea programs */
+/* For clock_gettime, which isn't in standard C */
+#define _POSIX_C_SOURCE 199309L
+
+#include <ctype.h>
+#include <errno.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include \"haumea_rt.h\"
+
+long display(long n) {
+    printf(\"%ld\\n\", n);
+    return 0;
+}
+
+jmp_buf *haumea_test_failed = NULL;
+
+long haumea_assert_equal(long left, long right, const char *file, int line) {
+    if (left != right) {
+        fflush(stdout);
+        fprintf(stderr, \"%s:%d: assertion failed: %ld is not equal to %ld\\n\", file, line, left, right);
+        if (haumea_test_failed) {
+            longjmp(*haumea_test_failed, 1);
+        }
+        haumea_exit_with_error();
+    }
+    return 0;
+}
+
+void haumea_exit_with_error(void) {
+    exit(1);
+}
+
+static void haumea_out_of_memory(void) {
+    fflush(stdout);
+    fprintf(stderr, \"out of memory\\n\");
+    exit(1);
+}
+
+/* Returns room for a text of length chars and its terminator */
+static char *haumea_new_text(size_t length) {
+    char *text = malloc(length + 1);
+    if (!text) {
+        haumea_out_of_memory();
+    }
+    return text;
+}
+
+long haumea_display_text(long text) {
+    printf(\"%s\\n\", HAUMEA_CHARS(text));
+    return 0;
+}
+
+/* Positions count bytes, and are clamped to the text */
+long haumea_substring(long text, long start, long length) {
+    const char *chars = HAUMEA_CHARS(text);
+    long text_length = (long)strlen(chars);
+    char *part;
+    if (start < 0) {
+        start = 0;
+    }
+    if (start > text_length) {
+        start = text_length;
+    }
+    if (length < 0) {
+        length = 0;
+    }
+    if (length > text_length - start) {
+        length = text_length - start;
+    }
+    part = haumea_new_text((size_t)length);
+    memcpy(part, chars + start, (size_t)length);
+    part[length] = '\\0';
+    return (long)part;
+}
+
+long haumea_index_of(long text, long part) {
+    const char *found = strstr(HAUMEA_CHARS(text), HAUMEA_CHARS(part));
+    return found ? (long)(found - HAUMEA_CHARS(text)) : -1;
+}
+
+/* Texts are compared by their chars, as two texts with the same ones can be at different addresses */
+long haumea_same_text(long text, long other) {
+    return strcmp(HAUMEA_CHARS(text), HAUMEA_CHARS(other)) == 0;
+}
+
+long haumea_to_text(long n) {
+    char *text = haumea_new_text(3 * sizeof(long) + 1);
+    sprintf(text, \"%ld\", n);
+    return (long)text;
+}
+
+/* Texts that aren't numbers are 0 */
+long haumea_to_number(long text) {
+    const char *chars = HAUMEA_CHARS(text);
+    char *end;
+    long n;
+    errno = 0;
+    n = strtol(chars, &end, 10);
+    if (end == chars || *end != '\\0' || errno == ERANGE) {
+        return 0;
+    }
+    return n;
+}
+
+/* Returns a copy of text with map applied to each of its chars */
+static long haumea_map_chars(long text, int (*map)(int)) {
+    const char *chars = HAUMEA_CHARS(text);
+    size_t length = strlen(chars);
+    char *mapped = haumea_new_text(length);
+    size_t i;
+    for (i = 0; i < length; i++) {
+        mapped[i] = (char)map((unsigned char)chars[i]);
+    }
+    mapped[length] = '\\0';
+    return (long)mapped;
+}
+
+long haumea_uppercase(long text) {
+    return haumea_map_chars(text, toupper);
+}
+
+long haumea_lowercase(long text) {
+    return haumea_map_chars(text, tolower);
+}
+
+/* A file is the address of its FILE, and 0 is a file that couldn't be opened,
+ * which stops the program if it is used, as the C library can't be given it */
+static FILE *haumea_file(long file) {
+    if (!file) {
+        fflush(stdout);
+        fprintf(stderr, \"0 is not an open file\\n\");
+        exit(1);
+    }
+    return (FILE *)file;
+}
+
+/* The mode is \"r\" to read, \"w\" to write, or \"a\" to append */
+long haumea_open(long path, long mode) {
+    const char *chars = HAUMEA_CHARS(mode);
+    if (strcmp(chars, \"r\") != 0 && strcmp(chars, \"w\") != 0 && strcmp(chars, \"a\") != 0) {
+        return 0;
+    }
+    return (long)fopen(HAUMEA_CHARS(path), chars);
+}
+
+/* Returns the next line without its newline, or 0 at the end of the file */
+long haumea_read_line(long file) {
+    FILE *stream = haumea_file(file);
+    size_t length = 0;
+    size_t size = 64;
+    char *line = haumea_new_text(size);
+    int c;
+    while ((c = getc(stream)) != EOF && c != '\\n') {
+        if (length == size) {
+            size *= 2;
+            line = realloc(line, size + 1);
+            if (!line) {
+                haumea_out_of_memory();
+            }
+        }
+        line[length++] = (char)c;
+    }
+    if (c == EOF && length == 0) {
+        free(line);
+        return 0;
+    }
+    line[length] = '\\0';
+    return (long)line;
+}
+
+long haumea_write_line(long file, long text) {
+    fprintf(haumea_file(file), \"%s\\n\", HAUMEA_CHARS(text));
+    return 0;
+}
+
+long haumea_close(long file) {
+    fclose(haumea_file(file));
+    return 0;
+}
+
+/* The arguments after the name of the program */
+static int haumea_argc = 0;
+static char **haumea_argv = NULL;
+
+void haumea_set_arguments(int argc, char **argv) {
+    if (argc > 0) {
+        haumea_argc = argc - 1;
+        haumea_argv = argv + 1;
+    }
+}
+
+long haumea_argument_count(void) {
+    return haumea_argc;
+}
+
+/* Arguments that don't exist are 0, which the text builtins take as the empty text */
+long haumea_argument_at(long index) {
+    if (index < 0 || index >= haumea_argc) {
+        return 0;
+    }
+    return (long)haumea_argv[index];
+}
+
+/* The number of seconds since the Unix epoch */
+long haumea_current_time(void) {
+    return (long)time(NULL);
+}
+
+/* The number of milliseconds since the Unix epoch */
+long haumea_current_milliseconds(void) {
+    struct timespec now;
+    clock_gettime(CLOCK_REALTIME, &now);
+    return (long)now.tv_sec * 1000 + now.tv_nsec / 1000000;
+}
+
+/* since is a time from haumea_current_milliseconds */
+long haumea_elapsed_milliseconds(long since) {
+    return haumea_current_milliseconds() - since;
+}
+
+/* Variables that aren't set are 0 */
+long haumea_environment_value(long name) {
+    return (long)getenv(HAUMEA_CHARS(name));
+}
+";
+
+/// Returns the name of the C function that a call to the function name compiles to
+///
+/// # Examples
+/// ```
+/// # use haumea::runtime::c_function_name;
+/// assert_eq!(c_function_name("uppercase"), "haumea_uppercase");
+/// assert_eq!(c_function_name("double"), "double");
+/// ```
+pub fn c_function_name(name: &str) -> &str {
+    match INTRINSICS.iter().find(|&&(builtin, _)| builtin == name) {
+        Some(&(_, c_name)) => c_name,
+        None => name,
+    }
+}
+
+/// Writes the header and the source of the runtime into the directory dir
+///
+/// The generated C includes the header, so this should be the directory that it is written to.
+/// Files that are already there are replaced.
+pub fn write_runtime(dir: &Path) -> Result<(), String> {
+    for &(name, contents) in &[(HEADER_NAME, HEADER), (SOURCE_NAME, SOURCE)] {
+        let path = dir.join(name);
+        fs::write(&path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Returns true if the program made of files needs the full runtime, given the choice of runtime
+///
+/// With Runtime::Auto this is true if the program calls any builtins that the minimal runtime doesn't have.
+/// Returns an error if the minimal runtime was chosen for such a program.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::options::Runtime;
+/// # use haumea::runtime::needs_full_runtime;
+/// let program = [SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new("to main do display(1) end")).unwrap(),
+/// }];
+/// assert_eq!(needs_full_runtime(&program, Runtime::Auto), Ok(false));
+/// assert_eq!(needs_full_runtime(&program, Runtime::Full), Ok(true));
+/// let tests = [SourceFile {
+///     name: "test.hm".to_string(),
+///     program: parser::parse(Scanner::new("to test_one do assert_equal(1, 1) end")).unwrap(),
+/// }];
+/// assert_eq!(needs_full_runtime(&tests, Runtime::Auto), Ok(true));
+/// assert!(needs_full_runtime(&tests, Runtime::Minimal).is_err());
+/// ```
+pub fn needs_full_runtime(files: &[SourceFile], runtime: Runtime) -> Result<bool, String> {
+    let graph = CallGraph::new(files);
+    let missing = graph.functions.iter()
+        .flat_map(|caller| caller.calls.iter())
+        .find(|callee| {
+            let is_builtin = BUILTINS.iter().any(|&(builtin, _)| builtin == *callee);
+            is_builtin && !MINIMAL_BUILTINS.contains(&&callee[..])
+        });
+    match (runtime, missing) {
+        (Runtime::Full, _) => Ok(true),
+        (Runtime::Auto, missing) => Ok(missing.is_some()),
+        (Runtime::Minimal, Some(builtin)) => {
+            Err(format!("{} isn't in the minimal runtime, so the program needs --runtime full", builtin))
+        },
+        (Runtime::Minimal, None) => Ok(false),
+    }
+}
+
+/// Returns true if the generated C in c_source uses the full runtime, so it has to be compiled with it
+pub fn is_used_by(c_source: &str) -> bool {
+    c_source.contains(INCLUDE)
+}
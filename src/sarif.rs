@@ -0,0 +1,125 @@
+/// src/sarif.rs
+/// Writes errors and warnings as a SARIF log, which code scanning services like GitHub's read
+use codes;
+use diagnostic::{Diagnostic, Severity};
+use json::Json;
+
+/// The version of SARIF that the logs are written in
+pub const SARIF_VERSION: &str = "2.1.0";
+
+/// The schema of that version
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Returns the SARIF log of diagnostics, and of failure, an error that isn't in the program
+/// like a file that couldn't be read, if haumea stopped with one
+///
+/// The log has one run, of haumea, whose rules are the codes of the diagnostics with their
+/// explanations. Files are given by the names that haumea was given for them, which are
+/// relative to the directory it was run in.
+///
+/// # Examples
+/// ```
+/// # use haumea::diagnostic::{Diagnostic, ErrorKind};
+/// # use haumea::json::Json;
+/// # use haumea::sarif;
+/// # use haumea::scanner::Span;
+/// let warning = Diagnostic::warning(ErrorKind::Semantic, "The variable x is never used", Span { line: 2, column: 5 })
+///     .with_code("W0002")
+///     .in_file("src/main.hm");
+/// let log = sarif::log(&[warning], None);
+/// assert_eq!(log.get("version").and_then(Json::as_str), Some("2.1.0"));
+/// let run = &log.get("runs").and_then(Json::as_array).unwrap()[0];
+/// let rules = run.get("tool").and_then(|tool| tool.get("driver")).and_then(|driver| driver.get("rules")).unwrap();
+/// assert_eq!(rules.as_array().unwrap()[0].get("id").and_then(Json::as_str), Some("W0002"));
+/// let result = &run.get("results").and_then(Json::as_array).unwrap()[0];
+/// assert_eq!(result.get("level").and_then(Json::as_str), Some("warning"));
+/// assert_eq!(result.get("locations").unwrap().to_string(),
+///            r#"[{"physicalLocation":{"artifactLocation":{"uri":"src/main.hm"},"region":{"startLine":2,"startColumn":5}}}]"#);
+///
+/// let log = sarif::log(&[], Some("Could not read main.hm"));
+/// let run = &log.get("runs").and_then(Json::as_array).unwrap()[0];
+/// let result = &run.get("results").and_then(Json::as_array).unwrap()[0];
+/// assert_eq!(result.get("message").unwrap().to_string(), r#"{"text":"Could not read main.hm"}"#);
+/// ```
+pub fn log(diagnostics: &[Diagnostic], failure: Option<&str>) -> Json {
+    let mut rule_codes: Vec<&'static str> = vec![];
+    for diagnostic in diagnostics {
+        if let Some(code) = diagnostic.code {
+            if !rule_codes.contains(&code) {
+                rule_codes.push(code);
+            }
+        }
+    }
+    let rules = rule_codes.iter().map(|&code| rule(code)).collect::<Vec<_>>();
+    let mut results = diagnostics.iter().map(|diagnostic| {
+        let rule_index = diagnostic.code.and_then(|code| rule_codes.iter().position(|&known| known == code));
+        result(diagnostic, rule_index)
+    }).collect::<Vec<_>>();
+    if let Some(failure) = failure {
+        results.push(Json::object(vec![
+            ("level", Json::from("error")),
+            ("message", message(failure)),
+        ]));
+    }
+    let driver = Json::object(vec![
+        ("name", Json::from("haumea")),
+        ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+        ("rules", Json::from(rules)),
+    ]);
+    Json::object(vec![
+        ("$schema", Json::from(SCHEMA)),
+        ("version", Json::from(SARIF_VERSION)),
+        ("runs", Json::from(vec![Json::object(vec![
+            ("tool", Json::object(vec![("driver", driver)])),
+            ("results", Json::from(results)),
+        ])])),
+    ])
+}
+
+/// Returns the rule for code, which is described by its explanation
+fn rule(code: &str) -> Json {
+    let explanation = codes::explain(code).unwrap_or("");
+    let summary = explanation.lines().next().unwrap_or("");
+    Json::object(vec![
+        ("id", Json::from(code)),
+        ("shortDescription", message(summary)),
+        ("help", message(explanation)),
+    ])
+}
+
+/// Returns the result for diagnostic, whose code is the rule at rule_index, if it has one
+///
+/// Any help goes at the end of the message, as SARIF only has help for rules
+fn result(diagnostic: &Diagnostic, rule_index: Option<usize>) -> Json {
+    let level = match diagnostic.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+    };
+    let text = match diagnostic.help {
+        Some(ref help) => format!("{}\nhelp: {}", diagnostic.message, help),
+        None => diagnostic.message.clone(),
+    };
+    let mut members = vec![];
+    if let (Some(code), Some(index)) = (diagnostic.code, rule_index) {
+        members.push(("ruleId", Json::from(code)));
+        members.push(("ruleIndex", Json::from(index)));
+    }
+    members.push(("level", Json::from(level)));
+    members.push(("message", message(&text)));
+    if let Some(ref file) = diagnostic.file {
+        let mut location = vec![("artifactLocation", Json::object(vec![("uri", Json::from(&file[..]))]))];
+        if let Some(span) = diagnostic.span {
+            location.push(("region", Json::object(vec![
+                ("startLine", Json::from(span.line)),
+                ("startColumn", Json::from(span.column)),
+            ])));
+        }
+        members.push(("locations", Json::from(vec![Json::object(vec![("physicalLocation", Json::object(location))])])));
+    }
+    Json::object(members)
+}
+
+/// Returns a SARIF message with text
+fn message(text: &str) -> Json {
+    Json::object(vec![("text", Json::from(text))])
+}
@@ -1,7 +1,34 @@
 /// src/scanner.rs
 /// The scanner for the haumea language
-
 use std::str::Chars; // We need to bring the Chars struct into scope
+use diagnostic::{Diagnostic, ErrorKind};
+
+/// The chars that operators like + and <= are made of
+///
+/// Any run of them is read as one operator, which the parser rejects if it isn't one of OPERATORS
+pub const OPERATOR_CHARS: &[char] = &['+', '=', '-', '*', '/', '<', '>', '~', '|', '&'];
+
+/// The operators, including the ones that are spelt like names
+pub const OPERATORS: &[&str] = &["+", "=", "-", "*", "/", "<", ">", ">=", "<=",
+                                 "~", "|", "&", "and", "or", "not", "(", ")", "!="];
+
+/// The chars that names are made of, which don't include digits
+pub const IDENT_CHARS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_";
+
+/// The reserved words of haumea, which can't be names
+///
+/// The words of repeat and break after the first, like times and out, are only keywords where
+/// they are used, so that programs can keep using them as names
+pub const KEYWORDS: &[&str] = &["to", "with", "is", "return", "do", "end",
+                                "if", "then", "else", "let", "be",
+                                "set", "change", "by", "increase", "decrease", "swap", "variable", "variables",
+                                "persistent", "external", "function", "include", "exported", "repeat"];
+
+/// The escapes in texts, each of which is a char that comes after a \\, with the char it stands for
+pub const ESCAPES: &[(char, char)] = &[('\\', '\\'), ('"', '"'), ('n', '\n'), ('t', '\t')];
+
+/// The name that starts a block of inline C when a { comes after it, like c { puts("hi"); }
+pub const INLINE_C: &str = "c";
 
 /// The scanner struct
 #[derive(Debug)]
@@ -24,6 +51,19 @@ pub struct Scanner<'a> {
     reserved_words: Vec<&'static str>,
     /// The look ahead char
     pub peek: Option<char>,
+    /// The position of the look ahead char
+    position: Span,
+    /// The position of the start of the last token read
+    token_start: Span,
+}
+
+/// A position in the source code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    /// The line number, starting at 1
+    pub line: usize,
+    /// The column number, starting at 1
+    pub column: usize,
 }
 
 /// An enum representing the various tokens that can occur
@@ -52,10 +92,36 @@ pub enum Token {
     Rp,
     /// A comma
     Comma,
+    /// An @, which starts an attribute like @test
+    At,
+    /// A text literal, like "Hello, world!"
+    ///
+    /// The content is the text between the quotes, with its escapes replaced
+    Text(String),
+    /// A text literal that the end of the line or the input was reached in
+    UnterminatedText,
+    /// An escape in a text literal that isn't one of \\, \", \n, or \t
+    ///
+    /// The content is the char after the \\
+    BadEscape(char),
+    /// A block of inline C, like c { printf("hi\n"); }
+    ///
+    /// The content is the C between the braces, exactly as it was written
+    InlineC(String),
+    /// A block of inline C that the end of the input was reached in
+    UnterminatedInlineC,
+    /// A comment, which runs from a # to the end of the line
+    ///
+    /// The content is the text after the #, without any trailing whitespace
+    Comment(String),
     /// An unexpected char was read
     ///
     /// The content is the char read
     Error(char),
+    /// A number too large to be read as an i32
+    ///
+    /// The content is the digits read
+    BadNumber(String),
     /// End of input
     EOF,
 }
@@ -71,20 +137,20 @@ impl<'a> Scanner<'a> {
     /// assert_eq!(scanner.source_str, source);
     /// assert_eq!(scanner.peek, Some(' '));
     /// ```
-    pub fn new(source: &'a str) -> Scanner {
+    pub fn new(source: &'a str) -> Scanner<'a> {
         let chars = source.chars();
         let peek = Some(' ');
         Scanner {
             source_str: source,
             source_chars: chars,
-            operator_chars: vec!['+', '=', '-', '*', '/', '<', '>', '~', '|', '&', '(', ')'],
-            operators: vec!["+", "=", "-", "*", "/", "<", ">", ">=", "<=",
-                            "~", "|", "&", "and", "or", "not", "(", ")", "!="],
-            ident_chars: "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_".chars().collect::<Vec<_>>(),
-            reserved_words: vec!["to", "with", "is", "return", "do", "end",
-                                 "if", "then", "else", "let", "be",
-                                 "set", "to", "change", "by", "variable"],
-            peek: peek,
+            operator_chars: OPERATOR_CHARS.to_vec(),
+            operators: OPERATORS.to_vec(),
+            ident_chars: IDENT_CHARS.chars().collect::<Vec<_>>(),
+            reserved_words: KEYWORDS.to_vec(),
+            peek,
+            // The fake space in peek sits just before the first real char
+            position: Span { line: 1, column: 0 },
+            token_start: Span { line: 1, column: 0 },
         }
     }
 
@@ -101,12 +167,17 @@ impl<'a> Scanner<'a> {
     /// ```
     pub fn next_token(&mut self) -> Token {
         self.skip_white();
+        self.token_start = self.position;
         match self.peek {
             Some(c) => {
                 if self.ident_chars.contains(&c) {
                     self.get_ident_token()
-                } else if c.is_digit(10) {
-                    Token::Number(self.get_num())
+                } else if c.is_ascii_digit() {
+                    let digits = self.get_num();
+                    match digits.parse::<i32>() {
+                        Ok(n) => Token::Number(n),
+                        Err(_) => Token::BadNumber(digits),
+                    }
                 } else if c == '(' {
                     self.get_char();
                     Token::Lp
@@ -116,6 +187,13 @@ impl<'a> Scanner<'a> {
                 } else if c == ',' {
                     self.get_char();
                     Token::Comma
+                } else if c == '@' {
+                    self.get_char();
+                    Token::At
+                } else if c == '#' {
+                    Token::Comment(self.get_comment())
+                } else if c == '"' {
+                    self.get_text()
                 } else if self.operator_chars.contains(&c) {
                     Token::Operator(self.get_op())
                 } else {
@@ -127,8 +205,80 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    /// Returns the next token in the source along with the position it started at
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::scanner::{Scanner, Token, Span};
+    /// let mut s = Scanner::new("1 + # one\n  foo");
+    /// assert_eq!(s.next_spanned(), (Token::Number(1), Span { line: 1, column: 1 }));
+    /// assert_eq!(s.next_spanned(), (Token::Operator("+".to_string()), Span { line: 1, column: 3 }));
+    /// assert_eq!(s.next_spanned(), (Token::Comment(" one".to_string()), Span { line: 1, column: 5 }));
+    /// assert_eq!(s.next_spanned(), (Token::Ident("foo".to_string()), Span { line: 2, column: 3 }));
+    /// ```
+    pub fn next_spanned(&mut self) -> (Token, Span) {
+        let token = self.next_token();
+        (token, self.token_start)
+    }
+
+    /// Reads all of the remaining tokens in the source, along with the positions they started at
+    ///
+    /// The last token is always Token::EOF. If a Token::Error, Token::BadNumber, Token::UnterminatedText,
+    /// Token::BadEscape, or Token::UnterminatedInlineC is read, a lex error describing it is returned instead
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::scanner::{Scanner, Token, Span};
+    /// let tokens = Scanner::new("f(x)").tokenize().unwrap();
+    /// assert_eq!(tokens.len(), 5);
+    /// assert_eq!(tokens[2], (Token::Ident("x".to_string()), Span { line: 1, column: 3 }));
+    /// assert_eq!(tokens[4].0, Token::EOF);
+    ///
+    /// let error = Scanner::new("f($)").tokenize().unwrap_err();
+    /// assert_eq!(error.to_string(), "1:3: error[E0001]: Unexpected character '$'");
+    ///
+    /// let tokens = Scanner::new(r#"f("a \"b\"\n")"#).tokenize().unwrap();
+    /// assert_eq!(tokens[2].0, Token::Text("a \"b\"\n".to_string()));
+    /// let error = Scanner::new("f(\"abc)").tokenize().unwrap_err();
+    /// assert_eq!(error.to_string(), "1:3: error[E0003]: The text has no closing quote");
+    /// ```
+    pub fn tokenize(&mut self) -> Result<Vec<(Token, Span)>, Diagnostic> {
+        let mut tokens = vec![];
+        loop {
+            let (token, span) = self.next_spanned();
+            match token {
+                Token::Error(c) => {
+                    return Err(Diagnostic::new(ErrorKind::Lex, format!("Unexpected character '{}'", c), span).with_code("E0001"));
+                },
+                Token::BadNumber(digits) => {
+                    return Err(Diagnostic::new(ErrorKind::Lex, format!("The number {} is too large", digits), span).with_code("E0002"));
+                },
+                Token::UnterminatedText => {
+                    return Err(Diagnostic::new(ErrorKind::Lex, "The text has no closing quote".to_string(), span).with_code("E0003"));
+                },
+                Token::BadEscape(c) => {
+                    return Err(Diagnostic::new(ErrorKind::Lex, format!("Unknown escape '\\{}' in a text", c), span).with_code("E0004"));
+                },
+                Token::UnterminatedInlineC => {
+                    return Err(Diagnostic::new(ErrorKind::Lex, "The inline C has no closing brace".to_string(), span).with_code("E0005"));
+                },
+                Token::EOF => {
+                    tokens.push((token, span));
+                    return Ok(tokens);
+                },
+                _ => tokens.push((token, span)),
+            }
+        }
+    }
+
     /// Sets self.peek to be the next char in self.source_chars
     fn get_char(&mut self) {
+        if self.peek == Some('\n') {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
         self.peek = self.source_chars.next();
     }
 
@@ -144,18 +294,18 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    /// Returns the next number that can be found in self.source_chars
-    fn get_num(&mut self) -> i32 {
+    /// Returns the digits of the next number that can be found in self.source_chars
+    fn get_num(&mut self) -> String {
         let mut s = String::new();
-        s.push(self.peek.unwrap());
+        s.extend(self.peek);
         loop {
             self.get_char();
             match self.peek {
-                Some(c) if c.is_digit(10) => s.push(c),
+                Some(c) if c.is_ascii_digit() => s.push(c),
                 _ => break,
             }
         }
-        s.parse::<i32>().unwrap()
+        s
     }
 
     /// Returns an Token that contains the next identifier in self.source_chars
@@ -166,7 +316,7 @@ impl<'a> Scanner<'a> {
     /// 3. Token::Ident (otherwise)
     fn get_ident_token(&mut self) -> Token {
         let mut s = String::new();
-        s.push(self.peek.unwrap());
+        s.extend(self.peek);
         loop {
             self.get_char();
             match self.peek {
@@ -174,6 +324,12 @@ impl<'a> Scanner<'a> {
                 _ => break,
             }
         };
+        if s == INLINE_C {
+            self.skip_white();
+            if self.peek == Some('{') {
+                return self.get_inline_c();
+            }
+        }
         if self.reserved_words.contains(&&s[..]) {
             Token::Keyword(s)
         } else if self.operators.contains(&&s[..]) {
@@ -183,10 +339,85 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    /// Returns the text of the comment that starts at self.peek, up to the end of the line
+    fn get_comment(&mut self) -> String {
+        let mut s = String::new();
+        loop {
+            self.get_char();
+            match self.peek {
+                Some(c) if c != '\n' => s.push(c),
+                _ => break,
+            }
+        }
+        s.trim_end().to_string()
+    }
+
+    /// Returns the Token for the block of inline C whose opening brace is self.peek
+    ///
+    /// The block ends at the brace that matches the opening one, so the braces in the C have to balance
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::scanner::{Scanner, Token};
+    /// let mut s = Scanner::new("c { if (x) { x = 0; } } c");
+    /// assert_eq!(s.next_token(), Token::InlineC(" if (x) { x = 0; } ".to_string()));
+    /// assert_eq!(s.next_token(), Token::Ident("c".to_string()));
+    /// assert_eq!(Scanner::new("c { x = 0;").next_token(), Token::UnterminatedInlineC);
+    /// ```
+    fn get_inline_c(&mut self) -> Token {
+        let mut c_source = String::new();
+        let mut depth = 1;
+        loop {
+            self.get_char();
+            match self.peek {
+                Some('{') => depth += 1,
+                Some('}') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.get_char();
+                        return Token::InlineC(c_source);
+                    }
+                },
+                Some(_) => (),
+                None => return Token::UnterminatedInlineC,
+            }
+            c_source.extend(self.peek);
+        }
+    }
+
+    /// Returns the Token for the text literal that starts at self.peek
+    ///
+    /// A text can't run over the end of a line, so that a missing quote is found where it's missing
+    fn get_text(&mut self) -> Token {
+        let mut s = String::new();
+        loop {
+            self.get_char();
+            match self.peek {
+                Some('"') => break,
+                Some('\\') => {
+                    self.get_char();
+                    let escape = ESCAPES.iter().find(|&&(escape, _)| Some(escape) == self.peek);
+                    match (self.peek, escape) {
+                        (_, Some(&(_, c))) => s.push(c),
+                        (Some(c), None) if c != '\n' => {
+                            self.get_char();
+                            return Token::BadEscape(c);
+                        },
+                        _ => return Token::UnterminatedText,
+                    }
+                },
+                Some(c) if c != '\n' => s.push(c),
+                _ => return Token::UnterminatedText,
+            }
+        }
+        self.get_char();
+        Token::Text(s)
+    }
+
     /// Returns a String containing the next symbol spelt operator
     fn get_op(&mut self) -> String {
         let mut s = String::new();
-        s.push(self.peek.unwrap());
+        s.extend(self.peek);
         loop {
             self.get_char();
             match self.peek {
@@ -209,7 +440,7 @@ impl<'a> Iterator for Scanner<'a> {
     /// # Examples
     ///```
     /// # use haumea::scanner::{Scanner, Token};
-    /// let s = Scanner::new("1 + 1");
+    /// let mut s = Scanner::new("1 + 1");
     /// assert_eq!(s.next(), Some(Token::Number(1)));
     /// assert_eq!(s.next(), Some(Token::Operator("+".to_string())));
     /// assert_eq!(s.next(), Some(Token::Number(1)));
@@ -0,0 +1,339 @@
+/// src/semantic.rs
+/// Classifies the tokens of a source file by what they name, for editors to highlight them
+use checker::BUILTINS;
+use parser;
+use pretty::text_literal;
+use scanner::{Scanner, Span, Token};
+
+/// What a token is, as far as highlighting it goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    /// A keyword, like to or repeat
+    Keyword,
+    /// The name of a function, where it is declared, called, or passed to another one
+    Function,
+    /// A parameter of a function
+    Parameter,
+    /// A variable
+    Variable,
+    /// The label of a repeat, which break can name
+    Label,
+    /// The text before the name of a parameter or an external function that is a text
+    Type,
+    /// An attribute, like @inline, which is its @ and its name
+    Attribute,
+    /// A number
+    Number,
+    /// A text
+    Text,
+    /// A comment
+    Comment,
+    /// An operator, including the ones that are spelt like names, like and
+    Operator,
+}
+
+/// Every TokenKind, in the order of the legend that the language server gives editors
+pub const TOKEN_KINDS: &[TokenKind] = &[
+    TokenKind::Keyword,
+    TokenKind::Function,
+    TokenKind::Parameter,
+    TokenKind::Variable,
+    TokenKind::Label,
+    TokenKind::Type,
+    TokenKind::Attribute,
+    TokenKind::Number,
+    TokenKind::Text,
+    TokenKind::Comment,
+    TokenKind::Operator,
+];
+
+impl TokenKind {
+    /// Returns the name of the kind among the semantic token types of the Language Server Protocol
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::semantic::TokenKind;
+    /// assert_eq!(TokenKind::Attribute.name(), "decorator");
+    /// assert_eq!(TokenKind::Text.name(), "string");
+    /// ```
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenKind::Keyword => "keyword",
+            TokenKind::Function => "function",
+            TokenKind::Parameter => "parameter",
+            TokenKind::Variable => "variable",
+            TokenKind::Label => "label",
+            TokenKind::Type => "type",
+            TokenKind::Attribute => "decorator",
+            TokenKind::Number => "number",
+            TokenKind::Text => "string",
+            TokenKind::Comment => "comment",
+            TokenKind::Operator => "operator",
+        }
+    }
+}
+
+/// A token of a source file along with what it is
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticToken {
+    /// Where the token starts
+    pub span: Span,
+    /// How many chars the token takes up, which are all on the line it starts on
+    pub length: usize,
+    pub kind: TokenKind,
+    /// Whether this is where what the token names is declared, like the name after to
+    pub declaration: bool,
+    /// Whether the token names one of haumea's builtins, like display
+    pub builtin: bool,
+}
+
+/// Returns the tokens of source that can be classified, in order, with what each of them is
+///
+/// The functions are the ones in the parsed program, so that a function passed to another one
+/// is highlighted as a function, unless a parameter or a variable of the same name hides it.
+/// A source that can't be parsed is still classified from its tokens alone, so highlighting
+/// keeps working while it is being typed: the name after a `to` that starts a line is a function,
+/// as is a name before a bracket. Brackets, commas, inline C (which is highlighted as C by the
+/// grammar) and tokens that aren't valid aren't classified.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Span;
+/// # use haumea::semantic::{self, SemanticToken, TokenKind};
+/// let source = "to square with (x) do\n    variable y is x * x\n    return y\nend";
+/// let tokens = semantic::classify(source);
+/// let kinds = tokens.iter().map(|token| token.kind).collect::<Vec<_>>();
+/// assert_eq!(kinds, vec![
+///     TokenKind::Keyword, TokenKind::Function, TokenKind::Keyword, TokenKind::Parameter, TokenKind::Keyword,
+///     TokenKind::Keyword, TokenKind::Variable, TokenKind::Keyword, TokenKind::Parameter, TokenKind::Operator,
+///     TokenKind::Parameter, TokenKind::Keyword, TokenKind::Variable, TokenKind::Keyword,
+/// ]);
+/// assert_eq!(tokens[6], SemanticToken {
+///     span: Span { line: 2, column: 14 },
+///     length: 1,
+///     kind: TokenKind::Variable,
+///     declaration: true,
+///     builtin: false,
+/// });
+///
+/// let tokens = semantic::classify("to main do\n    display(\"hi\") # greet\n    repeat 2 times as twice do");
+/// assert_eq!((tokens[1].kind, tokens[1].declaration), (TokenKind::Function, true));
+/// assert_eq!(tokens[3].kind, TokenKind::Function);
+/// assert!(tokens[3].builtin);
+/// assert_eq!((tokens[4].kind, tokens[4].length), (TokenKind::Text, 4));
+/// assert_eq!((tokens[5].kind, tokens[5].length), (TokenKind::Comment, 7));
+/// assert_eq!(tokens[10].kind, TokenKind::Label);
+///
+/// // times is only a keyword after the count of a repeat
+/// let tokens = semantic::classify("to main do\n    variable times\n    repeat times times display(times)\nend");
+/// let kinds = tokens[5..9].iter().map(|token| token.kind).collect::<Vec<_>>();
+/// assert_eq!(kinds, vec![TokenKind::Keyword, TokenKind::Variable, TokenKind::Keyword, TokenKind::Function]);
+/// assert_eq!(tokens[9].kind, TokenKind::Variable);
+///
+/// let tokens = semantic::classify("repeat n times display(n) otherwise display(otherwise)");
+/// assert_eq!((tokens[5].kind, tokens[7].kind), (TokenKind::Keyword, TokenKind::Variable));
+///
+/// let tokens = semantic::classify("break out of rows");
+/// let kinds = tokens.iter().map(|token| token.kind).collect::<Vec<_>>();
+/// assert_eq!(kinds, vec![TokenKind::Keyword, TokenKind::Keyword, TokenKind::Keyword, TokenKind::Label]);
+/// ```
+pub fn classify(source: &str) -> Vec<SemanticToken> {
+    let tokens = spanned_tokens(source);
+    let program = Scanner::new(source).tokenize().and_then(parser::parse_tokens).ok();
+    // The tokens of the names of the functions where they are declared
+    let names = match program {
+        // The name is the first token with it after where the function starts
+        Some(ref program) => program.iter().filter_map(|func| {
+            let start = tokens.iter().position(|&(_, span)| span == func.span)?;
+            let offset = tokens[start..].iter().position(|(token, _)| matches!(*token, Token::Ident(ref name) if *name == func.name))?;
+            Some(start + offset)
+        }).collect::<Vec<_>>(),
+        None => (0..tokens.len()).filter(|&index| starts_function(&tokens, index)).collect(),
+    };
+    let functions = program.as_ref().map_or(&[][..], |program| &program[..]);
+    let contextual = contextual_keywords(&tokens);
+    let mut classified = vec![];
+    // The parameters and the variables of the function that the tokens are in, so far
+    let mut parameters: Vec<&str> = vec![];
+    let mut locals: Vec<&str> = vec![];
+    // How deep in the brackets of the signature of a function the tokens are
+    let mut signature_depth = 0;
+    // Whether the tokens are in the list of names after variables
+    let mut declaring = false;
+    for (index, &(ref token, span)) in tokens.iter().enumerate() {
+        let previous = if index > 0 { Some(&tokens[index - 1].0) } else { None };
+        let next = tokens.get(index + 1).map(|(token, _)| token);
+        match *token {
+            Token::Lp if signature_depth > 0 => signature_depth += 1,
+            Token::Lp if index >= 2 && is_keyword(previous, "with") && names.contains(&(index - 2)) => signature_depth = 1,
+            Token::Rp if signature_depth > 0 => signature_depth -= 1,
+            _ => (),
+        }
+        let mut semantic = SemanticToken {
+            span,
+            length: token_length(token),
+            kind: TokenKind::Keyword,
+            declaration: false,
+            builtin: false,
+        };
+        semantic.kind = match *token {
+            Token::Keyword(ref keyword) => {
+                declaring = keyword == "variables";
+                TokenKind::Keyword
+            },
+            Token::Operator(_) => TokenKind::Operator,
+            Token::Number(_) | Token::BadNumber(_) => TokenKind::Number,
+            Token::Text(_) => TokenKind::Text,
+            Token::Comment(_) => TokenKind::Comment,
+            Token::At => TokenKind::Attribute,
+            Token::Ident(_) if contextual[index] => TokenKind::Keyword,
+            Token::Ident(ref name) => {
+                let kind = if previous == Some(&Token::At) {
+                    TokenKind::Attribute
+                } else if names.contains(&index) {
+                    parameters.clear();
+                    locals.clear();
+                    semantic.declaration = true;
+                    TokenKind::Function
+                } else if signature_depth > 0 && matches!(next, Some(&Token::Ident(_))) {
+                    // External functions have text before the parameters that are texts
+                    TokenKind::Type
+                } else if signature_depth > 0 {
+                    parameters.push(name);
+                    semantic.declaration = true;
+                    TokenKind::Parameter
+                } else if is_keyword(next, "function") && is_keyword(previous, "external") {
+                    TokenKind::Type
+                } else if index > 0 && contextual[index - 1] && (is_word(previous, "as") || is_word(previous, "of")) {
+                    TokenKind::Label
+                } else if is_keyword(previous, "variable") || declaring {
+                    locals.push(name);
+                    semantic.declaration = true;
+                    TokenKind::Variable
+                } else if locals.contains(&&name[..]) {
+                    TokenKind::Variable
+                } else if parameters.contains(&&name[..]) {
+                    TokenKind::Parameter
+                } else if next == Some(&Token::Lp) {
+                    semantic.builtin = BUILTINS.iter().any(|&(builtin, _)| builtin == name);
+                    TokenKind::Function
+                } else if functions.iter().any(|func| func.name == *name) {
+                    TokenKind::Function
+                } else {
+                    TokenKind::Variable
+                };
+                declaring = declaring && next == Some(&Token::Comma);
+                kind
+            },
+            Token::Comma => continue,
+            _ => {
+                declaring = false;
+                continue;
+            },
+        };
+        classified.push(semantic);
+    }
+    classified
+}
+
+/// Returns whether each of tokens is a name that is a keyword where it is, like the times of a repeat
+///
+/// The times is the first name after the count of the repeat, which is a name that follows
+/// something that can end an expression, the as is right after it, and the words of
+/// break out of follow each other. An otherwise, after a repeat, comes where a statement has
+/// just ended and isn't the name of a function being called.
+fn contextual_keywords(tokens: &[(Token, Span)]) -> Vec<bool> {
+    let mut contextual = vec![false; tokens.len()];
+    // Whether a repeat has been seen whose times hasn't been
+    let mut counting = false;
+    let mut repeated = false;
+    for (index, (token, _)) in tokens.iter().enumerate() {
+        let previous = index.checked_sub(1).map(|previous| &tokens[previous].0);
+        let next = tokens.get(index + 1).map(|(token, _)| token);
+        let ends_expression = matches!(previous, Some(&Token::Ident(_)) | Some(&Token::Number(_)) |
+                                                 Some(&Token::Text(_)) | Some(&Token::Rp));
+        contextual[index] = match *token {
+            Token::Ident(ref word) if word == "times" => counting && ends_expression,
+            Token::Ident(ref word) if word == "as" => {
+                index > 0 && contextual[index - 1] && is_word(previous, "times") && matches!(next, Some(&Token::Ident(_)))
+            },
+            Token::Ident(ref word) if word == "break" => is_word(next, "out"),
+            Token::Ident(ref word) if word == "out" => index > 0 && contextual[index - 1] && is_word(previous, "break"),
+            Token::Ident(ref word) if word == "of" => index > 0 && contextual[index - 1] && is_word(previous, "out"),
+            Token::Ident(ref word) if word == "otherwise" => {
+                repeated && next != Some(&Token::Lp) && (ends_expression || is_keyword(previous, "end"))
+            },
+            _ => false,
+        };
+        if is_keyword(Some(token), "repeat") {
+            counting = true;
+            repeated = true;
+        } else if contextual[index] && is_word(Some(token), "times") {
+            counting = false;
+        }
+    }
+    contextual
+}
+
+/// Returns true if the token at index is the name of a function that is being declared, going
+/// by the tokens before it on its line, like `to` or `external text function`
+fn starts_function(tokens: &[(Token, Span)], index: usize) -> bool {
+    let line = tokens[index].1.line;
+    let is_header = |token: &Token| match *token {
+        Token::Keyword(ref keyword) => ["to", "external", "function", "exported"].contains(&&keyword[..]),
+        Token::Ident(ref name) => name == "text",
+        _ => false,
+    };
+    let previous = index.checked_sub(1).map(|previous| &tokens[previous].0);
+    matches!(tokens[index].0, Token::Ident(_)) &&
+        (is_keyword(previous, "to") || is_keyword(previous, "function")) &&
+        tokens[..index].iter().rev().take_while(|&&(_, span)| span.line == line).all(|(token, _)| is_header(token))
+}
+
+/// Returns every token in source, including the ones that aren't valid, each with where it starts
+pub fn spanned_tokens(source: &str) -> Vec<(Token, Span)> {
+    let mut tokens = vec![];
+    let mut scanner = Scanner::new(source);
+    loop {
+        let (token, span) = scanner.next_spanned();
+        let done = token == Token::EOF;
+        tokens.push((token, span));
+        if done {
+            return tokens;
+        }
+    }
+}
+
+/// Returns how many characters token takes up in the source
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Token;
+/// # use haumea::semantic::token_length;
+/// assert_eq!(token_length(&Token::Text("say \"hi\"".to_string())), 12);
+/// assert_eq!(token_length(&Token::Number(42)), 2);
+/// ```
+pub fn token_length(token: &Token) -> usize {
+    match *token {
+        Token::Ident(ref s) | Token::Keyword(ref s) | Token::Operator(ref s) | Token::BadNumber(ref s) => {
+            s.chars().count()
+        },
+        Token::Number(n) => n.to_string().len(),
+        Token::Text(ref s) => text_literal(s).chars().count(),
+        Token::Comment(ref s) => s.chars().count() + 1,
+        // This counts a single space between the c and the brace, which is how blocks are usually written
+        Token::InlineC(ref s) => s.chars().count() + 4,
+        Token::EOF => 0,
+        _ => 1,
+    }
+}
+
+/// Returns true if token is the keyword keyword
+fn is_keyword(token: Option<&Token>, keyword: &str) -> bool {
+    matches!(token, Some(Token::Keyword(word)) if word == keyword)
+}
+
+/// Returns true if token is the name word
+fn is_word(token: Option<&Token>, word: &str) -> bool {
+    matches!(token, Some(Token::Ident(name)) if name == word)
+}
@@ -0,0 +1,62 @@
+/// src/snapshot.rs
+/// Compares generated C with snapshots of it that are checked in, for golden tests
+use std::fs;
+use std::path::Path;
+use options::CompilerOptions;
+use parser::{self, SourceFile};
+use scanner::Scanner;
+
+/// The environment variable that makes compare_snapshot write the snapshots instead of checking them
+pub const UPDATE_VARIABLE: &str = "UPDATE_SNAPSHOTS";
+
+/// Compiles the program in source, which is named name, to C with options
+///
+/// The name is used in the #line directives, so it should be the same every time. It is
+/// compiled with haumea::compile, as it is by `haumea build`, and the error is the first one it has.
+///
+/// # Examples
+/// ```
+/// # use haumea::options::CompilerOptions;
+/// # use haumea::snapshot::compile_source;
+/// let c = compile_source("main.hm", "to main do display(42) end", &CompilerOptions::new("main.hm")).unwrap();
+/// assert!(c.contains("display(42l);"));
+/// assert!(compile_source("main.hm", "to main do display(x) end", &CompilerOptions::new("main.hm")).is_err());
+/// ```
+pub fn compile_source(name: &str, source: &str, options: &CompilerOptions) -> Result<String, String> {
+    let program = parser::parse(Scanner::new(source)).map_err(|e| e.in_file(name).to_string())?;
+    let files = [SourceFile { name: name.to_string(), program }];
+    ::compile(&files, options).map_err(|errors| errors[0].to_string())
+}
+
+/// Checks that actual is the same as the snapshot at path
+///
+/// With update the snapshot is written instead, which is how snapshots are made
+/// and how they are changed when the C is meant to change. An error says where
+/// actual first differs from the snapshot.
+pub fn compare_snapshot(path: &Path, actual: &str, update: bool) -> Result<(), String> {
+    if update {
+        return fs::write(path, actual).map_err(|e| format!("Could not write {}: {}", path.display(), e));
+    }
+    let expected = match fs::read_to_string(path) {
+        Ok(expected) => expected,
+        Err(_) => return Err(format!("{} doesn't exist, so run the tests with {}=1 to make it",
+                                     path.display(), UPDATE_VARIABLE)),
+    };
+    if expected == actual {
+        return Ok(());
+    }
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(expected), Some(actual)) if expected == actual => line += 1,
+            (expected, actual) => {
+                return Err(format!("{}:{}: the C doesn't match the snapshot (run the tests with {}=1 if it should)\n\
+                                    expected: {}\nactual:   {}",
+                                   path.display(), line, UPDATE_VARIABLE,
+                                   expected.unwrap_or("<the end of the file>"), actual.unwrap_or("<the end of the file>")));
+            },
+        }
+    }
+}
@@ -0,0 +1,168 @@
+/// src/sourcemap.rs
+/// Maps the lines of generated C back to the lines of haumea that they were compiled from, and back again
+use json::Json;
+
+/// The version of the format of the JSON that SourceMap::to_json writes
+pub const SOURCE_MAP_VERSION: usize = 1;
+
+/// A run of lines of C that were all compiled from one line of haumea
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    /// The index in SourceMap::sources of the haumea source
+    pub source: usize,
+    /// The line of the haumea source, starting at 1
+    pub line: usize,
+    /// The first line of the C, starting at 1
+    pub c_start: usize,
+    /// The last line of the C, which is in the run too
+    pub c_end: usize,
+}
+
+/// Which lines of generated C each line of haumea produced
+///
+/// The C has a #line directive before each function and statement, which C compilers and
+/// debuggers read, and the map is worked out from them, so it is the same as what they see.
+/// It doesn't need them to be there, though: a debugger that only sees line numbers in the C
+/// can use it, and it can be turned around to find the C of a line of haumea.
+///
+/// # Examples
+/// ```
+/// # use haumea::codegen;
+/// # use haumea::options::CompilerOptions;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::scanner::Scanner;
+/// # use haumea::sourcemap::SourceMap;
+/// let program = parser::parse(Scanner::new("to main do\n    variable x is 1\n    display(x)\nend")).unwrap();
+/// let files = [SourceFile { name: "main.hm".to_string(), program }];
+/// let mut c = String::new();
+/// codegen::compile_files(&mut c, &files, false, &CompilerOptions::new("main.hm"));
+///
+/// let map = SourceMap::from_c(&c);
+/// assert_eq!(map.sources, vec!["main.hm"]);
+/// // The last statement of a function has the brace that ends it too
+/// let (start, end) = map.c_lines("main.hm", 3)[0];
+/// assert_eq!(c.lines().skip(start - 1).take(end - start + 1).collect::<Vec<_>>(), vec!["    display(x);", "}"]);
+/// assert_eq!(map.haumea_line(start), Some(("main.hm", 3)));
+/// assert_eq!(map.haumea_line(1), None);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    /// The names of the haumea sources, in the order they first appear in the C
+    pub sources: Vec<String>,
+    /// The runs of lines, in the order they are in the C
+    pub mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    /// Works out the map of c from its #line directives
+    ///
+    /// Blank lines and the directives themselves aren't mapped, and neither is anything after
+    /// a comment at the start of a line, which is how codegen starts the parts of the C that
+    /// aren't compiled from the program, like the runtime and the test harness.
+    pub fn from_c(c: &str) -> SourceMap {
+        let mut map = SourceMap::default();
+        let mut current: Option<(usize, usize)> = None;
+        // Whether the last mapping can be made longer, as nothing has been mapped to anything else since it
+        let mut open = false;
+        for (index, text) in c.lines().enumerate() {
+            let c_line = index + 1;
+            if let Some((line, source)) = parse_line_directive(text) {
+                let source = match map.sources.iter().position(|known| *known == source) {
+                    Some(index) => index,
+                    None => {
+                        map.sources.push(source);
+                        map.sources.len() - 1
+                    },
+                };
+                current = Some((source, line));
+                continue;
+            }
+            if text.starts_with("/*") {
+                current = None;
+                open = false;
+                continue;
+            }
+            let (source, line) = match current {
+                Some(current) if !text.trim().is_empty() => current,
+                _ => continue,
+            };
+            match map.mappings.last_mut() {
+                Some(last) if open && last.source == source && last.line == line => last.c_end = c_line,
+                _ => map.mappings.push(Mapping { source, line, c_start: c_line, c_end: c_line }),
+            }
+            open = true;
+        }
+        map
+    }
+
+    /// Returns the first and last lines of each run of C compiled from line of source
+    pub fn c_lines(&self, source: &str, line: usize) -> Vec<(usize, usize)> {
+        let index = match self.sources.iter().position(|known| known == source) {
+            Some(index) => index,
+            None => return vec![],
+        };
+        self.mappings.iter()
+            .filter(|mapping| mapping.source == index && mapping.line == line)
+            .map(|mapping| (mapping.c_start, mapping.c_end))
+            .collect()
+    }
+
+    /// Returns the haumea source and line that c_line of the C was compiled from, if it was
+    pub fn haumea_line(&self, c_line: usize) -> Option<(&str, usize)> {
+        self.mappings.iter()
+            .find(|mapping| mapping.c_start <= c_line && c_line <= mapping.c_end)
+            .map(|mapping| (&self.sources[mapping.source][..], mapping.line))
+    }
+
+    /// Returns the map as JSON, for the C in the file named c_file
+    ///
+    /// Each mapping is an object with the index of its source in sources, its line, and the
+    /// first and last lines of its C, c_start and c_end.
+    ///
+    /// # Examples
+    /// ```
+    /// # use haumea::sourcemap::SourceMap;
+    /// let map = SourceMap::from_c("/* prolog */\n#line 2 \"a.hm\"\n    f();\n    g();\n");
+    /// assert_eq!(map.to_json("a.c").to_string(),
+    ///            r#"{"version":1,"file":"a.c","sources":["a.hm"],"mappings":[{"source":0,"line":2,"c_start":3,"c_end":4}]}"#);
+    /// ```
+    pub fn to_json(&self, c_file: &str) -> Json {
+        let mappings = self.mappings.iter().map(|mapping| Json::object(vec![
+            ("source", Json::from(mapping.source)),
+            ("line", Json::from(mapping.line)),
+            ("c_start", Json::from(mapping.c_start)),
+            ("c_end", Json::from(mapping.c_end)),
+        ])).collect::<Vec<_>>();
+        Json::object(vec![
+            ("version", Json::from(SOURCE_MAP_VERSION)),
+            ("file", Json::from(c_file)),
+            ("sources", Json::from(self.sources.iter().map(|source| Json::from(&source[..])).collect::<Vec<_>>())),
+            ("mappings", Json::from(mappings)),
+        ])
+    }
+}
+
+/// Returns the line and the name of the source in a directive like `#line 3 "main.hm"`, if text is one
+fn parse_line_directive(text: &str) -> Option<(usize, String)> {
+    let rest = text.strip_prefix("#line ")?;
+    let space = rest.find(' ')?;
+    let line = rest[..space].parse().ok()?;
+    let literal = rest[space + 1..].trim();
+    if !literal.starts_with('"') || !literal.ends_with('"') || literal.len() < 2 {
+        return None;
+    }
+    let mut name = String::new();
+    let mut chars = literal[1..literal.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            name.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => name.push('\n'),
+            't' => name.push('\t'),
+            escaped => name.push(escaped),
+        }
+    }
+    Some((line, name))
+}
@@ -0,0 +1,35 @@
+/// src/synthetic.rs
+/// Makes up programs of any size, for the tests and benchmarks that need large ones
+use std::fmt::Write;
+
+/// Returns the source of a program with functions functions, counting main
+///
+/// Each function calls two of the ones before it, so the call graph is as deep as there are
+/// functions and every function is used. The program is the same for the same functions.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser;
+/// # use haumea::synthetic;
+/// let program = parser::parse(Scanner::new(&synthetic::program(100))).unwrap();
+/// assert_eq!(program.len(), 100);
+/// assert_eq!(program[1].name, "f_b");
+/// ```
+pub fn program(functions: usize) -> String {
+    let count = functions.max(2) - 1;
+    let mut source = format!("to {} with (n) do return n end\n", function_name(0));
+    for index in 1..count {
+        write!(source, "to {} with (n) do\n    variable x\n    set x to {}(n) + {}(1)\n    return x * {}\nend\n",
+               function_name(index), function_name(index - 1), function_name(index / 2), index).unwrap();
+    }
+    writeln!(source, "to main do display({}(2)) end", function_name(count - 1)).unwrap();
+    source
+}
+
+/// Returns the name of the function numbered index in a program, like f_bc for 12,
+/// as names can't have digits in them
+pub fn function_name(index: usize) -> String {
+    let letters = index.to_string().bytes().map(|digit| (digit - b'0' + b'a') as char).collect::<String>();
+    format!("f_{}", letters)
+}
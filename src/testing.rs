@@ -0,0 +1,90 @@
+/// src/testing.rs
+/// Compiles haumea programs and runs them, for tests that check what the programs do
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use options::CompilerOptions;
+use snapshot;
+use toolchain;
+
+/// The name that programs are compiled under, which is what their errors start with
+const PROGRAM_NAME: &str = "test.hm";
+
+/// How many programs have been built, which keeps the executables of tests running at once apart
+static BUILT: AtomicUsize = AtomicUsize::new(0);
+
+/// What a program did when it was run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    /// What the program wrote to stdout
+    pub stdout: String,
+    /// What the program wrote to stderr, like the errors from `--safe`
+    pub stderr: String,
+    /// The exit code of the program, which is the low byte of what main returned
+    pub exit_code: i32,
+}
+
+/// Compiles the program in source with the C compiler and runs it, giving it stdin
+///
+/// The C compiler is the one that haumea uses anyway, so `$CC` or `cc`. The program is
+/// built in the temporary directory, and deleted once it has run. An error means that the
+/// program has errors or that it couldn't be built, rather than that it failed when it ran.
+///
+/// # Examples
+/// ```no_run
+/// # use haumea::testing::run_program;
+/// let source = "external function getchar\n\
+///               to main do\n display(getchar())\n return 3\nend";
+/// let output = run_program(source, "A").unwrap();
+/// assert_eq!(output.stdout, "65\n");
+/// assert_eq!(output.exit_code, 3);
+/// ```
+pub fn run_program(source: &str, stdin: &str) -> Result<Output, String> {
+    run_program_with(PROGRAM_NAME, source, stdin, &CompilerOptions::new(PROGRAM_NAME))
+}
+
+/// Does what run_program does, with the program named name and compiled with options
+///
+/// options can pick the C compiler and its flags, the runtime, and `--safe`
+pub fn run_program_with(name: &str, source: &str, stdin: &str, options: &CompilerOptions)
+                        -> Result<Output, String> {
+    let c_source = snapshot::compile_source(name, source, options)?;
+    let built = BUILT.fetch_add(1, Ordering::SeqCst);
+    let executable = toolchain::temp_executable(Path::new(&format!("test-{}", built)));
+    let result = toolchain::build_executable(&c_source, &executable, options)
+        .and_then(|_| run_with_input(&executable, stdin));
+    let _ = fs::remove_file(&executable);
+    result
+}
+
+/// Runs the executable at path, writing stdin to it and keeping what it writes
+fn run_with_input(path: &Path, stdin: &str) -> Result<Output, String> {
+    let error = |e| format!("Could not run {}: {}", path.display(), e);
+    let mut child = Command::new(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(error)?;
+    // The input is written from another thread, so that a program that writes a lot
+    // before it reads can't leave both of them waiting for each other
+    let mut input = child.stdin.take();
+    let stdin = stdin.to_string();
+    let writer = thread::spawn(move || {
+        if let Some(ref mut input) = input {
+            // A program that stops before it reads everything is fine
+            let _ = input.write_all(stdin.as_bytes());
+        }
+    });
+    let output = child.wait_with_output().map_err(error)?;
+    let _ = writer.join();
+    Ok(Output {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        // A program killed by a signal has no exit code, so it counts as a failure
+        exit_code: output.status.code().unwrap_or(1),
+    })
+}
@@ -0,0 +1,196 @@
+/// src/texts.rs
+/// Finds the expressions that are known to be texts, so that = and != compare their chars
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::mem;
+use parser::{Expression, Operator, SourceFile, Statement, StatementKind};
+
+/// The builtins that return texts
+pub const TEXT_BUILTINS: &[&str] = &["substring", "to_text", "uppercase", "lowercase", "read_line", "argument_at",
+                                     "environment_value"];
+
+/// Returns the names of the functions in files that return texts, which are the builtins
+/// that do and the functions declared with `external text function`
+pub fn text_functions(files: &[SourceFile]) -> HashSet<&str> {
+    let mut text_functions: HashSet<&str> = TEXT_BUILTINS.iter().cloned().collect();
+    for func in files.iter().flat_map(|file| file.program.iter()).filter(|func| func.code.is_none() && func.returns_text) {
+        text_functions.insert(&func.name);
+    }
+    text_functions
+}
+
+/// Returns true if expression is known to be a text, which is when it is written in quotes
+/// or calls a function that returns_text says returns one
+///
+/// Every value is a number, so a variable or a parameter is never known to be a text.
+pub fn is_text(expression: &Expression, returns_text: &dyn Fn(&str) -> bool) -> bool {
+    match *expression {
+        Expression::Text(_) => true,
+        Expression::Call { ref function, .. } => returns_text(function),
+        _ => false,
+    }
+}
+
+/// Returns true if = or != between left and right compares texts, which is when one side is known
+/// to be a text and the other isn't a number, as `read_line() = 0` tells whether there was a line
+pub fn compares_texts(left: &Expression, right: &Expression, returns_text: &dyn Fn(&str) -> bool) -> bool {
+    let is_number = |expression: &Expression| matches!(*expression, Expression::Integer(_));
+    (is_text(left, returns_text) || is_text(right, returns_text)) && !is_number(left) && !is_number(right)
+}
+
+/// Returns files with each = and != that compares_texts says compares texts made into
+/// a call of same_text, so that the texts are compared by their chars rather than by which texts they are
+///
+/// This is done to the program as it was written, before it is optimized or flattened, as they
+/// can put the sides of a comparison into variables or put texts where a function's parameters were,
+/// and the interpreter runs the program as it was written. files are only copied if there is a
+/// comparison to change.
+///
+/// # Examples
+/// ```
+/// # use haumea::scanner::Scanner;
+/// # use haumea::parser::{self, SourceFile};
+/// # use haumea::{pretty, texts};
+/// let source = "external text function getenv with (text name)\n\
+///               to main do\n    check(x = \"yes\", getenv(\"HOME\") = y, x = y, getenv(\"PATH\") = 0)\nend";
+/// let file = SourceFile {
+///     name: "main.hm".to_string(),
+///     program: parser::parse(Scanner::new(source)).unwrap(),
+/// };
+/// let files = [file];
+/// let compared = texts::compare_by_chars(&files);
+/// let mut out = String::new();
+/// pretty::print_function(&mut out, &compared[0].program[1]);
+/// assert_eq!(out, "to main do
+///     check(same_text(x, \"yes\"), same_text(getenv(\"HOME\"), y), x = y, getenv(\"PATH\") = 0)
+/// end
+/// ");
+/// ```
+pub fn compare_by_chars<'a>(files: &'a [SourceFile]) -> Cow<'a, [SourceFile]> {
+    let text_functions = text_functions(files).into_iter().map(|name| name.to_string()).collect::<HashSet<_>>();
+    let returns_text = |name: &str| text_functions.contains(name);
+    let compares = |expression: &Expression| has_text_comparison(expression, &returns_text);
+    // Most programs don't, and cloning an expression recurses as deep as it is nested
+    let mut codes = files.iter().flat_map(|file| file.program.iter()).filter_map(|func| func.code.as_ref());
+    if !codes.any(|code| any_expression(code, &compares)) {
+        return Cow::Borrowed(files);
+    }
+    let mut files = files.to_vec();
+    for func in files.iter_mut().flat_map(|file| file.program.iter_mut()) {
+        if let Some(ref mut code) = func.code {
+            for_each_expression(code, &mut |expression| compare_in_expression(expression, &returns_text));
+        }
+    }
+    Cow::Owned(files)
+}
+
+/// Returns true if f is true for an expression in statement or the statements in it
+fn any_expression(statement: &Statement, f: &dyn Fn(&Expression) -> bool) -> bool {
+    match statement.kind {
+        StatementKind::Return(ref expression) |
+        StatementKind::Set(_, ref expression) |
+        StatementKind::Define(_, ref expression) |
+        StatementKind::Change(_, ref expression) => f(expression),
+        StatementKind::If { ref cond, ref if_clause, ref else_clause, .. } => {
+            f(cond) || any_expression(if_clause, f) || else_clause.as_ref().is_some_and(|clause| any_expression(clause, f))
+        },
+        StatementKind::Repeat { ref count, ref body, .. } => f(count) || any_expression(body, f),
+        StatementKind::Do(ref block) => block.iter().any(|statement| any_expression(statement, f)),
+        StatementKind::Call { ref arguments, .. } => arguments.iter().any(f),
+        StatementKind::Var(_) | StatementKind::Persistent(_) | StatementKind::Swap(..) |
+        StatementKind::InlineC(_) | StatementKind::Break(_) => false,
+    }
+}
+
+/// Calls f with each expression in statement and the statements in it
+fn for_each_expression<F: FnMut(&mut Expression)>(statement: &mut Statement, f: &mut F) {
+    match statement.kind {
+        StatementKind::Return(ref mut expression) |
+        StatementKind::Set(_, ref mut expression) |
+        StatementKind::Define(_, ref mut expression) |
+        StatementKind::Change(_, ref mut expression) => f(expression),
+        StatementKind::If { ref mut cond, ref mut if_clause, ref mut else_clause, .. } => {
+            f(cond);
+            for_each_expression(if_clause, f);
+            if let Some(ref mut else_clause) = *else_clause {
+                for_each_expression(else_clause, f);
+            }
+        },
+        StatementKind::Repeat { ref mut count, ref mut body, .. } => {
+            f(count);
+            for_each_expression(body, f);
+        },
+        StatementKind::Do(ref mut block) => {
+            for statement in block {
+                for_each_expression(statement, f);
+            }
+        },
+        StatementKind::Call { ref mut arguments, .. } => {
+            for argument in arguments {
+                f(argument);
+            }
+        },
+        StatementKind::Var(_) | StatementKind::Persistent(_) | StatementKind::Swap(..) |
+        StatementKind::InlineC(_) | StatementKind::Break(_) => (),
+    }
+}
+
+/// Returns true if operator is = or !=
+fn is_equality(operator: &Operator) -> bool {
+    *operator == Operator::Equals || *operator == Operator::NotEquals
+}
+
+/// Returns true if expression, or an expression in it, compares texts
+///
+/// Like the walks of expressions below, this keeps a stack of the expressions left to look at
+/// rather than recursing, as machine-generated programs can nest them far deeper than the stack could.
+fn has_text_comparison(expression: &Expression, returns_text: &dyn Fn(&str) -> bool) -> bool {
+    let mut pending = vec![expression];
+    while let Some(expression) = pending.pop() {
+        match *expression {
+            Expression::BinaryOp { ref operator, ref left, ref right } => {
+                if is_equality(operator) && compares_texts(left, right, returns_text) {
+                    return true;
+                }
+                pending.push(left);
+                pending.push(right);
+            },
+            Expression::UnaryOp { ref expression, .. } => pending.push(expression),
+            Expression::Call { ref arguments, .. } => pending.extend(arguments),
+            Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) => (),
+        }
+    }
+    false
+}
+
+/// Makes the comparisons of texts in expression into calls of same_text
+fn compare_in_expression(expression: &mut Expression, returns_text: &dyn Fn(&str) -> bool) {
+    let mut pending = vec![expression];
+    while let Some(expression) = pending.pop() {
+        let compares = match *expression {
+            Expression::BinaryOp { ref operator, ref left, ref right } => {
+                is_equality(operator) && compares_texts(left, right, returns_text)
+            },
+            _ => false,
+        };
+        if compares {
+            if let Expression::BinaryOp { operator, left, right } = mem::replace(expression, Expression::Integer(0)) {
+                let same = Expression::Call { function: "same_text".to_string(), arguments: vec![*left, *right] };
+                *expression = if operator == Operator::NotEquals {
+                    Expression::UnaryOp { operator: Operator::LogicalNot, expression: Box::new(same) }
+                } else {
+                    same
+                };
+            }
+        }
+        match *expression {
+            Expression::BinaryOp { ref mut left, ref mut right, .. } => {
+                pending.push(left);
+                pending.push(right);
+            },
+            Expression::UnaryOp { ref mut expression, .. } => pending.push(expression),
+            Expression::Call { ref mut arguments, .. } => pending.extend(arguments.iter_mut()),
+            Expression::Integer(_) | Expression::Text(_) | Expression::Ident(_) => (),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+/// src/timings.rs
+/// Measures how long each phase of a compilation takes, for --timings
+use std::time::{Duration, Instant};
+
+/// The time taken by each phase of a compilation, along with some statistics about it
+pub struct Timings {
+    /// Whether anything is measured at all
+    enabled: bool,
+    /// The name of each phase, the total time spent in it, and notes about its results
+    phases: Vec<(&'static str, Duration, Vec<String>)>,
+}
+
+impl Timings {
+    /// Constructs a new Timings, which only measures anything if enabled is true
+    pub fn new(enabled: bool) -> Timings {
+        Timings {
+            enabled,
+            phases: vec![],
+        }
+    }
+
+    /// Runs f, adding the time it takes to the phase named phase
+    pub fn time<T, F: FnOnce() -> T>(&mut self, phase: &'static str, f: F) -> T {
+        if !self.enabled {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        match self.phases.iter_mut().find(|&&mut (name, _, _)| name == phase) {
+            Some(&mut (_, ref mut total, _)) => *total += elapsed,
+            None => self.phases.push((phase, elapsed, vec![])),
+        }
+        result
+    }
+
+    /// Adds a note about the results of the phase named phase
+    ///
+    /// note is only called if timings are enabled
+    pub fn note<F: FnOnce() -> String>(&mut self, phase: &'static str, note: F) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(&mut (_, _, ref mut notes)) = self.phases.iter_mut().find(|&&mut (name, _, _)| name == phase) {
+            notes.push(note());
+        }
+    }
+
+    /// Prints the timings to stderr
+    pub fn report(&self) {
+        if !self.enabled {
+            return;
+        }
+        let mut total = Duration::new(0, 0);
+        for &(name, elapsed, ref notes) in &self.phases {
+            total += elapsed;
+            let notes = if notes.is_empty() { String::new() } else { format!(" ({})", notes.join(", ")) };
+            eprintln!("[timings] {:<10} {:>10.3}ms{}", name, millis(elapsed), notes);
+        }
+        eprintln!("[timings] {:<10} {:>10.3}ms", "total", millis(total));
+    }
+}
+
+/// Returns d in milliseconds
+fn millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1000.0 + f64::from(d.subsec_nanos()) / 1_000_000.0
+}
@@ -0,0 +1,241 @@
+/// src/toolchain.rs
+/// Drives the system C compiler to turn generated C into native code
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, ExitStatus};
+use coverage;
+use options::{CompilerOptions, STDIO_PATH};
+use runtime;
+
+/// The C compiler used when $CC isn't set
+const DEFAULT_CC: &str = "cc";
+
+/// The archiver used to make static libraries when $AR isn't set
+const DEFAULT_AR: &str = "ar";
+
+/// Returns the C compiler to use: options.cc if it is set, then the value of $CC,
+/// and `cc` if neither is set
+pub fn c_compiler(options: &CompilerOptions) -> String {
+    if let Some(ref cc) = options.cc {
+        return cc.clone();
+    }
+    match env::var("CC") {
+        Ok(ref cc) if !cc.is_empty() => cc.clone(),
+        _ => DEFAULT_CC.to_string(),
+    }
+}
+
+/// Compiles the C source in c_source into an executable at output
+///
+/// The C is written to a temporary directory along with the runtime library,
+/// which it is linked with if it uses it, and the directory is removed afterwards.
+/// The C compiler's own error messages are passed straight through to stderr;
+/// since the generated C contains #line directives they point at the haumea source.
+///
+/// The C compiler is chosen by c_compiler, and is passed the flag for options.opt_level and then
+/// options.cflags, so that they can change it, before its other arguments.
+/// The executable is linked with options.libraries, which are looked for in options.library_paths.
+pub fn build_executable(c_source: &str, output: &Path, options: &CompilerOptions) -> Result<(), String> {
+    run_c_compiler(c_source, output, &[], true, options)
+}
+
+/// Compiles the C source in c_source into a static library at output
+///
+/// The C is compiled into an object file with `-c`, along with the runtime library if it uses it,
+/// and the object files are archived by $AR (or `ar` if that isn't set).
+/// The libraries in options aren't linked in, so the programs that use the library have to link with them.
+pub fn build_static_library(c_source: &str, output: &Path, options: &CompilerOptions) -> Result<(), String> {
+    let dir = temp_path(output, "build");
+    fs::create_dir_all(&dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    let result = archive_in(&dir, c_source, output, options);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+/// Does the work of build_static_library, in the temporary directory dir
+fn archive_in(dir: &Path, c_source: &str, output: &Path, options: &CompilerOptions) -> Result<(), String> {
+    let c_file = temp_c_file(dir, output);
+    write_sources(dir, &c_file, c_source)?;
+    let mut sources = vec![c_file];
+    if runtime::is_used_by(c_source) {
+        sources.push(dir.join(runtime::SOURCE_NAME));
+    }
+    let cc = c_compiler(options);
+    let mut objects = vec![];
+    for source in sources {
+        let object = source.with_extension("o");
+        let status = Command::new(&cc).arg(options.opt_level.c_flag()).args(&options.cflags)
+            .arg("-c").arg(&source).arg("-o").arg(&object).status();
+        check_status(status, &format!("C compiler ({})", cc), "compile the program")?;
+        objects.push(object);
+    }
+    let ar = match env::var("AR") {
+        Ok(ref ar) if !ar.is_empty() => ar.clone(),
+        _ => DEFAULT_AR.to_string(),
+    };
+    // ar adds to an archive that is already there, so an old library would keep its objects
+    let _ = fs::remove_file(output);
+    let status = Command::new(&ar).arg("rcs").arg(output).args(&objects).status();
+    check_status(status, &format!("archiver ({})", ar), "archive the library")
+}
+
+/// Compiles the C source in c_source into a shared library at output
+///
+/// The code is position independent, and like an executable the library is linked with the runtime
+/// library if it uses it and with options.libraries. Its functions keep their names in haumea,
+/// and are the only symbols that it exports apart from the runtime's.
+pub fn build_shared_library(c_source: &str, output: &Path, options: &CompilerOptions) -> Result<(), String> {
+    let shared = if cfg!(target_os = "macos") { "-dynamiclib" } else { "-shared" };
+    run_c_compiler(c_source, output, &["-fPIC", shared], true, options)
+}
+
+/// Compiles the C source in c_source into assembly at output
+pub fn build_assembly(c_source: &str, output: &Path, options: &CompilerOptions) -> Result<(), String> {
+    run_c_compiler(c_source, output, &["-S"], false, options)
+}
+
+/// Writes c_source to a temporary directory and runs the C compiler on it with the extra flags
+///
+/// The header of the runtime is always written next to the C. With link the C compiler links
+/// an executable, so the source of the runtime is compiled along with the C if it uses it,
+/// and the libraries in options are linked in.
+fn run_c_compiler(c_source: &str, output: &Path, flags: &[&str], link: bool, options: &CompilerOptions)
+                  -> Result<(), String> {
+    let dir = temp_path(output, "build");
+    fs::create_dir_all(&dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    let result = compile_in(&dir, c_source, output, flags, link, options);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+/// Does the work of run_c_compiler, in the temporary directory dir
+fn compile_in(dir: &Path, c_source: &str, output: &Path, flags: &[&str], link: bool,
+              options: &CompilerOptions) -> Result<(), String> {
+    let c_file = temp_c_file(dir, output);
+    write_sources(dir, &c_file, c_source)?;
+    let cc = c_compiler(options);
+    let mut command = Command::new(&cc);
+    command.arg(options.opt_level.c_flag()).args(&options.cflags).args(flags).arg(&c_file);
+    if link && runtime::is_used_by(c_source) {
+        command.arg(dir.join(runtime::SOURCE_NAME));
+    }
+    // Libraries only supply what the files before them need, so they go last
+    if link {
+        command.args(link_flags(options));
+    }
+    check_status(command.arg("-o").arg(output).status(), &format!("C compiler ({})", cc), "compile the program")
+}
+
+/// Writes c_source to c_file, and the runtime library next to it in the directory dir
+fn write_sources(dir: &Path, c_file: &Path, c_source: &str) -> Result<(), String> {
+    File::create(c_file)
+        .and_then(|mut file| file.write_all(c_source.as_bytes()))
+        .map_err(|e| format!("Could not write {}: {}", c_file.display(), e))?;
+    runtime::write_runtime(dir)
+}
+
+/// Turns the status of a run of tool, like "C compiler (cc)", into an error if it couldn't be run
+/// or failed to do task
+fn check_status(status: io::Result<ExitStatus>, tool: &str, task: &str) -> Result<(), String> {
+    match status {
+        Ok(ref status) if status.success() => Ok(()),
+        Ok(_) => Err(format!("The {} failed to {}", tool, task)),
+        Err(e) => Err(format!("Could not run the {}: {}", tool, e)),
+    }
+}
+
+/// Returns the flags that make the C compiler link with the libraries in options
+///
+/// # Examples
+/// ```
+/// # use haumea::options::CompilerOptions;
+/// # use haumea::toolchain::link_flags;
+/// let mut options = CompilerOptions::new("main.hm");
+/// options.libraries = vec!["m".to_string(), "curses".to_string()];
+/// options.library_paths = vec!["/opt/lib".into()];
+/// assert_eq!(link_flags(&options), vec!["-L/opt/lib", "-lm", "-lcurses"]);
+/// ```
+pub fn link_flags(options: &CompilerOptions) -> Vec<String> {
+    let paths = options.library_paths.iter().map(|path| format!("-L{}", path.display()));
+    let libraries = options.libraries.iter().map(|library| format!("-l{}", library));
+    paths.chain(libraries).collect()
+}
+
+/// Runs the executable at path with the same stdin, stdout, and stderr as the compiler
+///
+/// Returns the exit code of the program
+pub fn run_executable(path: &Path, arguments: &[String]) -> Result<i32, String> {
+    exit_code(Command::new(path).args(arguments), path)
+}
+
+/// Runs the executable at path like run_executable, for a program compiled with --instrument-coverage
+/// that writes its coverage report to report
+pub fn run_executable_with_coverage(path: &Path, arguments: &[String], report: &Path) -> Result<i32, String> {
+    exit_code(Command::new(path).args(arguments).env(coverage::FILE_VARIABLE, report), path)
+}
+
+/// Runs command, which runs the executable at path, returning its exit code
+///
+/// A program killed by a signal has no exit code, so this says which signal it was and
+/// returns 128 plus it, like a shell does
+fn exit_code(command: &mut Command, path: &Path) -> Result<i32, String> {
+    match command.status() {
+        Ok(status) => match signal(&status) {
+            Some(signal) => {
+                eprintln!("error: the program was killed by signal {}", signal);
+                Ok(128 + signal)
+            },
+            None => Ok(status.code().unwrap_or(1)),
+        },
+        Err(e) => Err(format!("Could not run {}: {}", path.display(), e)),
+    }
+}
+
+/// Returns the signal that killed the program that exited with status, if one did
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+/// Returns the signal that killed the program that exited with status, which only unix has
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Returns a path in the temporary directory for an executable built from input
+pub fn temp_executable(input: &Path) -> PathBuf {
+    temp_path(input, env::consts::EXE_EXTENSION)
+}
+
+/// Returns a path in the temporary directory for assembly built from input
+pub fn temp_assembly(input: &Path) -> PathBuf {
+    temp_path(input, "s")
+}
+
+/// Returns the path of the C source of output in the temporary directory dir
+fn temp_c_file(dir: &Path, output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_else(|| OsStr::new("out"));
+    dir.join(stem).with_extension("c")
+}
+
+/// Returns a path in the temporary directory named after path, with the extension extension
+fn temp_path(path: &Path, extension: &str) -> PathBuf {
+    let stem = match path.file_stem() {
+        Some(stem) if stem == STDIO_PATH => "stdin".to_string(),
+        Some(stem) => stem.to_string_lossy().into_owned(),
+        None => "out".to_string(),
+    };
+    let prefix = format!("haumea-{}-", process::id());
+    // Paths that are already temporary (like the output of run) don't need another prefix
+    let mut name = if stem.starts_with(&prefix) { stem } else { prefix + &stem };
+    if !extension.is_empty() {
+        name.push('.');
+        name.push_str(extension);
+    }
+    env::temp_dir().join(name)
+}
@@ -0,0 +1,79 @@
+/// src/watch.rs
+/// Watch mode, which recompiles haumea programs whenever they are saved
+use std::collections::BTreeMap;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use haumea::options::CompilerOptions;
+use haumea::project::source_files;
+
+/// How long to wait between checks for changed files
+///
+/// Files are polled rather than watched with the notify crate, as haumea has no dependencies,
+/// and reading the few files of a program twice a second costs next to nothing
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches the file or directory that is the input of options,
+/// recompiling the program made of its files when any of them changes
+///
+/// The files are compiled together, as build compiles its inputs. A file has changed when its
+/// contents have, so a save is seen even if it keeps the modification time, and a save that
+/// changes nothing isn't. If run is true the program is also run after it is compiled. This never returns unless the input can't be read.
+/// If options.quiet is set only errors are printed.
+pub fn watch(options: &CompilerOptions, run: bool) -> Result<(), String> {
+    let mut last_contents = BTreeMap::new();
+    if !options.quiet {
+        println!("[watch] Watching {} for changes", options.input().display());
+    }
+    loop {
+        let files = source_files(options.input())?;
+        let mut changed = false;
+        let mut settled = true;
+        for path in &files {
+            match fs::read(path) {
+                Ok(contents) => if last_contents.get(path) != Some(&contents) {
+                    last_contents.insert(path.clone(), contents);
+                    changed = true;
+                },
+                // The file may be in the middle of being saved, so try again next time
+                Err(_) => settled = false,
+            }
+        }
+        // Forget deleted files, as the program without them has to be rebuilt too
+        let count = last_contents.len();
+        last_contents.retain(|path: &PathBuf, _: &mut Vec<u8>| path.exists());
+        changed = changed || last_contents.len() != count;
+        if changed && settled && !files.is_empty() {
+            rebuild(files, options, run);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Compiles (and maybe runs) the program made of files, printing the outcome
+fn rebuild(files: Vec<PathBuf>, options: &CompilerOptions, run: bool) {
+    let path = options.input().to_path_buf();
+    let mut options = options.clone();
+    options.inputs = files;
+    if !options.quiet {
+        println!("[watch] Compiling {}", path.display());
+    }
+    // A broken program mustn't stop the watcher, even if the compiler panics on it
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        if run {
+            super::run(&options, &[]).map(|code| format!("exited with code {}", code))
+        } else {
+            let outcome = if options.check { "has no errors" } else { "compiled successfully" };
+            super::build(&options).map(|_| outcome.to_string())
+        }
+    }));
+    match result {
+        Ok(Ok(outcome)) => if !options.quiet {
+            println!("[watch] {} {}", path.display(), outcome)
+        },
+        Ok(Err(error)) => eprintln!("{}", error),
+        Err(_) => eprintln!("[watch] {} failed to compile", path.display()),
+    }
+}
@@ -0,0 +1,18 @@
+//! Checks that the Builtins section of the README lists every builtin, as it says it does
+extern crate haumea;
+
+use std::fs;
+use haumea::checker::BUILTINS;
+
+#[test]
+fn every_builtin_is_in_the_readme() {
+    let readme = fs::read_to_string("README.md").unwrap();
+    let start = readme.find("\n# Builtins\n").unwrap();
+    let end = readme[start + 1..].find("\n# ").map_or(readme.len(), |end| start + 1 + end);
+    let section = &readme[start..end];
+    let missing = BUILTINS.iter()
+        .map(|&(name, _)| name)
+        .filter(|name| !section.contains(&format!("`{}(", name)))
+        .collect::<Vec<_>>();
+    assert!(missing.is_empty(), "the README doesn't list {:?}", missing);
+}
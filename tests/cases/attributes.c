@@ -0,0 +1,51 @@
+
+/* Haumea prolog */
+int printf(const char *format, ...);
+
+static long display(long n) {
+    printf("%ld\n", n);
+    return 0;
+}
+/* End prolog */
+
+/* Start compiled program */
+long square(long x);
+long sum_of_squares(long a, long b);
+long squares_added(long a, long b);
+
+#line 3 "tests/cases/attributes.hm"
+long square(long x)
+{
+#line 3 "tests/cases/attributes.hm"
+    return (x * x);
+}
+
+#line 6 "tests/cases/attributes.hm"
+long sum_of_squares(long a, long b)
+{
+#line 6 "tests/cases/attributes.hm"
+    return ((a * a) + (b * b));
+}
+
+#line 10 "tests/cases/attributes.hm"
+long squares_added(long a, long b)
+{
+#line 10 "tests/cases/attributes.hm"
+    return ((a * a) + (b * b));
+}
+
+#line 12 "tests/cases/attributes.hm"
+int main()
+#line 12 "tests/cases/attributes.hm"
+{
+#line 13 "tests/cases/attributes.hm"
+    long n = 3l;
+#line 14 "tests/cases/attributes.hm"
+    display(25l);
+#line 15 "tests/cases/attributes.hm"
+    display(square(4l));
+#line 16 "tests/cases/attributes.hm"
+    display(squares_added(1l, 2l));
+}
+
+/* End compiled program */
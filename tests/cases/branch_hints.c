@@ -0,0 +1,52 @@
+
+/* Haumea prolog */
+int printf(const char *format, ...);
+
+static long display(long n) {
+    printf("%ld\n", n);
+    return 0;
+}
+
+/* Branch hints, which only GCC and Clang understand */
+#if defined(__GNUC__) || defined(__clang__)
+#define haumea_likely(condition) __builtin_expect(!!(condition), 1)
+#define haumea_unlikely(condition) __builtin_expect(!!(condition), 0)
+#else
+#define haumea_likely(condition) (condition)
+#define haumea_unlikely(condition) (condition)
+#endif
+/* End prolog */
+
+/* Start compiled program */
+long check(long n);
+
+#line 1 "tests/cases/branch_hints.hm"
+long check(long n)
+#line 1 "tests/cases/branch_hints.hm"
+{
+#line 2 "tests/cases/branch_hints.hm"
+    if (haumea_unlikely((n == 0l)))
+#line 2 "tests/cases/branch_hints.hm"
+        return 1l;
+    else
+#line 3 "tests/cases/branch_hints.hm"
+        if (haumea_likely((n > 0l)))
+#line 3 "tests/cases/branch_hints.hm"
+            return 2l;
+#line 4 "tests/cases/branch_hints.hm"
+    return 3l;
+}
+
+#line 7 "tests/cases/branch_hints.hm"
+int main()
+#line 7 "tests/cases/branch_hints.hm"
+{
+#line 8 "tests/cases/branch_hints.hm"
+    display(check(0l));
+#line 9 "tests/cases/branch_hints.hm"
+    display(check(5l));
+#line 10 "tests/cases/branch_hints.hm"
+    display(check(-5l));
+}
+
+/* End compiled program */
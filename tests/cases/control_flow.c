@@ -0,0 +1,51 @@
+
+/* Haumea prolog */
+int printf(const char *format, ...);
+
+static long display(long n) {
+    printf("%ld\n", n);
+    return 0;
+}
+/* End prolog */
+
+/* Start compiled program */
+long sign(long n);
+
+#line 1 "tests/cases/control_flow.hm"
+long sign(long n)
+#line 1 "tests/cases/control_flow.hm"
+{
+#line 2 "tests/cases/control_flow.hm"
+    if (n > 0l)
+#line 2 "tests/cases/control_flow.hm"
+        return 1l;
+    else
+#line 3 "tests/cases/control_flow.hm"
+        if (n < 0l)
+#line 3 "tests/cases/control_flow.hm"
+            return -1l;
+        else
+#line 4 "tests/cases/control_flow.hm"
+            return 0l;
+}
+
+#line 7 "tests/cases/control_flow.hm"
+int main()
+#line 7 "tests/cases/control_flow.hm"
+{
+#line 8 "tests/cases/control_flow.hm"
+    long total = 0l;
+#line 9 "tests/cases/control_flow.hm"
+    total = 0l;
+#line 10 "tests/cases/control_flow.hm"
+    {
+#line 11 "tests/cases/control_flow.hm"
+        total += sign(5l);
+#line 12 "tests/cases/control_flow.hm"
+        total += sign(-5l);
+    }
+#line 14 "tests/cases/control_flow.hm"
+    display(total);
+}
+
+/* End compiled program */
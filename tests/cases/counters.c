@@ -0,0 +1,31 @@
+
+/* Haumea prolog */
+int printf(const char *format, ...);
+
+static long display(long n) {
+    printf("%ld\n", n);
+    return 0;
+}
+/* End prolog */
+
+/* Start compiled program */
+
+#line 1 "tests/cases/counters.hm"
+int main()
+#line 1 "tests/cases/counters.hm"
+{
+#line 2 "tests/cases/counters.hm"
+    long x = 0l;
+#line 3 "tests/cases/counters.hm"
+    x = 0l;
+#line 4 "tests/cases/counters.hm"
+    x += 1l;
+#line 5 "tests/cases/counters.hm"
+    x -= 1l;
+#line 6 "tests/cases/counters.hm"
+    x -= 2l;
+#line 7 "tests/cases/counters.hm"
+    display(-2l);
+}
+
+/* End compiled program */
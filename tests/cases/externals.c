@@ -0,0 +1,40 @@
+
+/* Haumea prolog */
+int printf(const char *format, ...);
+
+static long display(long n) {
+    printf("%ld\n", n);
+    return 0;
+}
+/* End prolog */
+
+/* Start compiled program */
+const char *getenv(const char *name);
+long strlen(const char *s);
+long qsort(long base, long count, long size, long (*compare)(long, long));
+long compare(long a, long b);
+/* getenv is given texts as const char * that it mustn't change or free, and the text it returns is never freed */
+#define getenv(name) ((long)getenv((char *)(name)))
+/* strlen is given texts as const char * that it mustn't change or free */
+#define strlen(s) strlen((char *)(s))
+#define qsort(base, count, size, compare) qsort((base), (count), (size), (void *)(compare))
+
+#line 5 "tests/cases/externals.hm"
+long compare(long a, long b)
+#line 5 "tests/cases/externals.hm"
+{
+#line 6 "tests/cases/externals.hm"
+    return (a - b);
+}
+
+#line 9 "tests/cases/externals.hm"
+int main()
+#line 9 "tests/cases/externals.hm"
+{
+#line 10 "tests/cases/externals.hm"
+    display(strlen(getenv(((long)"HOME"))));
+#line 11 "tests/cases/externals.hm"
+    qsort(0l, 0l, 8l, compare);
+}
+
+/* End compiled program */
@@ -0,0 +1,30 @@
+
+/* Haumea prolog */
+#include "haumea_rt.h"
+/* End prolog */
+
+/* Start compiled program */
+
+#line 2 "tests/cases/full_runtime.hm"
+int main(int argc, char **argv)
+{
+    haumea_set_arguments(argc, argv);
+#line 2 "tests/cases/full_runtime.hm"
+    {
+#line 3 "tests/cases/full_runtime.hm"
+        long name = 0l;
+#line 4 "tests/cases/full_runtime.hm"
+        name = haumea_uppercase(((long)"haumea"));
+#line 5 "tests/cases/full_runtime.hm"
+        haumea_display_text(name);
+#line 6 "tests/cases/full_runtime.hm"
+        if (haumea_argument_count() > 0l)
+#line 6 "tests/cases/full_runtime.hm"
+            haumea_display_text(haumea_argument_at(0l));
+        else
+#line 7 "tests/cases/full_runtime.hm"
+            display(0l);
+    }
+}
+
+/* End compiled program */
@@ -0,0 +1,30 @@
+
+/* Haumea prolog */
+int printf(const char *format, ...);
+
+static long display(long n) {
+    printf("%ld\n", n);
+    return 0;
+}
+/* End prolog */
+
+/* Start compiled program */
+long square(long n);
+
+#line 2 "tests/cases/minimal.hm"
+long square(long n)
+#line 2 "tests/cases/minimal.hm"
+{
+#line 3 "tests/cases/minimal.hm"
+    return (n * n);
+}
+
+#line 6 "tests/cases/minimal.hm"
+int main()
+#line 6 "tests/cases/minimal.hm"
+{
+#line 7 "tests/cases/minimal.hm"
+    display((square(7l) + 1l));
+}
+
+/* End compiled program */
@@ -0,0 +1,34 @@
+
+/* Haumea prolog */
+int printf(const char *format, ...);
+
+static long display(long n) {
+    printf("%ld\n", n);
+    return 0;
+}
+/* End prolog */
+
+/* Start compiled program */
+
+#line 1 "tests/cases/repeat.hm"
+int main()
+#line 1 "tests/cases/repeat.hm"
+{
+#line 2 "tests/cases/repeat.hm"
+    long total = 0l;
+#line 3 "tests/cases/repeat.hm"
+    total = 0l;
+#line 4 "tests/cases/repeat.hm"
+    for (long haumea_repeat_4_5 = 3l; haumea_repeat_4_5 > 0; haumea_repeat_4_5--)
+#line 4 "tests/cases/repeat.hm"
+        {
+#line 5 "tests/cases/repeat.hm"
+            for (long haumea_repeat_5_9 = 2l; haumea_repeat_5_9 > 0; haumea_repeat_5_9--)
+#line 5 "tests/cases/repeat.hm"
+                total += 1l;
+        }
+#line 7 "tests/cases/repeat.hm"
+    display(total);
+}
+
+/* End compiled program */
@@ -0,0 +1,31 @@
+
+/* Haumea prolog */
+int printf(const char *format, ...);
+
+static long display(long n) {
+    printf("%ld\n", n);
+    return 0;
+}
+/* End prolog */
+
+/* Start compiled program */
+
+#line 1 "tests/cases/swap.hm"
+int main()
+#line 1 "tests/cases/swap.hm"
+{
+#line 2 "tests/cases/swap.hm"
+    long x = 1l;
+#line 3 "tests/cases/swap.hm"
+    long y = 2l;
+#line 4 "tests/cases/swap.hm"
+    {
+        long haumea_swap_4_5 = x;
+        x = y;
+        y = haumea_swap_4_5;
+    }
+#line 5 "tests/cases/swap.hm"
+    display(21l);
+}
+
+/* End compiled program */
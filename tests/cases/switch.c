@@ -0,0 +1,92 @@
+
+/* Haumea prolog */
+int printf(const char *format, ...);
+
+static long display(long n) {
+    printf("%ld\n", n);
+    return 0;
+}
+/* End prolog */
+
+/* Start compiled program */
+long describe(long n);
+long show(long day);
+
+#line 1 "tests/cases/switch.hm"
+long describe(long n)
+#line 1 "tests/cases/switch.hm"
+{
+#line 2 "tests/cases/switch.hm"
+    switch (n)
+    {
+#line 2 "tests/cases/switch.hm"
+    case 1l:
+        {
+#line 2 "tests/cases/switch.hm"
+            return 10l;
+        }
+        break;
+#line 3 "tests/cases/switch.hm"
+    case 2l:
+#line 3 "tests/cases/switch.hm"
+        {
+#line 4 "tests/cases/switch.hm"
+            long doubled = (n * 2l);
+#line 5 "tests/cases/switch.hm"
+            return doubled;
+        }
+        break;
+#line 7 "tests/cases/switch.hm"
+    case 3l:
+        {
+#line 7 "tests/cases/switch.hm"
+            return 30l;
+        }
+        break;
+    default:
+        {
+#line 8 "tests/cases/switch.hm"
+            if (n > 3l)
+#line 8 "tests/cases/switch.hm"
+                return 40l;
+        }
+        break;
+    }
+#line 9 "tests/cases/switch.hm"
+    return 0l;
+}
+
+#line 12 "tests/cases/switch.hm"
+long show(long day)
+#line 12 "tests/cases/switch.hm"
+{
+#line 13 "tests/cases/switch.hm"
+    if (day == 0l)
+#line 13 "tests/cases/switch.hm"
+        display(0l);
+    else
+#line 14 "tests/cases/switch.hm"
+        if (day == 1l)
+#line 14 "tests/cases/switch.hm"
+            display(1l);
+        else
+#line 15 "tests/cases/switch.hm"
+            if (day == 1l)
+#line 15 "tests/cases/switch.hm"
+                display(11l);
+            else
+#line 16 "tests/cases/switch.hm"
+                display(describe(day));
+}
+
+#line 19 "tests/cases/switch.hm"
+int main()
+#line 19 "tests/cases/switch.hm"
+{
+#line 20 "tests/cases/switch.hm"
+    show(1l);
+#line 21 "tests/cases/switch.hm"
+    show(2l);
+}
+
+/* End compiled program */
@@ -0,0 +1,45 @@
+//! Checks that every code given to a diagnostic in src has an explanation for `haumea explain`,
+//! and that no code is explained twice
+extern crate haumea;
+
+use std::collections::HashSet;
+use std::fs;
+use haumea::codes::{self, EXPLANATIONS};
+
+/// Returns the codes in source, which are the only things in quotes that look like E0201 or W0001
+fn codes_in(source: &str) -> Vec<&str> {
+    source.split('"')
+        .skip(1)
+        .step_by(2)
+        .filter(|text| {
+            text.len() == 5 && (text.starts_with('E') || text.starts_with('W'))
+                && text[1..].bytes().all(|byte| byte.is_ascii_digit())
+        })
+        .collect()
+}
+
+#[test]
+fn every_code_is_explained() {
+    let mut unexplained = vec![];
+    for entry in fs::read_dir("src").unwrap() {
+        let path = entry.unwrap().path();
+        if path.ends_with("codes.rs") {
+            continue;
+        }
+        let source = fs::read_to_string(&path).unwrap();
+        for code in codes_in(&source) {
+            if codes::explain(code).is_none() {
+                unexplained.push(format!("{} in {}", code, path.display()));
+            }
+        }
+    }
+    assert!(unexplained.is_empty(), "these codes have no explanation: {:?}", unexplained);
+}
+
+#[test]
+fn codes_are_explained_once() {
+    let mut seen = HashSet::new();
+    for &(code, _) in EXPLANATIONS {
+        assert!(seen.insert(code), "{} is explained twice", code);
+    }
+}
@@ -0,0 +1,149 @@
+//! Checks haumea debug by running a Debug Adapter Protocol session with it, as an editor would
+//!
+//! The session needs gdb and a C compiler, so it is skipped without them.
+#![cfg(feature = "os")]
+extern crate haumea;
+
+use std::env;
+use std::fs;
+use std::io::BufReader;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use haumea::json::Json;
+use haumea::lsp::{read_message, write_message};
+use haumea::options::CompilerOptions;
+use haumea::toolchain;
+
+/// How long to wait for each message from the adapter before giving up on it
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+const PROGRAM: &str = "\
+to square with (x) do
+    variable y is x * x
+    return y
+end
+
+to main do
+    display(square(4))
+end
+";
+
+/// Returns true if there is gdb and a C compiler to debug programs with
+fn has_debugger() -> bool {
+    let options = CompilerOptions::new("debug.hm");
+    let found = Command::new("gdb").arg("--version").output().is_ok() &&
+        Command::new(toolchain::c_compiler(&options)).arg("--version").output().is_ok();
+    if !found {
+        eprintln!("skipping the tests of haumea debug, as there is no gdb or no C compiler");
+    }
+    found
+}
+
+/// Returns a request with the seq and arguments
+fn request(seq: i64, command: &str, arguments: Json) -> Json {
+    Json::object(vec![
+        ("seq", Json::from(seq)),
+        ("type", Json::from("request")),
+        ("command", Json::from(command)),
+        ("arguments", arguments),
+    ])
+}
+
+#[test]
+fn programs_stop_at_breakpoints() {
+    if !has_debugger() {
+        return;
+    }
+    let path = env::temp_dir().join(format!("haumea-{}-debug.hm", std::process::id()));
+    fs::write(&path, PROGRAM).unwrap();
+    let path = fs::canonicalize(&path).unwrap();
+    let mut adapter = Command::new(env!("CARGO_BIN_EXE_haumea"))
+        .arg("debug")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut input = adapter.stdin.take().unwrap();
+    // The messages are read on a thread, so that a session that goes wrong fails rather than hanging
+    let (sender, messages) = mpsc::channel();
+    let mut output = BufReader::new(adapter.stdout.take().unwrap());
+    thread::spawn(move || {
+        while let Ok(Some(message)) = read_message(&mut output) {
+            if sender.send(message).is_err() {
+                return;
+            }
+        }
+    });
+    let mut seq = 0;
+    let mut send = |command: &str, arguments: Json| {
+        seq += 1;
+        write_message(&mut input, &request(seq, command, arguments)).unwrap();
+        seq
+    };
+    // Returns the first message that matches, having checked that the responses before it succeeded
+    let until = |matches: &dyn Fn(&Json) -> bool| -> Json {
+        loop {
+            let message = messages.recv_timeout(TIMEOUT).expect("the debug adapter stopped answering");
+            if matches(&message) {
+                return message;
+            }
+            if message.get("type").and_then(Json::as_str) == Some("response") {
+                assert_eq!(message.get("success"), Some(&Json::from(true)), "{}", message);
+            }
+        }
+    };
+    let response = |seq: i64| move |message: &Json| message.get("request_seq").and_then(Json::as_i64) == Some(seq);
+    let event = |name: &'static str| move |message: &Json| message.get("event").and_then(Json::as_str) == Some(name);
+    let body = |message: &Json| message.get("body").cloned().unwrap_or(Json::Null);
+
+    let id = send("initialize", Json::object(vec![("adapterID", Json::from("haumea"))]));
+    until(&response(id));
+    let id = send("launch", Json::object(vec![("program", Json::from(&path.display().to_string()[..]))]));
+    let launched = until(&response(id));
+    assert_eq!(launched.get("success"), Some(&Json::from(true)), "{}", launched);
+    until(&event("initialized"));
+    let id = send("setBreakpoints", Json::object(vec![
+        ("source", Json::object(vec![("path", Json::from(&path.display().to_string()[..]))])),
+        ("breakpoints", Json::from(vec![Json::object(vec![("line", Json::from(2))])])),
+    ]));
+    let breakpoints = body(&until(&response(id)));
+    let breakpoint = &breakpoints.get("breakpoints").and_then(Json::as_array).unwrap()[0];
+    assert_eq!(breakpoint.get("verified"), Some(&Json::from(true)), "{}", breakpoint);
+    assert_eq!(breakpoint.get("line"), Some(&Json::from(2)));
+    send("configurationDone", Json::Null);
+    let stopped = body(&until(&event("stopped")));
+    assert_eq!(stopped.get("reason").and_then(Json::as_str), Some("breakpoint"));
+
+    let id = send("stackTrace", Json::object(vec![("threadId", Json::from(1))]));
+    let trace = body(&until(&response(id)));
+    let frames = trace.get("stackFrames").and_then(Json::as_array).unwrap();
+    let frame = |index: usize| {
+        (frames[index].get("name").and_then(Json::as_str).unwrap(), frames[index].get("line").and_then(Json::as_i64).unwrap())
+    };
+    assert_eq!(frame(0), ("square", 2));
+    assert_eq!(frame(frames.len() - 1), ("main", 7));
+    let id = send("variables", Json::object(vec![("variablesReference", Json::from(1))]));
+    let variables = body(&until(&response(id)));
+    let x = variables.get("variables").and_then(Json::as_array).unwrap().iter()
+        .find(|variable| variable.get("name").and_then(Json::as_str) == Some("x"))
+        .cloned()
+        .unwrap();
+    assert_eq!(x.get("value").and_then(Json::as_str), Some("4"));
+    let id = send("evaluate", Json::object(vec![("expression", Json::from("x * x")), ("frameId", Json::from(1))]));
+    assert_eq!(body(&until(&response(id))).get("result").and_then(Json::as_str), Some("16"));
+
+    send("continue", Json::object(vec![("threadId", Json::from(1))]));
+    let output = body(&until(&|message: &Json| {
+        event("output")(message) && message.get("body").and_then(|body| body.get("category")) == Some(&Json::from("stdout"))
+    }));
+    assert_eq!(output.get("output").and_then(Json::as_str), Some("16\n"));
+    let exited = body(&until(&event("exited")));
+    assert_eq!(exited.get("exitCode"), Some(&Json::from(0)));
+    until(&event("terminated"));
+    let id = send("disconnect", Json::Null);
+    until(&response(id));
+    assert!(adapter.wait().unwrap().success());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,119 @@
+//! Compiles every program in the corpus, the golden cases, and the examples twice,
+//! and checks that everything generated from them is byte-for-byte the same both times,
+//! and that compiling a large program on several threads, or with a cache, gives the same C as without
+extern crate haumea;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use haumea::cache::FunctionCache;
+use haumea::callgraph::CallGraph;
+use haumea::codegen;
+use haumea::doc;
+use haumea::dot;
+use haumea::options::CompilerOptions;
+use haumea::parser::{self, SourceFile};
+use haumea::pretty;
+use haumea::scanner::Scanner;
+use haumea::snapshot;
+use haumea::synthetic;
+
+/// Returns the programs to compile, with their names
+fn corpus() -> Vec<(String, String)> {
+    let mut paths: Vec<PathBuf> = vec![];
+    for dir in &["tests/corpus", "tests/cases", "examples"] {
+        paths.extend(fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension() == Some("hau".as_ref()) || path.extension() == Some("hm".as_ref())));
+    }
+    paths.sort();
+    let mut programs = paths.into_iter()
+        .map(|path| (path.display().to_string(), fs::read_to_string(&path).unwrap()))
+        .collect::<Vec<_>>();
+    programs.push(("generated.hm".to_string(), synthetic::program(300)));
+    programs
+}
+
+/// Returns everything that haumea generates from source, one thing after another
+fn generate(name: &str, source: &str) -> String {
+    let files = vec![SourceFile {
+        name: name.to_string(),
+        program: parser::parse(Scanner::new(source)).unwrap(),
+    }];
+    let mut out = String::new();
+    for safe in &[false, true] {
+        let mut options = CompilerOptions::new(name);
+        options.safe = *safe;
+        out.push_str(&snapshot::compile_source(name, source, &options).unwrap());
+        codegen::compile_library(&mut out, &files, true, &options);
+    }
+    codegen::compile_header(&mut out, &files, "HEADER_H");
+    codegen::compile_interface(&mut out, &files, "INTERFACE_H");
+    pretty::print_program(&mut out, &files[0].program);
+    out.push_str(&dot::program_to_dot(&files));
+    let graph = CallGraph::new(&files);
+    out.push_str(&graph.to_dot());
+    out.push_str(&graph.to_json().to_string());
+    out.push_str(&doc::markdown(&files));
+    out.push_str(&doc::html(&files));
+    out
+}
+
+#[test]
+fn compiling_twice_gives_the_same_output() {
+    let mut different = vec![];
+    for (name, source) in corpus() {
+        if generate(&name, &source) != generate(&name, &source) {
+            different.push(name);
+        }
+    }
+    assert!(different.is_empty(), "compiling these twice gave different output: {}", different.join(", "));
+}
+
+#[test]
+fn compiling_on_several_threads_gives_the_same_c() {
+    let source = synthetic::program(3000);
+    let compile = |threads| {
+        let mut options = CompilerOptions::new("generated.hm");
+        options.codegen_threads = Some(threads);
+        snapshot::compile_source("generated.hm", &source, &options).unwrap()
+    };
+    let c = compile(1);
+    for threads in &[2, 3, 8] {
+        assert!(compile(*threads) == c, "compiling on {} threads gave different C", threads);
+    }
+}
+
+#[test]
+fn compiling_with_a_cache_gives_the_same_c() {
+    let dir = env::temp_dir().join(format!("haumea-cache-test-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let compile = |source: &str, cache: Option<&mut FunctionCache>| {
+        let files = vec![SourceFile {
+            name: "generated.hm".to_string(),
+            program: parser::parse(Scanner::new(source)).unwrap(),
+        }];
+        let mut options = CompilerOptions::new("generated.hm");
+        options.codegen_threads = Some(4);
+        let mut c = String::new();
+        match cache {
+            Some(cache) => codegen::compile_files_cached(&mut c, &files, false, &options, cache),
+            None => codegen::compile_files(&mut c, &files, false, &options),
+        }
+        c
+    };
+    let source = synthetic::program(3000);
+    let mut cache = FunctionCache::open(&dir).unwrap();
+    assert!(compile(&source, Some(&mut cache)) == compile(&source, None), "compiling into the cache gave different C");
+    assert_eq!((cache.hits, cache.misses), (0, 3000));
+    let mut cache = FunctionCache::open(&dir).unwrap();
+    assert!(compile(&source, Some(&mut cache)) == compile(&source, None), "compiling from the cache gave different C");
+    assert_eq!((cache.hits, cache.misses), (3000, 0));
+    // Changing one function without moving the others only compiles it again
+    let changed = source.replace("return x * 7\n", "return x * 70\n");
+    let mut cache = FunctionCache::open(&dir).unwrap();
+    assert!(compile(&changed, Some(&mut cache)) == compile(&changed, None), "a changed function came from the cache");
+    assert_eq!((cache.hits, cache.misses), (2999, 1));
+    fs::remove_dir_all(&dir).unwrap();
+}
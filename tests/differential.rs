@@ -0,0 +1,48 @@
+//! Runs every program in the corpus and the examples with both the interpreter and the C backend,
+//! and checks that they agree, at every optimization level, with and without temporaries, and minified
+#![cfg(feature = "os")]
+extern crate haumea;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use haumea::differential;
+use haumea::options::{CompilerOptions, OptLevel};
+use haumea::toolchain;
+
+#[test]
+fn interpreter_and_c_backend_agree() {
+    let mut programs = vec![];
+    for dir in &["tests/corpus", "examples"] {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() == Some("hau".as_ref()) {
+                programs.push(path);
+            }
+        }
+    }
+    programs.sort();
+    let mut options = CompilerOptions::new(PathBuf::from("differential.hau"));
+    // The corpus can't be compiled without a C compiler, so then there is nothing to compare
+    if Command::new(toolchain::c_compiler(&options)).arg("--version").output().is_err() {
+        eprintln!("skipping the differential tests, as there is no C compiler");
+        return;
+    }
+    let mut failures = vec![];
+    for path in &programs {
+        let source = fs::read_to_string(path).unwrap();
+        for &level in &[OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            // Each mode is the name it is reported by, then whether it uses temporaries and minifies
+            for &(mode, temporaries, minify) in &[("", false, false), (" with temporaries", true, false),
+                                                   (" minified", false, true)] {
+                options.opt_level = level;
+                options.temporaries = temporaries;
+                options.minify = minify;
+                if let Err(difference) = differential::compare(&path.display().to_string(), &source, &options) {
+                    failures.push(format!("{:?}{}: {}", level, mode, difference));
+                }
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
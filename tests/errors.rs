@@ -0,0 +1,57 @@
+//! Checks the errors that haumea reports for each program in tests/errors
+//!
+//! The first line of each program is a comment like `# exit code: 4`, giving the code that
+//! haumea has to exit with, and what it writes to stderr has to match the .stderr file next
+//! to it. A second line like `# flags: --pedantic` gives haumea more flags for that program.
+//! Run with UPDATE_SNAPSHOTS=1 to write the .stderr files instead.
+extern crate haumea;
+
+use std::env;
+use std::fs;
+use std::process::Command;
+use haumea::snapshot::{self, UPDATE_VARIABLE};
+
+/// The comment that starts each program, followed by the exit code
+const EXIT_CODE_PREFIX: &str = "# exit code: ";
+
+/// The comment that can come after it, followed by the flags
+const FLAGS_PREFIX: &str = "# flags: ";
+
+#[test]
+fn errors_match_expectations() {
+    let update = env::var(UPDATE_VARIABLE).map(|value| !value.is_empty() && value != "0").unwrap_or(false);
+    let mut programs = fs::read_dir("tests/errors").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("hm".as_ref()))
+        .collect::<Vec<_>>();
+    programs.sort();
+    assert!(!programs.is_empty(), "there are no programs in tests/errors");
+    let mut failures = vec![];
+    for program in &programs {
+        let name = program.display().to_string();
+        let source = fs::read_to_string(program).unwrap();
+        let expected_code = match source.lines().next().and_then(|line| line.strip_prefix(EXIT_CODE_PREFIX)) {
+            Some(code) => code.trim().parse::<i32>().unwrap(),
+            None => {
+                failures.push(format!("{} doesn't start with a {:?} comment", name, EXIT_CODE_PREFIX));
+                continue;
+            },
+        };
+        let flags = source.lines().nth(1).and_then(|line| line.strip_prefix(FLAGS_PREFIX)).unwrap_or("");
+        let output = Command::new(env!("CARGO_BIN_EXE_haumea"))
+            .args(["build", "--check", "--quiet"])
+            .args(flags.split_whitespace())
+            .arg(&name)
+            .output()
+            .unwrap();
+        let code = output.status.code();
+        if code != Some(expected_code) {
+            failures.push(format!("{}: haumea exited with {:?} rather than {}", name, code, expected_code));
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if let Err(error) = snapshot::compare_snapshot(&program.with_extension("stderr"), &stderr, update) {
+            failures.push(error);
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
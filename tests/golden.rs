@@ -0,0 +1,43 @@
+//! Compiles every program in tests/cases and checks the C against the .c snapshot next to it
+//!
+//! Run with UPDATE_SNAPSHOTS=1 to write the snapshots instead
+extern crate haumea;
+
+use std::env;
+use std::fs;
+use haumea::options::{CompilerOptions, OptLevel};
+use haumea::snapshot::{self, UPDATE_VARIABLE};
+
+#[test]
+fn generated_c_matches_snapshots() {
+    let update = env::var(UPDATE_VARIABLE).map(|value| !value.is_empty() && value != "0").unwrap_or(false);
+    let mut cases = fs::read_dir("tests/cases").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("hm".as_ref()))
+        .collect::<Vec<_>>();
+    cases.sort();
+    assert!(!cases.is_empty(), "there are no cases in tests/cases");
+    let mut failures = vec![];
+    for case in &cases {
+        let name = case.display().to_string();
+        let source = fs::read_to_string(case).unwrap();
+        let result = snapshot::compile_source(&name, &source, &CompilerOptions::new(case.clone()))
+            .and_then(|c| snapshot::compare_snapshot(&case.with_extension("c"), &c, update));
+        if let Err(error) = result {
+            failures.push(error);
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn cases_are_worked_out_without_the_optimizer() {
+    let mut options = CompilerOptions::new("cases.hm");
+    options.opt_level = OptLevel::O0;
+    let source = "to name with (n) do\n    if n = 2 * 2 then return 1\n    else if n = 0 - 1 then return 2\n    \
+                  else if 3 = n then return 3\n    return 0\nend\n";
+    let c = snapshot::compile_source("cases.hm", source, &options).unwrap();
+    for case in &["case 4l:", "case -1l:", "case 3l:"] {
+        assert!(c.contains(case), "{} isn't in\n{}", case, c);
+    }
+}
@@ -0,0 +1,262 @@
+//! Checks the language server by running scripted sessions with it, as an editor would
+extern crate haumea;
+
+use haumea::json::Json;
+use haumea::lsp::{read_message, serve, write_message};
+
+/// The document that the sessions open
+const URI: &str = "file:///main.hm";
+
+/// The source of the document, whose lines and columns the positions in the tests count from 0
+const SOURCE: &str = "\
+# Halves n
+to half with (n) do
+    return n / 2
+end
+
+external function strlen with (text s)
+
+to main do
+    variable x
+    set x to half(4)
+    display(x + strlen(\"hi\"))
+end
+";
+
+/// Returns a request with the id and params
+fn request(id: i64, method: &str, params: Json) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::from("2.0")),
+        ("id", Json::from(id)),
+        ("method", Json::from(method)),
+        ("params", params),
+    ])
+}
+
+/// Returns a notification with the params
+fn notification(method: &str, params: Json) -> Json {
+    Json::object(vec![("jsonrpc", Json::from("2.0")), ("method", Json::from(method)), ("params", params)])
+}
+
+/// Returns the params of a request about the position in the document
+fn at(line: i64, character: i64) -> Json {
+    Json::object(vec![
+        ("textDocument", Json::object(vec![("uri", Json::from(URI))])),
+        ("position", Json::object(vec![("line", Json::from(line)), ("character", Json::from(character))])),
+    ])
+}
+
+/// Returns the didOpen notification that opens the document with text
+fn open(text: &str) -> Json {
+    notification("textDocument/didOpen", Json::object(vec![
+        ("textDocument", Json::object(vec![
+            ("uri", Json::from(URI)),
+            ("languageId", Json::from("haumea")),
+            ("version", Json::from(1)),
+            ("text", Json::from(text)),
+        ])),
+    ]))
+}
+
+/// Runs a session of the messages, between an initialize and a shutdown and exit,
+/// returning every message that the server sent in reply to them
+fn session(messages: Vec<Json>) -> Vec<Json> {
+    let mut script = vec![request(0, "initialize", Json::object(vec![]))];
+    script.extend(messages);
+    script.push(request(-1, "shutdown", Json::Null));
+    script.push(notification("exit", Json::Null));
+    let mut input = vec![];
+    for message in &script {
+        write_message(&mut input, message).unwrap();
+    }
+    let mut output = vec![];
+    serve(&input[..], &mut output).unwrap();
+    let mut replies = vec![];
+    let mut output = &output[..];
+    while let Some(reply) = read_message(&mut output).unwrap() {
+        replies.push(reply);
+    }
+    // The first reply is to initialize and the last is to shutdown
+    assert_eq!(replies.pop().unwrap(), Json::object(vec![("jsonrpc", Json::from("2.0")), ("id", Json::from(-1)),
+                                                         ("result", Json::Null)]));
+    replies.remove(0);
+    replies
+}
+
+/// Returns the result of the reply to the request with id
+fn result(replies: &[Json], id: i64) -> &Json {
+    let reply = replies.iter().find(|reply| reply.get("id").and_then(Json::as_i64) == Some(id)).unwrap();
+    reply.get("result").unwrap()
+}
+
+/// Returns the start of range as a line and a character
+fn start(range: &Json) -> (i64, i64) {
+    let start = range.get("start").unwrap();
+    (start.get("line").and_then(Json::as_i64).unwrap(), start.get("character").and_then(Json::as_i64).unwrap())
+}
+
+#[test]
+fn messages_are_framed_by_their_length() {
+    let message = Json::object(vec![("id", Json::from(1)), ("text", Json::from("é"))]);
+    let mut framed = vec![];
+    write_message(&mut framed, &message).unwrap();
+    // The length counts bytes, not characters
+    assert_eq!(String::from_utf8(framed.clone()).unwrap(), "Content-Length: 20\r\n\r\n{\"id\":1,\"text\":\"é\"}");
+    framed.extend(b"Content-Type: application/vscode-jsonrpc; charset=utf-8\r\nContent-Length: 2\r\n\r\n{}");
+    let mut input = &framed[..];
+    assert_eq!(read_message(&mut input).unwrap(), Some(message));
+    assert_eq!(read_message(&mut input).unwrap(), Some(Json::object(vec![])));
+    assert_eq!(read_message(&mut input).unwrap(), None);
+    assert!(read_message(&mut &b"Content-Type: text\r\n\r\n{}"[..]).is_err());
+}
+
+#[test]
+fn the_server_has_to_be_shut_down_before_it_exits() {
+    let mut input = vec![];
+    write_message(&mut input, &notification("exit", Json::Null)).unwrap();
+    assert!(serve(&input[..], &mut vec![]).is_err());
+    // Nor can the client just go away
+    assert!(serve(&b""[..], &mut vec![]).is_err());
+}
+
+#[test]
+fn initialize_says_what_the_server_can_do() {
+    let mut input = vec![];
+    for message in &[request(1, "initialize", Json::object(vec![])), request(2, "shutdown", Json::Null),
+                     notification("exit", Json::Null)] {
+        write_message(&mut input, message).unwrap();
+    }
+    let mut output = vec![];
+    serve(&input[..], &mut output).unwrap();
+    let mut output = &output[..];
+    let replies = [read_message(&mut output).unwrap().unwrap()];
+    let capabilities = result(&replies, 1).get("capabilities").unwrap();
+    for capability in &["definitionProvider", "hoverProvider", "documentSymbolProvider"] {
+        assert_eq!(capabilities.get(capability), Some(&Json::from(true)), "{}", capability);
+    }
+    assert_eq!(capabilities.get("textDocumentSync"), Some(&Json::from(1)));
+    // The kinds and modifiers of semantic tokens are indexes into the legend
+    let legend = capabilities.get("semanticTokensProvider").and_then(|provider| provider.get("legend")).unwrap();
+    let types = legend.get("tokenTypes").and_then(Json::as_array).unwrap();
+    assert_eq!((types[0].as_str(), types[1].as_str(), types[7].as_str()), (Some("keyword"), Some("function"), Some("number")));
+    let modifiers = legend.get("tokenModifiers").and_then(Json::as_array).unwrap();
+    assert_eq!(modifiers, &[Json::from("declaration"), Json::from("defaultLibrary")][..]);
+    assert!(read_message(&mut output).unwrap().is_some());
+    assert_eq!(read_message(&mut output).unwrap(), None);
+}
+
+#[test]
+fn opened_documents_get_diagnostics() {
+    let change = notification("textDocument/didChange", Json::object(vec![
+        ("textDocument", Json::object(vec![("uri", Json::from(URI)), ("version", Json::from(2))])),
+        ("contentChanges", Json::from(vec![Json::object(vec![("text", Json::from("to main do display(y) end\n"))])])),
+    ]));
+    let close = notification("textDocument/didClose", Json::object(vec![
+        ("textDocument", Json::object(vec![("uri", Json::from(URI))])),
+    ]));
+    let replies = session(vec![open(SOURCE), change, close]);
+    assert_eq!(replies.len(), 3);
+    let diagnostics = replies.iter().map(|reply| {
+        assert_eq!(reply.get("method").and_then(Json::as_str), Some("textDocument/publishDiagnostics"));
+        let params = reply.get("params").unwrap();
+        assert_eq!(params.get("uri").and_then(Json::as_str), Some(URI));
+        params.get("diagnostics").and_then(Json::as_array).unwrap()
+    }).collect::<Vec<_>>();
+    assert!(diagnostics[0].is_empty());
+    assert_eq!(diagnostics[1].len(), 1);
+    let diagnostic = &diagnostics[1][0];
+    assert_eq!(diagnostic.get("severity"), Some(&Json::from(1)));
+    assert!(diagnostic.get("message").and_then(Json::as_str).unwrap().contains('y'));
+    assert_eq!(start(diagnostic.get("range").unwrap()), (0, 11));
+    assert!(diagnostics[2].is_empty());
+}
+
+#[test]
+fn definitions_are_found() {
+    let replies = session(vec![
+        open(SOURCE),
+        // half in the call to it, x in display, n in the body of half, and s in strlen's signature
+        request(1, "textDocument/definition", at(9, 14)),
+        request(2, "textDocument/definition", at(10, 12)),
+        request(3, "textDocument/definition", at(2, 11)),
+        request(4, "textDocument/definition", at(5, 36)),
+        request(5, "textDocument/definition", at(9, 10)),
+    ]);
+    for &(id, line, character) in &[(1, 1, 3), (2, 8, 13), (3, 1, 14), (4, 5, 36)] {
+        let location = result(&replies, id);
+        assert_eq!(location.get("uri").and_then(Json::as_str), Some(URI), "{}", id);
+        assert_eq!(start(location.get("range").unwrap()), (line, character), "{}", id);
+    }
+    // to isn't a name
+    assert_eq!(result(&replies, 5), &Json::Null);
+}
+
+#[test]
+fn parameters_after_a_function_parameter_are_found() {
+    let replies = session(vec![
+        open("external function each with (function visit with (n), count)\n"),
+        request(1, "textDocument/definition", at(0, 54)),
+    ]);
+    assert_eq!(start(result(&replies, 1).get("range").unwrap()), (0, 54));
+}
+
+#[test]
+fn hovers_show_signatures_and_documentation() {
+    let replies = session(vec![
+        open(SOURCE),
+        request(1, "textDocument/hover", at(9, 14)),
+        request(2, "textDocument/hover", at(10, 4)),
+        request(3, "textDocument/hover", at(4, 0)),
+    ]);
+    let contents = |id| result(&replies, id).get("contents").and_then(|contents| contents.get("value"))
+        .and_then(Json::as_str).unwrap().to_string();
+    assert_eq!(contents(1), "```haumea\nto half with (n)\n```\nHalves n\n");
+    assert_eq!(contents(2), "```haumea\nto display with (n1)\n```\nA builtin function");
+    assert_eq!(start(result(&replies, 1).get("range").unwrap()), (9, 13));
+    assert_eq!(result(&replies, 3), &Json::Null);
+}
+
+#[test]
+fn document_symbols_are_the_functions() {
+    let replies = session(vec![
+        open(SOURCE),
+        request(1, "textDocument/documentSymbol", Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", Json::from(URI))])),
+        ])),
+        request(2, "textDocument/documentSymbol", Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", Json::from("file:///closed.hm"))])),
+        ])),
+    ]);
+    let symbols = result(&replies, 1).as_array().unwrap();
+    let names = symbols.iter().map(|symbol| symbol.get("name").and_then(Json::as_str).unwrap()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["half", "strlen", "main"]);
+    assert_eq!(symbols[0].get("detail").and_then(Json::as_str), Some("with (n)"));
+    assert_eq!(symbols[0].get("kind"), Some(&Json::from(12)));
+    assert_eq!(start(symbols[1].get("selectionRange").unwrap()), (5, 18));
+    assert_eq!(start(symbols[2].get("range").unwrap()), (7, 0));
+    // A document that isn't open is an error, rather than having no symbols
+    let reply = replies.iter().find(|reply| reply.get("id") == Some(&Json::from(2))).unwrap();
+    assert_eq!(reply.get("error").and_then(|error| error.get("code")), Some(&Json::from(-32602)));
+}
+
+#[test]
+fn semantic_tokens_are_relative_to_the_one_before() {
+    let replies = session(vec![
+        open("to main do\n    display(42)\nend\n"),
+        request(1, "textDocument/semanticTokens/full", Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", Json::from(URI))])),
+        ])),
+    ]);
+    let data = result(&replies, 1).get("data").and_then(Json::as_array).unwrap();
+    let data = data.iter().map(|number| number.as_i64().unwrap()).collect::<Vec<_>>();
+    // Each token is its line and column after the one before, its length, its kind, and its modifiers.
+    // The column only counts from the token before on the same line, and from the start of the line after it.
+    assert_eq!(data, vec![
+        0, 0, 2, 0, 0, // to
+        0, 3, 4, 1, 1, // main, which is declared
+        0, 5, 2, 0, 0, // do
+        1, 4, 7, 1, 2, // display, which is a builtin
+        0, 8, 2, 7, 0, // 42
+        1, 0, 3, 0, 0, // end
+    ]);
+}
@@ -0,0 +1,93 @@
+//! Checks that haumea::mi reads the records that gdb writes, including ones it can't make sense of
+extern crate haumea;
+
+use haumea::json::Json;
+use haumea::mi::{self, Record, field, number_field};
+
+/// Returns the results of line, which has to be a result record
+fn results(line: &str) -> Json {
+    match Record::parse(line) {
+        Record::Result { results, .. } => results,
+        record => panic!("{} is {:?} rather than a result", line, record),
+    }
+}
+
+#[test]
+fn results_have_tokens_and_classes() {
+    assert_eq!(Record::parse("12^running"), Record::Result {
+        token: Some(12),
+        class: "running".to_string(),
+        results: Json::object(vec![]),
+    });
+    assert_eq!(Record::parse("^error,msg=\"No symbol \\\"y\\\" in current context.\""), Record::Result {
+        token: None,
+        class: "error".to_string(),
+        results: Json::object(vec![("msg", Json::from("No symbol \"y\" in current context."))]),
+    });
+}
+
+#[test]
+fn tuples_are_objects_and_lists_are_arrays() {
+    let stack = results("4^done,stack=[frame={level=\"0\",func=\"square\",line=\"2\"},\
+                         frame={level=\"1\",func=\"main\",line=\"7\"}]");
+    let frames = stack.get("stack").and_then(Json::as_array).unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(field(&frames[1], &["func"]), Some("main"));
+    assert_eq!(number_field(&frames[0], &["line"]), Some(2));
+    // Lists can have values without names too, and be empty
+    let variables = results("5^done,variables=[{name=\"x\",arg=\"1\",value=\"4\"}],groups=[\"i1\",\"i2\"],empty=[],none={}");
+    let variable = &variables.get("variables").and_then(Json::as_array).unwrap()[0];
+    assert_eq!(field(variable, &["value"]), Some("4"));
+    assert_eq!(variables.get("groups"), Some(&Json::from(vec![Json::from("i1"), Json::from("i2")])));
+    assert_eq!(variables.get("empty"), Some(&Json::from(vec![])));
+    assert_eq!(variables.get("none"), Some(&Json::object(vec![])));
+}
+
+#[test]
+fn strings_have_c_escapes() {
+    // Bytes that aren't ASCII are written as octal escapes, here the two bytes of é
+    let value = results("6^done,value=\"0x5555 \\\"caf\\303\\251\\\"\\t\\\\\"");
+    assert_eq!(field(&value, &["value"]), Some("0x5555 \"café\"\t\\"));
+    assert_eq!(Record::parse("&\"warning: \\101\\n\""), Record::Stream('&', "warning: A\n".to_string()));
+}
+
+#[test]
+fn exec_and_notify_records_are_told_apart() {
+    match Record::parse("*stopped,reason=\"exited\",exit-code=\"013\"") {
+        Record::Exec { class, results } => {
+            assert_eq!(class, "stopped");
+            assert_eq!(field(&results, &["exit-code"]), Some("013"));
+        },
+        record => panic!("{:?} isn't an exec record", record),
+    }
+    assert_eq!(Record::parse("=thread-group-added,id=\"i1\""), Record::Notify);
+    assert_eq!(Record::parse("+download,section=\".text\""), Record::Notify);
+    assert_eq!(Record::parse("(gdb)"), Record::Prompt);
+}
+
+#[test]
+fn lines_that_arent_records_are_from_the_program() {
+    for line in &["", "42", "~not a stream", "^", "*Stopped", "3~\"a token on a stream\"", "héllo"] {
+        assert_eq!(Record::parse(line), Record::Program(line.to_string()), "{:?}", line);
+    }
+}
+
+#[test]
+fn bad_records_are_read_as_far_as_they_can_be() {
+    // None of these are valid, but reading them mustn't panic or hang
+    for line in &["1^done,", "1^done,a", "1^done,a=", "1^done,a={b=\"1\"", "1^done,a=[{", "1^done,a=\"unterminated\\",
+                  "1^done,=,=,{}]", "~\"\\", "*stopped,x=[\"a\",b=\"c\",]"] {
+        Record::parse(line);
+    }
+    assert_eq!(field(&results("1^done,a={b=\"1\""), &["a", "b"]), Some("1"));
+}
+
+#[test]
+fn quoted_arguments_are_c_strings() {
+    assert_eq!(mi::quote("x * x"), "\"x * x\"");
+    assert_eq!(mi::quote("/path with spaces/main.hm:12"), "\"/path with spaces/main.hm:12\"");
+    assert_eq!(mi::quote("a \"quote\" and a \\"), "\"a \\\"quote\\\" and a \\\\\"");
+    // A quoted string reads back as what was quoted
+    let text = "say \"hi\" \\ bye";
+    assert_eq!(field(&results(&format!("1^done,value={}", mi::quote(text))), &["value"]), Some(text));
+}
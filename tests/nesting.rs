@@ -0,0 +1,56 @@
+//! Compiles expressions nested far deeper than the parser allows, as a program that builds
+//! its AST by hand can, and checks that the C comes out without overflowing the stack
+extern crate haumea;
+
+use std::mem;
+use haumea::codegen;
+use haumea::options::CompilerOptions;
+use haumea::parser::{self, Expression, Operator, SourceFile, StatementKind};
+use haumea::scanner::Scanner;
+
+const DEPTH: usize = 100_000;
+
+/// Returns 1 + (1 + (1 + ...)), nested depth deep
+fn nested_sum(depth: usize) -> Expression {
+    let mut expr = Expression::Integer(1);
+    for _ in 0..depth {
+        expr = Expression::BinaryOp {
+            operator: Operator::Add,
+            left: Box::new(Expression::Integer(1)),
+            right: Box::new(expr),
+        };
+    }
+    expr
+}
+
+/// Takes expr apart one level at a time, as dropping it would recurse as deep as it is nested
+fn dismantle(mut expr: Expression) {
+    while let Expression::BinaryOp { right, .. } = expr {
+        expr = *right;
+    }
+}
+
+/// Returns the expression that the first statement of the first function of program returns
+fn returned(program: &mut parser::Program) -> &mut Expression {
+    let body = program[0].code.as_mut().unwrap();
+    let first = match body.kind {
+        StatementKind::Do(ref mut block) => &mut block[0],
+        ref kind => panic!("main isn't a do block: {:?}", kind),
+    };
+    match first.kind {
+        StatementKind::Return(ref mut expr) => expr,
+        ref kind => panic!("main doesn't start with a return: {:?}", kind),
+    }
+}
+
+#[test]
+fn deeply_nested_expressions_compile() {
+    let mut program = parser::parse(Scanner::new("to main do return 0 end")).unwrap();
+    *returned(&mut program) = nested_sum(DEPTH);
+    let mut files = [SourceFile { name: "main.hm".to_string(), program }];
+    let mut c = String::new();
+    codegen::compile_files(&mut c, &files, false, &CompilerOptions::new("main.hm"));
+    let expected = format!("return {}1l{};", "(1l + ".repeat(DEPTH), ")".repeat(DEPTH));
+    assert!(c.contains(&expected));
+    dismantle(mem::replace(returned(&mut files[0].program), Expression::Integer(0)));
+}
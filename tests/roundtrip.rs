@@ -0,0 +1,391 @@
+//! Checks that pretty-printing a program and parsing what was printed gives back the same program,
+//! for lots of programs made up at random
+extern crate haumea;
+
+use std::env;
+use haumea::parser::{self, Attribute, Comments, Expression, Function, Operator, Program, Statement, StatementKind};
+use haumea::pretty;
+use haumea::scanner::{Scanner, Span};
+
+/// How many programs are made up, unless $ROUNDTRIP_PROGRAMS says how many
+const PROGRAMS: usize = 5000;
+
+/// How deeply statements and expressions are nested in the programs
+const MAX_DEPTH: usize = 5;
+
+/// The names of functions and variables, which include ones that are nearly keywords
+const NAMES: &[&str] = &["x", "total", "n", "display", "c", "text", "a_b", "Count", "ends", "todo"];
+
+/// The names of attributes, which the parser takes whether or not the checker knows them
+const ATTRIBUTES: &[&str] = &["inline", "test", "export", "deprecated", "to_do"];
+
+/// The binary operators that the parser understands
+const BINARY_OPERATORS: &[fn() -> Operator] = &[
+    || Operator::Add, || Operator::Sub, || Operator::Mul, || Operator::Div,
+    || Operator::Equals, || Operator::Gt, || Operator::Lt, || Operator::Gte, || Operator::Lte,
+    || Operator::LogicalAnd, || Operator::LogicalOr,
+];
+
+/// The chars of texts, including the ones that have to be escaped
+const TEXT_CHARS: &[char] = &['a', ' ', '"', '\\', '\n', '\t', '#', '{', 'é'];
+
+/// Makes up parts of programs from a seed, so that a failure happens again with the same seed
+struct Generator {
+    state: u64,
+}
+
+impl Generator {
+    /// Returns a number from 0 up to but not including n
+    fn below(&mut self, n: usize) -> usize {
+        // A linear congruential generator, keeping the better high bits
+        self.state = self.state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((self.state >> 33) as usize) % n
+    }
+
+    fn chance(&mut self, one_in: usize) -> bool {
+        self.below(one_in) == 0
+    }
+
+    fn name(&mut self) -> String {
+        NAMES[self.below(NAMES.len())].to_string()
+    }
+
+    /// Returns up to most names, all different, like the parameters of a function
+    fn names(&mut self, most: usize) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
+        for _ in 0..self.below(most + 1) {
+            let name = self.name();
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    fn text(&mut self) -> String {
+        (0..self.below(6)).map(|_| TEXT_CHARS[self.below(TEXT_CHARS.len())]).collect()
+    }
+
+    /// Returns the text of a comment, without its #
+    ///
+    /// The formatter doesn't leave spaces at the ends of lines, so comments don't end with them.
+    /// A comment that starts with # would be a documentation comment.
+    fn comment(&mut self) -> String {
+        self.text().replace('\n', "").trim_end().trim_start_matches('#').to_string()
+    }
+
+    fn comments(&mut self) -> Comments {
+        Comments {
+            leading: (0..self.below(3)).map(|_| self.comment()).collect(),
+            trailing: if self.chance(3) { Some(self.comment()) } else { None },
+            closing: vec![],
+        }
+    }
+
+    fn program(&mut self) -> Program {
+        (0..self.below(3) + 1).map(|_| self.function()).collect()
+    }
+
+    fn function(&mut self) -> Function {
+        let external = self.chance(4);
+        let signature = if self.chance(3) { None } else { Some(self.names(3)) };
+        let parameters = signature.clone().unwrap_or_default();
+        // Only external functions have parameters that are texts or functions
+        let mut text_parameters = vec![];
+        let mut callback_parameters = vec![];
+        if external {
+            for parameter in parameters {
+                match self.below(3) {
+                    0 => text_parameters.push(parameter),
+                    1 => {
+                        let callback_signature = self.names(2);
+                        callback_parameters.push((parameter, callback_signature));
+                    },
+                    _ => (),
+                }
+            }
+        }
+        Function {
+            name: self.name(),
+            signature,
+            code: if external { None } else { Some(self.statement(0)) },
+            span: Span { line: 1, column: 1 },
+            comments: Comments {
+                leading: (0..self.below(3)).map(|_| self.comment()).collect(),
+                ..Comments::default()
+            },
+            // The formatter doesn't leave spaces at the ends of lines
+            doc: (0..self.below(3)).map(|_| self.text().replace('\n', "").trim_end().to_string()).collect(),
+            includes: (0..self.below(2)).map(|_| "stdio.h".to_string()).collect(),
+            exported: !external && self.chance(4),
+            text_parameters,
+            returns_text: external && self.chance(2),
+            callback_parameters,
+            attributes: (0..self.below(3)).map(|_| Attribute {
+                name: ATTRIBUTES[self.below(ATTRIBUTES.len())].to_string(),
+                argument: if self.chance(2) { Some(self.text()) } else { None },
+                span: Span { line: 1, column: 1 },
+            }).collect(),
+        }
+    }
+
+    fn statement(&mut self, depth: usize) -> Statement {
+        let choices = if depth == MAX_DEPTH { 5 } else { 13 };
+        let kind = match self.below(choices) {
+            0 => StatementKind::Return(self.expression(depth + 1)),
+            1 => StatementKind::Var((0..1 + self.below(3)).map(|_| self.name()).collect()),
+            2 => StatementKind::Set(self.name(), self.expression(depth + 1)),
+            3 => StatementKind::Change(self.name(), self.expression(depth + 1)),
+            4 => StatementKind::Call {
+                function: self.name(),
+                arguments: (0..self.below(3)).map(|_| self.expression(depth + 1)).collect(),
+            },
+            5 => StatementKind::InlineC([" putchar(x); ", "{ int y; } ", ""][self.below(3)].to_string()),
+            6 => StatementKind::Do((0..self.below(4)).map(|_| self.statement(depth + 1)).collect()),
+            7 => StatementKind::Repeat {
+                count: self.expression(depth + 1),
+                label: if self.chance(2) { Some(self.name()) } else { None },
+                body: Box::new(self.statement(depth + 1)),
+            },
+            8 => StatementKind::Break(self.name()),
+            9 => StatementKind::Define(self.name(), self.expression(depth + 1)),
+            10 => StatementKind::Swap(self.name(), self.name()),
+            11 => StatementKind::Persistent(self.name()),
+            _ => {
+                let else_clause = if self.chance(2) { Some(self.statement(depth + 1)) } else { None };
+                let mut if_clause = self.statement(depth + 1);
+                // An else after an if without one belongs to the inner if, so there is
+                // no way to write an if without an else in the then of an if with an else
+                if else_clause.is_some() && is_open_if(&if_clause) {
+                    if_clause = statement(StatementKind::Do(vec![if_clause]));
+                }
+                StatementKind::If {
+                    cond: self.expression(depth + 1),
+                    if_clause: Box::new(if_clause),
+                    else_clause: else_clause.map(Box::new),
+                    attributes: (0..self.below(3)).map(|_| Attribute {
+                        name: ["likely", "unlikely"][self.below(2)].to_string(),
+                        argument: if self.chance(2) { Some(self.text()) } else { None },
+                        span: Span { line: 1, column: 1 },
+                    }).collect(),
+                }
+            },
+        };
+        let mut comments = self.comments();
+        match kind {
+            StatementKind::Do(_) => comments.closing = (0..self.below(3)).map(|_| self.comment()).collect(),
+            // An if or repeat ends with a statement of its own, which gets the comment at the end of the line
+            StatementKind::If { .. } | StatementKind::Repeat { .. } => comments.trailing = None,
+            _ => (),
+        }
+        Statement {
+            kind,
+            span: Span { line: 1, column: 1 },
+            comments,
+        }
+    }
+
+    fn expression(&mut self, depth: usize) -> Expression {
+        let choices = if depth == MAX_DEPTH { 3 } else { 6 };
+        match self.below(choices) {
+            // The parser only makes integers that aren't negative
+            0 => Expression::Integer([0, 7, i32::MAX][self.below(3)]),
+            1 => Expression::Text(self.text()),
+            2 => Expression::Ident(self.name()),
+            3 => Expression::BinaryOp {
+                operator: BINARY_OPERATORS[self.below(BINARY_OPERATORS.len())](),
+                left: Box::new(self.expression(depth + 1)),
+                right: Box::new(self.expression(depth + 1)),
+            },
+            // The only unary operator that the parser knows is -, which it makes a Sub
+            4 => Expression::UnaryOp {
+                operator: Operator::Sub,
+                expression: Box::new(self.expression(depth + 1)),
+            },
+            _ => Expression::Call {
+                function: self.name(),
+                arguments: (0..self.below(3)).map(|_| self.expression(depth + 1)).collect(),
+            },
+        }
+    }
+}
+
+/// Returns a statement of kind with no comments
+fn statement(kind: StatementKind) -> Statement {
+    Statement {
+        kind,
+        span: Span { line: 1, column: 1 },
+        comments: Comments::default(),
+    }
+}
+
+/// Returns true if statement is an if without an else, or ends with one
+fn is_open_if(statement: &Statement) -> bool {
+    match statement.kind {
+        StatementKind::If { ref else_clause, .. } => match *else_clause {
+            Some(ref else_clause) => is_open_if(else_clause),
+            None => true,
+        },
+        StatementKind::Repeat { ref body, .. } => is_open_if(body),
+        _ => false,
+    }
+}
+
+/// Returns why printing program and parsing it doesn't give it back, or None if it does
+fn roundtrip_failure(program: &Program) -> Option<String> {
+    let mut source = String::new();
+    pretty::print_program(&mut source, program);
+    match parser::parse(Scanner::new(&source)) {
+        Err(e) => Some(format!("the printed program doesn't parse: {}\n{}", e, source)),
+        // Programs are equal whatever their spans are, which printing changes
+        Ok(ref parsed) if parsed != program => {
+            Some(format!("the printed program parses differently:\n{}\nas\n{:#?}", source, parsed))
+        },
+        Ok(_) => None,
+    }
+}
+
+/// Returns programs a step smaller than program, each missing a part of it or with a part made simpler
+fn smaller_programs(program: &Program) -> Vec<Program> {
+    let mut smaller = vec![];
+    for (index, func) in program.iter().enumerate() {
+        if program.len() > 1 {
+            let mut without = program.clone();
+            without.remove(index);
+            smaller.push(without);
+        }
+        for func in smaller_functions(func) {
+            let mut with = program.clone();
+            with[index] = func;
+            smaller.push(with);
+        }
+    }
+    smaller
+}
+
+fn smaller_functions(func: &Function) -> Vec<Function> {
+    let mut smaller = vec![];
+    let with = |change: &dyn Fn(&mut Function)| {
+        let mut func = func.clone();
+        change(&mut func);
+        func
+    };
+    if !func.comments.leading.is_empty() || !func.doc.is_empty() || !func.attributes.is_empty() || !func.includes.is_empty() {
+        smaller.push(with(&|func| {
+            func.comments.leading.clear();
+            func.doc.clear();
+            func.attributes.clear();
+            func.includes.clear();
+        }));
+    }
+    if let Some(ref code) = func.code {
+        for code in smaller_statements(code) {
+            smaller.push(with(&|func| func.code = Some(code.clone())));
+        }
+    }
+    smaller
+}
+
+fn smaller_statements(statement: &Statement) -> Vec<Statement> {
+    let mut smaller = vec![];
+    let with = |kind: StatementKind| Statement { kind, ..statement.clone() };
+    if statement.comments != Comments::default() {
+        smaller.push(Statement { comments: Comments::default(), ..statement.clone() });
+    }
+    match statement.kind {
+        StatementKind::Return(ref value) => {
+            smaller.extend(smaller_expressions(value).into_iter().map(|value| with(StatementKind::Return(value))));
+        },
+        StatementKind::Set(ref name, ref value) => smaller.extend(smaller_expressions(value).into_iter()
+            .map(|value| with(StatementKind::Set(name.clone(), value)))),
+        StatementKind::Do(ref block) => for (index, inner) in block.iter().enumerate() {
+            smaller.push(inner.clone());
+            let mut without = block.clone();
+            without.remove(index);
+            smaller.push(with(StatementKind::Do(without)));
+            for inner in smaller_statements(inner) {
+                let mut with_inner = block.clone();
+                with_inner[index] = inner;
+                smaller.push(with(StatementKind::Do(with_inner)));
+            }
+        },
+        StatementKind::If { ref cond, ref if_clause, ref else_clause, ref attributes } => {
+            smaller.push((**if_clause).clone());
+            let if_with = |cond: &Expression, if_clause: &Statement, else_clause: Option<&Statement>| with(StatementKind::If {
+                cond: cond.clone(),
+                if_clause: Box::new(if_clause.clone()),
+                else_clause: else_clause.cloned().map(Box::new),
+                attributes: attributes.clone(),
+            });
+            let else_ref = else_clause.as_ref().map(|else_clause| &**else_clause);
+            if let Some(else_clause) = else_ref {
+                smaller.push(else_clause.clone());
+                smaller.push(if_with(cond, if_clause, None));
+                for else_clause in smaller_statements(else_clause) {
+                    smaller.push(if_with(cond, if_clause, Some(&else_clause)));
+                }
+            }
+            for cond in smaller_expressions(cond) {
+                smaller.push(if_with(&cond, if_clause, else_ref));
+            }
+            for if_clause in smaller_statements(if_clause) {
+                smaller.push(if_with(cond, &if_clause, else_ref));
+            }
+        },
+        StatementKind::Repeat { ref count, ref label, ref body } => {
+            smaller.push((**body).clone());
+            for body in smaller_statements(body) {
+                smaller.push(with(StatementKind::Repeat { count: count.clone(), label: label.clone(), body: Box::new(body) }));
+            }
+        },
+        _ => (),
+    }
+    // Only keep the statements that the generator could have made
+    smaller.retain(can_be_printed);
+    smaller
+}
+
+/// Returns true if statement can be printed in a way that parses back to it, which rules
+/// out an if without an else in the then of an if with one, and comments after an if or a repeat
+fn can_be_printed(statement: &Statement) -> bool {
+    match statement.kind {
+        StatementKind::If { ref if_clause, ref else_clause, .. } => {
+            statement.comments.trailing.is_none() && can_be_printed(if_clause) &&
+                else_clause.as_ref().is_none_or(|else_clause| !is_open_if(if_clause) && can_be_printed(else_clause))
+        },
+        StatementKind::Repeat { ref body, .. } => statement.comments.trailing.is_none() && can_be_printed(body),
+        StatementKind::Do(ref block) => block.iter().all(can_be_printed),
+        _ => statement.comments.closing.is_empty(),
+    }
+}
+
+fn smaller_expressions(expression: &Expression) -> Vec<Expression> {
+    match *expression {
+        Expression::BinaryOp { ref left, ref right, .. } => vec![(**left).clone(), (**right).clone()],
+        Expression::UnaryOp { expression: ref inner, .. } => vec![(**inner).clone()],
+        Expression::Call { ref arguments, .. } => arguments.clone(),
+        Expression::Integer(0) => vec![],
+        _ => vec![Expression::Integer(0)],
+    }
+}
+
+/// Returns the smallest program that can be found from program that still fails to round trip,
+/// taking a smaller program whenever there is one
+fn shrink(mut program: Program) -> Program {
+    while let Some(smaller) = smaller_programs(&program).into_iter().find(|program| roundtrip_failure(program).is_some()) {
+        program = smaller;
+    }
+    program
+}
+
+#[test]
+fn printing_and_parsing_gives_the_same_program() {
+    let programs = env::var("ROUNDTRIP_PROGRAMS").ok().and_then(|count| count.parse().ok()).unwrap_or(PROGRAMS);
+    for seed in 0..programs as u64 {
+        let program = Generator { state: seed }.program();
+        if roundtrip_failure(&program).is_some() {
+            let program = shrink(program);
+            panic!("seed {} (shrunk to {:#?}): {}", seed, program, roundtrip_failure(&program).unwrap());
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Checks that haumea::testing builds programs and runs them with the input it is given
+#![cfg(feature = "os")]
+extern crate haumea;
+
+use std::process::Command;
+use haumea::options::CompilerOptions;
+use haumea::testing::{run_program, run_program_with};
+use haumea::toolchain;
+
+/// Returns true if there is a C compiler to build the programs with
+fn has_c_compiler() -> bool {
+    let options = CompilerOptions::new("test.hm");
+    let found = Command::new(toolchain::c_compiler(&options)).arg("--version").output().is_ok();
+    if !found {
+        eprintln!("skipping the tests of haumea::testing, as there is no C compiler");
+    }
+    found
+}
+
+#[test]
+fn programs_read_stdin() {
+    if !has_c_compiler() {
+        return;
+    }
+    let source = "external function getchar\n\
+                  to main do\n    display(getchar())\n    display(getchar())\n    return getchar()\nend\n";
+    let output = run_program(source, "AB").unwrap();
+    assert_eq!(output.stdout, "65\n66\n");
+    // getchar gives -1 at the end of the input, and the exit code is its low byte
+    assert_eq!(output.exit_code, 255);
+}
+
+#[test]
+fn runtime_errors_go_to_stderr() {
+    if !has_c_compiler() {
+        return;
+    }
+    let mut options = CompilerOptions::new("divide.hm");
+    options.safe = true;
+    let source = "to main do\n    display(1)\n    display(1 / 0)\nend\n";
+    let output = run_program_with("divide.hm", source, "", &options).unwrap();
+    assert_eq!(output.stdout, "1\n");
+    assert_eq!(output.stderr, "divide.hm:3: division by zero\nbacktrace:\n    main at divide.hm:3\n");
+    assert_ne!(output.exit_code, 0);
+}
+
+#[test]
+fn runtime_errors_have_backtraces() {
+    if !has_c_compiler() {
+        return;
+    }
+    let mut options = CompilerOptions::new("nested.hm");
+    options.safe = true;
+    let source = "to half with (n) is n / 2\n\
+                  to check with (n) do\n    assert_equal(half(n), 1)\n    return n\nend\n\
+                  to main do\n    display(check(2))\n    display(check(4))\nend\n";
+    let output = run_program_with("nested.hm", source, "", &options).unwrap();
+    assert_eq!(output.stdout, "2\n");
+    assert_eq!(output.stderr, "nested.hm:3: assertion failed: 2 is not equal to 1\n\
+                               backtrace:\n    check at nested.hm:3\n    main at nested.hm:8\n");
+    assert_ne!(output.exit_code, 0);
+}
+
+#[test]
+fn runtime_errors_in_returned_values_are_on_the_line_of_the_return() {
+    if !has_c_compiler() {
+        return;
+    }
+    let mut options = CompilerOptions::new("return.hm");
+    options.safe = true;
+    options.instrument_coverage = true;
+    let source = "to divide with (a, b) is a / b\n\
+                  to main do\n    display(divide(1, 0))\nend\n";
+    let output = run_program_with("return.hm", source, "", &options).unwrap();
+    assert_eq!(output.stderr, "return.hm:1: division by zero\nbacktrace:\n    divide at return.hm:1\n    main at return.hm:3\n");
+}
+
+#[test]
+fn safe_clauses_only_run_when_they_should() {
+    if !has_c_compiler() {
+        return;
+    }
+    let mut options = CompilerOptions::new("clauses.hm");
+    options.safe = true;
+    let source = "to check with (n) do\n    if n then display(1) else display(2)\n    if n then display(3)\n    \
+                  repeat n times display(4)\n    return 0\nend\n\
+                  to main do\n    display(check(0))\n    display(check(1))\nend\n";
+    let output = run_program_with("clauses.hm", source, "", &options).unwrap();
+    assert_eq!(output.stdout, "2\n0\n1\n3\n4\n0\n");
+}
+
+#[test]
+fn safe_programs_can_use_names_from_the_c_library() {
+    if !has_c_compiler() {
+        return;
+    }
+    let mut options = CompilerOptions::new("names.hm");
+    options.safe = true;
+    let source = "to div with (a, b) is a / b\nto abs with (n) is n\nto exit with (code) is code\n\
+                  to main do\n    display(div(6, 3))\n    display(abs(4))\n    display(exit(5))\nend\n";
+    let output = run_program_with("names.hm", source, "", &options).unwrap();
+    assert_eq!(output.stdout, "2\n4\n5\n");
+    // assert_equal needs the full runtime
+    let source = source.replace("display(div(6, 3))", "assert_equal(div(6, 3), 2)");
+    let output = run_program_with("names.hm", &source, "", &options).unwrap();
+    assert_eq!(output.stdout, "4\n5\n");
+}
+
+#[test]
+fn missing_texts_are_empty() {
+    if !has_c_compiler() {
+        return;
+    }
+    let source = "to main do\n    display_text(argument_at(99))\n    display(index_of(argument_at(99), \"\"))\n    \
+                  display_text(environment_value(\"HAUMEA_NOT_SET\"))\n    \
+                  display_text(uppercase(argument_at(-1)))\nend\n";
+    let output = run_program(source, "").unwrap();
+    assert_eq!(output.stdout, "\n0\n\n\n");
+    assert_eq!(output.exit_code, 0);
+}
+
+#[test]
+fn files_that_cant_be_opened_are_errors() {
+    if !has_c_compiler() {
+        return;
+    }
+    let source = "to main do\n    variable file\n    set file to open(\"haumea/no/such/file.txt\", \"r\")\n    \
+                  display(file)\n    display_text(read_line(file))\nend\n";
+    let output = run_program(source, "").unwrap();
+    assert_eq!(output.stdout, "0\n");
+    assert_eq!(output.stderr, "0 is not an open file\n");
+    assert_eq!(output.exit_code, 1);
+}
+
+#[test]
+fn elapsed_milliseconds_counts_from_current_milliseconds() {
+    if !has_c_compiler() {
+        return;
+    }
+    let source = "to main do\n    variable start\n    set start to current_milliseconds()\n    \
+                  display(current_time() - start / 1000 <= 1)\n    \
+                  display(elapsed_milliseconds(start) >= 0 and elapsed_milliseconds(start) < 1000)\nend\n";
+    let output = run_program(source, "").unwrap();
+    assert_eq!(output.stdout, "1\n1\n");
+}
+
+#[test]
+fn errors_in_programs_are_errors() {
+    let error = run_program("to main do display(x) end", "").unwrap_err();
+    assert!(error.starts_with("test.hm:1:"), "{}", error);
+}
@@ -0,0 +1,57 @@
+//! The haumea compiler built for WebAssembly, so that a web page like a playground can compile
+//! programs to C without a server
+//!
+//! It is built with [wasm-pack](https://rustwasm.github.io/wasm-pack/), which makes a JavaScript
+//! module with `compile_to_c` in it:
+//!
+//! ```text
+//! $ wasm-pack build --target web
+//! ```
+//!
+//! ```js
+//! import init, { compile_to_c } from "./pkg/haumea_wasm.js";
+//!
+//! await init();
+//! try {
+//!     console.log(compile_to_c("to main do display(6 * 7) end"));
+//! } catch (error) {
+//!     console.log(error.message);
+//! }
+//! ```
+use haumea::diagnostic::Diagnostic;
+use haumea::options::CompilerOptions;
+use haumea::parser::{self, SourceFile};
+use haumea::scanner::Scanner;
+use wasm_bindgen::prelude::*;
+
+/// The name of the program in its errors and in the #line directives of its C
+const SOURCE_NAME: &str = "main.hm";
+
+/// Compiles the haumea program in source to C, like `haumea build` does
+///
+/// If the program has errors, this throws an Error whose message has each of them on a line
+/// of its own, like `main.hm:2:5: error[E0201]: ...`. A program that calls builtins that aren't in the
+/// minimal runtime, like `display_text`, includes `haumea_rt.h`, which the C has to be compiled with.
+#[wasm_bindgen]
+pub fn compile_to_c(source: &str) -> Result<String, JsValue> {
+    compile(source).map_err(|errors| {
+        let message = errors.iter().map(|error| error.to_string()).collect::<Vec<_>>().join("\n");
+        JsError::new(&message).into()
+    })
+}
+
+/// Compiles the haumea program in source to C, or returns its errors, for compile_to_c
+///
+/// # Examples
+/// ```
+/// let c = haumea_wasm::compile("to main do display(6 * 7) end").unwrap();
+/// assert!(c.contains("display(42l);"));
+///
+/// let errors = haumea_wasm::compile("to main do\n    display(x)\nend").unwrap_err();
+/// assert_eq!(errors[0].to_string(), "main.hm:2:5: error[E0201]: The variable x is not declared");
+/// ```
+pub fn compile(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let program = parser::parse(Scanner::new(source)).map_err(|error| vec![error.in_file(SOURCE_NAME)])?;
+    let files = [SourceFile { name: SOURCE_NAME.to_string(), program }];
+    haumea::compile(&files, &CompilerOptions::new(SOURCE_NAME))
+}